@@ -0,0 +1,58 @@
+//! Throughput of concurrent WAL writers with and without group commit
+//!
+//! Every write is synced before it is acknowledged (`Durability::Always`), which is
+//! where sharing one sync between writers pays off. Run with
+//! `cargo bench --bench wal_group_commit`.
+
+use std::time::{Duration, Instant};
+use zephyrite::storage::wal::{Durability, WalManager, WalOperation, WalOptions};
+
+const WRITES_PER_THREAD: u32 = 200;
+const THREAD_COUNTS: [u32; 3] = [1, 4, 16];
+
+fn run(threads: u32, group_commit: bool) -> Duration {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let options = WalOptions::default()
+        .with_durability(Durability::Always)
+        .with_group_commit(group_commit);
+    let wal = WalManager::open(dir.path().join("bench.wal"), &options).expect("open WAL");
+
+    let started = Instant::now();
+    std::thread::scope(|scope| {
+        for thread in 0..threads {
+            let wal = &wal;
+            scope.spawn(move || {
+                for i in 0..WRITES_PER_THREAD {
+                    wal.log_operation(WalOperation::Put {
+                        key: format!("key:{thread}:{i}"),
+                        value: vec![b'x'; 128],
+                        expires_at: None,
+                        version: Some(1),
+                    })
+                    .expect("log operation");
+                }
+            });
+        }
+    });
+    started.elapsed()
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>14} {:>14} {:>8}",
+        "threads", "per-write", "group", "speedup"
+    );
+
+    for threads in THREAD_COUNTS {
+        let writes = f64::from(threads * WRITES_PER_THREAD);
+        let per_write = writes / run(threads, false).as_secs_f64();
+        let group = writes / run(threads, true).as_secs_f64();
+
+        println!(
+            "{threads:>8} {:>10.0} op/s {:>10.0} op/s {:>7.1}x",
+            per_write,
+            group,
+            group / per_write
+        );
+    }
+}
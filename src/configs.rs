@@ -1,5 +1,13 @@
 //! HTTP Server Configuration
+use crate::storage::wal::{DEFAULT_MAX_SEGMENT_SIZE, EncryptionKey};
+use crate::storage::{
+    CompactionPolicy, Compression, Durability, RecoveryMode, RecoveryTarget, StorageResult,
+};
 use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Default interval between background sweeps for expired keys
+const DEFAULT_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Storage backend type
 #[derive(Debug, Clone)]
@@ -8,6 +16,42 @@ pub enum StorageType {
     Memory,
     /// Persistent storage with WAL
     Persistent,
+    /// Page-based storage on disk, so data is not limited to memory
+    Disk,
+}
+
+/// Where point-in-time recovery reads from and how far it replays
+#[derive(Debug, Clone)]
+pub struct PointInTimeRecovery {
+    /// WAL file path of the storage to recover from, which is left unchanged
+    pub source_wal_path: String,
+    /// Last point in the source WAL to recover
+    pub target: RecoveryTarget,
+}
+
+/// Where an encryption key is loaded from
+///
+/// The key is written as 64 hexadecimal digits or in base64; a key file may
+/// also hold the 32 raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    /// A file holding the key
+    File(String),
+    /// An environment variable holding the key
+    Env(String),
+}
+
+impl KeySource {
+    /// Load the key
+    ///
+    /// # Errors
+    /// Returns a `StorageError::Encryption` if the key cannot be read or is malformed.
+    pub fn load(&self) -> StorageResult<EncryptionKey> {
+        match self {
+            KeySource::File(path) => EncryptionKey::from_file(path),
+            KeySource::Env(name) => EncryptionKey::from_env(name),
+        }
+    }
 }
 
 /// Storage configuration
@@ -15,12 +59,38 @@ pub enum StorageType {
 pub struct StorageConfig {
     /// Type of storage backend to use
     pub storage_type: StorageType,
-    /// Memory capacity limit (bytes)
+    /// Initial capacity of in-memory storage; disk storage uses `cache_pages` instead
     pub memory_capacity: Option<usize>,
     /// WAL file path for persistent storage
     pub wal_file_path: Option<String>,
+    /// Data directory for disk storage
+    pub data_dir: Option<String>,
+    /// Pages the buffer pool of disk storage caches; the storage's default if `None`
+    pub cache_pages: Option<usize>,
     /// Whether to use checksums for data integrity
     pub use_checksums: bool,
+    /// When WAL writes are forced to stable storage
+    pub durability: Durability,
+    /// Size in bytes after which the WAL rolls over to a new segment file
+    pub wal_segment_size: u64,
+    /// How WAL records and snapshots are compressed
+    pub wal_compression: Compression,
+    /// Key WAL records and snapshots are encrypted with; unencrypted if `None`
+    pub encryption_key: Option<KeySource>,
+    /// Keys rotated out that existing data may still be encrypted with; it is
+    /// re-encrypted with `encryption_key` on startup
+    pub previous_encryption_keys: Vec<KeySource>,
+    /// What recovery does with damaged WAL records
+    pub wal_recovery: RecoveryMode,
+    /// Whether the server refuses to start when a WAL entry fails to replay
+    pub fail_on_replay_errors: bool,
+    /// Recover another storage up to a point in time into `wal_file_path` on
+    /// startup; `wal_file_path` must not hold data yet, so clear this once it has run
+    pub point_in_time_recovery: Option<PointInTimeRecovery>,
+    /// When the WAL of persistent storage is compacted in the background
+    pub compaction: CompactionPolicy,
+    /// How often the server sweeps expired keys out of storage
+    pub expiry_interval: Duration,
 }
 
 impl Default for StorageConfig {
@@ -29,7 +99,19 @@ impl Default for StorageConfig {
             storage_type: StorageType::Memory,
             memory_capacity: None,
             wal_file_path: None,
+            data_dir: None,
+            cache_pages: None,
             use_checksums: true,
+            durability: Durability::default(),
+            wal_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            wal_compression: Compression::default(),
+            encryption_key: None,
+            previous_encryption_keys: Vec::new(),
+            wal_recovery: RecoveryMode::default(),
+            fail_on_replay_errors: false,
+            point_in_time_recovery: None,
+            compaction: CompactionPolicy::default(),
+            expiry_interval: DEFAULT_EXPIRY_INTERVAL,
         }
     }
 }
@@ -42,7 +124,29 @@ impl StorageConfig {
             storage_type: StorageType::Persistent,
             memory_capacity: None,
             wal_file_path: Some(wal_file_path.into()),
+            data_dir: None,
+            cache_pages: None,
             use_checksums: true,
+            durability: Durability::default(),
+            wal_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            wal_compression: Compression::default(),
+            encryption_key: None,
+            previous_encryption_keys: Vec::new(),
+            wal_recovery: RecoveryMode::default(),
+            fail_on_replay_errors: false,
+            point_in_time_recovery: None,
+            compaction: CompactionPolicy::default(),
+            expiry_interval: DEFAULT_EXPIRY_INTERVAL,
+        }
+    }
+
+    /// Creates a new disk storage configuration
+    #[must_use]
+    pub fn disk(data_dir: impl Into<String>) -> Self {
+        Self {
+            storage_type: StorageType::Disk,
+            data_dir: Some(data_dir.into()),
+            ..Self::default()
         }
     }
 
@@ -53,7 +157,19 @@ impl StorageConfig {
             storage_type: StorageType::Memory,
             memory_capacity: None,
             wal_file_path: None,
+            data_dir: None,
+            cache_pages: None,
             use_checksums: true,
+            durability: Durability::default(),
+            wal_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            wal_compression: Compression::default(),
+            encryption_key: None,
+            previous_encryption_keys: Vec::new(),
+            wal_recovery: RecoveryMode::default(),
+            fail_on_replay_errors: false,
+            point_in_time_recovery: None,
+            compaction: CompactionPolicy::default(),
+            expiry_interval: DEFAULT_EXPIRY_INTERVAL,
         }
     }
 
@@ -64,12 +180,96 @@ impl StorageConfig {
         self
     }
 
+    /// Sets how many pages the buffer pool of disk storage caches
+    #[must_use]
+    pub fn with_cache_pages(mut self, cache_pages: usize) -> Self {
+        self.cache_pages = Some(cache_pages);
+        self
+    }
+
     /// Sets whether to use checksums
     #[must_use]
     pub fn with_checksums(mut self, use_checksums: bool) -> Self {
         self.use_checksums = use_checksums;
         self
     }
+
+    /// Sets when WAL writes are forced to stable storage
+    #[must_use]
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets the size in bytes after which the WAL rolls over to a new segment file
+    #[must_use]
+    pub fn with_wal_segment_size(mut self, wal_segment_size: u64) -> Self {
+        self.wal_segment_size = wal_segment_size;
+        self
+    }
+
+    /// Sets how WAL records and snapshots are compressed
+    #[must_use]
+    pub fn with_wal_compression(mut self, wal_compression: Compression) -> Self {
+        self.wal_compression = wal_compression;
+        self
+    }
+
+    /// Sets the key WAL records and snapshots are encrypted with
+    #[must_use]
+    pub fn with_encryption_key(mut self, key: KeySource) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Adds a rotated-out key that existing data may still be encrypted with
+    #[must_use]
+    pub fn with_previous_encryption_key(mut self, key: KeySource) -> Self {
+        self.previous_encryption_keys.push(key);
+        self
+    }
+
+    /// Sets what recovery does with damaged WAL records
+    #[must_use]
+    pub fn with_wal_recovery(mut self, wal_recovery: RecoveryMode) -> Self {
+        self.wal_recovery = wal_recovery;
+        self
+    }
+
+    /// Sets whether the server refuses to start when a WAL entry fails to replay
+    #[must_use]
+    pub fn with_fail_on_replay_errors(mut self, fail_on_replay_errors: bool) -> Self {
+        self.fail_on_replay_errors = fail_on_replay_errors;
+        self
+    }
+
+    /// Sets the storage to recover up to a point in time on startup
+    #[must_use]
+    pub fn with_point_in_time_recovery(
+        mut self,
+        source_wal_path: impl Into<String>,
+        target: RecoveryTarget,
+    ) -> Self {
+        self.point_in_time_recovery = Some(PointInTimeRecovery {
+            source_wal_path: source_wal_path.into(),
+            target,
+        });
+        self
+    }
+
+    /// Sets when the WAL is compacted in the background
+    #[must_use]
+    pub fn with_compaction(mut self, compaction: CompactionPolicy) -> Self {
+        self.compaction = compaction;
+        self
+    }
+
+    /// Sets how often expired keys are swept out of storage
+    #[must_use]
+    pub fn with_expiry_interval(mut self, interval: Duration) -> Self {
+        self.expiry_interval = interval;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
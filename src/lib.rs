@@ -11,6 +11,8 @@ pub mod storage;
 /// Utility functions and helpers
 pub mod utils;
 
-pub use configs::{Config, StorageConfig, StorageType};
+pub use configs::{Config, KeySource, StorageConfig, StorageType};
 pub use server::Server;
-pub use storage::{MemoryStorage, PersistentStorage, StorageEngine, StorageError, StorageResult};
+pub use storage::{
+    DiskStorage, MemoryStorage, PersistentStorage, StorageEngine, StorageError, StorageResult,
+};
@@ -1,11 +1,16 @@
 //! This is a crate documentation comment.
 //! It provides documentation for the entire crate.
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing::info;
-use zephyrite::{Config, Server, StorageConfig};
+use zephyrite::storage::wal::DEFAULT_MAX_SEGMENT_SIZE;
+use zephyrite::storage::{CompactionPolicy, Compression, Durability, RecoveryMode, RecoveryTarget};
+use zephyrite::{Config, KeySource, Server, StorageConfig};
 
+// Every on/off command line flag is a bool
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 #[command(name = "zephyrite")]
 #[command(about = "A high-performance key-value store")]
@@ -19,21 +24,177 @@ struct Cli {
     #[arg(short, long, default_value = "info")]
     log_level: Option<String>,
 
-    /// Enable persistent storage with Write-Ahead Log
-    #[arg(long)]
+    /// Storage backend to use
+    #[arg(long, value_enum, value_name = "BACKEND")]
+    storage: Option<Backend>,
+
+    /// Enable persistent storage with Write-Ahead Log (same as --storage persistent)
+    #[arg(long, conflicts_with = "storage")]
     persistent: bool,
 
+    /// Directory holding the database and WAL files of disk storage
+    #[arg(long, value_name = "PATH", default_value = "zephyrite-data")]
+    data_dir: PathBuf,
+
     /// Path to the WAL file (implies --persistent)
     #[arg(long, value_name = "PATH")]
     wal_file: Option<PathBuf>,
 
-    /// Initial memory capacity for storage
+    /// Initial memory capacity for storage (not used by disk storage, see --cache-pages)
     #[arg(long, value_name = "SIZE")]
     memory_capacity: Option<usize>,
 
+    /// Pages of 4 KiB the buffer pool of disk storage caches (default 4096, at least 64)
+    #[arg(long, value_name = "PAGES")]
+    cache_pages: Option<usize>,
+
     /// Disable checksums in WAL entries (only for persistent storage)
     #[arg(long)]
     no_checksums: bool,
+
+    /// When WAL writes are synced to disk: `always`, `interval:<ms>` or `os`
+    #[arg(long, value_name = "MODE", default_value = "os")]
+    durability: Durability,
+
+    /// Size in bytes after which the WAL rolls over to a new segment file
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_SEGMENT_SIZE)]
+    wal_segment_size: u64,
+
+    /// How WAL records and snapshots are compressed: `none`, `lz4`, `zstd` or `zstd:<level>`
+    #[arg(long, value_name = "ALGORITHM", default_value = "none")]
+    wal_compression: Compression,
+
+    /// File holding the key WAL records and snapshots are encrypted with
+    #[arg(long, value_name = "PATH", conflicts_with = "encryption_key_env")]
+    encryption_key_file: Option<String>,
+
+    /// Environment variable holding the key WAL records and snapshots are encrypted with
+    #[arg(long, value_name = "VAR")]
+    encryption_key_env: Option<String>,
+
+    /// File holding a rotated-out key to re-encrypt existing data from (repeatable)
+    #[arg(long, value_name = "PATH")]
+    previous_encryption_key_file: Vec<String>,
+
+    /// Environment variable holding a rotated-out key to re-encrypt existing data from (repeatable)
+    #[arg(long, value_name = "VAR")]
+    previous_encryption_key_env: Vec<String>,
+
+    /// What to do with damaged WAL records on startup: `strict`, `truncate-tail` or `skip-corrupt`
+    #[arg(long, value_name = "MODE", default_value = "strict")]
+    wal_recovery: RecoveryMode,
+
+    /// Refuse to start if any WAL entry fails to replay during recovery
+    #[arg(long)]
+    fail_on_replay_errors: bool,
+
+    /// WAL file of a storage to recover into --wal-file up to --recover-to, leaving it unchanged,
+    /// then exit without serving; start again without it to serve the recovered data
+    #[arg(long, value_name = "PATH", requires = "recover_to")]
+    recover_from: Option<PathBuf>,
+
+    /// Last sequence number or RFC 3339 timestamp to recover from --recover-from
+    #[arg(long, value_name = "TARGET", requires = "recover_from")]
+    recover_to: Option<RecoveryTarget>,
+
+    /// Compact the WAL once its segments add up to this many bytes
+    #[arg(long, value_name = "BYTES")]
+    compact_wal_size: Option<u64>,
+
+    /// Compact the WAL once it holds this many entries per live key
+    #[arg(long, value_name = "RATIO")]
+    compact_ratio: Option<f64>,
+
+    /// Compact the WAL at least this often while it has new entries
+    #[arg(long, value_name = "SECONDS")]
+    compact_interval: Option<u64>,
+
+    /// Never compact the WAL in the background
+    #[arg(long)]
+    no_auto_compaction: bool,
+}
+
+/// Storage backends selectable on the command line
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    /// Everything in memory, lost on exit
+    Memory,
+    /// Everything in memory, with a Write-Ahead Log to rebuild it from
+    Persistent,
+    /// Pages on disk cached in a buffer pool, with a Write-Ahead Log
+    Disk,
+}
+
+impl Cli {
+    /// The storage backend asked for, explicitly or through the WAL options
+    fn backend(&self) -> Backend {
+        match self.storage {
+            Some(backend) => backend,
+            None if self.persistent || self.wal_file.is_some() || self.recover_from.is_some() => {
+                Backend::Persistent
+            }
+            None => Backend::Memory,
+        }
+    }
+}
+
+/// Apply the WAL settings shared by persistent and disk storage
+fn with_wal_settings(mut config: StorageConfig, cli: &Cli) -> StorageConfig {
+    config = config
+        .with_checksums(!cli.no_checksums)
+        .with_durability(cli.durability)
+        .with_wal_segment_size(cli.wal_segment_size)
+        .with_wal_compression(cli.wal_compression)
+        .with_wal_recovery(cli.wal_recovery)
+        .with_fail_on_replay_errors(cli.fail_on_replay_errors);
+    info!("🔒 WAL durability: {}", cli.durability);
+    info!("📂 WAL segment size: {} bytes", cli.wal_segment_size);
+    info!("🗜️  WAL compression: {}", cli.wal_compression);
+    info!("🩹 WAL recovery mode: {}", cli.wal_recovery);
+
+    if let Some(path) = &cli.encryption_key_file {
+        config = config.with_encryption_key(KeySource::File(path.clone()));
+    } else if let Some(name) = &cli.encryption_key_env {
+        config = config.with_encryption_key(KeySource::Env(name.clone()));
+    }
+    for path in &cli.previous_encryption_key_file {
+        config = config.with_previous_encryption_key(KeySource::File(path.clone()));
+    }
+    for name in &cli.previous_encryption_key_env {
+        config = config.with_previous_encryption_key(KeySource::Env(name.clone()));
+    }
+    if config.encryption_key.is_some() {
+        info!(
+            "🔐 WAL encryption enabled with {} previous keys",
+            config.previous_encryption_keys.len()
+        );
+    }
+
+    if cli.no_checksums {
+        info!("⚠️  WAL checksums disabled");
+    }
+
+    config
+}
+
+/// The background compaction policy asked for on the command line
+fn compaction_policy(cli: &Cli) -> CompactionPolicy {
+    if cli.no_auto_compaction {
+        info!("🧹 Automatic WAL compaction disabled");
+        return CompactionPolicy::disabled();
+    }
+
+    let mut policy = CompactionPolicy::default();
+    if let Some(bytes) = cli.compact_wal_size {
+        policy = policy.with_max_wal_bytes(Some(bytes));
+    }
+    if let Some(ratio) = cli.compact_ratio {
+        policy = policy.with_max_entry_ratio(Some(ratio));
+    }
+    if let Some(seconds) = cli.compact_interval {
+        policy = policy.with_interval(Some(Duration::from_secs(seconds)));
+    }
+    policy
 }
 
 #[tokio::main]
@@ -57,40 +218,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // Determine storage configuration
-    let storage_config = if cli.persistent || cli.wal_file.is_some() {
-        let wal_path = cli
-            .wal_file
-            .unwrap_or_else(|| PathBuf::from("zephyrite.wal"));
-        info!("💾 Using persistent storage with WAL file: {:?}", wal_path);
-
-        let mut config = StorageConfig::persistent(wal_path.to_string_lossy().to_string())
-            .with_checksums(!cli.no_checksums);
-
-        if let Some(capacity) = cli.memory_capacity {
-            config = config.with_memory_capacity(capacity);
-            info!("🧠 Memory capacity set to: {}", capacity);
-        }
+    let storage_config = match cli.backend() {
+        Backend::Persistent => {
+            let wal_path = cli
+                .wal_file
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("zephyrite.wal"));
+            info!("💾 Using persistent storage with WAL file: {:?}", wal_path);
+
+            let mut config = with_wal_settings(
+                StorageConfig::persistent(wal_path.to_string_lossy().to_string()),
+                &cli,
+            )
+            .with_compaction(compaction_policy(&cli));
 
-        if cli.no_checksums {
-            info!("⚠️  WAL checksums disabled");
+            if let Some(capacity) = cli.memory_capacity {
+                config = config.with_memory_capacity(capacity);
+                info!("🧠 Memory capacity set to: {}", capacity);
+            }
+
+            if let (Some(source), Some(target)) = (&cli.recover_from, cli.recover_to) {
+                config = config.with_point_in_time_recovery(source.to_string_lossy(), target);
+                info!("⏪ Recovering {:?} up to {}", source, target);
+            }
+
+            config
         }
+        Backend::Disk => {
+            info!("🗄️  Using disk storage in {:?}", cli.data_dir);
+            let mut config = with_wal_settings(
+                StorageConfig::disk(cli.data_dir.to_string_lossy().to_string()),
+                &cli,
+            );
 
-        config
-    } else {
-        info!("⚡ Using in-memory storage (no persistence)");
-        let mut config = StorageConfig::memory();
+            if let Some(cache_pages) = cli.cache_pages {
+                config = config.with_cache_pages(cache_pages);
+                info!("🧠 Page cache set to: {} pages", cache_pages);
+            }
 
-        if let Some(capacity) = cli.memory_capacity {
-            config = config.with_memory_capacity(capacity);
-            info!("🧠 Memory capacity set to: {}", capacity);
+            config
         }
+        Backend::Memory => {
+            info!("⚡ Using in-memory storage (no persistence)");
+            let mut config = StorageConfig::memory();
+
+            if let Some(capacity) = cli.memory_capacity {
+                config = config.with_memory_capacity(capacity);
+                info!("🧠 Memory capacity set to: {}", capacity);
+            }
 
-        config
+            config
+        }
     };
 
     let config = Config::with_storage(cli.port, storage_config);
     let server = Server::new(config)?;
 
+    // Recovery is one-shot: a restart with the same flags would find the output taken
+    if cli.recover_from.is_some() {
+        info!("⏪ Recovery complete; start without --recover-from and --recover-to to serve it");
+        return Ok(());
+    }
+
     server.start().await?;
 
     Ok(())
@@ -1,19 +1,74 @@
-use crate::storage::utils::{validate_key, validate_value};
-use crate::storage::{StorageEngine, StorageError};
+use crate::storage::persistent::RecoveryReport;
+use crate::storage::utils::{
+    MAX_BATCH_BYTES, MAX_BATCH_OPS, key_successor, validate_key, validate_value,
+};
+use crate::storage::{BatchOperation, StorageEngine, StorageError, Value};
+use crate::utils::time;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
+    body::Body,
+    extract::{Path, Query, State, rejection::QueryRejection},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
 };
+use bytes::Bytes;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{error, info, instrument, warn};
 
 use super::types::{
-    ErrorResponse, GetKeyResponse, HealthResponse, ListKeysResponse, PutKeyRequest,
+    BatchOperationRequest, BatchResponse, DiscardedRecordResponse, DumpRecord, ErrorResponse,
+    GetKeyResponse, HealthResponse, ListKeysQuery, ListKeysResponse, PutKeyRequest,
+    RecoveryReportResponse, ValueEncoding,
 };
 
 type HandlerResult<T> = std::result::Result<T, (StatusCode, Json<ErrorResponse>)>;
 
+/// Media type used for raw binary request and response bodies
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Header carrying a TTL in seconds on PUT, and the remaining TTL on GET
+const TTL_HEADER: &str = "x-zephyrite-ttl";
+
+/// Number of keys returned by `GET /keys` when no limit is given
+const DEFAULT_LIST_LIMIT: usize = 1000;
+
+/// Largest page `GET /keys` will return, regardless of the requested limit
+const MAX_LIST_LIMIT: usize = 10_000;
+
+/// Media type of the `GET /dump` stream
+const NDJSON: &str = "application/x-ndjson";
+
+/// Number of entries `GET /dump` reads from storage at a time
+const DUMP_BATCH_SIZE: usize = 256;
+
+/// Largest `POST /batch` body accepted: room for a batch at the storage limits
+/// with every value base64-encoded, plus the JSON around each operation
+pub(super) const BATCH_BODY_LIMIT: usize = MAX_BATCH_BYTES / 3 * 4 + MAX_BATCH_OPS * 128;
+
+/// Number of encoded batches buffered between the storage reader and the client
+const DUMP_BUFFERED_BATCHES: usize = 4;
+
+/// Precondition of a conditional PUT request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Precondition {
+    /// `If-Match: "<version>"` - replace only if the key is at this version
+    Version(u64),
+    /// `If-Match: *` - replace only if the key exists
+    Exists,
+    /// `If-None-Match: *` - create only if the key does not exist
+    Absent,
+}
+
+/// Decoded body of a PUT request
+struct PutBody {
+    value: Bytes,
+    ttl: Option<u64>,
+}
+
 /// Represents the different storage operations that can fail
 #[derive(Debug, Clone, Copy)]
 enum Operation {
@@ -21,6 +76,8 @@ enum Operation {
     PutKey,
     DeleteKey,
     ListKeys,
+    WriteBatch,
+    Dump,
 }
 
 impl std::fmt::Display for Operation {
@@ -30,6 +87,8 @@ impl std::fmt::Display for Operation {
             Operation::PutKey => write!(f, "put_key"),
             Operation::DeleteKey => write!(f, "delete_key"),
             Operation::ListKeys => write!(f, "list_keys"),
+            Operation::WriteBatch => write!(f, "write_batch"),
+            Operation::Dump => write!(f, "dump"),
         }
     }
 }
@@ -61,6 +120,24 @@ fn handle_storage_error(
                 message: msg,
             }),
         ),
+        StorageError::KeyAlreadyExists(key) => (
+            StatusCode::PRECONDITION_FAILED,
+            Json(ErrorResponse {
+                error: "precondition_failed".to_string(),
+                message: format!("Key '{key}' already exists"),
+            }),
+        ),
+        StorageError::VersionMismatch {
+            key,
+            expected,
+            actual,
+        } => (
+            StatusCode::PRECONDITION_FAILED,
+            Json(ErrorResponse {
+                error: "precondition_failed".to_string(),
+                message: format!("Key '{key}' is at version {actual}, expected {expected}"),
+            }),
+        ),
         e => {
             error!("Storage error in {}: {}", operation, e);
             (
@@ -74,6 +151,169 @@ fn handle_storage_error(
     }
 }
 
+/// Build an error response that does not originate from the storage layer
+fn error_response(
+    status: StatusCode,
+    error: &str,
+    message: impl Into<String>,
+) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.to_string(),
+            message: message.into(),
+        }),
+    )
+}
+
+/// Media type of the request body, without parameters such as `charset`
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(str::trim)
+}
+
+/// Whether the client asked for the raw value instead of the JSON envelope
+fn accepts_octet_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| {
+            accept
+                .split(',')
+                .any(|media| media.split(';').next().map(str::trim) == Some(OCTET_STREAM))
+        })
+}
+
+/// TTL in seconds from the `X-Zephyrite-TTL` header, if present
+fn ttl_header(headers: &HeaderMap) -> HandlerResult<Option<u64>> {
+    headers
+        .get(TTL_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| {
+                    error_response(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_ttl",
+                        "TTL header must be a whole number of seconds",
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// Format a key version as a strong entity tag
+fn etag(version: u64) -> String {
+    format!("\"{version}\"")
+}
+
+/// Parse an entity tag produced by [`etag`], accepting the weak `W/` form
+fn parse_etag(tag: &str) -> Option<u64> {
+    let tag = tag.trim();
+    let tag = tag.strip_prefix("W/").unwrap_or(tag);
+    tag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()
+}
+
+/// Read the `If-Match` / `If-None-Match` headers of a PUT request
+fn put_precondition(headers: &HeaderMap) -> HandlerResult<Option<Precondition>> {
+    let header_value = |name| {
+        headers
+            .get(name)
+            .map(|value| value.to_str().map(str::trim).unwrap_or_default())
+    };
+    let invalid = |message: &str| {
+        error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_precondition",
+            message.to_string(),
+        )
+    };
+
+    match (
+        header_value(header::IF_MATCH),
+        header_value(header::IF_NONE_MATCH),
+    ) {
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => Err(invalid("If-Match and If-None-Match cannot be combined")),
+        (Some("*"), None) => Ok(Some(Precondition::Exists)),
+        (Some(tag), None) => parse_etag(tag)
+            .map(|version| Some(Precondition::Version(version)))
+            .ok_or_else(|| invalid("If-Match must be '*' or a single ETag")),
+        (None, Some("*")) => Ok(Some(Precondition::Absent)),
+        (None, Some(_)) => Err(invalid("If-None-Match on PUT only supports '*'")),
+    }
+}
+
+/// Whether a GET request's `If-None-Match` header matches the current version
+fn if_none_match(headers: &HeaderMap, version: u64) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.trim() == "*" || tags.split(',').any(|tag| parse_etag(tag) == Some(version))
+        })
+}
+
+/// Encode a stored value for a JSON body, falling back to base64 for binary data
+fn encode_value(value: &Value) -> (String, ValueEncoding) {
+    match value.as_str() {
+        Some(text) => (text.to_string(), ValueEncoding::Utf8),
+        None => (STANDARD.encode(&value.value), ValueEncoding::Base64),
+    }
+}
+
+/// Decode a value carried inside a JSON body
+fn decode_value(value: String, encoding: ValueEncoding) -> HandlerResult<Bytes> {
+    match encoding {
+        ValueEncoding::Utf8 => Ok(Bytes::from(value)),
+        ValueEncoding::Base64 => STANDARD.decode(value).map(Bytes::from).map_err(|_| {
+            error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_value",
+                "Value is not valid base64",
+            )
+        }),
+    }
+}
+
+/// Extract the value bytes and TTL from a PUT body
+///
+/// `application/octet-stream` bodies are stored as-is, JSON bodies are decoded
+/// from a [`PutKeyRequest`]. A `ttl` field in the JSON body takes precedence
+/// over the TTL header.
+fn decode_put_body(headers: &HeaderMap, body: Bytes) -> HandlerResult<PutBody> {
+    let header_ttl = ttl_header(headers)?;
+
+    match content_type(headers) {
+        Some(OCTET_STREAM) => Ok(PutBody {
+            value: body,
+            ttl: header_ttl,
+        }),
+        Some(media) if media == "application/json" || media.ends_with("+json") => {
+            let request: PutKeyRequest = serde_json::from_slice(&body).map_err(|e| {
+                error_response(StatusCode::BAD_REQUEST, "invalid_json", e.to_string())
+            })?;
+
+            let value = decode_value(request.value, request.encoding)?;
+
+            Ok(PutBody {
+                value,
+                ttl: request.ttl.or(header_ttl),
+            })
+        }
+        _ => Err(error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            format!("Expected application/json or {OCTET_STREAM} request body"),
+        )),
+    }
+}
+
 /// Health check endpoint
 #[instrument]
 pub async fn health_check() -> Json<HealthResponse> {
@@ -85,11 +325,15 @@ pub async fn health_check() -> Json<HealthResponse> {
 }
 
 /// GET /keys/:key - Retrieve a value by key
-#[instrument(skip(storage))]
+///
+/// Responds with the raw bytes when the client sends `Accept: application/octet-stream`,
+/// otherwise with a JSON envelope.
+#[instrument(skip(storage, headers))]
 pub async fn get_key(
     Path(key): Path<String>,
     State(storage): State<Arc<dyn StorageEngine>>,
-) -> HandlerResult<Json<GetKeyResponse>> {
+    headers: HeaderMap,
+) -> HandlerResult<Response> {
     if let Err(e) = validate_key(&key) {
         return Err(handle_storage_error(e, Operation::GetKey));
     }
@@ -102,14 +346,53 @@ pub async fn get_key(
                 "Successfully retrieved key: {}, size: {} bytes",
                 key, stored_value.metadata.size
             );
-            Ok(Json(GetKeyResponse {
-                key: key.clone(),
-                value: stored_value.value,
-                found: true,
-                size: stored_value.metadata.size,
-                created_at: stored_value.metadata.created_at,
-                updated_at: stored_value.metadata.updated_at,
-            }))
+            let version = stored_value.metadata.version;
+            if if_none_match(&headers, version) {
+                return Ok(
+                    (StatusCode::NOT_MODIFIED, [(header::ETAG, etag(version))]).into_response()
+                );
+            }
+
+            // Round up so a key with time left never reports a TTL of zero
+            let ttl = stored_value
+                .metadata
+                .ttl_remaining()
+                .map(|remaining| remaining.as_millis().div_ceil(1000))
+                .map(|secs| u64::try_from(secs).unwrap_or(u64::MAX));
+
+            if accepts_octet_stream(&headers) {
+                let mut response = (
+                    [
+                        (header::CONTENT_TYPE, OCTET_STREAM.to_string()),
+                        (header::ETAG, etag(version)),
+                    ],
+                    stored_value.value,
+                )
+                    .into_response();
+                if let Some(ttl) = ttl {
+                    response.headers_mut().insert(TTL_HEADER, ttl.into());
+                }
+                return Ok(response);
+            }
+
+            let (value, encoding) = encode_value(&stored_value);
+
+            Ok((
+                [(header::ETAG, etag(version))],
+                Json(GetKeyResponse {
+                    key: key.clone(),
+                    value,
+                    encoding,
+                    found: true,
+                    size: stored_value.metadata.size,
+                    created_at: stored_value.metadata.created_at,
+                    updated_at: stored_value.metadata.updated_at,
+                    expires_at: stored_value.metadata.expires_at.map(time::format_timestamp),
+                    ttl,
+                    version,
+                }),
+            )
+                .into_response())
         }
         Err(StorageError::KeyNotFound(_)) => {
             warn!("Key not found: {}", key);
@@ -123,37 +406,99 @@ pub async fn get_key(
 }
 
 /// PUT /keys/:key - Store a key-value pair
-#[instrument(skip(storage, request))]
+///
+/// Accepts either a JSON [`PutKeyRequest`] or a raw `application/octet-stream` body.
+/// `If-Match` and `If-None-Match: *` turn the write into a compare-and-swap or
+/// create-only write, answering 412 when the precondition does not hold.
+#[instrument(skip(storage, headers, body))]
 pub async fn put_key(
     Path(key): Path<String>,
     State(storage): State<Arc<dyn StorageEngine>>,
-    Json(request): Json<PutKeyRequest>,
-) -> HandlerResult<StatusCode> {
+    headers: HeaderMap,
+    body: Bytes,
+) -> HandlerResult<Response> {
     if let Err(e) = validate_key(&key) {
         return Err(handle_storage_error(e, Operation::PutKey));
     }
 
-    if let Err(e) = validate_value(&request.value) {
+    let precondition = put_precondition(&headers)?;
+    let PutBody { value, ttl } = decode_put_body(&headers, body)?;
+
+    if let Err(e) = validate_value(&value) {
         return Err(handle_storage_error(e, Operation::PutKey));
     }
 
-    let value_size = request.value.len();
+    let value_size = value.len();
     info!("Storing key: {}, value size: {} bytes", key, value_size);
 
-    match storage.put(&key, &request.value) {
+    if let Some(precondition) = precondition {
+        if ttl.is_some() {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_precondition",
+                "TTL cannot be combined with conditional headers",
+            ));
+        }
+        return conditional_put(storage.as_ref(), &key, &value, precondition);
+    }
+
+    let result = match ttl {
+        Some(ttl) => storage.put_with_ttl(&key, &value, Duration::from_secs(ttl)),
+        None => storage.put(&key, &value),
+    };
+
+    match result {
         Ok(was_new) => {
             if was_new {
                 info!("Successfully created new key: {}", key);
-                Ok(StatusCode::CREATED)
+                Ok(StatusCode::CREATED.into_response())
             } else {
                 info!("Successfully updated existing key: {}", key);
-                Ok(StatusCode::OK)
+                Ok(StatusCode::OK.into_response())
             }
         }
         Err(e) => Err(handle_storage_error(e, Operation::PutKey)),
     }
 }
 
+/// Perform a PUT guarded by `If-Match` / `If-None-Match`, returning the new `ETag`
+fn conditional_put(
+    storage: &dyn StorageEngine,
+    key: &str,
+    value: &[u8],
+    precondition: Precondition,
+) -> HandlerResult<Response> {
+    let result = match precondition {
+        Precondition::Absent => storage
+            .put_if_absent(key, value)
+            .map(|version| (StatusCode::CREATED, version)),
+        Precondition::Exists => storage
+            .get(key)
+            .and_then(|current| storage.compare_and_swap(key, current.metadata.version, value))
+            .map(|version| (StatusCode::OK, version)),
+        Precondition::Version(expected) => storage
+            .compare_and_swap(key, expected, value)
+            .map(|version| (StatusCode::OK, version)),
+    };
+
+    match result {
+        Ok((status, version)) => {
+            info!("Conditionally stored key: {}, version: {}", key, version);
+            Ok((status, [(header::ETAG, etag(version))]).into_response())
+        }
+        // If-Match on a missing key fails the precondition rather than the lookup
+        Err(StorageError::KeyNotFound(_)) => Err(error_response(
+            StatusCode::PRECONDITION_FAILED,
+            "precondition_failed",
+            format!("Key '{key}' does not exist"),
+        )),
+        Err(e) => {
+            warn!("Conditional put on key {} failed: {}", key, e);
+            Err(handle_storage_error(e, Operation::PutKey))
+        }
+    }
+}
+
 /// DELETE /keys/:key - Delete a key
 #[instrument(skip(storage))]
 pub async fn delete_key(
@@ -180,21 +525,230 @@ pub async fn delete_key(
     }
 }
 
-/// GET /keys - List all keys
-#[instrument(skip(storage))]
+/// POST /batch - Apply a list of puts and deletes atomically
+///
+/// The body is a JSON array of `{"op": "put", "key", "value", "encoding"?}` and
+/// `{"op": "delete", "key"}` objects. Either all operations are applied or none.
+#[instrument(skip(storage, body))]
+pub async fn write_batch(
+    State(storage): State<Arc<dyn StorageEngine>>,
+    body: Bytes,
+) -> HandlerResult<Json<BatchResponse>> {
+    let requests: Vec<BatchOperationRequest> = serde_json::from_slice(&body)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, "invalid_json", e.to_string()))?;
+
+    let operations = requests
+        .into_iter()
+        .map(|request| match request {
+            BatchOperationRequest::Put {
+                key,
+                value,
+                encoding,
+            } => Ok(BatchOperation::Put {
+                key,
+                value: decode_value(value, encoding)?,
+            }),
+            BatchOperationRequest::Delete { key } => Ok(BatchOperation::Delete { key }),
+        })
+        .collect::<HandlerResult<Vec<_>>>()?;
+
+    info!("Applying batch of {} operations", operations.len());
+
+    match storage.write_batch(&operations) {
+        Ok(()) => {
+            info!(
+                "Successfully applied batch of {} operations",
+                operations.len()
+            );
+            Ok(Json(BatchResponse {
+                applied: operations.len(),
+            }))
+        }
+        Err(e) => Err(handle_storage_error(e, Operation::WriteBatch)),
+    }
+}
+
+/// GET /keys - List keys in lexicographic order, one page at a time
+///
+/// Supports `prefix`, `start` (inclusive), `end` (exclusive), `limit` and `cursor`
+/// query parameters. Pass the returned `next_cursor` back as `cursor` to fetch
+/// the following page.
+#[instrument(skip(storage, query))]
 pub async fn list_keys(
     State(storage): State<Arc<dyn StorageEngine>>,
+    query: Result<Query<ListKeysQuery>, QueryRejection>,
 ) -> HandlerResult<Json<ListKeysResponse>> {
-    info!("Listing all keys");
+    let Query(query) = query
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, "invalid_query", e.body_text()))?;
+
+    info!("Listing keys: {:?}", query);
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .min(MAX_LIST_LIMIT);
+    let resume_from = query.cursor.as_deref().map(decode_cursor).transpose()?;
+
+    // The lower bound is the tightest of `start`, `prefix` and the cursor position.
+    // Keys sharing the prefix form one contiguous run from there on, so the run
+    // can simply be cut where it ends.
+    let start = [
+        query.start.as_deref(),
+        query.prefix.as_deref(),
+        resume_from.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+
+    // Ask for one key more than the page holds to learn whether another page follows
+    let result = storage.scan_keys(start, query.end.as_deref(), limit.saturating_add(1));
+
+    match result {
+        Ok(scanned) => {
+            let prefix = query.prefix.as_deref().unwrap_or_default();
+            let mut keys: Vec<String> = scanned
+                .into_iter()
+                .take_while(|key| key.starts_with(prefix))
+                .collect();
+
+            let next_cursor = if keys.len() > limit {
+                keys.truncate(limit);
+                keys.last().map(|key| encode_cursor(key))
+            } else {
+                None
+            };
 
-    match storage.keys() {
-        Ok(keys) => {
             info!("Successfully retrieved {} keys", keys.len());
             Ok(Json(ListKeysResponse {
                 count: keys.len(),
                 keys,
+                next_cursor,
             }))
         }
         Err(e) => Err(handle_storage_error(e, Operation::ListKeys)),
     }
 }
+
+/// Encode the last key of a page as an opaque continuation token
+fn encode_cursor(last_key: &str) -> String {
+    URL_SAFE_NO_PAD.encode(last_key)
+}
+
+/// Decode a continuation token into the smallest key the next page may start at
+fn decode_cursor(cursor: &str) -> HandlerResult<String> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .map(|last_key| key_successor(&last_key))
+        .ok_or_else(|| {
+            error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_cursor",
+                "Cursor is not a valid continuation token",
+            )
+        })
+}
+
+/// GET /dump - Stream every live key-value pair as newline-delimited JSON
+///
+/// Entries are read from storage in batches on a blocking thread and written
+/// out as they are encoded, so the dataset is never materialised in full. The
+/// dump is not a point-in-time snapshot: writes made while it runs may or may
+/// not appear in it.
+#[instrument(skip(storage))]
+pub async fn dump(State(storage): State<Arc<dyn StorageEngine>>) -> Response {
+    info!("Streaming dump of all keys");
+
+    let (tx, rx) = tokio::sync::mpsc::channel(DUMP_BUFFERED_BATCHES);
+
+    tokio::task::spawn_blocking(move || {
+        for batch in storage.iter_from(None, DUMP_BATCH_SIZE) {
+            let chunk = batch
+                .map(|entries| encode_dump_batch(&entries))
+                .map_err(|e| {
+                    error!("Storage error in {}: {}", Operation::Dump, e);
+                    std::io::Error::other(e)
+                });
+            let failed = chunk.is_err();
+
+            // Stop once the client has gone away; a failed read aborts the response
+            if tx.blocking_send(chunk).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+
+    ([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(stream)).into_response()
+}
+
+/// GET /admin/recovery - Report what recovery found when the storage was opened
+///
+/// Only persistent and disk storage recover anything; for in-memory storage the
+/// endpoint responds with 404.
+#[instrument(skip(report))]
+pub async fn recovery_report(
+    State(report): State<Option<Arc<RecoveryReport>>>,
+) -> HandlerResult<Json<RecoveryReportResponse>> {
+    let Some(report) = report else {
+        return Err(error_response(
+            StatusCode::NOT_FOUND,
+            "not_persistent",
+            "Storage is not persistent, there is nothing to recover",
+        ));
+    };
+
+    Ok(Json(RecoveryReportResponse {
+        mode: report.mode.to_string(),
+        target: report.target.map(|target| target.to_string()),
+        snapshot_used: report.snapshot_used(),
+        snapshot_sequence_number: report.snapshot_sequence_number,
+        snapshot_keys: report.snapshot_keys,
+        entries_read: report.entries_read,
+        entries_applied: report.entries_applied,
+        entries_failed: report.entries_failed,
+        entries_skipped: report.entries_skipped,
+        discarded: report
+            .discarded
+            .iter()
+            .map(|record| DiscardedRecordResponse {
+                segment: record.segment.display().to_string(),
+                offset: record.offset,
+                bytes: record.bytes,
+                reason: record.reason.clone(),
+                truncated: record.truncated,
+            })
+            .collect(),
+        last_sequence_number: report.last_sequence_number,
+        duration_ms: report.duration.as_secs_f64() * 1000.0,
+        clean: report.is_clean(),
+    }))
+}
+
+/// Encode a batch of entries as NDJSON lines
+fn encode_dump_batch(entries: &[(String, Value)]) -> Bytes {
+    let mut chunk = Vec::new();
+
+    for (key, stored_value) in entries {
+        let (value, encoding) = encode_value(stored_value);
+        let record = DumpRecord {
+            key: key.clone(),
+            value,
+            encoding,
+            version: stored_value.metadata.version,
+            expires_at: stored_value.metadata.expires_at.map(time::format_timestamp),
+        };
+
+        // Serializing a struct of strings and numbers into memory cannot fail
+        if serde_json::to_writer(&mut chunk, &record).is_ok() {
+            chunk.push(b'\n');
+        }
+    }
+
+    Bytes::from(chunk)
+}
@@ -7,35 +7,51 @@ mod types;
 pub use types::*;
 
 use crate::{
-    Config, StorageType,
-    storage::{MemoryStorage, PersistentStorage, StorageEngine},
+    Config, StorageConfig, StorageType,
+    storage::{
+        DiskStorage, MemoryStorage, PersistentStorage, StorageEngine, WalOptions,
+        compaction::CompactionWorker, disk::storage::DEFAULT_CACHE_PAGES,
+        persistent::RecoveryReport, wal::Keyring,
+    },
 };
 use axum::{
     Router,
-    routing::{delete, get, put},
+    extract::DefaultBodyLimit,
+    routing::{delete, get, post, put},
 };
 use std::sync::Arc;
-use tracing::info;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
 
-use handlers::{delete_key, get_key, health_check, list_keys, put_key};
+use handlers::{
+    BATCH_BODY_LIMIT, delete_key, dump, get_key, health_check, list_keys, put_key, recovery_report,
+    write_batch,
+};
 
 /// HTTP Server with integrated storage
 pub struct Server {
     config: Config,
     storage: Arc<dyn StorageEngine>,
+    /// The storage again when it is persistent, for background WAL compaction
+    persistent: Option<Arc<PersistentStorage>>,
+    /// What recovery found when the storage was opened, if it recovers anything
+    recovery_report: Option<Arc<RecoveryReport>>,
 }
 
 impl Server {
     /// Creates a new server instance with the given configuration and creates storage based on config.
     ///
     /// # Errors
-    /// Returns an error if persistent storage initialization fails (e.g., WAL file access issues).
+    /// Returns an error if persistent or disk storage initialization fails (e.g., WAL file access issues).
     pub fn new(config: Config) -> Result<Self> {
-        let storage: Arc<dyn StorageEngine> = match config.storage.storage_type {
-            StorageType::Memory => match config.storage.memory_capacity {
-                Some(capacity) => Arc::new(MemoryStorage::with_capacity(capacity)),
-                None => Arc::new(MemoryStorage::new()),
-            },
+        match config.storage.storage_type {
+            StorageType::Memory => {
+                let storage: Arc<dyn StorageEngine> = match config.storage.memory_capacity {
+                    Some(capacity) => Arc::new(MemoryStorage::with_capacity(capacity)),
+                    None => Arc::new(MemoryStorage::new()),
+                };
+                Ok(Self::with_storage(config, storage))
+            }
             StorageType::Persistent => {
                 let wal_file_path = config.storage.wal_file_path.as_ref().ok_or_else(|| {
                     ServerError::StartupError(
@@ -43,29 +59,72 @@ impl Server {
                     )
                 })?;
 
-                let persistent_storage = match config.storage.memory_capacity {
-                    Some(capacity) => PersistentStorage::new_with_options(
-                        wal_file_path,
-                        capacity,
-                        config.storage.use_checksums,
-                    )
-                    .map_err(ServerError::StorageError)?,
-                    None => {
-                        PersistentStorage::new(wal_file_path).map_err(ServerError::StorageError)?
+                let wal_options = wal_options(&config.storage)?;
+                let persistent_storage = Arc::new(
+                    match &config.storage.point_in_time_recovery {
+                        Some(recovery) => PersistentStorage::recover_to(
+                            &recovery.source_wal_path,
+                            recovery.target,
+                            wal_file_path,
+                            &wal_options,
+                        ),
+                        None => PersistentStorage::with_wal_options(wal_file_path, &wal_options),
                     }
-                };
+                    .map_err(ServerError::StorageError)?,
+                );
+
+                check_replay(&config.storage, persistent_storage.recovery_report())?;
 
-                Arc::new(persistent_storage)
+                Ok(Self {
+                    storage: Arc::clone(&persistent_storage) as Arc<dyn StorageEngine>,
+                    recovery_report: Some(Arc::new(persistent_storage.recovery_report().clone())),
+                    persistent: Some(persistent_storage),
+                    config,
+                })
             }
-        };
+            StorageType::Disk => {
+                let data_dir = config.storage.data_dir.as_ref().ok_or_else(|| {
+                    ServerError::StartupError(
+                        "Data directory required for disk storage".to_string(),
+                    )
+                })?;
+                if config.storage.encryption_key.is_some() {
+                    return Err(ServerError::StartupError(
+                        "Disk storage does not support encryption".to_string(),
+                    ));
+                }
+                if config.storage.point_in_time_recovery.is_some() {
+                    return Err(ServerError::StartupError(
+                        "Disk storage does not support point-in-time recovery".to_string(),
+                    ));
+                }
+
+                let cache_pages = config.storage.cache_pages.unwrap_or(DEFAULT_CACHE_PAGES);
+                let disk_storage = DiskStorage::with_options(
+                    data_dir,
+                    cache_pages,
+                    &wal_options(&config.storage)?,
+                )
+                .map_err(ServerError::StorageError)?;
+                check_replay(&config.storage, disk_storage.recovery_report())?;
 
-        Ok(Self::with_storage(config, storage))
+                Ok(Self {
+                    recovery_report: Some(Arc::new(disk_storage.recovery_report().clone())),
+                    ..Self::with_storage(config, Arc::new(disk_storage))
+                })
+            }
+        }
     }
 
     /// Creates a new server instance with the given configuration and custom storage.
     #[must_use]
     pub fn with_storage(config: Config, storage: Arc<dyn StorageEngine>) -> Self {
-        Self { config, storage }
+        Self {
+            config,
+            storage,
+            persistent: None,
+            recovery_report: None,
+        }
     }
 
     /// Start the server and listen for incoming requests.
@@ -104,6 +163,28 @@ impl Server {
             let _ = tx.send(listener.local_addr().unwrap());
         }
 
+        let expiry_reaper = self.spawn_expiry_reaper();
+        let compaction = self.start_compaction()?;
+
+        let result = Self::serve(listener, app, shutdown_signal).await;
+
+        expiry_reaper.abort();
+        if let Some(compaction) = compaction {
+            // Stopping waits for a compaction in progress to finish
+            let _ = tokio::task::spawn_blocking(move || drop(compaction)).await;
+        }
+        result
+    }
+
+    /// Serve requests on an already bound listener until the shutdown signal fires
+    async fn serve<F>(
+        listener: tokio::net::TcpListener,
+        app: Router,
+        shutdown_signal: Option<F>,
+    ) -> Result<()>
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
         match shutdown_signal {
             Some(sig) => {
                 axum::serve(listener, app)
@@ -131,6 +212,41 @@ impl Server {
             .await
     }
 
+    /// Spawn the background task that removes expired keys
+    fn spawn_expiry_reaper(&self) -> JoinHandle<()> {
+        let storage = Arc::clone(&self.storage);
+        let period = self.config.storage.expiry_interval;
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let storage = Arc::clone(&storage);
+                match tokio::task::spawn_blocking(move || storage.purge_expired()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(removed)) => debug!("Expired {} keys", removed),
+                    Ok(Err(e)) => warn!("Failed to purge expired keys: {}", e),
+                    Err(e) => warn!("Expiry reaper task failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Start compacting the WAL in the background, if storage is persistent
+    fn start_compaction(&self) -> Result<Option<CompactionWorker>> {
+        let policy = &self.config.storage.compaction;
+        match &self.persistent {
+            Some(storage) if policy.is_enabled() => {
+                info!("🧹 Automatic WAL compaction enabled");
+                Ok(Some(storage.start_compaction(policy.clone())?))
+            }
+            _ => Ok(None),
+        }
+    }
+
     /// Create the axum router with all endpoints
     fn create_router(&self) -> Router {
         Router::new()
@@ -140,6 +256,57 @@ impl Server {
             .route("/keys/{key}", get(get_key))
             .route("/keys/{key}", put(put_key))
             .route("/keys/{key}", delete(delete_key))
+            .route(
+                "/batch",
+                post(write_batch).layer(DefaultBodyLimit::max(BATCH_BODY_LIMIT)),
+            )
+            .route("/dump", get(dump))
             .with_state(Arc::clone(&self.storage))
+            .merge(
+                Router::new()
+                    .route("/admin/recovery", get(recovery_report))
+                    .with_state(self.recovery_report.clone()),
+            )
+    }
+}
+
+/// WAL options from the storage configuration
+fn wal_options(config: &StorageConfig) -> Result<WalOptions> {
+    Ok(WalOptions::default()
+        .with_checksums(config.use_checksums)
+        .with_durability(config.durability)
+        .with_max_segment_size(config.wal_segment_size)
+        .with_recovery_mode(config.wal_recovery)
+        .with_compression(config.wal_compression)
+        .with_encryption(load_keyring(config)?))
+}
+
+/// Refuse to start if WAL entries failed to replay and the configuration asks to
+fn check_replay(config: &StorageConfig, report: &RecoveryReport) -> Result<()> {
+    if config.fail_on_replay_errors && report.entries_failed > 0 {
+        return Err(ServerError::StartupError(format!(
+            "{} of {} WAL entries failed to replay during recovery",
+            report.entries_failed,
+            report.entries_read - report.entries_skipped
+        )));
+    }
+    Ok(())
+}
+
+/// Load the encryption keys the storage configuration names
+fn load_keyring(config: &StorageConfig) -> Result<Keyring> {
+    let Some(source) = &config.encryption_key else {
+        if config.previous_encryption_keys.is_empty() {
+            return Ok(Keyring::default());
+        }
+        return Err(ServerError::StartupError(
+            "Previous encryption keys are configured without a current key".to_string(),
+        ));
+    };
+
+    let mut keyring = Keyring::new(source.load()?);
+    for source in &config.previous_encryption_keys {
+        keyring = keyring.with_previous(source.load()?);
     }
+    Ok(keyring)
 }
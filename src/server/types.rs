@@ -22,11 +22,56 @@ pub enum ServerError {
 /// Result type for server operations
 pub type Result<T> = std::result::Result<T, ServerError>;
 
+/// Encoding of a value carried inside a JSON body
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueEncoding {
+    /// The value is a plain UTF-8 string
+    #[default]
+    Utf8,
+    /// The value is binary data encoded as standard base64
+    Base64,
+}
+
 /// Request body for storing a value
 #[derive(Deserialize)]
 pub struct PutKeyRequest {
     /// The value to store
     pub value: String,
+    /// How `value` is encoded (defaults to UTF-8)
+    #[serde(default)]
+    pub encoding: ValueEncoding,
+    /// Optional time to live in seconds
+    #[serde(default)]
+    pub ttl: Option<u64>,
+}
+
+/// A single operation in a `POST /batch` request
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperationRequest {
+    /// Store a value under a key
+    Put {
+        /// The key to store
+        key: String,
+        /// The value to store
+        value: String,
+        /// How `value` is encoded (defaults to UTF-8)
+        #[serde(default)]
+        encoding: ValueEncoding,
+    },
+    /// Remove a key
+    Delete {
+        /// The key to delete
+        key: String,
+    },
+}
+
+/// Response for an applied batch
+#[derive(Serialize)]
+pub struct BatchResponse {
+    /// Number of operations applied
+    pub applied: usize,
 }
 
 /// Response for health check endpoint
@@ -47,6 +92,8 @@ pub struct GetKeyResponse {
     pub key: String,
     /// The value associated with the key
     pub value: String,
+    /// How `value` is encoded (base64 for values that are not valid UTF-8)
+    pub encoding: ValueEncoding,
     /// Whether the key was found
     pub found: bool,
     /// Size of the value in bytes
@@ -55,6 +102,27 @@ pub struct GetKeyResponse {
     pub created_at: String,
     /// Last updated timestamp of the key
     pub updated_at: String,
+    /// Expiry timestamp of the key, if it was stored with a TTL
+    pub expires_at: Option<String>,
+    /// Remaining time to live in seconds, if the key was stored with a TTL
+    pub ttl: Option<u64>,
+    /// Current version of the key, also sent as the `ETag` header
+    pub version: u64,
+}
+
+/// Query parameters for listing keys
+#[derive(Debug, Default, Deserialize)]
+pub struct ListKeysQuery {
+    /// Only list keys starting with this prefix
+    pub prefix: Option<String>,
+    /// Only list keys greater than or equal to this key
+    pub start: Option<String>,
+    /// Only list keys strictly less than this key
+    pub end: Option<String>,
+    /// Maximum number of keys to return
+    pub limit: Option<usize>,
+    /// Continuation token from a previous page's `next_cursor`
+    pub cursor: Option<String>,
 }
 
 /// Response for listing keys
@@ -62,8 +130,71 @@ pub struct GetKeyResponse {
 pub struct ListKeysResponse {
     /// List of keys stored in the system
     pub keys: Vec<String>,
-    /// Count of keys stored
+    /// Count of keys in this page
     pub count: usize,
+    /// Token for fetching the next page, or `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// One line of the `GET /dump` NDJSON stream
+#[derive(Serialize)]
+pub struct DumpRecord {
+    /// The key
+    pub key: String,
+    /// The value, encoded as described by `encoding`
+    pub value: String,
+    /// How `value` is encoded (base64 for values that are not valid UTF-8)
+    pub encoding: ValueEncoding,
+    /// Current version of the key
+    pub version: u64,
+    /// Expiry timestamp of the key, if it was stored with a TTL
+    pub expires_at: Option<String>,
+}
+
+/// Response for `GET /admin/recovery`
+#[derive(Serialize)]
+pub struct RecoveryReportResponse {
+    /// How damaged WAL records were handled
+    pub mode: String,
+    /// Where replay stopped, for point-in-time recovery
+    pub target: Option<String>,
+    /// Whether recovery started from a snapshot
+    pub snapshot_used: bool,
+    /// Sequence number covered by the snapshot, if one was used
+    pub snapshot_sequence_number: Option<u64>,
+    /// Number of keys restored from the snapshot
+    pub snapshot_keys: usize,
+    /// Number of entries read from the WAL
+    pub entries_read: usize,
+    /// Number of WAL entries applied
+    pub entries_applied: usize,
+    /// Number of WAL entries that could not be applied
+    pub entries_failed: usize,
+    /// Number of WAL entries the snapshot already covered or past the target
+    pub entries_skipped: usize,
+    /// Damaged WAL records that were left out
+    pub discarded: Vec<DiscardedRecordResponse>,
+    /// Sequence number the WAL continued from
+    pub last_sequence_number: u64,
+    /// How long recovery took in milliseconds
+    pub duration_ms: f64,
+    /// Whether every WAL record was read and applied
+    pub clean: bool,
+}
+
+/// A damaged WAL record listed in a [`RecoveryReportResponse`]
+#[derive(Serialize)]
+pub struct DiscardedRecordResponse {
+    /// Segment file the record was in
+    pub segment: String,
+    /// Offset of the record in the segment
+    pub offset: u64,
+    /// Number of bytes left out
+    pub bytes: u64,
+    /// Why the record could not be read
+    pub reason: String,
+    /// Whether the segment was truncated at the record
+    pub truncated: bool,
 }
 
 /// Error response
@@ -0,0 +1,284 @@
+//! When persistent storage compacts its WAL on its own
+//!
+//! A [`CompactionWorker`] wakes up every [`CompactionPolicy::check_interval`]
+//! and runs [`PersistentStorage::compact_wal`] as soon as any of the policy's
+//! thresholds is reached.
+
+use super::error::{StorageError, StorageResult};
+use super::persistent::PersistentStorage;
+use super::worker::PeriodicWorker;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Default time between threshold checks
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default WAL size that triggers a compaction (256 MiB)
+const DEFAULT_MAX_WAL_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Default ratio of WAL entries to live keys that triggers a compaction
+const DEFAULT_MAX_ENTRY_RATIO: f64 = 4.0;
+
+/// Default number of WAL entries below which the ratio is not considered
+const DEFAULT_MIN_WAL_ENTRIES: u64 = 10_000;
+
+/// Thresholds that trigger an automatic WAL compaction
+///
+/// A threshold set to `None` never triggers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionPolicy {
+    /// How often the thresholds are checked
+    pub check_interval: Duration,
+    /// Compact once the WAL segments add up to this many bytes
+    pub max_wal_bytes: Option<u64>,
+    /// Compact once the WAL holds this many entries per live key
+    pub max_entry_ratio: Option<f64>,
+    /// Number of WAL entries needed before the ratio is considered, so small
+    /// datasets are not compacted over and over
+    pub min_wal_entries: u64,
+    /// Compact at least this often while there are new WAL entries
+    pub interval: Option<Duration>,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            max_wal_bytes: Some(DEFAULT_MAX_WAL_BYTES),
+            max_entry_ratio: Some(DEFAULT_MAX_ENTRY_RATIO),
+            min_wal_entries: DEFAULT_MIN_WAL_ENTRIES,
+            interval: None,
+        }
+    }
+}
+
+impl CompactionPolicy {
+    /// A policy that never compacts automatically
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            max_wal_bytes: None,
+            max_entry_ratio: None,
+            interval: None,
+            ..Self::default()
+        }
+    }
+
+    /// Sets how often the thresholds are checked
+    #[must_use]
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Sets the WAL size in bytes that triggers a compaction
+    #[must_use]
+    pub fn with_max_wal_bytes(mut self, max_wal_bytes: Option<u64>) -> Self {
+        self.max_wal_bytes = max_wal_bytes;
+        self
+    }
+
+    /// Sets the ratio of WAL entries to live keys that triggers a compaction
+    #[must_use]
+    pub fn with_max_entry_ratio(mut self, max_entry_ratio: Option<f64>) -> Self {
+        self.max_entry_ratio = max_entry_ratio;
+        self
+    }
+
+    /// Sets the number of WAL entries needed before the ratio is considered
+    #[must_use]
+    pub fn with_min_wal_entries(mut self, min_wal_entries: u64) -> Self {
+        self.min_wal_entries = min_wal_entries;
+        self
+    }
+
+    /// Sets how often the WAL is compacted regardless of its size
+    #[must_use]
+    pub fn with_interval(mut self, interval: Option<Duration>) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Whether any threshold can trigger a compaction
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.max_wal_bytes.is_some() || self.max_entry_ratio.is_some() || self.interval.is_some()
+    }
+
+    /// The first threshold that `state` reaches, if any
+    #[must_use]
+    pub fn trigger(&self, state: &WalState) -> Option<CompactionTrigger> {
+        if state.wal_entries == 0 {
+            return None;
+        }
+
+        if self
+            .max_wal_bytes
+            .is_some_and(|max_wal_bytes| state.wal_bytes >= max_wal_bytes)
+        {
+            return Some(CompactionTrigger::WalSize);
+        }
+
+        if state.wal_entries >= self.min_wal_entries
+            && self
+                .max_entry_ratio
+                .is_some_and(|max_entry_ratio| state.entry_ratio() >= max_entry_ratio)
+        {
+            return Some(CompactionTrigger::EntryRatio);
+        }
+
+        if self
+            .interval
+            .is_some_and(|interval| state.since_last_compaction >= interval)
+        {
+            return Some(CompactionTrigger::Interval);
+        }
+
+        None
+    }
+}
+
+/// What a compaction policy looks at
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalState {
+    /// Total size of the WAL segments in bytes
+    pub wal_bytes: u64,
+    /// Number of WAL entries written since the last checkpoint
+    pub wal_entries: u64,
+    /// Number of live keys
+    pub live_keys: usize,
+    /// Time since the last compaction, or since the storage was opened
+    pub since_last_compaction: Duration,
+}
+
+impl WalState {
+    /// WAL entries per live key
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn entry_ratio(&self) -> f64 {
+        self.wal_entries as f64 / self.live_keys.max(1) as f64
+    }
+}
+
+/// The threshold that triggered a compaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionTrigger {
+    /// The WAL grew past its size limit
+    WalSize,
+    /// The WAL holds too many entries per live key
+    EntryRatio,
+    /// The compaction interval elapsed
+    Interval,
+}
+
+impl fmt::Display for CompactionTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompactionTrigger::WalSize => write!(f, "WAL size"),
+            CompactionTrigger::EntryRatio => write!(f, "WAL entries per live key"),
+            CompactionTrigger::Interval => write!(f, "compaction interval"),
+        }
+    }
+}
+
+/// Background thread that compacts the WAL of a [`PersistentStorage`]
+///
+/// The thread stops as soon as the worker or the storage is dropped.
+pub struct CompactionWorker {
+    _worker: PeriodicWorker,
+}
+
+impl CompactionWorker {
+    /// Start checking `storage` against `policy`
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the thread cannot be started.
+    pub fn spawn(
+        storage: &Arc<PersistentStorage>,
+        policy: CompactionPolicy,
+    ) -> StorageResult<Self> {
+        let storage: Weak<_> = Arc::downgrade(storage);
+
+        let worker =
+            PeriodicWorker::spawn("zephyrite-compaction", policy.check_interval, move || {
+                let Some(storage) = storage.upgrade() else {
+                    return ControlFlow::Break(());
+                };
+
+                let trigger = match storage.wal_state() {
+                    Ok(state) => policy.trigger(&state),
+                    Err(e) => {
+                        warn!("Failed to check whether the WAL needs compaction: {}", e);
+                        return ControlFlow::Continue(());
+                    }
+                };
+
+                if let Some(trigger) = trigger {
+                    info!("Compacting WAL, triggered by {}", trigger);
+                    if let Err(e) = storage.compact_wal() {
+                        warn!("Automatic WAL compaction failed: {}", e);
+                    }
+                }
+                ControlFlow::Continue(())
+            })
+            .map_err(|e| {
+                StorageError::Internal(format!("Failed to start compaction worker: {e}"))
+            })?;
+
+        Ok(Self { _worker: worker })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(wal_bytes: u64, wal_entries: u64, live_keys: usize, since_secs: u64) -> WalState {
+        WalState {
+            wal_bytes,
+            wal_entries,
+            live_keys,
+            since_last_compaction: Duration::from_secs(since_secs),
+        }
+    }
+
+    #[test]
+    fn test_policy_triggers() {
+        let policy = CompactionPolicy::disabled()
+            .with_max_wal_bytes(Some(1000))
+            .with_max_entry_ratio(Some(4.0))
+            .with_min_wal_entries(100)
+            .with_interval(Some(Duration::from_secs(60)));
+
+        assert_eq!(policy.trigger(&state(10, 10, 10, 0)), None);
+        assert_eq!(
+            policy.trigger(&state(1000, 10, 10, 0)),
+            Some(CompactionTrigger::WalSize)
+        );
+        assert_eq!(
+            policy.trigger(&state(10, 400, 100, 0)),
+            Some(CompactionTrigger::EntryRatio)
+        );
+        assert_eq!(
+            policy.trigger(&state(10, 10, 10, 60)),
+            Some(CompactionTrigger::Interval)
+        );
+    }
+
+    #[test]
+    fn test_policy_ignores_small_or_unchanged_wal() {
+        let policy = CompactionPolicy::default().with_interval(Some(Duration::from_secs(1)));
+
+        // Few entries over few keys is a high ratio, but not worth compacting
+        assert_eq!(policy.trigger(&state(10, 50, 1, 0)), None);
+        // Nothing was written since the last checkpoint
+        assert_eq!(policy.trigger(&state(10, 0, 1, 3600)), None);
+
+        assert!(!CompactionPolicy::disabled().is_enabled());
+        assert!(CompactionPolicy::default().is_enabled());
+    }
+}
@@ -0,0 +1,409 @@
+//! Tests every storage engine must pass
+//!
+//! Each engine runs the suite from its own tests module with
+//! [`storage_engine_tests!`], given a function that opens an empty storage
+//! along with whatever has to outlive it, such as a temporary directory.
+
+use super::engine::{BatchOperation, INITIAL_VERSION, StorageEngine, Value};
+use super::error::StorageError;
+use bytes::Bytes;
+use std::time::Duration;
+
+/// Run the shared storage engine tests against the storage `$open()` returns
+/// as `(guard, storage)`
+macro_rules! storage_engine_tests {
+    ($open:path) => {
+        $crate::storage::conformance::storage_engine_tests!(
+            @tests $open;
+            test_new_storage,
+            test_put_and_get,
+            test_delete,
+            test_list_operations,
+            test_clear,
+            test_stats,
+            test_binary_values,
+            test_ttl_expiry_is_lazy,
+            test_purge_expired,
+            test_invalid_ttl,
+            test_versions_increase_on_update,
+            test_compare_and_swap,
+            test_put_if_absent,
+            test_write_batch,
+            test_write_batch_is_all_or_nothing,
+            test_keys_are_ordered,
+            test_scan_range,
+            test_scan_keys,
+            test_iter_from_batches,
+            test_iter_from_sees_writes_after_position,
+            test_scan_prefix,
+            test_invalid_key,
+            test_key_not_found,
+        );
+    };
+    (@tests $open:path; $($test:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $test() {
+                let (_guard, storage) = $open();
+                $crate::storage::conformance::$test(&storage);
+            }
+        )*
+    };
+}
+
+pub(crate) use storage_engine_tests;
+
+/// Store a value whose TTL has already run out
+fn put_expired(storage: &dyn StorageEngine, key: &str, value: &[u8]) {
+    storage
+        .put_with_ttl(key, value, Duration::from_nanos(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+}
+
+pub(crate) fn test_new_storage(storage: &dyn StorageEngine) {
+    assert_eq!(storage.stats().unwrap().key_count, 0);
+    assert!(storage.keys().unwrap().is_empty());
+}
+
+pub(crate) fn test_put_and_get(storage: &dyn StorageEngine) {
+    // Test putting a new key
+    assert!(storage.put("test_key", b"test_value").unwrap());
+    assert_eq!(storage.get("test_key").unwrap().value, "test_value");
+
+    // Test updating existing key
+    assert!(!storage.put("test_key", b"updated_value").unwrap());
+    assert_eq!(storage.get("test_key").unwrap().value, "updated_value");
+}
+
+pub(crate) fn test_delete(storage: &dyn StorageEngine) {
+    storage.put("test_key", b"test_value").unwrap();
+    assert!(storage.exists("test_key").unwrap());
+
+    assert!(storage.delete("test_key").unwrap());
+    assert!(!storage.exists("test_key").unwrap());
+
+    // Try deleting non-existent key
+    assert!(!storage.delete("non_existent").unwrap());
+}
+
+pub(crate) fn test_list_operations(storage: &dyn StorageEngine) {
+    storage.put("key1", b"value1").unwrap();
+    storage.put("key2", b"value2").unwrap();
+    storage.put("key3", b"value3").unwrap();
+
+    assert_eq!(storage.keys().unwrap(), vec!["key1", "key2", "key3"]);
+    assert_eq!(storage.values().unwrap().len(), 3);
+
+    let all_data = storage.all().unwrap();
+    assert_eq!(all_data.len(), 3);
+    assert_eq!(all_data.get("key1").unwrap().value, "value1");
+}
+
+pub(crate) fn test_clear(storage: &dyn StorageEngine) {
+    storage.put("key1", b"value1").unwrap();
+    storage.put("key2", &vec![7; 100_000]).unwrap();
+    assert_eq!(storage.stats().unwrap().key_count, 2);
+
+    storage.clear().unwrap();
+    assert_eq!(storage.stats().unwrap().key_count, 0);
+    assert!(storage.keys().unwrap().is_empty());
+
+    storage.put("key1", b"again").unwrap();
+    assert_eq!(storage.get("key1").unwrap().value, "again");
+}
+
+pub(crate) fn test_stats(storage: &dyn StorageEngine) {
+    storage.put("key1", b"value1").unwrap();
+    storage.get("key1").unwrap();
+    storage.delete("key1").unwrap();
+
+    let stats = storage.stats().unwrap();
+    assert_eq!(stats.get_operations_count, 1);
+    assert_eq!(stats.put_operations_count, 1);
+    assert_eq!(stats.delete_operations_count, 1);
+}
+
+pub(crate) fn test_binary_values(storage: &dyn StorageEngine) {
+    let payload = [0x00, 0xff, 0x10, 0x80, 0xfe];
+
+    storage.put("blob", &payload).unwrap();
+
+    let stored_value = storage.get("blob").unwrap();
+    assert_eq!(stored_value.value.as_ref(), &payload);
+    assert_eq!(stored_value.metadata.size, payload.len());
+    assert_eq!(storage.size_of_value("blob").unwrap(), payload.len());
+    assert!(stored_value.as_str().is_none());
+}
+
+pub(crate) fn test_ttl_expiry_is_lazy(storage: &dyn StorageEngine) {
+    put_expired(storage, "expired", b"value");
+    storage
+        .put_with_ttl("fresh", b"value", Duration::from_secs(60))
+        .unwrap();
+
+    assert!(!storage.exists("expired").unwrap());
+    assert!(matches!(
+        storage.get("expired"),
+        Err(StorageError::KeyNotFound(_))
+    ));
+    assert_eq!(storage.keys().unwrap(), vec!["fresh".to_string()]);
+
+    let remaining = storage
+        .get("fresh")
+        .unwrap()
+        .metadata
+        .ttl_remaining()
+        .unwrap();
+    assert!(remaining > Duration::from_secs(58));
+    assert!(remaining <= Duration::from_secs(60));
+
+    // Re-using an expired key counts as creating it
+    put_expired(storage, "stale", b"value");
+    assert!(storage.put("stale", b"new").unwrap());
+    assert!(storage.get("stale").unwrap().metadata.expires_at.is_none());
+}
+
+pub(crate) fn test_purge_expired(storage: &dyn StorageEngine) {
+    put_expired(storage, "a", b"1");
+    put_expired(storage, "b", b"2");
+    storage.put("c", b"3").unwrap();
+
+    // Expired keys are not counted, purged or not
+    assert_eq!(storage.stats().unwrap().key_count, 1);
+    assert_eq!(storage.purge_expired().unwrap(), 2);
+    assert_eq!(storage.stats().unwrap().key_count, 1);
+    assert_eq!(storage.purge_expired().unwrap(), 0);
+}
+
+pub(crate) fn test_invalid_ttl(storage: &dyn StorageEngine) {
+    let result = storage.put_with_ttl("key", b"value", Duration::ZERO);
+    assert!(matches!(result, Err(StorageError::InvalidValue(_))));
+    assert!(!storage.exists("key").unwrap());
+}
+
+pub(crate) fn test_versions_increase_on_update(storage: &dyn StorageEngine) {
+    storage.put("config", b"v1").unwrap();
+    let first = storage.get("config").unwrap();
+    assert_eq!(first.metadata.version, INITIAL_VERSION);
+
+    storage.put("config", b"v2").unwrap();
+    let second = storage.get("config").unwrap();
+    assert_eq!(second.metadata.version, INITIAL_VERSION + 1);
+    assert_eq!(second.metadata.created_at, first.metadata.created_at);
+
+    // A key created again continues past the versions it had before
+    storage.delete("config").unwrap();
+    assert_eq!(
+        storage.put_if_absent("config", b"v3").unwrap(),
+        INITIAL_VERSION + 2
+    );
+
+    put_expired(storage, "config", b"v4");
+    storage.put("config", b"v5").unwrap();
+    assert_eq!(
+        storage.get("config").unwrap().metadata.version,
+        INITIAL_VERSION + 4
+    );
+
+    storage.clear().unwrap();
+    storage.put("config", b"v6").unwrap();
+    assert_eq!(
+        storage.get("config").unwrap().metadata.version,
+        INITIAL_VERSION + 5
+    );
+}
+
+pub(crate) fn test_compare_and_swap(storage: &dyn StorageEngine) {
+    let result = storage.compare_and_swap("config", 1, b"value");
+    assert!(matches!(result, Err(StorageError::KeyNotFound(_))));
+
+    storage.put("config", b"v1").unwrap();
+    assert_eq!(storage.compare_and_swap("config", 1, b"v2").unwrap(), 2);
+
+    let result = storage.compare_and_swap("config", 1, b"v3");
+    assert_eq!(
+        result,
+        Err(StorageError::VersionMismatch {
+            key: "config".to_string(),
+            expected: 1,
+            actual: 2,
+        })
+    );
+    assert_eq!(storage.get("config").unwrap().value, "v2");
+}
+
+pub(crate) fn test_put_if_absent(storage: &dyn StorageEngine) {
+    assert_eq!(storage.put_if_absent("lock", b"owner-a").unwrap(), 1);
+
+    let result = storage.put_if_absent("lock", b"owner-b");
+    assert!(matches!(result, Err(StorageError::KeyAlreadyExists(_))));
+    assert_eq!(storage.get("lock").unwrap().value, "owner-a");
+
+    // An expired key no longer blocks the write, and its version is not reused
+    put_expired(storage, "lease", b"old");
+    assert_eq!(storage.put_if_absent("lease", b"new").unwrap(), 2);
+}
+
+pub(crate) fn test_write_batch(storage: &dyn StorageEngine) {
+    storage.put("stale", b"old").unwrap();
+
+    storage
+        .write_batch(&[
+            BatchOperation::Put {
+                key: "a".to_string(),
+                value: Bytes::from_static(b"1"),
+            },
+            BatchOperation::Put {
+                key: "a".to_string(),
+                value: Bytes::from_static(b"2"),
+            },
+            BatchOperation::Delete {
+                key: "stale".to_string(),
+            },
+        ])
+        .unwrap();
+
+    let a = storage.get("a").unwrap();
+    assert_eq!(a.value, "2");
+    assert_eq!(a.metadata.version, 2);
+    assert!(!storage.exists("stale").unwrap());
+}
+
+pub(crate) fn test_write_batch_is_all_or_nothing(storage: &dyn StorageEngine) {
+    let result = storage.write_batch(&[
+        BatchOperation::Put {
+            key: "valid".to_string(),
+            value: Bytes::from_static(b"value"),
+        },
+        BatchOperation::Delete { key: String::new() },
+    ]);
+
+    assert!(matches!(result, Err(StorageError::InvalidKey(_))));
+    assert!(!storage.exists("valid").unwrap());
+}
+
+pub(crate) fn test_keys_are_ordered(storage: &dyn StorageEngine) {
+    for key in ["user:2", "config", "user:10", "user:1"] {
+        storage.put(key, b"value").unwrap();
+    }
+
+    assert_eq!(
+        storage.keys().unwrap(),
+        vec!["config", "user:1", "user:10", "user:2"]
+    );
+}
+
+pub(crate) fn test_scan_range(storage: &dyn StorageEngine) {
+    for key in ["a", "b", "c", "d", "e"] {
+        storage.put(key, key.as_bytes()).unwrap();
+    }
+    put_expired(storage, "bb", b"gone");
+
+    let keys =
+        |entries: Vec<(String, Value)>| entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>();
+
+    assert_eq!(
+        keys(storage.scan(Some("b"), Some("d"), None).unwrap()),
+        vec!["b", "c"]
+    );
+    assert_eq!(
+        keys(storage.scan(Some("b"), None, Some(2)).unwrap()),
+        vec!["b", "c"]
+    );
+    assert_eq!(
+        keys(storage.scan(None, Some("b"), None).unwrap()),
+        vec!["a"]
+    );
+    assert!(storage.scan(Some("d"), Some("b"), None).unwrap().is_empty());
+}
+
+pub(crate) fn test_scan_keys(storage: &dyn StorageEngine) {
+    for key in ["d", "a", "c", "b"] {
+        storage.put(key, b"value").unwrap();
+    }
+    put_expired(storage, "bb", b"gone");
+
+    assert_eq!(storage.scan_keys(None, None, 2).unwrap(), vec!["a", "b"]);
+    assert_eq!(
+        storage.scan_keys(Some("b"), Some("d"), 10).unwrap(),
+        vec!["b", "c"]
+    );
+    assert!(
+        storage
+            .scan_keys(Some("c"), Some("a"), 10)
+            .unwrap()
+            .is_empty()
+    );
+    assert!(storage.scan_keys(None, None, 0).unwrap().is_empty());
+}
+
+pub(crate) fn test_iter_from_batches(storage: &dyn StorageEngine) {
+    assert_eq!(storage.iter_from(None, 2).count(), 0);
+
+    for key in ["e", "a", "d", "b", "c"] {
+        storage.put(key, key.as_bytes()).unwrap();
+    }
+
+    let batches: Vec<Vec<String>> = storage
+        .iter_from(None, 2)
+        .map(|batch| batch.unwrap().into_iter().map(|(key, _)| key).collect())
+        .collect();
+    assert_eq!(batches, vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+
+    let values: Vec<_> = storage
+        .iter_from(Some("c"), 10)
+        .flat_map(Result::unwrap)
+        .map(|(_, value)| value.value)
+        .collect();
+    assert_eq!(values, vec!["c", "d", "e"]);
+
+    // An exact multiple of the batch size ends with an empty read, not an empty batch
+    assert_eq!(storage.iter_from(Some("b"), 2).count(), 2);
+}
+
+pub(crate) fn test_iter_from_sees_writes_after_position(storage: &dyn StorageEngine) {
+    for key in ["a", "b", "c"] {
+        storage.put(key, b"value").unwrap();
+    }
+
+    let mut batches = storage.iter_from(None, 1);
+    batches.next().unwrap().unwrap();
+
+    storage.delete("b").unwrap();
+    storage.put("d", b"value").unwrap();
+
+    let rest: Vec<_> = batches
+        .flat_map(Result::unwrap)
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(rest, vec!["c", "d"]);
+}
+
+pub(crate) fn test_scan_prefix(storage: &dyn StorageEngine) {
+    for key in ["user:1:name", "user:1:email", "user:12:name", "user:2:name"] {
+        storage.put(key, b"value").unwrap();
+    }
+
+    let entries = storage.scan_prefix("user:1:", None).unwrap();
+    let keys: Vec<_> = entries.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(keys, vec!["user:1:email", "user:1:name"]);
+
+    assert_eq!(storage.scan_prefix("user:", Some(3)).unwrap().len(), 3);
+    assert!(storage.scan_prefix("order:", None).unwrap().is_empty());
+}
+
+pub(crate) fn test_invalid_key(storage: &dyn StorageEngine) {
+    // Empty key
+    let result = storage.put("", b"value");
+    assert!(matches!(result, Err(StorageError::InvalidKey(_))));
+
+    // Key with null byte
+    let result = storage.put("key\0", b"value");
+    assert!(matches!(result, Err(StorageError::InvalidKey(_))));
+}
+
+pub(crate) fn test_key_not_found(storage: &dyn StorageEngine) {
+    let result = storage.get("non_existent");
+    assert!(matches!(result, Err(StorageError::KeyNotFound(_))));
+}
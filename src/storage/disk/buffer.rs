@@ -1,25 +1,70 @@
 //! Buffer pool for caching pages in memory
 //!
 //! The buffer pool manages a cache of frequently accessed pages to reduce
-//! disk I/O and improve performance. It uses an LRU eviction policy.
+//! disk I/O and improve performance. It uses an LRU eviction policy. With a
+//! [`DiskManager`] attached, pages missing from the cache are read from the
+//! database file and dirty pages are written back when evicted or flushed.
+//!
+//! Cached pages live in frames linked into a list from least to most recently
+//! used, with a map from page ID to frame, so looking a page up, moving it to
+//! the back of the list and picking the page to evict all take constant time.
+//! Pinned pages are taken off the list while they are in use, so they can
+//! never be picked for eviction.
+//!
+//! The pool counts its dirty pages as they change. Callers mark pages dirty
+//! through the `&mut Page` they are lent, so the last page lent out is
+//! checked again on the next call into the pool, when that borrow has ended.
 
+use super::disk_manager::DiskManager;
 use super::page::Page;
-use crate::storage::error::StorageResult;
+use crate::storage::error::{StorageError, StorageResult};
 use std::collections::HashMap;
 use tracing::warn;
 
+/// Marks the end of the LRU list
+const NIL: usize = usize::MAX;
+
+/// A cached page and its place in the LRU list
+#[derive(Debug)]
+struct Frame {
+    page: Page,
+    /// Number of callers using the page; pinned frames are not in the LRU list
+    pin_count: u32,
+    /// Whether the page is counted in [`BufferPool::dirty_page_count`]
+    dirty: bool,
+    /// Next less recently used frame
+    prev: usize,
+    /// Next more recently used frame
+    next: usize,
+}
+
 /// Buffer pool for caching pages in memory
 ///
 /// The buffer pool maintains a cache of pages in memory to reduce disk I/O.
 /// It uses a Least Recently Used (LRU) eviction policy when the cache is full.
 pub struct BufferPool {
-    /// Cached pages indexed by page ID
-    pages: HashMap<u64, Page>,
+    /// Frame of each cached page, by page ID
+    page_table: HashMap<u64, usize>,
+    /// Frames of cached pages; those in `free_frames` hold no page
+    frames: Vec<Frame>,
+    /// Frames left empty by evicted or removed pages, for reuse
+    free_frames: Vec<usize>,
+    /// Least recently used unpinned frame, evicted first
+    lru_head: usize,
+    /// Most recently used unpinned frame
+    lru_tail: usize,
     /// Maximum number of pages to cache
     capacity: usize,
-    /// Access order for LRU eviction (most recent at end)
-    // TODO: Consider using a VecDeque or a dedicated LRU cache structure to achieve O(1) queue operations.
-    access_order: Vec<u64>,
+    /// Number of frames counted as dirty
+    dirty_pages: usize,
+    /// Frame of the page last lent out, which may have changed since
+    lent: Option<usize>,
+    /// Lookups that found the page cached
+    hits: u64,
+    /// Lookups that did not
+    misses: u64,
+    /// Database file pages are read from and written back to, if any
+    disk: Option<DiskManager>,
 }
 
 impl BufferPool {
@@ -27,97 +72,273 @@ impl BufferPool {
     #[must_use]
     pub fn new(capacity: usize) -> Self {
         BufferPool {
-            pages: HashMap::new(),
+            page_table: HashMap::new(),
+            frames: Vec::new(),
+            free_frames: Vec::new(),
+            lru_head: NIL,
+            lru_tail: NIL,
             capacity,
-            access_order: Vec::with_capacity(capacity),
+            dirty_pages: 0,
+            lent: None,
+            hits: 0,
+            misses: 0,
+            disk: None,
         }
     }
 
+    /// Read missing pages from, and write dirty pages back to, `disk`
+    #[must_use]
+    pub fn with_disk_manager(mut self, disk: DiskManager) -> Self {
+        self.disk = Some(disk);
+        self
+    }
+
+    /// The database file behind the buffer pool, if any
+    #[must_use]
+    pub fn disk_manager(&self) -> Option<&DiskManager> {
+        self.disk.as_ref()
+    }
+
+    /// Mutable access to the database file behind the buffer pool, if any
+    pub fn disk_manager_mut(&mut self) -> Option<&mut DiskManager> {
+        self.disk.as_mut()
+    }
+
     /// Get a page from the buffer pool if it exists
     ///
-    /// This will update the access order for LRU tracking.
+    /// This will update the access order for LRU tracking, and counts as a hit
+    /// or a miss.
     pub fn get_page(&mut self, page_id: u64) -> Option<&mut Page> {
-        match self.pages.get_mut(&page_id) {
-            Some(page) => {
-                self.access_order.retain(|&id| id != page_id);
-                self.access_order.push(page_id);
-                Some(page)
-            }
-            None => None,
+        self.settle();
+        if self.page_table.contains_key(&page_id) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
         }
+        self.touch(page_id)
+    }
+
+    /// Get a page, reading it from disk if it is not cached
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the page is not cached and there is no disk manager,
+    /// if it cannot be read, or if the buffer pool cannot hold it.
+    pub fn fetch_page(&mut self, page_id: u64) -> StorageResult<&mut Page> {
+        self.settle();
+        if self.page_table.contains_key(&page_id) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+            let page = self
+                .disk
+                .as_ref()
+                .ok_or_else(|| {
+                    StorageError::Internal(format!(
+                        "Page {page_id} is not cached and the buffer pool has no disk manager"
+                    ))
+                })?
+                .read_page(page_id)?;
+            self.insert_page(page)?;
+        }
+
+        self.cached(page_id)
+    }
+
+    /// Allocate a page in the database file and cache it, zeroed and dirty
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no disk manager or the buffer pool cannot
+    /// hold the page.
+    pub fn new_page(&mut self) -> StorageResult<&mut Page> {
+        self.settle();
+        let page_id = self
+            .disk
+            .as_mut()
+            .ok_or_else(|| {
+                StorageError::Internal("Cannot allocate a page without a disk manager".to_string())
+            })?
+            .allocate_page();
+
+        let mut page = Page::new(page_id);
+        page.mark_dirty();
+        self.insert_page(page)?;
+        self.cached(page_id)
     }
 
     /// Insert a page into the buffer pool
     ///
-    /// If the buffer pool is at capacity, this will evict the least recently used page.
+    /// If the buffer pool is at capacity, this will evict the least recently
+    /// used unpinned page, writing it back first if it is dirty. Without a disk
+    /// manager a dirty page has nowhere to go, so the least recently used clean
+    /// page is evicted instead.
     ///
     /// # Errors
     ///
-    /// Returns an error if the page cannot be inserted into the buffer pool.
+    /// Returns an error if a dirty page cannot be written back to disk, or if
+    /// every cached page is pinned (or, without a disk manager, pinned or dirty).
     pub fn insert_page(&mut self, page: Page) -> StorageResult<()> {
+        self.settle();
         let page_id = page.id;
 
         // If capacity is 0, don't store anything
         if self.capacity == 0 {
+            return Self::write_back(self.disk.as_mut(), &page);
+        }
+
+        if let Some(&frame) = self.page_table.get(&page_id) {
+            self.frames[frame].page = page;
+            self.recount(frame);
+            self.touch(page_id);
             return Ok(());
         }
 
-        // Evict pages if necessary
-        while self.pages.len() >= self.capacity {
-            if let Some(&lru_page_id) = self.access_order.first() {
-                self.access_order.remove(0);
-                if let Some(evicted_page) = self.pages.remove(&lru_page_id) {
-                    if evicted_page.is_dirty() {
-                        // TODO: write dirty pages back to disk here
-                        // Evicting dirty pages without writing back risks data loss.
-                        // Consider implementing the disk write here or making the warning behavior explicit in the API documentation.
-                        warn!("Evicting dirty page {} - changes may be lost", lru_page_id);
-                    }
-                }
-            } else {
-                break;
-            }
+        while self.page_table.len() >= self.capacity {
+            self.evict()?;
         }
 
-        self.pages.insert(page_id, page);
-        self.access_order.retain(|&id| id != page_id);
-        self.access_order.push(page_id);
+        let frame = Frame {
+            page,
+            pin_count: 0,
+            dirty: false,
+            prev: NIL,
+            next: NIL,
+        };
+        let index = if let Some(index) = self.free_frames.pop() {
+            self.frames[index] = frame;
+            index
+        } else {
+            self.frames.push(frame);
+            self.frames.len() - 1
+        };
+        self.page_table.insert(page_id, index);
+        self.recount(index);
+        self.push_back(index);
+        Ok(())
+    }
+
+    /// Get a page and pin it, reading it from disk if it is not cached
+    ///
+    /// A pinned page is never evicted; every pin must be released with
+    /// [`BufferPool::unpin_page`] for the page to become evictable again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the page cannot be fetched.
+    pub fn pin_page(&mut self, page_id: u64) -> StorageResult<&mut Page> {
+        self.fetch_page(page_id)?;
+        let index = self.frame_of(page_id)?;
+        if self.frames[index].pin_count == 0 {
+            self.unlink(index);
+        }
+        self.frames[index].pin_count += 1;
+        Ok(&mut self.frames[index].page)
+    }
+
+    /// Release a pin taken with [`BufferPool::pin_page`]
+    ///
+    /// Once its last pin is released the page becomes the most recently used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the page is not cached or not pinned.
+    pub fn unpin_page(&mut self, page_id: u64) -> StorageResult<()> {
+        let index = self.frame_of(page_id)?;
+        let frame = &mut self.frames[index];
+        if frame.pin_count == 0 {
+            return Err(StorageError::Internal(format!(
+                "Page {page_id} is not pinned"
+            )));
+        }
+        frame.pin_count -= 1;
+        if frame.pin_count == 0 {
+            self.push_back(index);
+        }
         Ok(())
     }
 
+    /// Number of pins held on a page, 0 if it is not cached
+    #[must_use]
+    pub fn pin_count(&self, page_id: u64) -> u32 {
+        self.page_table
+            .get(&page_id)
+            .map_or(0, |&index| self.frames[index].pin_count)
+    }
+
     /// Mark a page as dirty in the buffer pool
     pub fn mark_dirty(&mut self, page_id: u64) {
-        if let Some(page) = self.pages.get_mut(&page_id) {
-            page.mark_dirty();
+        self.settle();
+        if let Some(&index) = self.page_table.get(&page_id) {
+            self.frames[index].page.mark_dirty();
+            self.recount(index);
+        }
+    }
+
+    /// Number of dirty pages in the cache
+    #[must_use]
+    pub fn dirty_page_count(&self) -> usize {
+        match self.lent.map(|index| &self.frames[index]) {
+            Some(frame) if frame.page.is_dirty() && !frame.dirty => self.dirty_pages + 1,
+            Some(frame) if !frame.page.is_dirty() && frame.dirty => self.dirty_pages - 1,
+            _ => self.dirty_pages,
         }
     }
 
     /// Get a list of all dirty page IDs
     #[must_use]
     pub fn get_dirty_pages(&self) -> Vec<u64> {
-        self.pages
-            .iter()
-            .filter(|(_, page)| page.is_dirty())
-            .map(|(&id, _)| id)
+        self.pages()
+            .filter(|page| page.is_dirty())
+            .map(|page| page.id)
             .collect()
     }
 
-    /// Remove a page from the buffer pool
+    /// Drop a page from the cache and return it to the disk manager for reuse
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no disk manager or the page is pinned.
+    pub fn free_page(&mut self, page_id: u64) -> StorageResult<()> {
+        if self.pin_count(page_id) > 0 {
+            return Err(StorageError::Internal(format!(
+                "Cannot free page {page_id} while it is pinned"
+            )));
+        }
+        let disk = self.disk.as_mut().ok_or_else(|| {
+            StorageError::Internal("Cannot free a page without a disk manager".to_string())
+        })?;
+        disk.free_page(page_id);
+        self.remove_page(page_id);
+        Ok(())
+    }
+
+    /// Remove a page from the buffer pool, along with any pins on it
     pub fn remove_page(&mut self, page_id: u64) -> Option<Page> {
-        self.access_order.retain(|&id| id != page_id);
-        self.pages.remove(&page_id)
+        self.settle();
+        let index = self.page_table.remove(&page_id)?;
+        if self.frames[index].dirty {
+            self.dirty_pages -= 1;
+            self.frames[index].dirty = false;
+        }
+        if self.frames[index].pin_count == 0 {
+            self.unlink(index);
+        }
+        self.frames[index].pin_count = 0;
+        self.free_frames.push(index);
+        Some(std::mem::take(&mut self.frames[index].page))
     }
 
     /// Check if a page is cached
     #[must_use]
     pub fn contains_page(&self, page_id: u64) -> bool {
-        self.pages.contains_key(&page_id)
+        self.page_table.contains_key(&page_id)
     }
 
     /// Get the number of pages currently cached
     #[must_use]
     pub fn cached_page_count(&self) -> usize {
-        self.pages.len()
+        self.page_table.len()
     }
 
     /// Get the capacity of the buffer pool
@@ -130,46 +351,193 @@ impl BufferPool {
     ///
     /// This will log warnings for any dirty pages that are cleared.
     pub fn clear(&mut self) {
-        for (page_id, page) in &self.pages {
+        for page in self.pages() {
             if page.is_dirty() {
-                warn!("Clearing dirty page {} - changes may be lost", page_id);
+                warn!("Clearing dirty page {} - changes may be lost", page.id);
             }
         }
-        self.pages.clear();
-        self.access_order.clear();
+        self.page_table.clear();
+        self.frames.clear();
+        self.free_frames.clear();
+        self.lru_head = NIL;
+        self.lru_tail = NIL;
+        self.dirty_pages = 0;
+        self.lent = None;
     }
 
     /// Get statistics about the buffer pool
     #[must_use]
+    #[allow(clippy::cast_precision_loss)]
     pub fn stats(&self) -> BufferPoolStats {
-        let dirty_count = self.pages.values().filter(|page| page.is_dirty()).count();
+        let lookups = self.hits + self.misses;
 
         BufferPoolStats {
             capacity: self.capacity,
-            cached_pages: self.pages.len(),
-            dirty_pages: dirty_count,
-            hit_ratio: 0.0, // TODO: Track hits/misses for accurate hit ratio
+            cached_pages: self.page_table.len(),
+            dirty_pages: self.dirty_page_count(),
+            pinned_pages: self
+                .page_table
+                .values()
+                .filter(|&&index| self.frames[index].pin_count > 0)
+                .count(),
+            hits: self.hits,
+            misses: self.misses,
+            hit_ratio: if lookups == 0 {
+                0.0
+            } else {
+                self.hits as f64 / lookups as f64
+            },
         }
     }
 
     /// Flush all dirty pages
     ///
+    /// With a disk manager the pages are written to the database file, which
+    /// is then synced along with its header; otherwise only the dirty flags are
+    /// cleared.
+    ///
     /// # Errors
     ///
     /// Returns an error if any dirty pages cannot be flushed to disk.
     pub fn flush_dirty_pages(&mut self) -> StorageResult<Vec<u64>> {
-        let dirty_page_ids: Vec<u64> = self.get_dirty_pages();
+        self.settle();
+        let mut dirty_page_ids: Vec<u64> = self.get_dirty_pages();
+        dirty_page_ids.sort_unstable();
+
+        if let Some(disk) = &mut self.disk {
+            let pages: Vec<&Page> = dirty_page_ids
+                .iter()
+                .map(|page_id| &self.frames[self.page_table[page_id]].page)
+                .collect();
+            disk.write_pages(&pages)?;
+            disk.sync()?;
+        }
 
-        // TODO write these pages to disk here
-        // For now, just clear the dirty flags
         for page_id in &dirty_page_ids {
-            if let Some(page) = self.pages.get_mut(page_id) {
-                page.clear_dirty();
-            }
+            let index = self.page_table[page_id];
+            self.frames[index].page.clear_dirty();
+            self.recount(index);
         }
 
         Ok(dirty_page_ids)
     }
+
+    /// The cached pages, in no particular order
+    fn pages(&self) -> impl Iterator<Item = &Page> {
+        self.page_table
+            .values()
+            .map(|&index| &self.frames[index].page)
+    }
+
+    /// Get a page that was just inserted
+    fn cached(&mut self, page_id: u64) -> StorageResult<&mut Page> {
+        let capacity = self.capacity;
+        self.touch(page_id).ok_or_else(|| {
+            StorageError::Internal(format!(
+                "Page {page_id} does not fit in a buffer pool of capacity {capacity}"
+            ))
+        })
+    }
+
+    /// Frame of a cached page
+    fn frame_of(&self, page_id: u64) -> StorageResult<usize> {
+        self.page_table
+            .get(&page_id)
+            .copied()
+            .ok_or_else(|| StorageError::Internal(format!("Page {page_id} is not cached")))
+    }
+
+    /// Make a cached page the most recently used and lend it out
+    fn touch(&mut self, page_id: u64) -> Option<&mut Page> {
+        let index = *self.page_table.get(&page_id)?;
+        if self.frames[index].pin_count == 0 {
+            self.unlink(index);
+            self.push_back(index);
+        }
+        self.lent = Some(index);
+        Some(&mut self.frames[index].page)
+    }
+
+    /// Count the changes made to the page last lent out
+    fn settle(&mut self) {
+        if let Some(index) = self.lent.take() {
+            self.recount(index);
+        }
+    }
+
+    /// Bring the dirty count in line with the page in a frame
+    fn recount(&mut self, index: usize) {
+        let frame = &mut self.frames[index];
+        if frame.page.is_dirty() != frame.dirty {
+            frame.dirty = frame.page.is_dirty();
+            if frame.dirty {
+                self.dirty_pages += 1;
+            } else {
+                self.dirty_pages -= 1;
+            }
+        }
+    }
+
+    /// Evict one unpinned page to make room, writing it back if it is dirty
+    fn evict(&mut self) -> StorageResult<()> {
+        let mut index = self.lru_head;
+        if self.disk.is_none() {
+            while index != NIL && self.frames[index].page.is_dirty() {
+                index = self.frames[index].next;
+            }
+        }
+        if index == NIL {
+            return Err(StorageError::Internal(format!(
+                "Buffer pool is full: none of its {} pages can be evicted",
+                self.page_table.len()
+            )));
+        }
+
+        Self::write_back(self.disk.as_mut(), &self.frames[index].page)?;
+        let page_id = self.frames[index].page.id;
+        self.remove_page(page_id);
+        Ok(())
+    }
+
+    /// Take a frame off the LRU list
+    fn unlink(&mut self, index: usize) {
+        let Frame { prev, next, .. } = self.frames[index];
+        match prev {
+            NIL => self.lru_head = next,
+            prev => self.frames[prev].next = next,
+        }
+        match next {
+            NIL => self.lru_tail = prev,
+            next => self.frames[next].prev = prev,
+        }
+        self.frames[index].prev = NIL;
+        self.frames[index].next = NIL;
+    }
+
+    /// Put a frame at the most recently used end of the LRU list
+    fn push_back(&mut self, index: usize) {
+        self.frames[index].prev = self.lru_tail;
+        self.frames[index].next = NIL;
+        match self.lru_tail {
+            NIL => self.lru_head = index,
+            tail => self.frames[tail].next = index,
+        }
+        self.lru_tail = index;
+    }
+
+    /// Write a dirty page that is leaving the cache back to disk
+    fn write_back(disk: Option<&mut DiskManager>, page: &Page) -> StorageResult<()> {
+        if !page.is_dirty() {
+            return Ok(());
+        }
+
+        if let Some(disk) = disk {
+            disk.write_page(page)
+        } else {
+            warn!("Evicting dirty page {} - changes may be lost", page.id);
+            Ok(())
+        }
+    }
 }
 
 /// Statistics about the buffer pool
@@ -181,7 +549,13 @@ pub struct BufferPoolStats {
     pub cached_pages: usize,
     /// Number of dirty pages in cache
     pub dirty_pages: usize,
-    /// Cache hit ratio (0.0 to 1.0)
+    /// Number of pinned pages in cache
+    pub pinned_pages: usize,
+    /// Lookups that found the page cached
+    pub hits: u64,
+    /// Lookups that had to read the page from disk or found nothing
+    pub misses: u64,
+    /// Cache hit ratio (0.0 to 1.0), 0.0 before the first lookup
     pub hit_ratio: f64,
 }
 
@@ -341,7 +715,132 @@ mod tests {
         assert_eq!(stats.capacity, 5);
         assert_eq!(stats.cached_pages, 3);
         assert_eq!(stats.dirty_pages, 1);
-        assert!((stats.hit_ratio - 0.0).abs() < f64::EPSILON); // Not implemented yet
+        assert_eq!(stats.pinned_pages, 0);
+        assert!((stats.hit_ratio - 0.0).abs() < f64::EPSILON); // No lookups yet
+    }
+
+    #[test]
+    fn test_hit_ratio_counts_lookups() {
+        let mut pool = BufferPool::new(5);
+        assert!(pool.insert_page(create_test_page(1)).is_ok());
+
+        assert!(pool.get_page(1).is_some());
+        assert!(pool.get_page(1).is_some());
+        assert!(pool.fetch_page(1).is_ok());
+        assert!(pool.get_page(2).is_none());
+
+        let stats = pool.stats();
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 1);
+        assert!((stats.hit_ratio - 0.75).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_fetch_misses_read_from_disk() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::open(temp_dir.path().join("data.db")).unwrap();
+        let mut pool = BufferPool::new(1).with_disk_manager(disk);
+
+        let first = pool.new_page().unwrap().id;
+        let second = pool.new_page().unwrap().id;
+        pool.fetch_page(second).unwrap();
+        pool.fetch_page(first).unwrap();
+
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn test_pinned_pages_are_not_evicted() {
+        let mut pool = BufferPool::new(2);
+        assert!(pool.insert_page(create_test_page(1)).is_ok());
+        assert!(pool.insert_page(create_test_page(2)).is_ok());
+
+        // Page 1 is least recently used, but pinned
+        assert_eq!(pool.pin_page(1).unwrap().id, 1);
+        assert_eq!(pool.pin_count(1), 1);
+        assert!(pool.insert_page(create_test_page(3)).is_ok());
+        assert!(pool.contains_page(1));
+        assert!(!pool.contains_page(2));
+        assert_eq!(pool.stats().pinned_pages, 1);
+
+        // Every other page is pinned too
+        assert!(pool.pin_page(3).is_ok());
+        let err = pool.insert_page(create_test_page(4)).unwrap_err();
+        assert!(err.to_string().contains("can be evicted"));
+
+        // Unpinned pages become the most recently used
+        pool.unpin_page(3).unwrap();
+        pool.unpin_page(1).unwrap();
+        assert!(pool.insert_page(create_test_page(4)).is_ok());
+        assert!(pool.contains_page(1));
+        assert!(!pool.contains_page(3));
+    }
+
+    #[test]
+    fn test_pins_are_counted() {
+        let mut pool = BufferPool::new(2);
+        assert!(pool.insert_page(create_test_page(1)).is_ok());
+
+        pool.pin_page(1).unwrap();
+        pool.pin_page(1).unwrap();
+        pool.unpin_page(1).unwrap();
+        assert_eq!(pool.pin_count(1), 1);
+        pool.unpin_page(1).unwrap();
+        assert_eq!(pool.pin_count(1), 0);
+
+        assert!(pool.unpin_page(1).is_err());
+        assert!(pool.unpin_page(2).is_err());
+        assert_eq!(pool.pin_count(2), 0);
+    }
+
+    #[test]
+    fn test_pinned_pages_cannot_be_freed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::open(temp_dir.path().join("data.db")).unwrap();
+        let mut pool = BufferPool::new(2).with_disk_manager(disk);
+
+        let page_id = pool.new_page().unwrap().id;
+        pool.pin_page(page_id).unwrap();
+        assert!(pool.free_page(page_id).is_err());
+
+        pool.unpin_page(page_id).unwrap();
+        assert!(pool.free_page(page_id).is_ok());
+        assert!(!pool.contains_page(page_id));
+    }
+
+    #[test]
+    fn test_dirty_pages_are_kept_without_a_disk_manager() {
+        let mut pool = BufferPool::new(2);
+        assert!(pool.insert_page(create_dirty_page(1)).is_ok());
+        assert!(pool.insert_page(create_test_page(2)).is_ok());
+
+        // The clean page goes even though the dirty one is older
+        assert!(pool.insert_page(create_test_page(3)).is_ok());
+        assert!(pool.contains_page(1));
+        assert!(!pool.contains_page(2));
+
+        pool.mark_dirty(3);
+        assert!(pool.insert_page(create_test_page(4)).is_err());
+        assert_eq!(pool.get_dirty_pages().len(), 2);
+    }
+
+    #[test]
+    fn test_removed_frames_are_reused() {
+        let mut pool = BufferPool::new(3);
+        for id in 1..=3 {
+            assert!(pool.insert_page(create_test_page(id)).is_ok());
+        }
+        assert!(pool.remove_page(2).is_some());
+        assert!(pool.insert_page(create_test_page(4)).is_ok());
+        assert_eq!(pool.cached_page_count(), 3);
+
+        // Order is now 1, 3, 4 from least to most recently used
+        assert!(pool.insert_page(create_test_page(5)).is_ok());
+        assert!(!pool.contains_page(1));
+        assert!(pool.insert_page(create_test_page(6)).is_ok());
+        assert!(!pool.contains_page(3));
+        assert!(pool.contains_page(4));
     }
 
     #[test]
@@ -438,4 +937,89 @@ mod tests {
         assert!(pool.contains_page(3));
         assert!(pool.contains_page(4));
     }
+
+    #[test]
+    fn test_eviction_writes_dirty_pages_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::open(temp_dir.path().join("data.db")).unwrap();
+        let mut pool = BufferPool::new(1).with_disk_manager(disk);
+
+        let first = pool.new_page().unwrap();
+        first.write_data(0, b"first").unwrap();
+        let first = first.id;
+        let second = pool.new_page().unwrap().id;
+        assert!(!pool.contains_page(first));
+        assert!(pool.contains_page(second));
+
+        // The evicted page is read back from disk
+        let page = pool.fetch_page(first).unwrap();
+        assert_eq!(page.read_data(0, 5).unwrap(), b"first");
+        assert!(!page.is_dirty());
+    }
+
+    #[test]
+    fn test_flush_persists_dirty_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut pool = BufferPool::new(4).with_disk_manager(DiskManager::open(&path).unwrap());
+        let page_id = pool.new_page().unwrap().id;
+        pool.fetch_page(page_id)
+            .unwrap()
+            .write_data(10, b"durable")
+            .unwrap();
+        assert_eq!(pool.flush_dirty_pages().unwrap(), vec![page_id]);
+        assert!(pool.get_dirty_pages().is_empty());
+        drop(pool);
+
+        let mut pool = BufferPool::new(4).with_disk_manager(DiskManager::open(&path).unwrap());
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.read_data(10, 7).unwrap(), b"durable");
+    }
+
+    #[test]
+    fn test_dirty_pages_are_counted_as_they_change() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let disk = DiskManager::open(temp_dir.path().join("data.db")).unwrap();
+        let mut pool = BufferPool::new(2).with_disk_manager(disk);
+
+        let first = pool.new_page().unwrap().id;
+        let second = pool.new_page().unwrap().id;
+        assert_eq!(pool.dirty_page_count(), 2);
+        pool.flush_dirty_pages().unwrap();
+        assert_eq!(pool.dirty_page_count(), 0);
+
+        // Changes made through a lent page count before the next call, too
+        pool.fetch_page(first)
+            .unwrap()
+            .write_data(0, b"changed")
+            .unwrap();
+        assert_eq!(pool.dirty_page_count(), 1);
+        pool.get_page(second).unwrap();
+        assert_eq!(pool.dirty_page_count(), 1);
+        pool.mark_dirty(second);
+        assert_eq!(pool.dirty_page_count(), 2);
+
+        // Pages leaving the cache take their count with them
+        pool.remove_page(second);
+        assert_eq!(pool.dirty_page_count(), 1);
+        pool.new_page().unwrap();
+        pool.new_page().unwrap();
+        assert!(!pool.contains_page(first));
+        assert_eq!(pool.dirty_page_count(), 2);
+        assert_eq!(pool.dirty_page_count(), pool.get_dirty_pages().len());
+
+        pool.clear();
+        assert_eq!(pool.dirty_page_count(), 0);
+    }
+
+    #[test]
+    fn test_fetch_page_needs_a_disk_manager() {
+        let mut pool = BufferPool::new(2);
+        assert!(pool.fetch_page(1).is_err());
+        assert!(pool.new_page().is_err());
+
+        assert!(pool.insert_page(create_test_page(1)).is_ok());
+        assert_eq!(pool.fetch_page(1).unwrap().id, 1);
+    }
 }
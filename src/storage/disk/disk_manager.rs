@@ -0,0 +1,665 @@
+//! Reading and writing pages of the database file
+//!
+//! The database file is a sequence of [`PAGE_SIZE`] pages, addressed by page ID.
+//! Page 0 holds the [`FileHeader`]; every other page is read and written whole
+//! with positioned I/O, so readers never move a shared file cursor.
+//!
+//! Pages overwritten between two syncs have their previous images saved in a
+//! rollback journal next to the file, so a crash at any point leaves the file
+//! as it was at one sync or the next.
+
+use super::free_list;
+use super::header::{FileHeader, PAGE_SIZE};
+use super::journal::Journal;
+use super::page::Page;
+use super::page_manager::PageManager;
+use crate::storage::error::{StorageError, StorageResult};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+
+/// Page size as a file offset
+const PAGE_LEN: u64 = PAGE_SIZE as u64;
+
+/// Reads and writes the pages of a database file
+///
+/// The disk manager owns the file header and the [`PageManager`] that hands out
+/// page IDs; [`DiskManager::sync`] writes the header and the free-page list back
+/// so allocations survive a restart.
+#[derive(Debug)]
+pub struct DiskManager {
+    /// Open database file
+    file: File,
+    /// Path of the database file
+    path: PathBuf,
+    /// Header as last read or written, updated from the page manager on sync
+    header: FileHeader,
+    /// Allocation state of the file's pages
+    page_manager: PageManager,
+    /// Previous images of the pages overwritten since the last sync
+    journal: Journal,
+    /// Number of pages the file had at the last sync; only those are journaled
+    synced_pages: u64,
+    /// Whether pages were freed or reused since the free-page list was written
+    free_list_changed: bool,
+}
+
+impl DiskManager {
+    /// Open the database file at `path`, creating it if it does not exist
+    ///
+    /// A new file gets a header page; an existing one must start with a valid
+    /// header for this page size. If the journal holds page images, the file
+    /// was interrupted between two syncs and is rolled back to the first one.
+    /// Pages found past the header's `next_page` otherwise count as allocated.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the file cannot be opened, read or
+    /// written, or does not hold a valid Zephyrite header.
+    pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| io_error("open", &path, &e))?;
+
+        let len = file
+            .metadata()
+            .map_err(|e| io_error("read metadata of", &path, &e))?
+            .len();
+
+        let mut journal = Journal::open(&path)?;
+
+        if len == 0 {
+            // A journal left behind by an earlier file with this name is not ours
+            journal.clear()?;
+            let mut disk = Self {
+                file,
+                path,
+                header: FileHeader::new(),
+                page_manager: PageManager::new(),
+                journal,
+                synced_pages: 0,
+                free_list_changed: false,
+            };
+            disk.sync()?;
+            return Ok(disk);
+        }
+
+        let len = Self::roll_back(&file, &path, &mut journal)?.unwrap_or(len);
+
+        if len < FileHeader::HEADER_SIZE as u64 {
+            return Err(StorageError::Internal(format!(
+                "Database file {} is truncated: {len} bytes",
+                path.display()
+            )));
+        }
+
+        let mut bytes = [0; FileHeader::HEADER_SIZE];
+        read_exact_at(&file, &mut bytes, 0).map_err(|e| io_error("read header of", &path, &e))?;
+        let mut header = FileHeader::deserialize(&bytes)?;
+
+        if header.page_size() != PAGE_SIZE {
+            return Err(StorageError::Internal(format!(
+                "Database file {} uses {} byte pages, expected {PAGE_SIZE}",
+                path.display(),
+                header.page_size()
+            )));
+        }
+
+        header.set_next_page(header.next_page().max(len.div_ceil(PAGE_LEN)));
+        let next_page = header.next_page();
+
+        let mut disk = Self {
+            file,
+            path,
+            synced_pages: next_page,
+            header,
+            page_manager: PageManager::with_state(next_page, Vec::new()),
+            journal,
+            free_list_changed: false,
+        };
+
+        match free_list::read(disk.header.free_list_page(), next_page, |page_id| {
+            disk.read_page(page_id)
+        }) {
+            Ok(free_pages) => disk.page_manager = PageManager::with_state(next_page, free_pages),
+            Err(e) => {
+                // Losing track of free pages only wastes space, so the data stays usable
+                warn!("{e}; its pages will not be reused");
+                disk.free_list_changed = true;
+            }
+        }
+
+        Ok(disk)
+    }
+
+    /// Write the page images saved in the journal back and drop the pages
+    /// allocated after them, returning the new file length if there were any
+    fn roll_back(file: &File, path: &Path, journal: &mut Journal) -> StorageResult<Option<u64>> {
+        let images = journal.images()?;
+        if images.is_empty() {
+            journal.clear()?;
+            return Ok(None);
+        }
+
+        warn!(
+            "Rolling database file {} back to its last sync: restoring {} pages",
+            path.display(),
+            images.len()
+        );
+        for (page_id, image) in &images {
+            write_all_at(file, image, page_id * PAGE_LEN)
+                .map_err(|e| io_error("roll back", path, &e))?;
+        }
+
+        let mut bytes = [0; FileHeader::HEADER_SIZE];
+        read_exact_at(file, &mut bytes, 0).map_err(|e| io_error("read header of", path, &e))?;
+        let len = FileHeader::deserialize(&bytes)?.next_page() * PAGE_LEN;
+        file.set_len(len)
+            .map_err(|e| io_error("truncate", path, &e))?;
+        file.sync_all().map_err(|e| io_error("sync", path, &e))?;
+
+        journal.clear()?;
+        Ok(Some(len))
+    }
+
+    /// Path of the database file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The file header, as of the last sync
+    #[must_use]
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// Mutable access to the file header, written on the next sync
+    pub fn header_mut(&mut self) -> &mut FileHeader {
+        &mut self.header
+    }
+
+    /// Allocation state of the file's pages
+    #[must_use]
+    pub fn page_manager(&self) -> &PageManager {
+        &self.page_manager
+    }
+
+    /// Allocate a page, reusing a freed one if there is any
+    ///
+    /// A new page reads as zeros until it is written; a reused one still holds
+    /// whatever it held before.
+    pub fn allocate_page(&mut self) -> u64 {
+        let free_pages = self.page_manager.free_page_count();
+        let page_id = self.page_manager.allocate_page();
+        self.free_list_changed |= self.page_manager.free_page_count() < free_pages;
+        page_id
+    }
+
+    /// Return a page to the page manager for reuse
+    ///
+    /// The free-page list is written to the file on the next sync.
+    pub fn free_page(&mut self, page_id: u64) {
+        self.page_manager.free_page(page_id);
+        self.free_list_changed = true;
+    }
+
+    /// Read a page from the file
+    ///
+    /// A page that was allocated but never written reads as zeros.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if `page_id` is the header page or has
+    /// not been allocated, or if the file cannot be read.
+    pub fn read_page(&self, page_id: u64) -> StorageResult<Page> {
+        let offset = self.page_offset(page_id)?;
+        Ok(Page::from_data(page_id, self.read_image(offset)?))
+    }
+
+    /// Write a page to the file
+    ///
+    /// The write is not durable until [`DiskManager::sync`] is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the page is the header page, has
+    /// not been allocated or is not exactly one page long, or if the file
+    /// cannot be written.
+    pub fn write_page(&mut self, page: &Page) -> StorageResult<()> {
+        self.write_pages(&[page])
+    }
+
+    /// Write several pages to the file, saving the previous images of all of
+    /// them in the journal with a single sync
+    ///
+    /// The writes are not durable until [`DiskManager::sync`] is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if any page is the header page, has
+    /// not been allocated or is not exactly one page long, or if the journal
+    /// or the file cannot be written.
+    pub fn write_pages(&mut self, pages: &[&Page]) -> StorageResult<()> {
+        let mut offsets = Vec::with_capacity(pages.len());
+        for page in pages {
+            offsets.push(self.page_offset(page.id)?);
+            if page.data.len() != PAGE_SIZE as usize {
+                return Err(StorageError::Internal(format!(
+                    "Page {} is {} bytes, expected {PAGE_SIZE}",
+                    page.id,
+                    page.data.len()
+                )));
+            }
+        }
+
+        let mut images = Vec::new();
+        for page in pages {
+            if page.id < self.synced_pages
+                && !self.journal.contains(page.id)
+                && images.iter().all(|(id, _)| *id != page.id)
+            {
+                images.push((page.id, self.read_image(page.id * PAGE_LEN)?));
+            }
+        }
+        if !images.is_empty() && !self.journal.contains(0) {
+            // The header is rewritten on the next sync, which must be undone as well
+            images.push((0, self.read_image(0)?));
+        }
+        self.journal.save(&images)?;
+
+        for (page, offset) in pages.iter().zip(offsets) {
+            write_all_at(&self.file, &page.data, offset)
+                .map_err(|e| io_error("write page of", &self.path, &e))?;
+        }
+        Ok(())
+    }
+
+    /// Write the free-page list and the header, and make all writes so far durable
+    ///
+    /// Once the file is synced the journal is emptied, so a crash after this
+    /// returns keeps every write up to here.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the header cannot be written or
+    /// the file or journal cannot be synced.
+    pub fn sync(&mut self) -> StorageResult<()> {
+        if self.free_list_changed {
+            let (first, trunks) = free_list::trunk_pages(&self.page_manager.free_pages());
+            self.write_pages(&trunks.iter().collect::<Vec<_>>())?;
+            self.header.set_free_list_page(first);
+        }
+
+        self.header.set_next_page(self.page_manager.next_page_id());
+        self.header
+            .set_free_pages_count(self.page_manager.free_page_count() as u64);
+
+        if self.synced_pages > 0 && !self.journal.contains(0) {
+            self.journal.save(&[(0, self.read_image(0)?)])?;
+        }
+
+        let mut page = vec![0; PAGE_SIZE as usize];
+        page[..FileHeader::HEADER_SIZE].copy_from_slice(&self.header.serialize()?);
+        write_all_at(&self.file, &page, 0)
+            .map_err(|e| io_error("write header of", &self.path, &e))?;
+
+        self.file
+            .sync_data()
+            .map_err(|e| io_error("sync", &self.path, &e))?;
+
+        self.journal.clear()?;
+        self.synced_pages = self.header.next_page();
+        self.free_list_changed = false;
+        Ok(())
+    }
+
+    /// Read the page at `offset`, with zeros for any part past the end of the file
+    fn read_image(&self, offset: u64) -> StorageResult<Vec<u8>> {
+        let mut data = vec![0; PAGE_SIZE as usize];
+        let mut filled = 0;
+        while filled < data.len() {
+            match read_at(&self.file, &mut data[filled..], offset + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(io_error("read page of", &self.path, &e)),
+            }
+        }
+        Ok(data)
+    }
+
+    /// Offset of an allocated data page in the file
+    fn page_offset(&self, page_id: u64) -> StorageResult<u64> {
+        if page_id == 0 {
+            return Err(StorageError::Internal(
+                "Page 0 holds the file header".to_string(),
+            ));
+        }
+        if page_id >= self.page_manager.next_page_id() {
+            return Err(StorageError::Internal(format!(
+                "Page {page_id} has not been allocated"
+            )));
+        }
+        Ok(page_id * PAGE_LEN)
+    }
+}
+
+pub(super) fn io_error(action: &str, path: &Path, e: &io::Error) -> StorageError {
+    StorageError::Internal(format!(
+        "Failed to {action} database file {}: {e}",
+        path.display()
+    ))
+}
+
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    return file.read_at(buf, offset);
+    #[cfg(windows)]
+    return file.seek_read(buf, offset);
+}
+
+pub(super) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        #[cfg(unix)]
+        let written = file.write_at(buf, offset);
+        #[cfg(windows)]
+        let written = file.seek_write(buf, offset);
+
+        match written {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_file_gets_a_header() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header(), &FileHeader::new());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), PAGE_LEN);
+
+        drop(disk);
+        let disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header(), &FileHeader::new());
+        assert_eq!(disk.page_manager().next_page_id(), 1);
+    }
+
+    #[test]
+    fn test_pages_survive_reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let first = disk.allocate_page();
+        let second = disk.allocate_page();
+        let mut page = Page::new(second);
+        page.write_data(100, b"hello").unwrap();
+        disk.write_page(&page).unwrap();
+        disk.header_mut().set_index_page_id(first);
+        disk.sync().unwrap();
+        drop(disk);
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header().next_page(), 3);
+        assert_eq!(disk.header().index_page_id(), first);
+        assert_eq!(
+            disk.read_page(second).unwrap().read_data(100, 5).unwrap(),
+            b"hello"
+        );
+
+        // Allocated but never written
+        assert!(disk.read_page(first).unwrap().data.iter().all(|&b| b == 0));
+        assert_eq!(disk.allocate_page(), 3);
+    }
+
+    #[test]
+    fn test_pages_written_before_header_count_as_allocated() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let page_id = disk.allocate_page();
+        disk.write_page(&Page::from_data(page_id, b"unsynced".to_vec()))
+            .unwrap();
+        drop(disk);
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        assert_eq!(
+            disk.read_page(page_id).unwrap().read_data(0, 8).unwrap(),
+            b"unsynced"
+        );
+        assert_ne!(disk.allocate_page(), page_id);
+    }
+
+    #[test]
+    fn test_interrupted_writes_are_rolled_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let page_id = disk.allocate_page();
+        disk.write_page(&Page::from_data(page_id, vec![1; PAGE_SIZE as usize]))
+            .unwrap();
+        disk.header_mut().set_checkpoint_sequence(7);
+        disk.sync().unwrap();
+
+        // Overwrite the page, add another and update the header, then crash
+        disk.write_page(&Page::from_data(page_id, vec![2; PAGE_SIZE as usize]))
+            .unwrap();
+        let added = disk.allocate_page();
+        disk.write_page(&Page::from_data(added, vec![3; PAGE_SIZE as usize]))
+            .unwrap();
+        disk.header_mut().set_checkpoint_sequence(8);
+        let mut header = disk.header().serialize_vec().unwrap();
+        header.resize(PAGE_SIZE as usize, 0);
+        write_all_at(&disk.file, &header, 0).unwrap();
+        drop(disk);
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header().checkpoint_sequence(), 7);
+        assert!(
+            disk.read_page(page_id)
+                .unwrap()
+                .data
+                .iter()
+                .all(|&b| b == 1)
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 2 * PAGE_LEN);
+        assert_eq!(disk.allocate_page(), added);
+    }
+
+    #[test]
+    fn test_sync_empties_the_journal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let page_id = disk.allocate_page();
+        disk.sync().unwrap();
+
+        disk.write_page(&Page::from_data(page_id, vec![5; PAGE_SIZE as usize]))
+            .unwrap();
+        let journal = temp_dir.path().join("data.db-journal");
+        assert!(std::fs::metadata(&journal).unwrap().len() > 0);
+
+        disk.sync().unwrap();
+        assert_eq!(std::fs::metadata(&journal).unwrap().len(), 0);
+        drop(disk);
+
+        let disk = DiskManager::open(&path).unwrap();
+        assert!(
+            disk.read_page(page_id)
+                .unwrap()
+                .data
+                .iter()
+                .all(|&b| b == 5)
+        );
+    }
+
+    #[test]
+    fn test_free_pages_survive_reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let pages: Vec<u64> = (0..600).map(|_| disk.allocate_page()).collect();
+        for &page_id in &pages[..550] {
+            disk.free_page(page_id);
+        }
+        disk.sync().unwrap();
+        drop(disk);
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.page_manager().free_pages(), &pages[..550]);
+        assert_eq!(disk.header().free_pages_count(), 550);
+        assert_ne!(disk.header().free_list_page(), 0);
+
+        // Reusing pages, trunks included, keeps the list consistent
+        for _ in 0..549 {
+            disk.allocate_page();
+        }
+        disk.sync().unwrap();
+        drop(disk);
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.page_manager().free_pages(), &[pages[0]]);
+        assert_eq!(disk.allocate_page(), pages[0]);
+        disk.sync().unwrap();
+        drop(disk);
+
+        let disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header().free_list_page(), 0);
+        assert_eq!(disk.page_manager().free_page_count(), 0);
+        assert_eq!(disk.page_manager().next_page_id(), 601);
+    }
+
+    #[test]
+    fn test_interrupted_free_list_changes_are_rolled_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let first = disk.allocate_page();
+        let second = disk.allocate_page();
+        disk.write_page(&Page::from_data(second, vec![9; PAGE_SIZE as usize]))
+            .unwrap();
+        disk.free_page(first);
+        disk.sync().unwrap();
+
+        // Reuse the free page and free the other one, then crash mid-sync
+        assert_eq!(disk.allocate_page(), first);
+        disk.free_page(second);
+        let (_, trunks) = free_list::trunk_pages(&disk.page_manager().free_pages());
+        disk.write_pages(&trunks.iter().collect::<Vec<_>>())
+            .unwrap();
+        drop(disk);
+
+        let disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.page_manager().free_pages(), &[first]);
+        assert!(disk.page_manager().is_free(first));
+        assert!(disk.read_page(second).unwrap().data.iter().all(|&b| b == 9));
+    }
+
+    #[test]
+    fn test_damaged_free_list_is_dropped() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        let page_id = disk.allocate_page();
+        disk.allocate_page();
+        disk.free_page(page_id);
+        disk.sync().unwrap();
+        drop(disk);
+
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        write_all_at(&file, &[0xff], page_id * PAGE_LEN).unwrap();
+
+        let mut disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.page_manager().free_page_count(), 0);
+        disk.sync().unwrap();
+        drop(disk);
+
+        let disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header().free_list_page(), 0);
+    }
+
+    #[test]
+    fn test_invalid_page_ids_are_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut disk = DiskManager::open(temp_dir.path().join("data.db")).unwrap();
+
+        assert!(disk.read_page(0).is_err());
+        assert!(disk.write_page(&Page::new(0)).is_err());
+        assert!(disk.read_page(1).is_err());
+        assert!(disk.write_page(&Page::new(1)).is_err());
+
+        let page_id = disk.allocate_page();
+        let mut page = Page::new(page_id);
+        page.data.truncate(10);
+        assert!(disk.write_page(&page).is_err());
+    }
+
+    #[test]
+    fn test_invalid_files_are_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        std::fs::write(&path, b"ZEPHYRITE").unwrap();
+        let err = DiskManager::open(&path).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+
+        std::fs::write(&path, vec![7; PAGE_SIZE as usize]).unwrap();
+        let err = DiskManager::open(&path).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Invalid Zephyrite file identifier")
+        );
+
+        let mut header = FileHeader::new().serialize().unwrap();
+        header[11..13].copy_from_slice(&8192u16.to_le_bytes());
+        std::fs::write(&path, header).unwrap();
+        let err = DiskManager::open(&path).unwrap_err();
+        assert!(err.to_string().contains("8192 byte pages"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Free-page list stored in trunk pages
+//!
+//! The IDs of free pages are kept in a chain of trunk pages linked from the
+//! file header. Trunk pages are free pages themselves, so the list takes up no
+//! space that could otherwise be reused, and it is rewritten from the
+//! [`PageManager`](super::PageManager) on sync.
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 1 | page kind, always [`PageKind::FreeListTrunk`] |
+//! | 1 | 1 | reserved |
+//! | 2 | 2 | number of free page IDs in this trunk |
+//! | 4 | 8 | next trunk page, or 0 for the last one |
+//! | 12 | 8 per ID | free page IDs |
+
+use super::header::PAGE_SIZE;
+use super::page::{Page, PageKind};
+use crate::storage::error::{StorageError, StorageResult};
+
+/// Bytes at the start of a trunk page taken up by its header
+const TRUNK_HEADER_LEN: usize = 12;
+
+/// Free page IDs a trunk page holds besides itself
+const TRUNK_CAPACITY: usize = (PAGE_SIZE as usize - TRUNK_HEADER_LEN) / 8;
+
+/// Trunk pages holding `free_pages`, and the ID of the first one (0 if there are none)
+///
+/// The lowest free pages become the trunks, since allocation takes the
+/// highest ones first and would otherwise break up the list soonest.
+pub(super) fn trunk_pages(free_pages: &[u64]) -> (u64, Vec<Page>) {
+    let trunk_count = free_pages.len().div_ceil(TRUNK_CAPACITY + 1);
+    let (trunk_ids, leaf_ids) = free_pages.split_at(trunk_count);
+
+    let trunks = trunk_ids
+        .iter()
+        .zip(
+            leaf_ids
+                .chunks(TRUNK_CAPACITY)
+                .chain(std::iter::repeat(&[][..])),
+        )
+        .enumerate()
+        .map(|(i, (&page_id, leaves))| {
+            let next = trunk_ids.get(i + 1).copied().unwrap_or(0);
+            let count = u16::try_from(leaves.len()).unwrap_or(u16::MAX);
+
+            let mut page = Page::new(page_id);
+            page.set_kind(PageKind::FreeListTrunk);
+            page.data[2..4].copy_from_slice(&count.to_le_bytes());
+            page.data[4..TRUNK_HEADER_LEN].copy_from_slice(&next.to_le_bytes());
+            for (slot, leaf) in page.data[TRUNK_HEADER_LEN..]
+                .chunks_exact_mut(8)
+                .zip(leaves)
+            {
+                slot.copy_from_slice(&leaf.to_le_bytes());
+            }
+            page
+        })
+        .collect();
+
+    (trunk_ids.first().copied().unwrap_or(0), trunks)
+}
+
+/// Read the free page IDs from the chain of trunk pages starting at `first`
+///
+/// Every ID, trunks included, must be below `next_page`.
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if a page cannot be read, or if the
+/// chain is damaged: a page is not a trunk, an ID is out of range, or the
+/// chain is longer than the file.
+pub(super) fn read(
+    first: u64,
+    next_page: u64,
+    mut read_page: impl FnMut(u64) -> StorageResult<Page>,
+) -> StorageResult<Vec<u64>> {
+    let damaged = |reason: String| {
+        StorageError::Internal(format!(
+            "Free-page list starting at page {first} is damaged: {reason}"
+        ))
+    };
+    let check = |page_id: u64| {
+        if page_id == 0 || page_id >= next_page {
+            return Err(damaged(format!("page {page_id} is out of range")));
+        }
+        Ok(page_id)
+    };
+
+    let mut free_pages = Vec::new();
+    let mut trunk_id = first;
+    let mut trunks = 0u64;
+    while trunk_id != 0 {
+        // A damaged chain must not loop forever
+        trunks += 1;
+        if trunks >= next_page {
+            return Err(damaged("the chain loops".to_string()));
+        }
+
+        let page = read_page(check(trunk_id)?)?;
+        if page.kind() != Some(PageKind::FreeListTrunk) {
+            return Err(damaged(format!("page {trunk_id} is not a trunk page")));
+        }
+        let count = usize::from(u16::from_le_bytes([page.data[2], page.data[3]]));
+        if count > TRUNK_CAPACITY {
+            return Err(damaged(format!("page {trunk_id} holds {count} IDs")));
+        }
+
+        free_pages.push(trunk_id);
+        for id in page.data[TRUNK_HEADER_LEN..]
+            .chunks_exact(8)
+            .take(count)
+            .map(|id| u64::from_le_bytes(id.try_into().unwrap_or_default()))
+        {
+            free_pages.push(check(id)?);
+        }
+
+        trunk_id = u64::from_le_bytes(
+            page.data[4..TRUNK_HEADER_LEN]
+                .try_into()
+                .unwrap_or_default(),
+        );
+    }
+
+    Ok(free_pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn roundtrip(free_pages: &[u64], next_page: u64) -> StorageResult<Vec<u64>> {
+        let (first, trunks) = trunk_pages(free_pages);
+        let pages: HashMap<u64, Page> = trunks.into_iter().map(|page| (page.id, page)).collect();
+        read(first, next_page, |page_id| {
+            Ok(pages
+                .get(&page_id)
+                .cloned()
+                .unwrap_or_else(|| Page::new(page_id)))
+        })
+    }
+
+    #[test]
+    fn test_empty_list_has_no_trunks() {
+        let (first, trunks) = trunk_pages(&[]);
+        assert_eq!(first, 0);
+        assert!(trunks.is_empty());
+        assert!(roundtrip(&[], 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lowest_pages_become_trunks() {
+        let (first, trunks) = trunk_pages(&[3, 7, 9]);
+        assert_eq!(first, 3);
+        assert_eq!(trunks.len(), 1);
+        assert_eq!(trunks[0].kind(), Some(PageKind::FreeListTrunk));
+
+        let mut free_pages = roundtrip(&[3, 7, 9], 10).unwrap();
+        free_pages.sort_unstable();
+        assert_eq!(free_pages, vec![3, 7, 9]);
+    }
+
+    #[test]
+    fn test_long_lists_span_several_trunks() {
+        let free_pages: Vec<u64> = (1..=2000).collect();
+        let (first, trunks) = trunk_pages(&free_pages);
+        assert_eq!(first, 1);
+        assert_eq!(trunks.len(), 2000usize.div_ceil(TRUNK_CAPACITY + 1));
+
+        let mut read_back = roundtrip(&free_pages, 2001).unwrap();
+        read_back.sort_unstable();
+        assert_eq!(read_back, free_pages);
+    }
+
+    #[test]
+    fn test_damaged_lists_are_rejected() {
+        // IDs past the end of the file
+        assert!(roundtrip(&[3, 7, 9], 8).is_err());
+
+        // The first page is not a trunk
+        assert!(read(2, 10, |page_id| Ok(Page::new(page_id))).is_err());
+
+        // A trunk pointing at itself
+        let (first, mut trunks) = trunk_pages(&[2]);
+        trunks[0].data[4..12].copy_from_slice(&2u64.to_le_bytes());
+        let err = read(first, 10, |_| Ok(trunks[0].clone())).unwrap_err();
+        assert!(err.to_string().contains("loops"));
+    }
+}
@@ -9,27 +9,43 @@ use crate::{StorageError, StorageResult};
 pub const PAGE_SIZE: u16 = 4096;
 
 /// Version of the storage format
-const FORMAT_VERSION: u16 = 1;
+///
+/// Version 1 headers do not hold the key count.
+const FORMAT_VERSION: u16 = 2;
 
 /// Number to identify Zephyrite database files
 const ZEPHYRITE: [u8; 9] = *b"ZEPHYRITE";
 
 /// Header for the database file
+///
+/// The header is stored at the start of page 0, which holds nothing else.
 #[repr(C)]
-#[derive(Debug, Clone)]
-struct FileHeader {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHeader {
     zephyrite_file_id: [u8; 9],
     version: u16,
     page_size: u16,
     next_page: u64,
     free_pages_count: u64,
     index_page_id: u64,
+    checkpoint_sequence: u64,
+    free_list_page: u64,
+    retired_version: u64,
+    key_count: u64,
 }
-#[allow(dead_code)]
+
+impl Default for FileHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FileHeader {
     /// Size of the serialized header in bytes
-    const HEADER_SIZE: usize = 64;
+    pub const HEADER_SIZE: usize = 72;
 
+    /// Create the header of an empty database file
+    #[must_use]
     pub fn new() -> Self {
         Self {
             zephyrite_file_id: ZEPHYRITE,
@@ -38,11 +54,113 @@ impl FileHeader {
             next_page: 1,
             free_pages_count: 0,
             index_page_id: 0,
+            checkpoint_sequence: 0,
+            free_list_page: 0,
+            retired_version: 0,
+            key_count: 0,
         }
     }
 
+    /// Format version the file was written with
+    #[must_use]
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Size of the pages in the file
+    #[must_use]
+    pub fn page_size(&self) -> u16 {
+        self.page_size
+    }
+
+    /// First page ID that has never been allocated
+    #[must_use]
+    pub fn next_page(&self) -> u64 {
+        self.next_page
+    }
+
+    /// Set the first page ID that has never been allocated
+    pub fn set_next_page(&mut self, next_page: u64) {
+        self.next_page = next_page;
+    }
+
+    /// Number of pages that were freed and can be reused
+    #[must_use]
+    pub fn free_pages_count(&self) -> u64 {
+        self.free_pages_count
+    }
+
+    /// Set the number of pages that were freed and can be reused
+    pub fn set_free_pages_count(&mut self, free_pages_count: u64) {
+        self.free_pages_count = free_pages_count;
+    }
+
+    /// Page the index starts at, or 0 if there is no index yet
+    #[must_use]
+    pub fn index_page_id(&self) -> u64 {
+        self.index_page_id
+    }
+
+    /// Set the page the index starts at
+    pub fn set_index_page_id(&mut self, index_page_id: u64) {
+        self.index_page_id = index_page_id;
+    }
+
+    /// Sequence number of the last WAL entry the pages reflect, or 0 if none does
+    #[must_use]
+    pub fn checkpoint_sequence(&self) -> u64 {
+        self.checkpoint_sequence
+    }
+
+    /// Set the sequence number of the last WAL entry the pages reflect
+    pub fn set_checkpoint_sequence(&mut self, checkpoint_sequence: u64) {
+        self.checkpoint_sequence = checkpoint_sequence;
+    }
+
+    /// First trunk page of the free-page list, or 0 if no page is free
+    #[must_use]
+    pub fn free_list_page(&self) -> u64 {
+        self.free_list_page
+    }
+
+    /// Set the first trunk page of the free-page list
+    pub fn set_free_list_page(&mut self, free_list_page: u64) {
+        self.free_list_page = free_list_page;
+    }
+
+    /// Highest version any key removed before the last checkpoint had
+    #[must_use]
+    pub fn retired_version(&self) -> u64 {
+        self.retired_version
+    }
+
+    /// Set the highest version any removed key had
+    pub fn set_retired_version(&mut self, retired_version: u64) {
+        self.retired_version = retired_version;
+    }
+
+    /// Number of keys in the index as of the last checkpoint, or `None` if
+    /// the file was written by a version that did not record it
+    #[must_use]
+    pub fn key_count(&self) -> Option<u64> {
+        (self.version >= 2).then_some(self.key_count)
+    }
+
+    /// Set the number of keys in the index, upgrading the header to the
+    /// current format version
+    pub fn set_key_count(&mut self, key_count: u64) {
+        self.key_count = key_count;
+        self.version = FORMAT_VERSION;
+    }
+
+    /// Serialize the header into its fixed-size on-disk form
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the fields do not add up to the
+    /// expected layout.
     pub fn serialize(&self) -> StorageResult<[u8; Self::HEADER_SIZE]> {
-        const EXPECTED_DATA_SIZE: usize = 9 + 2 + 2 + 8 + 8 + 8; // 37 bytes
+        const EXPECTED_DATA_SIZE: usize = 9 + 2 + 2 + 8 + 8 + 8 + 8 + 8 + 8 + 8; // 69 bytes
         let mut bytes = [0u8; Self::HEADER_SIZE];
         let mut offset = 0;
 
@@ -51,8 +169,11 @@ impl FileHeader {
         offset = Self::write_bytes_at(&mut bytes, offset, &self.page_size.to_le_bytes())?;
         offset = Self::write_bytes_at(&mut bytes, offset, &self.next_page.to_le_bytes())?;
         offset = Self::write_bytes_at(&mut bytes, offset, &self.free_pages_count.to_le_bytes())?;
-        let final_offset =
-            Self::write_bytes_at(&mut bytes, offset, &self.index_page_id.to_le_bytes())?;
+        offset = Self::write_bytes_at(&mut bytes, offset, &self.index_page_id.to_le_bytes())?;
+        offset = Self::write_bytes_at(&mut bytes, offset, &self.checkpoint_sequence.to_le_bytes())?;
+        offset = Self::write_bytes_at(&mut bytes, offset, &self.free_list_page.to_le_bytes())?;
+        offset = Self::write_bytes_at(&mut bytes, offset, &self.retired_version.to_le_bytes())?;
+        let final_offset = Self::write_bytes_at(&mut bytes, offset, &self.key_count.to_le_bytes())?;
 
         if final_offset != EXPECTED_DATA_SIZE {
             return Err(StorageError::Internal(format!(
@@ -64,11 +185,20 @@ impl FileHeader {
     }
 
     /// Serialize method returning Vec<u8>
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the header cannot be serialized.
     pub fn serialize_vec(&self) -> StorageResult<Vec<u8>> {
         Ok(self.serialize()?.to_vec())
     }
 
     /// Deserialize header from byte slice
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the bytes are too short, are not a
+    /// Zephyrite file or hold invalid header fields.
     pub fn deserialize(bytes: &[u8]) -> StorageResult<Self> {
         if bytes.len() < Self::HEADER_SIZE {
             return Err(StorageError::Internal(format!(
@@ -92,6 +222,10 @@ impl FileHeader {
         let next_page = Self::read_u64_le(bytes, 13)?;
         let free_pages_count = Self::read_u64_le(bytes, 21)?;
         let index_page_id = Self::read_u64_le(bytes, 29)?;
+        let checkpoint_sequence = Self::read_u64_le(bytes, 37)?;
+        let free_list_page = Self::read_u64_le(bytes, 45)?;
+        let retired_version = Self::read_u64_le(bytes, 53)?;
+        let key_count = Self::read_u64_le(bytes, 61)?;
 
         let header = Self {
             zephyrite_file_id,
@@ -100,6 +234,10 @@ impl FileHeader {
             next_page,
             free_pages_count,
             index_page_id,
+            checkpoint_sequence,
+            free_list_page,
+            retired_version,
+            key_count,
         };
 
         header.validate()?;
@@ -199,6 +337,10 @@ mod tests {
         assert_eq!(header.next_page, 1);
         assert_eq!(header.free_pages_count, 0);
         assert_eq!(header.index_page_id, 0);
+        assert_eq!(header.checkpoint_sequence, 0);
+        assert_eq!(header.free_list_page, 0);
+        assert_eq!(header.retired_version, 0);
+        assert_eq!(header.key_count(), Some(0));
     }
 
     #[test]
@@ -236,6 +378,13 @@ mod tests {
         assert_eq!(original.next_page, deserialized.next_page);
         assert_eq!(original.free_pages_count, deserialized.free_pages_count);
         assert_eq!(original.index_page_id, deserialized.index_page_id);
+        assert_eq!(
+            original.checkpoint_sequence,
+            deserialized.checkpoint_sequence
+        );
+        assert_eq!(original.free_list_page, deserialized.free_list_page);
+        assert_eq!(original.retired_version, deserialized.retired_version);
+        assert_eq!(original.key_count, deserialized.key_count);
     }
 
     #[test]
@@ -406,6 +555,10 @@ mod tests {
         header.next_page = 42;
         header.free_pages_count = 100;
         header.index_page_id = 200;
+        header.checkpoint_sequence = 300;
+        header.free_list_page = 400;
+        header.retired_version = 500;
+        header.key_count = 600;
 
         let serialized = header.serialize().unwrap();
         let deserialized = FileHeader::deserialize(&serialized).unwrap();
@@ -417,13 +570,33 @@ mod tests {
         assert_eq!(header.next_page, deserialized.next_page);
         assert_eq!(header.free_pages_count, deserialized.free_pages_count);
         assert_eq!(header.index_page_id, deserialized.index_page_id);
+        assert_eq!(header.checkpoint_sequence, deserialized.checkpoint_sequence);
+        assert_eq!(header.free_list_page, deserialized.free_list_page);
+        assert_eq!(header.retired_version, deserialized.retired_version);
+        assert_eq!(header.key_count, deserialized.key_count);
+    }
+
+    #[test]
+    fn test_version_1_header_has_no_key_count() {
+        let mut header = FileHeader::new();
+        header.version = 1;
+        header.key_count = 600;
+
+        let deserialized = FileHeader::deserialize(&header.serialize().unwrap()).unwrap();
+        assert_eq!(deserialized.version(), 1);
+        assert_eq!(deserialized.key_count(), None);
+
+        let mut upgraded = deserialized;
+        upgraded.set_key_count(3);
+        assert_eq!(upgraded.version(), FORMAT_VERSION);
+        assert_eq!(upgraded.key_count(), Some(3));
     }
 
     #[test]
     fn test_file_header_constants() {
         assert_eq!(PAGE_SIZE, 4096);
-        assert_eq!(FORMAT_VERSION, 1);
+        assert_eq!(FORMAT_VERSION, 2);
         assert_eq!(ZEPHYRITE, *b"ZEPHYRITE");
-        assert_eq!(FileHeader::HEADER_SIZE, 64);
+        assert_eq!(FileHeader::HEADER_SIZE, 72);
     }
 }
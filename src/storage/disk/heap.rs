@@ -0,0 +1,445 @@
+//! Records of the disk engine and the overflow pages of large values
+//!
+//! Each key and its value are stored as one record in a slotted data page. A
+//! value that would make the record larger than [`MAX_INLINE_RECORD`] is moved
+//! into a chain of overflow pages, and the record keeps only its length and the
+//! first page of the chain.
+//!
+//! A record is the key length (`u16`) and the key, followed by either a 0 and
+//! the value, or a 1, the value length (`u32`) and the first overflow page
+//! (`u64`). An overflow page starts with its kind byte, a reserved byte, the
+//! length of the part of the value it holds (`u16`) and the next page of the
+//! chain (`u64`, 0 at the end).
+
+use super::buffer::BufferPool;
+use super::header::PAGE_SIZE;
+use super::index::IndexEntry;
+use super::page::{Page, PageKind};
+use crate::storage::error::{StorageError, StorageResult};
+
+/// Largest record stored in a data page as it is; larger values go to overflow pages
+pub const MAX_INLINE_RECORD: usize = PAGE_SIZE as usize / 4;
+
+/// Bytes of a value each overflow page holds
+pub const OVERFLOW_CAPACITY: usize = PAGE_SIZE as usize - OVERFLOW_HEADER_LEN;
+
+const OVERFLOW_HEADER_LEN: usize = 12;
+
+const INLINE: u8 = 0;
+const OVERFLOW: u8 = 1;
+
+/// A value as found in its record
+enum StoredValue {
+    /// The value is in the record
+    Inline(Vec<u8>),
+    /// The value is in a chain of overflow pages
+    Overflow {
+        /// Length of the value
+        len: u32,
+        /// First page of the chain
+        first_page: u64,
+    },
+}
+
+/// Stores records in the data pages of a buffer pool
+///
+/// New records go to the data page the last one went to, until it is full.
+#[derive(Debug, Default)]
+pub struct HeapFile {
+    /// Data page new records are added to
+    insert_page: Option<u64>,
+}
+
+impl HeapFile {
+    /// Create a heap file that starts a new data page for its first record
+    #[must_use]
+    pub fn new() -> Self {
+        Self { insert_page: None }
+    }
+
+    /// Store a value under `key` and return where it was stored
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the key or value is too large to
+    /// be stored, or if pages cannot be read or allocated.
+    pub fn insert(
+        &mut self,
+        pool: &mut BufferPool,
+        key: &str,
+        value: &[u8],
+    ) -> StorageResult<IndexEntry> {
+        let (record, size) = Self::encode(pool, key, value)?;
+        let (page_id, slot) = self.place(pool, &record)?;
+        Ok(IndexEntry::new(key.to_string(), page_id, slot, size))
+    }
+
+    /// Replace the value of the record `entry` points at
+    ///
+    /// The record stays in its slot if the page has room for it, and moves
+    /// otherwise; either way the returned entry says where it is now.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if `entry` does not point at a record
+    /// for its key, or if the new value cannot be stored.
+    pub fn update(
+        &mut self,
+        pool: &mut BufferPool,
+        entry: &IndexEntry,
+        value: &[u8],
+    ) -> StorageResult<IndexEntry> {
+        let old = Self::stored_value(pool, entry)?;
+        let (record, size) = Self::encode(pool, &entry.key, value)?;
+        if let StoredValue::Overflow { len, first_page } = old {
+            Self::free_overflow(pool, first_page, len)?;
+        }
+
+        let page = pool.fetch_page(entry.page_id)?;
+        if page.update_record(entry.slot, &record).is_ok() {
+            return Ok(IndexEntry::new(
+                entry.key.clone(),
+                entry.page_id,
+                entry.slot,
+                size,
+            ));
+        }
+
+        self.remove_record(pool, entry)?;
+        let (page_id, slot) = self.place(pool, &record)?;
+        Ok(IndexEntry::new(entry.key.clone(), page_id, slot, size))
+    }
+
+    /// Read the value of the record `entry` points at
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if `entry` does not point at a record
+    /// for its key, or if the record or its overflow pages are damaged.
+    pub fn read(pool: &mut BufferPool, entry: &IndexEntry) -> StorageResult<Vec<u8>> {
+        match Self::stored_value(pool, entry)? {
+            StoredValue::Inline(value) => Ok(value),
+            StoredValue::Overflow { len, first_page } => {
+                let mut value = Vec::with_capacity(len as usize);
+                for page_id in Self::overflow_chain(pool, first_page, len)? {
+                    let page = pool.fetch_page(page_id)?;
+                    value.extend_from_slice(overflow_chunk(page)?);
+                }
+                if value.len() != len as usize {
+                    return Err(StorageError::Internal(format!(
+                        "Value of '{}' is {} bytes, expected {len}",
+                        entry.key,
+                        value.len()
+                    )));
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// Delete the record `entry` points at, freeing its overflow pages
+    ///
+    /// A data page left without records is freed as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if `entry` does not point at a record
+    /// for its key, or if pages cannot be read or freed.
+    pub fn delete(&mut self, pool: &mut BufferPool, entry: &IndexEntry) -> StorageResult<()> {
+        if let StoredValue::Overflow { len, first_page } = Self::stored_value(pool, entry)? {
+            Self::free_overflow(pool, first_page, len)?;
+        }
+        self.remove_record(pool, entry)
+    }
+
+    /// Build the record for `key` and `value`, moving a large value to overflow pages
+    fn encode(pool: &mut BufferPool, key: &str, value: &[u8]) -> StorageResult<(Vec<u8>, u32)> {
+        let key_len = u16::try_from(key.len()).map_err(|_| {
+            StorageError::Internal(format!("Key of {} bytes is too long to store", key.len()))
+        })?;
+        let size = u32::try_from(value.len()).map_err(|_| {
+            StorageError::Internal(format!(
+                "Value of {} bytes is too large to store",
+                value.len()
+            ))
+        })?;
+
+        let mut record = Vec::with_capacity(2 + key.len() + 1 + value.len().min(12));
+        record.extend_from_slice(&key_len.to_le_bytes());
+        record.extend_from_slice(key.as_bytes());
+
+        if record.len() + 1 + value.len() <= MAX_INLINE_RECORD {
+            record.push(INLINE);
+            record.extend_from_slice(value);
+        } else {
+            let first_page = Self::write_overflow(pool, value)?;
+            record.push(OVERFLOW);
+            record.extend_from_slice(&size.to_le_bytes());
+            record.extend_from_slice(&first_page.to_le_bytes());
+        }
+
+        Ok((record, size))
+    }
+
+    /// Add a record to the current data page, or to a new one if it is full
+    fn place(&mut self, pool: &mut BufferPool, record: &[u8]) -> StorageResult<(u64, u16)> {
+        if let Some(page_id) = self.insert_page {
+            if let Ok(slot) = pool.fetch_page(page_id)?.insert_record(record) {
+                return Ok((page_id, slot));
+            }
+        }
+
+        let page = pool.new_page()?;
+        let page_id = page.id;
+        let slot = page.insert_record(record).map_err(|e| {
+            StorageError::Internal(format!(
+                "Record of {} bytes cannot be stored: {e}",
+                record.len()
+            ))
+        })?;
+        self.insert_page = Some(page_id);
+        Ok((page_id, slot))
+    }
+
+    /// Delete a record from its data page, freeing the page if it is left empty
+    fn remove_record(&mut self, pool: &mut BufferPool, entry: &IndexEntry) -> StorageResult<()> {
+        let page = pool.fetch_page(entry.page_id)?;
+        page.delete_record(entry.slot)
+            .map_err(|e| StorageError::Internal(format!("Page {}: {e}", entry.page_id)))?;
+
+        if page.slot_count() == 0 {
+            if self.insert_page == Some(entry.page_id) {
+                self.insert_page = None;
+            }
+            pool.free_page(entry.page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Find the record `entry` points at and check that it belongs to its key
+    fn stored_value(pool: &mut BufferPool, entry: &IndexEntry) -> StorageResult<StoredValue> {
+        let damaged = |reason: &str| {
+            StorageError::Internal(format!(
+                "Record for '{}' on page {} slot {} {reason}",
+                entry.key, entry.page_id, entry.slot
+            ))
+        };
+
+        let page = pool.fetch_page(entry.page_id)?;
+        if page.kind() != Some(PageKind::Data) {
+            return Err(damaged("is not on a data page"));
+        }
+        let record = page
+            .record(entry.slot)
+            .ok_or_else(|| damaged("does not exist"))?;
+
+        let (key_len, rest) = record
+            .split_first_chunk::<2>()
+            .ok_or_else(|| damaged("is truncated"))?;
+        let key_len = usize::from(u16::from_le_bytes(*key_len));
+        if rest.get(..key_len) != Some(entry.key.as_bytes()) {
+            return Err(damaged("belongs to another key"));
+        }
+
+        match rest[key_len..].split_first() {
+            Some((&INLINE, value)) => Ok(StoredValue::Inline(value.to_vec())),
+            Some((&OVERFLOW, pointer)) => {
+                let (len, first_page) = pointer
+                    .split_first_chunk::<4>()
+                    .and_then(|(len, rest)| Some((len, rest.first_chunk::<8>()?)))
+                    .ok_or_else(|| damaged("is truncated"))?;
+                Ok(StoredValue::Overflow {
+                    len: u32::from_le_bytes(*len),
+                    first_page: u64::from_le_bytes(*first_page),
+                })
+            }
+            _ => Err(damaged("is damaged")),
+        }
+    }
+
+    /// Write `value` to a new chain of overflow pages and return its first page
+    fn write_overflow(pool: &mut BufferPool, value: &[u8]) -> StorageResult<u64> {
+        // Written back to front, so each page knows the one after it
+        let mut next = 0u64;
+        for chunk in value.chunks(OVERFLOW_CAPACITY).rev() {
+            let chunk_len = u16::try_from(chunk.len()).unwrap_or(u16::MAX);
+            let page = pool.new_page()?;
+            page.set_kind(PageKind::Overflow);
+            page.data[2..4].copy_from_slice(&chunk_len.to_le_bytes());
+            page.data[4..OVERFLOW_HEADER_LEN].copy_from_slice(&next.to_le_bytes());
+            page.data[OVERFLOW_HEADER_LEN..OVERFLOW_HEADER_LEN + chunk.len()]
+                .copy_from_slice(chunk);
+            next = page.id;
+        }
+        Ok(next)
+    }
+
+    /// Pages of the overflow chain holding a value of `len` bytes
+    fn overflow_chain(pool: &mut BufferPool, first_page: u64, len: u32) -> StorageResult<Vec<u64>> {
+        let expected = (len as usize).div_ceil(OVERFLOW_CAPACITY);
+        let mut chain = Vec::with_capacity(expected);
+        let mut page_id = first_page;
+
+        while page_id != 0 {
+            // A damaged chain must not loop forever
+            if chain.len() == expected {
+                return Err(StorageError::Internal(format!(
+                    "Overflow chain starting at page {first_page} is longer than {expected} pages"
+                )));
+            }
+            chain.push(page_id);
+            let page = pool.fetch_page(page_id)?;
+            overflow_chunk(page)?;
+            page_id = u64::from_le_bytes(
+                page.data[4..OVERFLOW_HEADER_LEN]
+                    .try_into()
+                    .unwrap_or_default(),
+            );
+        }
+        Ok(chain)
+    }
+
+    /// Free the pages of an overflow chain
+    fn free_overflow(pool: &mut BufferPool, first_page: u64, len: u32) -> StorageResult<()> {
+        for page_id in Self::overflow_chain(pool, first_page, len)? {
+            pool.free_page(page_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// The part of a value an overflow page holds
+fn overflow_chunk(page: &Page) -> StorageResult<&[u8]> {
+    if page.kind() != Some(PageKind::Overflow) {
+        return Err(StorageError::Internal(format!(
+            "Page {} is not an overflow page",
+            page.id
+        )));
+    }
+    let len = usize::from(u16::from_le_bytes([page.data[2], page.data[3]]));
+    page.data
+        .get(OVERFLOW_HEADER_LEN..OVERFLOW_HEADER_LEN + len)
+        .ok_or_else(|| StorageError::Internal(format!("Overflow page {} is damaged", page.id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::disk::DiskManager;
+    use std::path::Path;
+
+    fn open_pool(path: &Path) -> BufferPool {
+        BufferPool::new(16).with_disk_manager(DiskManager::open(path).unwrap())
+    }
+
+    fn free_pages(pool: &BufferPool) -> usize {
+        pool.disk_manager()
+            .unwrap()
+            .page_manager()
+            .free_page_count()
+    }
+
+    #[test]
+    fn test_small_values_share_a_page() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"));
+        let mut heap = HeapFile::new();
+
+        let first = heap.insert(&mut pool, "first", b"one").unwrap();
+        let second = heap.insert(&mut pool, "second", b"").unwrap();
+        assert_eq!(first.page_id, second.page_id);
+        assert_ne!(first.slot, second.slot);
+        assert_eq!(second.size, 0);
+
+        assert_eq!(HeapFile::read(&mut pool, &first).unwrap(), b"one");
+        assert_eq!(HeapFile::read(&mut pool, &second).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_large_values_use_overflow_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+        let mut pool = open_pool(&path);
+        let mut heap = HeapFile::new();
+
+        // The largest value the API accepts, through a pool smaller than its chain
+        let value: Vec<u8> = (0..1_048_576u32).map(|i| (i % 251) as u8).collect();
+        let entry = heap.insert(&mut pool, "big", &value).unwrap();
+        assert_eq!(entry.size, 1_048_576);
+        assert_eq!(HeapFile::read(&mut pool, &entry).unwrap(), value);
+
+        pool.flush_dirty_pages().unwrap();
+        drop(pool);
+        let mut pool = open_pool(&path);
+        assert_eq!(HeapFile::read(&mut pool, &entry).unwrap(), value);
+
+        heap.delete(&mut pool, &entry).unwrap();
+        assert_eq!(
+            free_pages(&pool),
+            value.len().div_ceil(OVERFLOW_CAPACITY) + 1
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"));
+        let mut heap = HeapFile::new();
+
+        let entry = heap.insert(&mut pool, "key", b"small").unwrap();
+        let _neighbour = heap.insert(&mut pool, "neighbour", b"stays").unwrap();
+
+        // Shrinking and growing within the page keeps the slot
+        let entry2 = heap.update(&mut pool, &entry, b"tiny").unwrap();
+        assert_eq!((entry2.page_id, entry2.slot), (entry.page_id, entry.slot));
+        assert_eq!(HeapFile::read(&mut pool, &entry2).unwrap(), b"tiny");
+
+        // Growing past the inline limit moves the value to overflow pages
+        let large = vec![5; 3 * OVERFLOW_CAPACITY];
+        let entry3 = heap.update(&mut pool, &entry2, &large).unwrap();
+        assert_eq!(HeapFile::read(&mut pool, &entry3).unwrap(), large);
+
+        // ... and shrinking again frees them
+        let entry4 = heap.update(&mut pool, &entry3, b"small again").unwrap();
+        assert_eq!(HeapFile::read(&mut pool, &entry4).unwrap(), b"small again");
+        assert_eq!(free_pages(&pool), 3);
+    }
+
+    #[test]
+    fn test_full_pages_are_replaced_and_empty_ones_freed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"));
+        let mut heap = HeapFile::new();
+
+        let value = [1; 900];
+        let entries: Vec<IndexEntry> = (0..10)
+            .map(|i| heap.insert(&mut pool, &format!("key{i}"), &value).unwrap())
+            .collect();
+        assert_ne!(entries[0].page_id, entries[9].page_id);
+
+        let first_page = entries[0].page_id;
+        for entry in entries.iter().filter(|entry| entry.page_id == first_page) {
+            heap.delete(&mut pool, entry).unwrap();
+        }
+        assert_eq!(free_pages(&pool), 1);
+        assert!(HeapFile::read(&mut pool, &entries[9]).is_ok());
+    }
+
+    #[test]
+    fn test_stale_entries_are_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"));
+        let mut heap = HeapFile::new();
+
+        let entry = heap.insert(&mut pool, "key", b"value").unwrap();
+        let _other = heap.insert(&mut pool, "other", b"value").unwrap();
+
+        let wrong_key = IndexEntry::new("nope".to_string(), entry.page_id, entry.slot, 5);
+        assert!(HeapFile::read(&mut pool, &wrong_key).is_err());
+
+        heap.delete(&mut pool, &entry).unwrap();
+        let err = HeapFile::read(&mut pool, &entry).unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+        assert!(heap.delete(&mut pool, &entry).is_err());
+    }
+}
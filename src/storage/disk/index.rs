@@ -1,241 +1,748 @@
 //! Index management for disk storage
 //!
 //! The index provides fast key lookups by mapping keys to their location
-//! on disk (page ID and offset within the page).
+//! on disk (page ID and slot within the page). It is a B+tree stored in pages
+//! of the database file: inner nodes route a key to the child covering it, and
+//! the leaves hold the index entries in key order, each linked to the next so
+//! ranges can be read without going back up the tree. The root page is recorded
+//! in the file header, so an index reopens without being rebuilt.
+//!
+//! A node page starts with its kind, a reserved byte, the number of entries
+//! (`u16`) and a page ID (`u64`): the next leaf for leaves, or the child for
+//! keys below the first separator for inner nodes. Entries follow as the key
+//! length (`u16`) and key, then the record's page (`u64`), slot (`u16`) and
+//! value size (`u32`) in leaves, or the child page (`u64`) in inner nodes.
+//!
+//! Nodes are split when they outgrow their page but are not merged when they
+//! shrink; a leaf emptied by deletes stays in place and takes new keys in its
+//! range.
+
+use super::buffer::BufferPool;
+use super::header::PAGE_SIZE;
+use super::page::{Page, PageKind};
+use crate::storage::error::{StorageError, StorageResult};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Bound;
+
+/// Separator key and page of the right half of a node that was split
+type Split = (String, u64);
 
-use std::collections::HashMap;
+/// Longest key the index can store, matching the key validation of the API
+pub const MAX_KEY_LEN: usize = 1024;
+
+const NODE_HEADER_LEN: usize = 12;
+const LEAF_ENTRY_LEN: usize = 2 + 8 + 2 + 4;
+const INTERNAL_ENTRY_LEN: usize = 2 + 8;
 
 /// Index entry pointing to a data page
 ///
 /// Each index entry contains the information needed to locate
 /// a value stored on disk.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IndexEntry {
     /// The key this entry refers to
     pub key: String,
     /// Page ID where the data is stored
     pub page_id: u64,
-    /// Offset within the page where the data starts
-    pub offset: u16,
-    /// Size of the stored data in bytes
-    pub size: u16,
+    /// Slot of the record within the page
+    pub slot: u16,
+    /// Size of the stored value in bytes, which may span overflow pages
+    pub size: u32,
 }
 
 impl IndexEntry {
     /// Create a new index entry
     #[must_use]
-    pub fn new(key: String, page_id: u64, offset: u16, size: u16) -> Self {
+    pub fn new(key: String, page_id: u64, slot: u16, size: u32) -> Self {
         Self {
             key,
             page_id,
-            offset,
+            slot,
             size,
         }
     }
 
-    /// Get the end offset of the data (offset + size)
+    /// Check if this entry points at the same record as another entry
     #[must_use]
-    pub fn end_offset(&self) -> u16 {
-        self.offset.saturating_add(self.size)
+    pub fn same_record(&self, other: &IndexEntry) -> bool {
+        self.page_id == other.page_id && self.slot == other.slot
     }
+}
 
-    /// Check if this entry overlaps with another entry on the same page
-    #[must_use]
-    pub fn overlaps_with(&self, other: &IndexEntry) -> bool {
-        if self.page_id != other.page_id {
-            return false;
+/// A node of the tree as read from its page
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        /// Entries in key order
+        entries: Vec<IndexEntry>,
+        /// Leaf with the next keys, or 0 for the last leaf
+        next: u64,
+    },
+    Internal {
+        /// Child for keys below the first separator
+        first_child: u64,
+        /// Separator keys in order, each with the child for keys from it up
+        /// to the next separator
+        children: Vec<(String, u64)>,
+    },
+}
+
+impl Node {
+    /// Decode the node stored in `page`
+    fn read(page: &Page) -> StorageResult<Self> {
+        let damaged = || StorageError::Internal(format!("Index page {} is damaged", page.id));
+        let mut reader = NodeReader {
+            data: &page.data,
+            offset: 2,
+        };
+        let count = reader.u16().ok_or_else(damaged)?;
+        let link = reader.u64().ok_or_else(damaged)?;
+
+        match page.kind() {
+            Some(PageKind::IndexLeaf) => {
+                let entries = (0..count)
+                    .map(|_| {
+                        Some(IndexEntry::new(
+                            reader.key()?,
+                            reader.u64()?,
+                            reader.u16()?,
+                            reader.u32()?,
+                        ))
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or_else(damaged)?;
+                Ok(Node::Leaf {
+                    entries,
+                    next: link,
+                })
+            }
+            Some(PageKind::IndexInternal) => {
+                let children = (0..count)
+                    .map(|_| Some((reader.key()?, reader.u64()?)))
+                    .collect::<Option<_>>()
+                    .ok_or_else(damaged)?;
+                Ok(Node::Internal {
+                    first_child: link,
+                    children,
+                })
+            }
+            _ => Err(StorageError::Internal(format!(
+                "Page {} is not an index page",
+                page.id
+            ))),
         }
+    }
 
-        let self_end = self.end_offset();
-        let other_end = other.end_offset();
+    /// Encode the node into `page`, which it must fit in
+    fn write(&self, page: &mut Page) {
+        let mut data = Vec::with_capacity(PAGE_SIZE as usize);
+        let (kind, count, link) = match self {
+            Node::Leaf { entries, next } => (PageKind::IndexLeaf, entries.len(), *next),
+            Node::Internal {
+                first_child,
+                children,
+            } => (PageKind::IndexInternal, children.len(), *first_child),
+        };
+        data.extend_from_slice(&[kind as u8, 0]);
+        data.extend_from_slice(&to_u16(count).to_le_bytes());
+        data.extend_from_slice(&link.to_le_bytes());
+
+        match self {
+            Node::Leaf { entries, .. } => {
+                for entry in entries {
+                    data.extend_from_slice(&to_u16(entry.key.len()).to_le_bytes());
+                    data.extend_from_slice(entry.key.as_bytes());
+                    data.extend_from_slice(&entry.page_id.to_le_bytes());
+                    data.extend_from_slice(&entry.slot.to_le_bytes());
+                    data.extend_from_slice(&entry.size.to_le_bytes());
+                }
+            }
+            Node::Internal { children, .. } => {
+                for (key, child) in children {
+                    data.extend_from_slice(&to_u16(key.len()).to_le_bytes());
+                    data.extend_from_slice(key.as_bytes());
+                    data.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+        }
 
-        !(self_end <= other.offset || other_end <= self.offset)
+        data.resize(PAGE_SIZE as usize, 0);
+        page.data = data;
+        page.mark_dirty();
     }
+
+    /// Bytes the node takes up when encoded
+    fn encoded_len(&self) -> usize {
+        NODE_HEADER_LEN
+            + match self {
+                Node::Leaf { entries, .. } => entries
+                    .iter()
+                    .map(|entry| LEAF_ENTRY_LEN + entry.key.len())
+                    .sum::<usize>(),
+                Node::Internal { children, .. } => children
+                    .iter()
+                    .map(|(key, _)| INTERNAL_ENTRY_LEN + key.len())
+                    .sum(),
+            }
+    }
+
+    /// Split an overfull node in two by size, with `right_id` as the page of
+    /// the right half; returns the right half and the key separating them
+    fn split(&mut self, right_id: u64) -> (Node, String) {
+        let half = self.encoded_len() / 2;
+        match self {
+            Node::Leaf { entries, next } => {
+                let mut bytes = NODE_HEADER_LEN;
+                let mid = entries
+                    .iter()
+                    .position(|entry| {
+                        bytes += LEAF_ENTRY_LEN + entry.key.len();
+                        bytes > half
+                    })
+                    .unwrap_or(entries.len())
+                    .clamp(1, entries.len() - 1);
+
+                let right_entries = entries.split_off(mid);
+                let separator = right_entries[0].key.clone();
+                let right = Node::Leaf {
+                    entries: right_entries,
+                    next: *next,
+                };
+                *next = right_id;
+                (right, separator)
+            }
+            Node::Internal { children, .. } => {
+                let mut bytes = NODE_HEADER_LEN;
+                let mid = children
+                    .iter()
+                    .position(|(key, _)| {
+                        bytes += INTERNAL_ENTRY_LEN + key.len();
+                        bytes > half
+                    })
+                    .unwrap_or(children.len())
+                    .clamp(1, children.len() - 2);
+
+                let mut right_children = children.split_off(mid);
+                let (separator, first_child) = right_children.remove(0);
+                let right = Node::Internal {
+                    first_child,
+                    children: right_children,
+                };
+                (right, separator)
+            }
+        }
+    }
+}
+
+/// Reads the fields of a node page in order
+struct NodeReader<'a> {
+    data: &'a [u8],
+    offset: usize,
 }
 
-/// In-memory index for fast key lookups
+impl NodeReader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.offset..self.offset + N)?;
+        self.offset += N;
+        bytes.try_into().ok()
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn key(&mut self) -> Option<String> {
+        let len = usize::from(self.u16()?);
+        let key = self.data.get(self.offset..self.offset + len)?;
+        self.offset += len;
+        String::from_utf8(key.to_vec()).ok()
+    }
+}
+
+/// B+tree index for fast key lookups
 ///
-/// This index maintains a mapping from keys to their storage locations.
-// TODO: Consider persisting to disk as well
-#[derive(Debug, Default)]
+/// The index maintains a mapping from keys to their storage locations in the
+/// pages of a [`BufferPool`], which every operation takes.
+#[derive(Debug)]
 pub struct Index {
-    /// Map from key to index entry
-    entries: HashMap<String, IndexEntry>,
+    /// Page of the root node
+    root: u64,
 }
 
 impl Index {
-    /// Create a new empty index
-    #[must_use]
-    pub fn new() -> Self {
-        Self {
-            entries: HashMap::new(),
+    /// Open the index whose root is recorded in the file header, or create an
+    /// empty one if there is none yet
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the buffer pool has no disk
+    /// manager, or if the root page cannot be read or allocated.
+    pub fn open(pool: &mut BufferPool) -> StorageResult<Self> {
+        let root = pool
+            .disk_manager()
+            .ok_or_else(|| {
+                StorageError::Internal(
+                    "The index needs a buffer pool with a disk manager".to_string(),
+                )
+            })?
+            .header()
+            .index_page_id();
+
+        if root != 0 {
+            Node::read(pool.fetch_page(root)?)?;
+            return Ok(Self { root });
         }
+
+        let mut index = Self { root: 0 };
+        index.create_root(pool)?;
+        Ok(index)
     }
 
-    /// Create an index with initial capacity
+    /// Page of the root node
     #[must_use]
-    pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            entries: HashMap::with_capacity(capacity),
-        }
+    pub fn root_page_id(&self) -> u64 {
+        self.root
     }
 
     /// Insert or update an index entry
-    pub fn insert(&mut self, entry: IndexEntry) -> Option<IndexEntry> {
-        let key = entry.key.clone();
-        self.entries.insert(key, entry)
-    }
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the key is longer than
+    /// [`MAX_KEY_LEN`] or index pages cannot be read or allocated.
+    pub fn insert(
+        &mut self,
+        pool: &mut BufferPool,
+        entry: IndexEntry,
+    ) -> StorageResult<Option<IndexEntry>> {
+        if entry.key.len() > MAX_KEY_LEN {
+            return Err(StorageError::Internal(format!(
+                "Key of {} bytes is too long for the index (max {MAX_KEY_LEN})",
+                entry.key.len()
+            )));
+        }
 
-    /// Get an index entry by key
-    #[must_use]
-    pub fn get(&self, key: &str) -> Option<&IndexEntry> {
-        self.entries.get(key)
+        let (old, split) = Self::insert_into(pool, self.root, entry)?;
+        if let Some((separator, right)) = split {
+            let root = pool.new_page()?;
+            Node::Internal {
+                first_child: self.root,
+                children: vec![(separator, right)],
+            }
+            .write(root);
+            let root = root.id;
+            self.set_root(pool, root)?;
+        }
+        Ok(old)
     }
 
-    /// Remove an index entry by key
-    #[must_use]
-    pub fn remove(&mut self, key: &str) -> Option<IndexEntry> {
-        self.entries.remove(key)
+    /// Get an index entry by key
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn get(&self, pool: &mut BufferPool, key: &str) -> StorageResult<Option<IndexEntry>> {
+        let (_, entries, _) = self.find_leaf(pool, Some(key))?;
+        Ok(entries
+            .binary_search_by(|entry| entry.key.as_str().cmp(key))
+            .ok()
+            .map(|i| entries[i].clone()))
     }
 
     /// Check if a key exists in the index
-    #[must_use]
-    pub fn contains_key(&self, key: &str) -> bool {
-        self.entries.contains_key(key)
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn contains_key(&self, pool: &mut BufferPool, key: &str) -> StorageResult<bool> {
+        Ok(self.get(pool, key)?.is_some())
     }
 
-    /// Get all keys in the index
-    pub fn keys(&self) -> impl Iterator<Item = &str> {
-        self.entries.keys().map(String::as_str)
+    /// Remove an index entry by key
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn remove(
+        &mut self,
+        pool: &mut BufferPool,
+        key: &str,
+    ) -> StorageResult<Option<IndexEntry>> {
+        let (page_id, mut entries, next) = self.find_leaf(pool, Some(key))?;
+        let Ok(i) = entries.binary_search_by(|entry| entry.key.as_str().cmp(key)) else {
+            return Ok(None);
+        };
+
+        let removed = entries.remove(i);
+        Node::Leaf { entries, next }.write(pool.fetch_page(page_id)?);
+        Ok(Some(removed))
     }
 
-    /// Get all index entries
-    #[must_use]
-    pub fn entries(&self) -> &HashMap<String, IndexEntry> {
-        &self.entries
+    /// Iterate over the entries with keys in the given range, in key order
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the first leaf cannot be read;
+    /// the iterator returns errors for the leaves after it.
+    pub fn range<'a>(
+        &self,
+        pool: &'a mut BufferPool,
+        start: Bound<&str>,
+        end: Bound<&str>,
+    ) -> StorageResult<IndexRange<'a>> {
+        let first = match start {
+            Bound::Included(key) | Bound::Excluded(key) => Some(key),
+            Bound::Unbounded => None,
+        };
+        let (_, entries, next) = self.find_leaf(pool, first)?;
+
+        let entries = entries
+            .into_iter()
+            .filter(|entry| match start {
+                Bound::Included(key) => entry.key.as_str() >= key,
+                Bound::Excluded(key) => entry.key.as_str() > key,
+                Bound::Unbounded => true,
+            })
+            .collect();
+
+        Ok(IndexRange {
+            pool,
+            entries,
+            next,
+            end: match end {
+                Bound::Included(key) => Bound::Included(key.to_string()),
+                Bound::Excluded(key) => Bound::Excluded(key.to_string()),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+        })
     }
 
-    /// Get the number of entries in the index
-    #[must_use]
-    pub fn len(&self) -> usize {
-        self.entries.len()
+    /// Iterate over all entries in key order
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the first leaf cannot be read.
+    pub fn entries<'a>(&self, pool: &'a mut BufferPool) -> StorageResult<IndexRange<'a>> {
+        self.range(pool, Bound::Unbounded, Bound::Unbounded)
     }
 
-    /// Check if the index is empty
-    #[must_use]
-    pub fn is_empty(&self) -> bool {
-        self.entries.is_empty()
-    }
-
-    /// Clear all entries from the index
-    pub fn clear(&mut self) {
-        self.entries.clear();
+    /// Get the number of entries in the index, by reading all leaves
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn len(&self, pool: &mut BufferPool) -> StorageResult<usize> {
+        let mut len = 0;
+        for entry in self.entries(pool)? {
+            entry?;
+            len += 1;
+        }
+        Ok(len)
     }
 
-    /// Get entries that are stored on a specific page
-    #[must_use]
-    pub fn entries_on_page(&self, page_id: u64) -> Vec<&IndexEntry> {
-        self.entries
-            .values()
-            .filter(|entry| entry.page_id == page_id)
-            .collect()
+    /// Check if the index is empty
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn is_empty(&self, pool: &mut BufferPool) -> StorageResult<bool> {
+        Ok(self.entries(pool)?.next().transpose()?.is_none())
     }
 
-    /// Find pages that contain data (for iteration or compaction)
-    #[must_use]
-    pub fn used_pages(&self) -> Vec<u64> {
-        let mut pages: Vec<u64> = self.entries.values().map(|entry| entry.page_id).collect();
-        pages.sort_unstable();
-        pages.dedup();
-        pages
+    /// Clear all entries from the index, freeing its pages
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read or freed.
+    pub fn clear(&mut self, pool: &mut BufferPool) -> StorageResult<()> {
+        for page_id in self.pages(pool)? {
+            pool.free_page(page_id)?;
+        }
+        self.create_root(pool)
     }
 
     /// Get statistics about the index
-    #[must_use]
-    pub fn stats(&self) -> IndexStats {
-        if self.entries.is_empty() {
-            return IndexStats {
-                entry_count: 0,
-                page_count: 0,
-                total_data_size: 0,
-                average_key_length: 0.0,
-                average_value_size: 0.0,
-                max_value_size: 0,
-                min_value_size: 0,
-                average_entries_per_page: 0.0,
-            };
-        }
-
-        // Use a HashSet to count unique pages efficiently
-        let mut unique_pages = std::collections::HashSet::new();
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn stats(&self, pool: &mut BufferPool) -> StorageResult<IndexStats> {
+        let index_pages = self.pages(pool)?.len();
+
+        let mut unique_pages = HashSet::new();
+        let mut entry_count = 0;
         let mut total_key_length = 0;
         let mut total_data_size = 0;
-        let mut min_value_size = u16::MAX;
+        let mut min_value_size = u32::MAX;
         let mut max_value_size = 0;
 
-        for entry in self.entries.values() {
+        for entry in self.entries(pool)? {
+            let entry = entry?;
             unique_pages.insert(entry.page_id);
+            entry_count += 1;
             total_key_length += entry.key.len();
             total_data_size += entry.size as usize;
             min_value_size = min_value_size.min(entry.size);
             max_value_size = max_value_size.max(entry.size);
         }
 
-        let entry_count = self.entries.len();
+        if entry_count == 0 {
+            return Ok(IndexStats {
+                index_pages,
+                ..IndexStats::default()
+            });
+        }
+
         let page_count = unique_pages.len();
 
         #[allow(clippy::cast_precision_loss)]
-        IndexStats {
+        Ok(IndexStats {
             entry_count,
             page_count,
+            index_pages,
             total_data_size,
             average_key_length: total_key_length as f64 / entry_count as f64,
             average_value_size: total_data_size as f64 / entry_count as f64,
             max_value_size,
             min_value_size,
-            average_entries_per_page: if page_count > 0 {
-                entry_count as f64 / page_count as f64
-            } else {
-                0.0
-            },
-        }
+            average_entries_per_page: entry_count as f64 / page_count as f64,
+        })
     }
 
     /// Validate the index for consistency
-    #[must_use]
-    pub fn validate(&self) -> Vec<String> {
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if index pages cannot be read.
+    pub fn validate(&self, pool: &mut BufferPool) -> StorageResult<Vec<String>> {
         let mut errors = Vec::new();
+        let mut previous: Option<String> = None;
+        let mut records = std::collections::HashMap::new();
+
+        for entry in self.entries(pool)? {
+            let entry = entry?;
+            if previous
+                .as_ref()
+                .is_some_and(|previous| *previous >= entry.key)
+            {
+                errors.push(format!("Key {} is out of order", entry.key));
+            }
 
-        // Check for overlapping entries on the same page
-        let mut page_entries: HashMap<u64, Vec<&IndexEntry>> = HashMap::new();
-        for entry in self.entries.values() {
-            page_entries.entry(entry.page_id).or_default().push(entry);
+            // Check for entries pointing at the same record
+            if let Some(other) = records.insert((entry.page_id, entry.slot), entry.key.clone()) {
+                errors.push(format!(
+                    "Entries on page {} share slot {}: {} and {}",
+                    entry.page_id, entry.slot, other, entry.key
+                ));
+            }
+            previous = Some(entry.key);
         }
 
-        for (page_id, entries) in page_entries {
-            for i in 0..entries.len() {
-                for j in i + 1..entries.len() {
-                    if entries[i].overlaps_with(entries[j]) {
-                        errors.push(format!(
-                            "Overlapping entries on page {}: {} and {}",
-                            page_id, entries[i].key, entries[j].key
-                        ));
+        Ok(errors)
+    }
+
+    /// Insert `entry` into the subtree at `page_id`; returns the entry it
+    /// replaced, and the separator and page of a new right sibling if the node split
+    fn insert_into(
+        pool: &mut BufferPool,
+        page_id: u64,
+        entry: IndexEntry,
+    ) -> StorageResult<(Option<IndexEntry>, Option<Split>)> {
+        let mut node = Node::read(pool.fetch_page(page_id)?)?;
+
+        let old = match &mut node {
+            Node::Leaf { entries, .. } => {
+                match entries.binary_search_by(|existing| existing.key.cmp(&entry.key)) {
+                    Ok(i) => Some(std::mem::replace(&mut entries[i], entry)),
+                    Err(i) => {
+                        entries.insert(i, entry);
+                        None
                     }
                 }
             }
+            Node::Internal {
+                first_child,
+                children,
+            } => {
+                let child = child_for(*first_child, children, &entry.key);
+                let (old, split) = Self::insert_into(pool, child, entry)?;
+                let Some((separator, right)) = split else {
+                    return Ok((old, None));
+                };
+                let i = children.partition_point(|(key, _)| *key < separator);
+                children.insert(i, (separator, right));
+                old
+            }
+        };
+
+        if node.encoded_len() <= PAGE_SIZE as usize {
+            node.write(pool.fetch_page(page_id)?);
+            return Ok((old, None));
+        }
+
+        let right_id = pool.new_page()?.id;
+        let (right, separator) = node.split(right_id);
+        right.write(pool.fetch_page(right_id)?);
+        node.write(pool.fetch_page(page_id)?);
+        Ok((old, Some((separator, right_id))))
+    }
+
+    /// Find the leaf that holds `key`, or the first leaf; returns its page,
+    /// entries and next leaf
+    fn find_leaf(
+        &self,
+        pool: &mut BufferPool,
+        key: Option<&str>,
+    ) -> StorageResult<(u64, Vec<IndexEntry>, u64)> {
+        let mut page_id = self.root;
+        loop {
+            match Node::read(pool.fetch_page(page_id)?)? {
+                Node::Leaf { entries, next } => return Ok((page_id, entries, next)),
+                Node::Internal {
+                    first_child,
+                    children,
+                } => {
+                    page_id = match key {
+                        Some(key) => child_for(first_child, &children, key),
+                        None => first_child,
+                    };
+                }
+            }
+        }
+    }
+
+    /// All pages of the tree
+    fn pages(&self, pool: &mut BufferPool) -> StorageResult<Vec<u64>> {
+        let mut pages = Vec::new();
+        let mut pending = vec![self.root];
+        while let Some(page_id) = pending.pop() {
+            pages.push(page_id);
+            if let Node::Internal {
+                first_child,
+                children,
+            } = Node::read(pool.fetch_page(page_id)?)?
+            {
+                pending.push(first_child);
+                pending.extend(children.into_iter().map(|(_, child)| child));
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Start a new tree with an empty leaf as its root
+    fn create_root(&mut self, pool: &mut BufferPool) -> StorageResult<()> {
+        let root = pool.new_page()?;
+        Node::Leaf {
+            entries: Vec::new(),
+            next: 0,
         }
+        .write(root);
+        let root = root.id;
+        self.set_root(pool, root)
+    }
 
-        errors
+    /// Make `root` the root node and record it in the file header
+    fn set_root(&mut self, pool: &mut BufferPool, root: u64) -> StorageResult<()> {
+        pool.disk_manager_mut()
+            .ok_or_else(|| {
+                StorageError::Internal(
+                    "The index needs a buffer pool with a disk manager".to_string(),
+                )
+            })?
+            .header_mut()
+            .set_index_page_id(root);
+        self.root = root;
+        Ok(())
+    }
+}
+
+/// Child of an inner node that covers `key`
+fn child_for(first_child: u64, children: &[(String, u64)], key: &str) -> u64 {
+    match children.partition_point(|(separator, _)| separator.as_str() <= key) {
+        0 => first_child,
+        i => children[i - 1].1,
+    }
+}
+
+/// Lengths within a node page always fit in a `u16`
+fn to_u16(value: usize) -> u16 {
+    u16::try_from(value).expect("index node lengths fit in u16")
+}
+
+/// Entries of an [`Index`] in key order, read leaf by leaf
+pub struct IndexRange<'a> {
+    pool: &'a mut BufferPool,
+    /// Entries of the current leaf not returned yet
+    entries: VecDeque<IndexEntry>,
+    /// Leaf after the current one, or 0
+    next: u64,
+    /// Where the range ends
+    end: Bound<String>,
+}
+
+impl Iterator for IndexRange<'_> {
+    type Item = StorageResult<IndexEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.entries.is_empty() {
+            if self.next == 0 {
+                return None;
+            }
+            match self
+                .pool
+                .fetch_page(self.next)
+                .and_then(|page| Node::read(page))
+            {
+                Ok(Node::Leaf { entries, next }) => {
+                    self.entries = entries.into();
+                    self.next = next;
+                }
+                Ok(Node::Internal { .. }) => {
+                    let page_id = std::mem::take(&mut self.next);
+                    return Some(Err(StorageError::Internal(format!(
+                        "Index page {page_id} is linked as a leaf"
+                    ))));
+                }
+                Err(e) => {
+                    self.next = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let entry = self.entries.pop_front()?;
+        let in_range = match &self.end {
+            Bound::Included(end) => entry.key <= *end,
+            Bound::Excluded(end) => entry.key < *end,
+            Bound::Unbounded => true,
+        };
+        if in_range {
+            Some(Ok(entry))
+        } else {
+            self.entries.clear();
+            self.next = 0;
+            None
+        }
     }
 }
 
 /// Statistics about the index
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct IndexStats {
     /// Number of entries in the index
     pub entry_count: usize,
     /// Number of pages that contain data
     pub page_count: usize,
+    /// Number of pages the index itself takes up
+    pub index_pages: usize,
     /// Total size of all stored data in bytes
     pub total_data_size: usize,
     /// Average length of keys in characters
@@ -243,9 +750,9 @@ pub struct IndexStats {
     /// Average size of stored values in bytes
     pub average_value_size: f64,
     /// Maximum value size in bytes
-    pub max_value_size: u16,
+    pub max_value_size: u32,
     /// Minimum value size in bytes
-    pub min_value_size: u16,
+    pub min_value_size: u32,
     /// Average number of entries per page
     pub average_entries_per_page: f64,
 }
@@ -253,257 +760,267 @@ pub struct IndexStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::disk::DiskManager;
+    use std::path::Path;
+
+    fn open_pool(path: &Path, capacity: usize) -> BufferPool {
+        BufferPool::new(capacity).with_disk_manager(DiskManager::open(path).unwrap())
+    }
+
+    fn entry(key: &str, page_id: u64, slot: u16, size: u32) -> IndexEntry {
+        IndexEntry::new(key.to_string(), page_id, slot, size)
+    }
+
+    fn keys(range: IndexRange<'_>) -> Vec<String> {
+        range.map(|entry| entry.unwrap().key).collect()
+    }
 
     #[test]
     fn test_index_entry_new() {
         let entry = IndexEntry::new("test_key".to_string(), 1, 100, 50);
         assert_eq!(entry.key, "test_key");
         assert_eq!(entry.page_id, 1);
-        assert_eq!(entry.offset, 100);
+        assert_eq!(entry.slot, 100);
         assert_eq!(entry.size, 50);
     }
 
     #[test]
-    fn test_index_entry_end_offset() {
-        let entry = IndexEntry::new("test".to_string(), 1, 100, 50);
-        assert_eq!(entry.end_offset(), 150);
-    }
-
-    #[test]
-    fn test_index_entry_end_offset_overflow() {
-        let entry = IndexEntry::new("test".to_string(), 1, u16::MAX - 10, 20);
-        assert_eq!(entry.end_offset(), u16::MAX);
+    fn test_index_entry_large_value() {
+        let entry = IndexEntry::new("test".to_string(), 1, 0, 1_048_576);
+        assert_eq!(entry.size, 1_048_576);
     }
 
     #[test]
-    fn test_index_entry_overlaps_with_different_pages() {
+    fn test_index_entry_same_record_different_pages() {
         let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
         let entry2 = IndexEntry::new("key2".to_string(), 2, 100, 50);
-        assert!(!entry1.overlaps_with(&entry2));
+        assert!(!entry1.same_record(&entry2));
     }
 
     #[test]
-    fn test_index_entry_overlaps_with_same_page_no_overlap() {
+    fn test_index_entry_same_record_different_slots() {
         let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 150, 50);
-        assert!(!entry1.overlaps_with(&entry2));
+        let entry2 = IndexEntry::new("key2".to_string(), 1, 101, 50);
+        assert!(!entry1.same_record(&entry2));
     }
 
     #[test]
-    fn test_index_entry_overlaps_with_same_page_overlap() {
+    fn test_index_entry_same_record() {
         let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 120, 50);
-        assert!(entry1.overlaps_with(&entry2));
-    }
-
-    #[test]
-    fn test_index_entry_overlaps_with_adjacent_entries() {
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 150, 50);
-        assert!(!entry1.overlaps_with(&entry2));
+        let entry2 = IndexEntry::new("key2".to_string(), 1, 100, 70);
+        assert!(entry1.same_record(&entry2));
     }
 
     #[test]
     fn test_index_new() {
-        let index = Index::new();
-        assert!(index.is_empty());
-        assert_eq!(index.len(), 0);
-    }
-
-    #[test]
-    fn test_index_with_capacity() {
-        let index = Index::with_capacity(10);
-        assert!(index.is_empty());
-        assert_eq!(index.len(), 0);
-    }
-
-    #[test]
-    fn test_index_insert_and_get() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test_key".to_string(), 1, 100, 50);
-
-        assert!(index.insert(entry.clone()).is_none());
-        assert_eq!(index.len(), 1);
-        assert!(!index.is_empty());
-
-        let retrieved = index.get("test_key").unwrap();
-        assert_eq!(retrieved.key, "test_key");
-        assert_eq!(retrieved.page_id, 1);
-        assert_eq!(retrieved.offset, 100);
-        assert_eq!(retrieved.size, 50);
-    }
-
-    #[test]
-    fn test_index_insert_duplicate_key() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("test_key".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("test_key".to_string(), 2, 200, 75);
-
-        assert!(index.insert(entry1.clone()).is_none());
-        let old_entry = index.insert(entry2.clone()).unwrap();
-
-        assert_eq!(old_entry.page_id, 1);
-        assert_eq!(index.get("test_key").unwrap().page_id, 2);
-    }
-
-    #[test]
-    fn test_index_get_nonexistent() {
-        let index = Index::new();
-        assert!(index.get("nonexistent").is_none());
-    }
-
-    #[test]
-    fn test_index_contains_key() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test_key".to_string(), 1, 100, 50);
-
-        assert!(!index.contains_key("test_key"));
-        index.insert(entry);
-        assert!(index.contains_key("test_key"));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let index = Index::open(&mut pool).unwrap();
+
+        assert!(index.is_empty(&mut pool).unwrap());
+        assert_eq!(index.len(&mut pool).unwrap(), 0);
+        assert_eq!(
+            pool.disk_manager().unwrap().header().index_page_id(),
+            index.root_page_id()
+        );
     }
 
     #[test]
-    fn test_index_remove() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test_key".to_string(), 1, 100, 50);
-
-        index.insert(entry.clone());
-        assert!(index.contains_key("test_key"));
-
-        let removed = index.remove("test_key").unwrap();
-        assert_eq!(removed.key, "test_key");
-        assert!(!index.contains_key("test_key"));
-        assert!(index.is_empty());
+    fn test_index_needs_a_disk_manager() {
+        let mut pool = BufferPool::new(8);
+        assert!(Index::open(&mut pool).is_err());
     }
 
     #[test]
-    fn test_index_remove_nonexistent() {
-        let mut index = Index::new();
-        assert!(index.remove("nonexistent").is_none());
+    fn test_index_insert_get_remove() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let mut index = Index::open(&mut pool).unwrap();
+
+        assert!(
+            index
+                .insert(&mut pool, entry("key", 1, 2, 3))
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            index.get(&mut pool, "key").unwrap(),
+            Some(entry("key", 1, 2, 3))
+        );
+        assert!(index.contains_key(&mut pool, "key").unwrap());
+        assert!(!index.contains_key(&mut pool, "other").unwrap());
+
+        // Inserting an existing key replaces its entry
+        assert_eq!(
+            index.insert(&mut pool, entry("key", 4, 5, 6)).unwrap(),
+            Some(entry("key", 1, 2, 3))
+        );
+        assert_eq!(index.len(&mut pool).unwrap(), 1);
+
+        assert_eq!(
+            index.remove(&mut pool, "key").unwrap(),
+            Some(entry("key", 4, 5, 6))
+        );
+        assert_eq!(index.remove(&mut pool, "key").unwrap(), None);
+        assert!(index.get(&mut pool, "key").unwrap().is_none());
     }
 
     #[test]
-    fn test_index_keys() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 2, 200, 75);
-
-        index.insert(entry1);
-        index.insert(entry2);
-
-        let keys: Vec<_> = index.keys().collect();
-        assert_eq!(keys.len(), 2);
-        assert!(keys.contains(&"key1"));
-        assert!(keys.contains(&"key2"));
+    fn test_index_splits_and_stays_ordered() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 4);
+        let mut index = Index::open(&mut pool).unwrap();
+
+        // Inserted out of order, with some long keys, through a small pool
+        let mut expected: Vec<String> = (0..3000u32)
+            .map(|i| {
+                let n = i.wrapping_mul(7919) % 3000;
+                if n % 100 == 0 {
+                    format!("{n:05}{}", "x".repeat(900))
+                } else {
+                    format!("{n:05}")
+                }
+            })
+            .collect();
+        for (i, key) in expected.iter().enumerate() {
+            let slot = u16::try_from(i % 100).unwrap();
+            index
+                .insert(&mut pool, entry(key, 1 + i as u64, slot, 10))
+                .unwrap();
+        }
+        expected.sort();
+
+        let root = index.root_page_id();
+        assert!(matches!(
+            Node::read(pool.fetch_page(root).unwrap()).unwrap(),
+            Node::Internal { .. }
+        ));
+        assert_eq!(keys(index.entries(&mut pool).unwrap()), expected);
+        assert!(index.validate(&mut pool).unwrap().is_empty());
+
+        for key in &expected {
+            assert_eq!(index.get(&mut pool, key).unwrap().unwrap().key, *key);
+        }
     }
 
     #[test]
-    fn test_index_entries() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test_key".to_string(), 1, 100, 50);
-
-        index.insert(entry.clone());
+    fn test_index_range() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let mut index = Index::open(&mut pool).unwrap();
+
+        for i in 0..1000u16 {
+            index
+                .insert(&mut pool, entry(&format!("key{i:04}"), 1, i, 0))
+                .unwrap();
+        }
 
-        let entries = index.entries();
-        assert_eq!(entries.len(), 1);
-        assert!(entries.contains_key("test_key"));
+        let range = index
+            .range(
+                &mut pool,
+                Bound::Included("key0100"),
+                Bound::Excluded("key0103"),
+            )
+            .unwrap();
+        assert_eq!(keys(range), ["key0100", "key0101", "key0102"]);
+
+        let range = index
+            .range(&mut pool, Bound::Excluded("key0997"), Bound::Unbounded)
+            .unwrap();
+        assert_eq!(keys(range), ["key0998", "key0999"]);
+
+        let range = index
+            .range(&mut pool, Bound::Unbounded, Bound::Included("key0001"))
+            .unwrap();
+        assert_eq!(keys(range), ["key0000", "key0001"]);
+
+        let range = index
+            .range(&mut pool, Bound::Included("nope"), Bound::Unbounded)
+            .unwrap();
+        assert!(keys(range).is_empty());
     }
 
     #[test]
-    fn test_index_clear() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test_key".to_string(), 1, 100, 50);
-
-        index.insert(entry);
-        assert!(!index.is_empty());
-
-        index.clear();
-        assert!(index.is_empty());
-        assert_eq!(index.len(), 0);
+    fn test_index_reopens_without_rebuild() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.db");
+
+        let mut pool = open_pool(&path, 8);
+        let mut index = Index::open(&mut pool).unwrap();
+        for i in 0..2000u16 {
+            index
+                .insert(&mut pool, entry(&format!("key{i:04}"), 1, i, 0))
+                .unwrap();
+        }
+        for i in (0..2000u16).step_by(2) {
+            index.remove(&mut pool, &format!("key{i:04}")).unwrap();
+        }
+        let root = index.root_page_id();
+        pool.flush_dirty_pages().unwrap();
+        drop(pool);
+
+        let mut pool = open_pool(&path, 8);
+        let index = Index::open(&mut pool).unwrap();
+        assert_eq!(index.root_page_id(), root);
+        assert_eq!(index.len(&mut pool).unwrap(), 1000);
+        assert_eq!(
+            index.get(&mut pool, "key1001").unwrap(),
+            Some(entry("key1001", 1, 1001, 0))
+        );
+        assert!(index.get(&mut pool, "key1000").unwrap().is_none());
     }
 
     #[test]
-    fn test_index_entries_on_page() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 200, 75);
-        let entry3 = IndexEntry::new("key3".to_string(), 2, 100, 50);
-
-        index.insert(entry1);
-        index.insert(entry2);
-        index.insert(entry3);
-
-        let page1_entries = index.entries_on_page(1);
-        assert_eq!(page1_entries.len(), 2);
-
-        let page2_entries = index.entries_on_page(2);
-        assert_eq!(page2_entries.len(), 1);
-
-        let page3_entries = index.entries_on_page(3);
-        assert_eq!(page3_entries.len(), 0);
+    fn test_index_clear_frees_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let mut index = Index::open(&mut pool).unwrap();
+
+        for i in 0..500u16 {
+            index
+                .insert(&mut pool, entry(&format!("{i:0>200}"), 1, i, 0))
+                .unwrap();
+        }
+        let pages = index.stats(&mut pool).unwrap().index_pages;
+        assert!(pages > 1);
+
+        index.clear(&mut pool).unwrap();
+        assert!(index.is_empty(&mut pool).unwrap());
+        let free = pool
+            .disk_manager()
+            .unwrap()
+            .page_manager()
+            .free_page_count();
+        assert_eq!(free, pages - 1);
     }
 
     #[test]
-    fn test_index_used_pages() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 3, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 200, 75);
-        let entry3 = IndexEntry::new("key3".to_string(), 3, 300, 25);
-        let entry4 = IndexEntry::new("key4".to_string(), 2, 100, 50);
-
-        index.insert(entry1);
-        index.insert(entry2);
-        index.insert(entry3);
-        index.insert(entry4);
-
-        let used_pages = index.used_pages();
-        assert_eq!(used_pages, vec![1, 2, 3]);
+    fn test_index_rejects_long_keys() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let mut index = Index::open(&mut pool).unwrap();
+
+        let key = "k".repeat(MAX_KEY_LEN + 1);
+        assert!(index.insert(&mut pool, entry(&key, 1, 0, 0)).is_err());
+        let key = "k".repeat(MAX_KEY_LEN);
+        assert!(index.insert(&mut pool, entry(&key, 1, 0, 0)).is_ok());
     }
 
     #[test]
-    fn test_index_stats_empty() {
-        let index = Index::new();
-        let stats = index.stats();
+    fn test_index_stats() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let mut index = Index::open(&mut pool).unwrap();
 
+        let stats = index.stats(&mut pool).unwrap();
         assert_eq!(stats.entry_count, 0);
-        assert_eq!(stats.page_count, 0);
-        assert_eq!(stats.total_data_size, 0);
-        assert!((stats.average_key_length - 0.0).abs() < f64::EPSILON);
-        assert!((stats.average_value_size - 0.0).abs() < f64::EPSILON);
-        assert_eq!(stats.max_value_size, 0);
-        assert_eq!(stats.min_value_size, 0);
-        assert!((stats.average_entries_per_page - 0.0).abs() < f64::EPSILON);
-    }
+        assert_eq!(stats.index_pages, 1);
 
-    #[test]
-    fn test_index_stats_single_entry() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test".to_string(), 1, 100, 50);
-        index.insert(entry);
-
-        let stats = index.stats();
-        assert_eq!(stats.entry_count, 1);
-        assert_eq!(stats.page_count, 1);
-        assert_eq!(stats.total_data_size, 50);
-        assert!((stats.average_key_length - 4.0).abs() < f64::EPSILON);
-        assert!((stats.average_value_size - 50.0).abs() < f64::EPSILON);
-        assert_eq!(stats.max_value_size, 50);
-        assert_eq!(stats.min_value_size, 50);
-        assert!((stats.average_entries_per_page - 1.0).abs() < f64::EPSILON);
-    }
+        index.insert(&mut pool, entry("key1", 1, 0, 50)).unwrap();
+        index.insert(&mut pool, entry("key22", 1, 1, 75)).unwrap();
+        index.insert(&mut pool, entry("key333", 2, 0, 25)).unwrap();
 
-    #[test]
-    fn test_index_stats_multiple_entries() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key22".to_string(), 1, 200, 75);
-        let entry3 = IndexEntry::new("key333".to_string(), 2, 100, 25);
-
-        index.insert(entry1);
-        index.insert(entry2);
-        index.insert(entry3);
-
-        let stats = index.stats();
+        let stats = index.stats(&mut pool).unwrap();
         assert_eq!(stats.entry_count, 3);
         assert_eq!(stats.page_count, 2);
         assert_eq!(stats.total_data_size, 150);
@@ -515,87 +1032,20 @@ mod tests {
     }
 
     #[test]
-    fn test_index_validate_no_errors() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 200, 50);
-        let entry3 = IndexEntry::new("key3".to_string(), 2, 100, 50);
-
-        index.insert(entry1);
-        index.insert(entry2);
-        index.insert(entry3);
-
-        let errors = index.validate();
-        assert!(errors.is_empty());
-    }
-
-    #[test]
-    fn test_index_validate_overlapping_entries() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 120, 50);
+    fn test_index_validate_shared_records() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut pool = open_pool(&temp_dir.path().join("data.db"), 8);
+        let mut index = Index::open(&mut pool).unwrap();
 
-        index.insert(entry1);
-        index.insert(entry2);
+        index.insert(&mut pool, entry("key1", 1, 100, 50)).unwrap();
+        index.insert(&mut pool, entry("key2", 1, 101, 50)).unwrap();
+        assert!(index.validate(&mut pool).unwrap().is_empty());
 
-        let errors = index.validate();
+        index.insert(&mut pool, entry("key3", 1, 100, 50)).unwrap();
+        let errors = index.validate(&mut pool).unwrap();
         assert_eq!(errors.len(), 1);
-        assert!(errors[0].contains("Overlapping entries on page 1"));
+        assert!(errors[0].contains("Entries on page 1 share slot 100"));
         assert!(errors[0].contains("key1"));
-        assert!(errors[0].contains("key2"));
-    }
-
-    #[test]
-    fn test_index_validate_multiple_overlapping_entries() {
-        let mut index = Index::new();
-        let entry1 = IndexEntry::new("key1".to_string(), 1, 100, 50);
-        let entry2 = IndexEntry::new("key2".to_string(), 1, 120, 50);
-        let entry3 = IndexEntry::new("key3".to_string(), 1, 140, 50);
-
-        index.insert(entry1);
-        index.insert(entry2);
-        index.insert(entry3);
-
-        let errors = index.validate();
-        assert!(errors.len() >= 2);
-    }
-
-    #[test]
-    fn test_index_validate_empty_index() {
-        let index = Index::new();
-        let errors = index.validate();
-        assert!(errors.is_empty());
-    }
-
-    #[test]
-    fn test_index_entry_clone() {
-        let entry1 = IndexEntry::new("test".to_string(), 1, 100, 50);
-        let entry2 = entry1.clone();
-
-        assert_eq!(entry1.key, entry2.key);
-        assert_eq!(entry1.page_id, entry2.page_id);
-        assert_eq!(entry1.offset, entry2.offset);
-        assert_eq!(entry1.size, entry2.size);
-    }
-
-    #[test]
-    fn test_index_stats_clone() {
-        let mut index = Index::new();
-        let entry = IndexEntry::new("test".to_string(), 1, 100, 50);
-        index.insert(entry);
-
-        let stats1 = index.stats();
-        let stats2 = stats1.clone();
-
-        assert_eq!(stats1.entry_count, stats2.entry_count);
-        assert_eq!(stats1.page_count, stats2.page_count);
-        assert_eq!(stats1.total_data_size, stats2.total_data_size);
-    }
-
-    #[test]
-    fn test_index_default() {
-        let index = Index::default();
-        assert!(index.is_empty());
-        assert_eq!(index.len(), 0);
+        assert!(errors[0].contains("key3"));
     }
 }
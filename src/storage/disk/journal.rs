@@ -0,0 +1,138 @@
+//! Rollback journal that makes the page writes between two syncs atomic
+//!
+//! Before a page that existed at the last sync is overwritten, its previous
+//! image is appended to the journal and the journal is synced. The journal is
+//! emptied once the next sync has made the new pages and header durable. A
+//! database file opened with a non-empty journal was interrupted between two
+//! syncs, and writing the saved images back returns it to the last sync.
+//!
+//! Each entry is the page ID (`u64`), a CRC32C of the ID and the image (`u32`),
+//! and the page image. An entry that was torn while being written was never
+//! synced, so the page it saves was not overwritten yet and the entry, along
+//! with anything after it, is ignored.
+
+use super::disk_manager::{io_error, read_exact_at, write_all_at};
+use super::header::PAGE_SIZE;
+use crate::storage::error::StorageResult;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Bytes in front of each page image
+const ENTRY_HEADER_LEN: usize = 12;
+
+/// Bytes of a whole entry
+const ENTRY_LEN: usize = ENTRY_HEADER_LEN + PAGE_SIZE as usize;
+
+/// Previous images of the pages overwritten since the last sync
+#[derive(Debug)]
+pub(super) struct Journal {
+    /// Open journal file
+    file: File,
+    /// Path of the journal file
+    path: PathBuf,
+    /// Pages whose previous image has been saved
+    saved: HashSet<u64>,
+    /// Length of the journal file
+    len: u64,
+}
+
+impl Journal {
+    /// Open the journal of the database file at `database`, creating it if needed
+    pub(super) fn open(database: &Path) -> StorageResult<Self> {
+        let mut path = database.as_os_str().to_owned();
+        path.push("-journal");
+        let path = PathBuf::from(path);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| io_error("open", &path, &e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| io_error("read metadata of", &path, &e))?
+            .len();
+
+        Ok(Self {
+            file,
+            path,
+            saved: HashSet::new(),
+            len,
+        })
+    }
+
+    /// Whether the previous image of `page_id` has been saved
+    pub(super) fn contains(&self, page_id: u64) -> bool {
+        self.saved.contains(&page_id)
+    }
+
+    /// Append previous page images and make them durable
+    pub(super) fn save(&mut self, images: &[(u64, Vec<u8>)]) -> StorageResult<()> {
+        if images.is_empty() {
+            return Ok(());
+        }
+
+        let mut bytes = Vec::with_capacity(images.len() * ENTRY_LEN);
+        for (page_id, image) in images {
+            let id = page_id.to_le_bytes();
+            bytes.extend_from_slice(&id);
+            bytes.extend_from_slice(
+                &crc32c::crc32c_append(crc32c::crc32c(&id), image).to_le_bytes(),
+            );
+            bytes.extend_from_slice(image);
+        }
+
+        write_all_at(&self.file, &bytes, self.len)
+            .map_err(|e| io_error("write", &self.path, &e))?;
+        self.file
+            .sync_data()
+            .map_err(|e| io_error("sync", &self.path, &e))?;
+
+        self.len += bytes.len() as u64;
+        self.saved
+            .extend(images.iter().map(|(page_id, _)| *page_id));
+        Ok(())
+    }
+
+    /// The saved images, oldest first, up to the first damaged entry
+    pub(super) fn images(&self) -> StorageResult<Vec<(u64, Vec<u8>)>> {
+        let mut images = Vec::new();
+        let mut entry = vec![0; ENTRY_LEN];
+
+        let mut offset = 0;
+        while offset + ENTRY_LEN as u64 <= self.len {
+            read_exact_at(&self.file, &mut entry, offset)
+                .map_err(|e| io_error("read", &self.path, &e))?;
+
+            let (header, image) = entry.split_at(ENTRY_HEADER_LEN);
+            let id: [u8; 8] = header[..8].try_into().unwrap_or_default();
+            let crc = u32::from_le_bytes(header[8..].try_into().unwrap_or_default());
+            if crc32c::crc32c_append(crc32c::crc32c(&id), image) != crc {
+                break;
+            }
+
+            images.push((u64::from_le_bytes(id), image.to_vec()));
+            offset += ENTRY_LEN as u64;
+        }
+
+        Ok(images)
+    }
+
+    /// Empty the journal once the pages it saved are no longer needed
+    pub(super) fn clear(&mut self) -> StorageResult<()> {
+        if self.len > 0 {
+            self.file
+                .set_len(0)
+                .map_err(|e| io_error("truncate", &self.path, &e))?;
+            self.file
+                .sync_data()
+                .map_err(|e| io_error("sync", &self.path, &e))?;
+            self.len = 0;
+        }
+        self.saved.clear();
+        Ok(())
+    }
+}
@@ -1,15 +1,30 @@
 //! Disk-based storage implementation
 //!
 //! This module provides disk-based storage functionality including:
-//! - Page management for efficient disk storage
+//! - Page management for efficient disk storage, with a free-page list kept in the file
 //! - File header management for database files
+//! - Page I/O against the database file
+//! - Records in slotted pages, with overflow pages for large values
+//! - A B+tree index mapping keys to their records
+//! - [`DiskStorage`], the storage engine built from these parts
 
 pub mod buffer;
+pub mod disk_manager;
+mod free_list;
 pub mod header;
+pub mod heap;
 pub mod index;
+mod journal;
 pub mod page;
 /// Page manager for handling disk-based page operations
 pub mod page_manager;
+pub mod storage;
 
-pub use page::Page;
+pub use buffer::BufferPool;
+pub use disk_manager::DiskManager;
+pub use header::FileHeader;
+pub use heap::HeapFile;
+pub use index::{Index, IndexEntry};
+pub use page::{Page, PageKind};
 pub use page_manager::PageManager;
+pub use storage::DiskStorage;
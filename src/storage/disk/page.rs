@@ -2,9 +2,63 @@
 //!
 //! Pages are the fundamental unit of storage in the disk-based engine.
 //! Each page is a fixed-size block that can store data efficiently.
+//!
+//! Data pages use a slotted layout: a page header, then a directory of slots
+//! growing from the front, and records growing from the back towards it.
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 1 | page kind, see [`PageKind`] |
+//! | 1 | 1 | reserved |
+//! | 2 | 2 | number of slots |
+//! | 4 | 2 | start of the record area (0 means the end of the page) |
+//! | 6 | 2 | bytes in the record area freed by deleted records |
+//! | 8 | 4 per slot | record offset and length; offset 0 marks a deleted slot |
+//!
+//! A zeroed page is therefore an empty data page. Slot numbers stay the same
+//! while a record is updated or other records are deleted, so they can be
+//! stored in the index; the records themselves move when the page is compacted.
 
 use super::header::PAGE_SIZE;
 
+/// Bytes at the start of a data page taken up by its header
+pub const PAGE_HEADER_LEN: usize = 8;
+
+/// Bytes each slot takes up in the slot directory
+pub const SLOT_LEN: usize = 4;
+
+const SLOT_COUNT_OFFSET: usize = 2;
+const FREE_END_OFFSET: usize = 4;
+const FRAGMENTED_OFFSET: usize = 6;
+
+/// What a page is used for, as recorded in its first byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    /// Slotted page holding records
+    Data = 0,
+    /// Part of a value too large to be stored in a data page
+    Overflow = 1,
+    /// Leaf node of the index
+    IndexLeaf = 2,
+    /// Inner node of the index
+    IndexInternal = 3,
+    /// Trunk page of the free-page list
+    FreeListTrunk = 4,
+}
+
+impl PageKind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(PageKind::Data),
+            1 => Some(PageKind::Overflow),
+            2 => Some(PageKind::IndexLeaf),
+            3 => Some(PageKind::IndexInternal),
+            4 => Some(PageKind::FreeListTrunk),
+            _ => None,
+        }
+    }
+}
+
 /// A page in the database file
 ///
 /// Pages are fixed-size blocks that store data on disk. Each page has a unique ID
@@ -100,13 +154,207 @@ impl Page {
         Ok(&self.data[offset..offset + length])
     }
 
+    /// What the page is used for, or `None` if its kind byte is unknown
+    #[must_use]
+    pub fn kind(&self) -> Option<PageKind> {
+        PageKind::from_byte(self.data[0])
+    }
+
+    /// Set what the page is used for
+    pub fn set_kind(&mut self, kind: PageKind) {
+        self.data[0] = kind as u8;
+        self.mark_dirty();
+    }
+
+    /// Number of slots in the slot directory, deleted ones included
+    #[must_use]
+    pub fn slot_count(&self) -> u16 {
+        self.read_u16(SLOT_COUNT_OFFSET)
+    }
+
     /// Get the amount of free space in the page
+    ///
+    /// This is the space a data page has left for records and their slots,
+    /// counting the space of deleted records that compaction would reclaim.
     #[must_use]
     pub fn free_space(&self) -> usize {
-        PAGE_SIZE as usize - self.data.len()
+        self.contiguous_free_space() + usize::from(self.read_u16(FRAGMENTED_OFFSET))
+    }
+
+    /// Add a record to a data page, reusing a deleted slot if there is one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record does not fit in the page.
+    pub fn insert_record(&mut self, record: &[u8]) -> Result<u16, String> {
+        let reused = (0..self.slot_count()).find(|&slot| self.slot(slot).0 == 0);
+        let needed = record.len() + if reused.is_some() { 0 } else { SLOT_LEN };
+        if needed > self.free_space() {
+            return Err("Record does not fit in page".to_string());
+        }
+
+        let slot = reused.unwrap_or_else(|| {
+            let slot = self.slot_count();
+            self.write_u16(SLOT_COUNT_OFFSET, slot + 1);
+            slot
+        });
+        self.place_record(slot, record);
+        Ok(slot)
+    }
+
+    /// Get the record in `slot`, or `None` if the slot is deleted or does not exist
+    #[must_use]
+    pub fn record(&self, slot: u16) -> Option<&[u8]> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        match self.slot(slot) {
+            (0, _) => None,
+            (offset, len) => self.data.get(offset..offset + len),
+        }
+    }
+
+    /// Iterate over the records of a data page with their slots
+    pub fn records(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        (0..self.slot_count()).filter_map(|slot| self.record(slot).map(|record| (slot, record)))
+    }
+
+    /// Replace the record in `slot`, keeping its slot number
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the slot holds no record or the new record does not
+    /// fit in the page; the page is left unchanged.
+    pub fn update_record(&mut self, slot: u16, record: &[u8]) -> Result<(), String> {
+        let (offset, len) = self.live_slot(slot)?;
+
+        if record.len() <= len {
+            self.data[offset..offset + record.len()].copy_from_slice(record);
+            self.write_slot(slot, offset, record.len());
+            self.add_fragmented(len - record.len());
+            self.mark_dirty();
+            return Ok(());
+        }
+
+        if record.len() > self.free_space() + len {
+            return Err("Record does not fit in page".to_string());
+        }
+        self.write_slot(slot, 0, 0);
+        self.add_fragmented(len);
+        self.place_record(slot, record);
+        Ok(())
+    }
+
+    /// Delete the record in `slot`, leaving a tombstone so other slots keep their numbers
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the slot holds no record.
+    pub fn delete_record(&mut self, slot: u16) -> Result<(), String> {
+        let (_, len) = self.live_slot(slot)?;
+        self.write_slot(slot, 0, 0);
+        self.add_fragmented(len);
+
+        // Deleted slots at the end of the directory are not referenced by anything
+        let mut slot_count = self.slot_count();
+        while slot_count > 0 && self.slot(slot_count - 1).0 == 0 {
+            slot_count -= 1;
+        }
+        self.write_u16(SLOT_COUNT_OFFSET, slot_count);
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Move the records of a data page together, so all free space is in one piece
+    pub fn compact(&mut self) {
+        let records: Vec<(u16, Vec<u8>)> = self
+            .records()
+            .map(|(slot, record)| (slot, record.to_vec()))
+            .collect();
+
+        let mut free_end = PAGE_SIZE as usize;
+        for (slot, record) in records {
+            free_end -= record.len();
+            self.data[free_end..free_end + record.len()].copy_from_slice(&record);
+            self.write_slot(slot, free_end, record.len());
+        }
+        self.set_free_end(free_end);
+        self.write_u16(FRAGMENTED_OFFSET, 0);
+        self.mark_dirty();
+    }
+
+    /// Write `record` into the record area and point `slot` at it
+    ///
+    /// The caller has checked that the record fits once the page is compacted.
+    fn place_record(&mut self, slot: u16, record: &[u8]) {
+        if record.len() > self.contiguous_free_space() {
+            self.compact();
+        }
+
+        let offset = self.free_end() - record.len();
+        self.data[offset..offset + record.len()].copy_from_slice(record);
+        self.write_slot(slot, offset, record.len());
+        self.set_free_end(offset);
+        self.mark_dirty();
+    }
+
+    fn live_slot(&self, slot: u16) -> Result<(usize, usize), String> {
+        if slot >= self.slot_count() || self.slot(slot).0 == 0 {
+            return Err(format!("Slot {slot} holds no record"));
+        }
+        Ok(self.slot(slot))
+    }
+
+    fn contiguous_free_space(&self) -> usize {
+        let directory_end = PAGE_HEADER_LEN + usize::from(self.slot_count()) * SLOT_LEN;
+        self.free_end().saturating_sub(directory_end)
+    }
+
+    fn free_end(&self) -> usize {
+        match self.read_u16(FREE_END_OFFSET) {
+            0 => PAGE_SIZE as usize,
+            free_end => usize::from(free_end),
+        }
+    }
+
+    fn set_free_end(&mut self, free_end: usize) {
+        // The end of the page is stored as 0, so a zeroed page is empty
+        self.write_u16(FREE_END_OFFSET, u16::try_from(free_end).unwrap_or(0));
+    }
+
+    fn add_fragmented(&mut self, bytes: usize) {
+        let fragmented = usize::from(self.read_u16(FRAGMENTED_OFFSET)) + bytes;
+        self.write_u16(FRAGMENTED_OFFSET, to_u16(fragmented));
+    }
+
+    fn slot(&self, slot: u16) -> (usize, usize) {
+        let at = PAGE_HEADER_LEN + usize::from(slot) * SLOT_LEN;
+        (
+            usize::from(self.read_u16(at)),
+            usize::from(self.read_u16(at + 2)),
+        )
+    }
+
+    fn write_slot(&mut self, slot: u16, offset: usize, len: usize) {
+        let at = PAGE_HEADER_LEN + usize::from(slot) * SLOT_LEN;
+        self.write_u16(at, to_u16(offset));
+        self.write_u16(at + 2, to_u16(len));
+    }
+
+    fn read_u16(&self, at: usize) -> u16 {
+        u16::from_le_bytes([self.data[at], self.data[at + 1]])
+    }
+
+    fn write_u16(&mut self, at: usize, value: u16) {
+        self.data[at..at + 2].copy_from_slice(&value.to_le_bytes());
     }
 }
 
+/// Offsets and lengths within a page always fit in a `u16`
+fn to_u16(value: usize) -> u16 {
+    u16::try_from(value).expect("page offsets fit in u16")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -120,7 +368,7 @@ mod tests {
         assert!(!page.dirty);
         assert!(!page.is_dirty());
         assert_eq!(page.size(), PAGE_SIZE as usize);
-        assert_eq!(page.free_space(), 0);
+        assert_eq!(page.free_space(), PAGE_SIZE as usize - PAGE_HEADER_LEN);
 
         // All data should be zeroed
         assert!(page.data.iter().all(|&x| x == 0));
@@ -279,12 +527,99 @@ mod tests {
 
     #[test]
     fn test_page_free_space() {
-        let page = Page::new(1);
-        assert_eq!(page.free_space(), 0);
+        let mut page = Page::new(1);
+        assert_eq!(page.free_space(), PAGE_SIZE as usize - PAGE_HEADER_LEN);
+
+        page.insert_record(&[1; 100]).unwrap();
+        assert_eq!(
+            page.free_space(),
+            PAGE_SIZE as usize - PAGE_HEADER_LEN - SLOT_LEN - 100
+        );
+    }
+
+    #[test]
+    fn test_slotted_records() {
+        let mut page = Page::new(1);
+        assert_eq!(page.kind(), Some(PageKind::Data));
+
+        let first = page.insert_record(b"first").unwrap();
+        let second = page.insert_record(b"second").unwrap();
+        let empty = page.insert_record(b"").unwrap();
+        assert_eq!((first, second, empty), (0, 1, 2));
+        assert!(page.is_dirty());
+        assert_eq!(page.record(first), Some(&b"first"[..]));
+        assert_eq!(page.record(second), Some(&b"second"[..]));
+        assert_eq!(page.record(empty), Some(&b""[..]));
+        assert_eq!(page.record(3), None);
+
+        // Slots keep their numbers when other records are deleted
+        page.delete_record(first).unwrap();
+        assert_eq!(page.record(first), None);
+        assert_eq!(page.record(second), Some(&b"second"[..]));
+        assert!(page.delete_record(first).is_err());
+
+        // ... and the deleted slot is reused
+        assert_eq!(page.insert_record(b"third").unwrap(), first);
+        assert_eq!(
+            page.records().collect::<Vec<_>>(),
+            vec![(0, &b"third"[..]), (1, &b"second"[..]), (2, &b""[..])]
+        );
+
+        // Deleted slots at the end are dropped
+        page.delete_record(empty).unwrap();
+        page.delete_record(second).unwrap();
+        assert_eq!(page.slot_count(), 1);
+    }
+
+    #[test]
+    fn test_update_record() {
+        let mut page = Page::new(1);
+        let slot = page.insert_record(b"hello world").unwrap();
+        let other = page.insert_record(b"other").unwrap();
+
+        page.update_record(slot, b"hi").unwrap();
+        assert_eq!(page.record(slot), Some(&b"hi"[..]));
+
+        let longer = vec![7; 500];
+        page.update_record(slot, &longer).unwrap();
+        assert_eq!(page.record(slot), Some(&longer[..]));
+        assert_eq!(page.record(other), Some(&b"other"[..]));
+
+        assert!(page.update_record(slot, &[0; PAGE_SIZE as usize]).is_err());
+        assert_eq!(page.record(slot), Some(&longer[..]));
+        assert!(page.update_record(5, b"missing").is_err());
+    }
+
+    #[test]
+    fn test_deleted_space_is_reclaimed() {
+        let mut page = Page::new(1);
+        let record = [9; 1000];
+        let slots: Vec<u16> = (0..4)
+            .map(|_| page.insert_record(&record).unwrap())
+            .collect();
+        assert!(page.insert_record(&record).is_err());
+
+        page.delete_record(slots[1]).unwrap();
+        page.delete_record(slots[2]).unwrap();
+        let free = page.free_space();
+
+        // The new record only fits once the page is compacted
+        let big = [3; 1500];
+        let slot = page.insert_record(&big).unwrap();
+        assert_eq!(page.record(slot), Some(&big[..]));
+        assert_eq!(page.record(slots[0]), Some(&record[..]));
+        assert_eq!(page.record(slots[3]), Some(&record[..]));
+        assert_eq!(page.free_space(), free - big.len());
+    }
+
+    #[test]
+    fn test_page_kind() {
+        let mut page = Page::new(1);
+        page.set_kind(PageKind::Overflow);
+        assert_eq!(page.kind(), Some(PageKind::Overflow));
 
-        let data = vec![1, 2, 3];
-        let page2 = Page::from_data(1, data);
-        assert_eq!(page2.free_space(), 0);
+        page.data[0] = 0xff;
+        assert_eq!(page.kind(), None);
     }
 
     #[test]
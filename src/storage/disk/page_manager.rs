@@ -4,13 +4,15 @@
 //! Pages are the fundamental unit of storage in the disk-based engine.
 //! Each page is a fixed-size block that can store data efficiently.
 
+use std::collections::BTreeSet;
+
 /// Page management utilities
 #[derive(Debug, Default)]
 pub struct PageManager {
     /// Next page ID to allocate
     next_page_id: u64,
-    /// List of free page IDs
-    free_pages: Vec<u64>,
+    /// Free page IDs, in ascending order
+    free_pages: BTreeSet<u64>,
 }
 
 impl PageManager {
@@ -20,7 +22,7 @@ impl PageManager {
         Self {
             // Page 0 is reserved for the header
             next_page_id: 1,
-            free_pages: Vec::new(),
+            free_pages: BTreeSet::new(),
         }
     }
 
@@ -29,14 +31,17 @@ impl PageManager {
     pub fn with_state(next_page_id: u64, free_pages: Vec<u64>) -> Self {
         Self {
             next_page_id,
-            free_pages,
+            free_pages: free_pages.into_iter().collect(),
         }
     }
 
     /// Allocates a new page ID
+    ///
+    /// The highest free page is reused first, so pages at the end of the file
+    /// are taken before those near its start.
     #[must_use]
     pub fn allocate_page(&mut self) -> u64 {
-        if let Some(id) = self.free_pages.pop() {
+        if let Some(id) = self.free_pages.pop_last() {
             return id;
         }
 
@@ -46,22 +51,28 @@ impl PageManager {
     }
 
     /// Frees a page ID
+    ///
+    /// Freeing a page that is already free has no effect.
     pub fn free_page(&mut self, id: u64) {
-        if !self.free_pages.contains(&id) {
-            self.free_pages.push(id);
-            self.free_pages.sort_unstable(); // Keep free pages sorted
-        }
+        self.free_pages.insert(id);
+    }
+
+    /// Whether a page ID is free
+    #[must_use]
+    pub fn is_free(&self, id: u64) -> bool {
+        self.free_pages.contains(&id)
     }
+
     /// Get the next page ID to allocate
     #[must_use]
     pub fn next_page_id(&self) -> u64 {
         self.next_page_id
     }
 
-    /// Get the list of free pages
+    /// Get the free page IDs, in ascending order
     #[must_use]
-    pub fn free_pages(&self) -> &[u64] {
-        &self.free_pages
+    pub fn free_pages(&self) -> Vec<u64> {
+        self.free_pages.iter().copied().collect()
     }
 
     /// Get the total number of pages managed
@@ -244,6 +255,17 @@ mod page_manager_tests {
         assert_eq!(manager.next_page_id(), 6);
     }
 
+    #[test]
+    fn test_page_manager_with_state_sorts_and_dedups() {
+        let mut manager = PageManager::with_state(20, vec![15, 5, 10, 5]);
+
+        assert_eq!(manager.free_pages(), &[5, 10, 15]);
+        assert!(manager.is_free(10));
+        assert!(!manager.is_free(11));
+        assert_eq!(manager.allocate_page(), 15);
+        assert!(!manager.is_free(15));
+    }
+
     #[test]
     fn test_page_manager_page_zero_reserved() {
         let manager = PageManager::new();
@@ -0,0 +1,1449 @@
+//! Storage engine that keeps its data in a database file
+//!
+//! [`DiskStorage`] stores each key and value as a record in the pages of a
+//! database file, found through the B+tree index, so the data it holds is not
+//! limited by memory: only the pages in the buffer pool are.
+//!
+//! Every write is appended to a WAL before it is applied to the pages. A
+//! checkpoint writes the dirty pages back along with the sequence number of the
+//! last WAL entry they reflect, and deletes the WAL segments up to it. On open,
+//! the WAL entries after the checkpoint are replayed.
+
+use super::buffer::BufferPool;
+use super::disk_manager::DiskManager;
+use super::header::PAGE_SIZE;
+use super::heap::HeapFile;
+use super::index::{Index, IndexEntry};
+use crate::storage::engine::{
+    BatchOperation, ScanBatches, Stats, StorageEngine, Value, ValueMetadata, next_version,
+};
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::persistent::RecoveryReport;
+use crate::storage::utils::{expiry_from_ttl, validate_batch, validate_key, validate_value};
+use crate::storage::wal::{PendingWrite, Replaced, UndoLog, WalManager, WalOperation, WalOptions};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Name of the database file in the data directory
+pub const DATABASE_FILE: &str = "zephyrite.db";
+
+/// Name of the WAL in the data directory
+pub const WAL_FILE: &str = "zephyrite.wal";
+
+/// Pages the buffer pool holds unless told otherwise (16 MiB)
+pub const DEFAULT_CACHE_PAGES: usize = 4096;
+
+/// Fewest pages the buffer pool is given
+pub const MIN_CACHE_PAGES: usize = 64;
+
+/// WAL entries after which a write triggers a checkpoint
+const CHECKPOINT_ENTRIES: u64 = 10_000;
+
+/// Index entries read at a time while scanning
+const SCAN_CHUNK: usize = 256;
+
+/// Records a purge examines at most, continuing where the previous one stopped
+const PURGE_CHUNK: usize = 4096;
+
+/// Storage engine that keeps its data in a database file, with a WAL for
+/// durability between checkpoints
+///
+/// Keys stored with a TTL are expired lazily: reads treat them as missing,
+/// while [`StorageEngine::purge_expired`] removes them. Each purge examines up
+/// to [`PURGE_CHUNK`] records, taking up where the previous one stopped and
+/// starting over from the first key once it reaches the last.
+///
+/// The number of live keys is the number of keys in the index minus those
+/// known to have expired. Expiry times are known for the keys written with a
+/// TTL since the storage was opened, and for the records with a TTL read since,
+/// so a key stored with a TTL before then is counted until it is read or purged.
+pub struct DiskStorage {
+    /// Pages and the structures on them, locked for every operation
+    ///
+    /// Readers need the lock too, since reading a page may load it into the
+    /// buffer pool.
+    state: Mutex<DiskState>,
+    /// Write-Ahead Log for the writes since the last checkpoint
+    wal: WalManager,
+    /// Directory holding the database file and the WAL
+    data_dir: PathBuf,
+    /// What recovering from the WAL found when the storage was opened
+    recovery_report: RecoveryReport,
+}
+
+impl DiskStorage {
+    /// Open the disk storage in `data_dir`, creating it if it does not exist
+    ///
+    /// # Errors
+    /// Returns an error if the directory, database file or WAL cannot be
+    /// created or read, or the WAL cannot be replayed.
+    pub fn new(data_dir: impl AsRef<Path>) -> StorageResult<Self> {
+        Self::with_options(data_dir, DEFAULT_CACHE_PAGES, &WalOptions::default())
+    }
+
+    /// Open the disk storage in `data_dir` with a buffer pool of `cache_pages`
+    /// pages and a WAL opened with `wal_options`
+    ///
+    /// The buffer pool gets at least [`MIN_CACHE_PAGES`] pages.
+    ///
+    /// # Errors
+    /// Returns an error if the directory, database file or WAL cannot be
+    /// created or read, or the WAL cannot be replayed.
+    pub fn with_options(
+        data_dir: impl AsRef<Path>,
+        cache_pages: usize,
+        wal_options: &WalOptions,
+    ) -> StorageResult<Self> {
+        let data_dir = data_dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&data_dir).map_err(|e| {
+            StorageError::Internal(format!(
+                "Failed to create data directory {}: {e}",
+                data_dir.display()
+            ))
+        })?;
+
+        let wal = WalManager::open(data_dir.join(WAL_FILE), wal_options)?;
+        Self::with_wal(data_dir, cache_pages, wal)
+    }
+
+    /// Open the disk storage in `data_dir` around its opened WAL
+    fn with_wal(data_dir: PathBuf, cache_pages: usize, wal: WalManager) -> StorageResult<Self> {
+        let mut state = DiskState::open(
+            &data_dir.join(DATABASE_FILE),
+            cache_pages.max(MIN_CACHE_PAGES),
+        )?;
+        let recovery_report = Self::recover(&wal, &mut state)?;
+
+        Ok(Self {
+            state: Mutex::new(state),
+            wal,
+            data_dir,
+            recovery_report,
+        })
+    }
+
+    /// Replay the WAL entries after the last checkpoint onto the pages
+    fn recover(wal: &WalManager, state: &mut DiskState) -> StorageResult<RecoveryReport> {
+        info!("Starting WAL recovery...");
+        let started = Instant::now();
+        let covered = state.checkpoint_sequence;
+        let mut report = RecoveryReport {
+            mode: wal.recovery_mode(),
+            snapshot_sequence_number: (covered > 0).then_some(covered),
+            snapshot_keys: state.key_count,
+            ..RecoveryReport::default()
+        };
+
+        let recovery = wal.recover_entries()?;
+        report.entries_read = recovery.entries.len();
+        report.discarded = recovery.discarded;
+        if !report.discarded.is_empty() {
+            warn!(
+                "Discarded {} damaged WAL records ({} bytes) during recovery",
+                report.discarded.len(),
+                report.bytes_discarded()
+            );
+        }
+
+        let mut entries = recovery.entries;
+        // Entries up to the checkpoint are already in the pages
+        entries.retain(|entry| entry.sequence_number > covered);
+        report.entries_skipped = report.entries_read - entries.len();
+        wal.advance_sequence_number(covered)?;
+
+        for entry in &entries {
+            match state.apply(&entry.operation) {
+                Ok(()) => report.entries_applied += 1,
+                Err(e) => {
+                    report.entries_failed += 1;
+                    warn!(
+                        "Failed to recover operation at sequence {}: {}",
+                        entry.sequence_number, e
+                    );
+                }
+            }
+        }
+
+        if !entries.is_empty() {
+            info!(
+                "Recovered {} of {} WAL entries after checkpoint {}",
+                report.entries_applied,
+                entries.len(),
+                covered
+            );
+            checkpoint(wal, state)?;
+        }
+
+        report.last_sequence_number = wal.current_sequence_number()?;
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+
+    /// Directory holding the database file and the WAL
+    #[must_use]
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// What recovering from the WAL found when the storage was opened
+    #[must_use]
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Write all dirty pages to the database file and delete the WAL segments
+    /// they cover, returning the sequence number of the last entry covered
+    ///
+    /// Checkpoints also run on their own as the WAL or the dirty pages grow,
+    /// and when the storage is dropped.
+    ///
+    /// # Errors
+    /// Returns an error if pages cannot be written or WAL segments cannot be
+    /// deleted.
+    pub fn checkpoint(&self) -> StorageResult<u64> {
+        checkpoint(&self.wal, &mut *self.lock_state()?)
+    }
+
+    /// Store a key-value pair that expires at an absolute point in time
+    ///
+    /// Returns Ok(true) if the key was created, Ok(false) if it was updated.
+    ///
+    /// # Errors
+    /// Returns an error if the key or value is invalid, or if the write cannot
+    /// be logged or stored.
+    pub fn put_with_expiry(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<bool> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let mut state = self.lock_state()?;
+
+        let created = state.version_of(key)?.is_none();
+        let version = state.next_version(key)?;
+        state.put_ops += 1;
+        self.log_put(state, key, value, expires_at, version)?;
+        Ok(created)
+    }
+
+    fn lock_state(&self) -> StorageResult<MutexGuard<'_, DiskState>> {
+        self.state
+            .lock()
+            .map_err(|_| StorageError::Internal("Failed to acquire storage lock".to_string()))
+    }
+
+    /// Append `operation` to the WAL and `apply` it to the pages under the
+    /// lock, then release the lock and wait until the entry is durable
+    ///
+    /// If the entry never becomes durable, `replaced` is put back along with
+    /// what every later write replaced, since their entries are lost too.
+    fn commit<T>(
+        &self,
+        mut state: MutexGuard<'_, DiskState>,
+        operation: WalOperation,
+        replaced: Replaced,
+        apply: impl FnOnce(&mut DiskState) -> StorageResult<T>,
+    ) -> StorageResult<T> {
+        let pending = self.wal.append(operation)?;
+        let result = apply(&mut state);
+
+        self.finish(state, pending, replaced, result.is_ok())?;
+        result
+    }
+
+    /// Record what the write logged as `pending` replaced, release the lock
+    /// and wait until the entry is durable, undoing it if it never is
+    ///
+    /// A checkpoint that is due runs first if the write was `applied`.
+    fn finish(
+        &self,
+        mut state: MutexGuard<'_, DiskState>,
+        pending: PendingWrite,
+        replaced: Replaced,
+        applied: bool,
+    ) -> StorageResult<()> {
+        state.undo.record(&self.wal, pending, replaced)?;
+        if applied {
+            self.checkpoint_if_needed(&mut state, pending.sequence_number);
+        }
+        drop(state);
+
+        if let Err(e) = self.wal.wait_durable(&pending) {
+            self.lock_state()?.undo_lost_writes(&self.wal)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Log and store a put with an explicit version
+    fn log_put(
+        &self,
+        mut state: MutexGuard<'_, DiskState>,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+        version: u64,
+    ) -> StorageResult<()> {
+        let operation = WalOperation::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+            expires_at,
+            version: Some(version),
+        };
+
+        let replaced = state.stored([key])?;
+        self.commit(state, operation, replaced, |state| {
+            state.store(key, value, expires_at, Some(version))
+        })
+    }
+
+    /// Checkpoint once half the buffer pool is dirty or the WAL has grown long
+    ///
+    /// A failed checkpoint leaves the write it follows intact; it is retried
+    /// after the next one.
+    fn checkpoint_if_needed(&self, state: &mut DiskState, sequence_number: u64) {
+        let dirty_pages = state.pool.dirty_page_count();
+        if dirty_pages * 2 < state.pool.capacity()
+            && sequence_number - state.checkpoint_sequence < CHECKPOINT_ENTRIES
+        {
+            return;
+        }
+
+        if let Err(e) = checkpoint(&self.wal, state) {
+            warn!("Checkpoint of {} failed: {}", self.data_dir.display(), e);
+        }
+    }
+}
+
+impl Drop for DiskStorage {
+    fn drop(&mut self) {
+        // Without a final checkpoint the WAL is replayed on the next open
+        if let Ok(state) = self.state.get_mut() {
+            if let Err(e) = checkpoint(&self.wal, state) {
+                warn!("Checkpoint of {} failed: {}", self.data_dir.display(), e);
+            }
+        }
+    }
+}
+
+impl StorageEngine for DiskStorage {
+    fn put(&self, key: &str, value: &[u8]) -> StorageResult<bool> {
+        self.put_with_expiry(key, value, None)
+    }
+
+    fn put_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> StorageResult<bool> {
+        self.put_with_expiry(key, value, Some(expiry_from_ttl(ttl)?))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+    ) -> StorageResult<u64> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let mut state = self.lock_state()?;
+
+        let current = state
+            .version_of(key)?
+            .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))?;
+
+        if current != expected_version {
+            return Err(StorageError::VersionMismatch {
+                key: key.to_string(),
+                expected: expected_version,
+                actual: current,
+            });
+        }
+
+        state.put_ops += 1;
+        self.log_put(state, key, value, None, current + 1)?;
+        Ok(current + 1)
+    }
+
+    fn put_if_absent(&self, key: &str, value: &[u8]) -> StorageResult<u64> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let mut state = self.lock_state()?;
+
+        if state.version_of(key)?.is_some() {
+            return Err(StorageError::KeyAlreadyExists(key.to_string()));
+        }
+
+        let version = state.next_version(key)?;
+        state.put_ops += 1;
+        self.log_put(state, key, value, None, version)?;
+        Ok(version)
+    }
+
+    fn write_batch(&self, operations: &[BatchOperation]) -> StorageResult<()> {
+        validate_batch(operations)?;
+
+        if operations.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.lock_state()?;
+
+        // Pin every put's version up front so replay assigns exactly the same ones.
+        // A key deleted in the batch keeps its next version, so it is not reused.
+        let mut pending: HashMap<&str, u64> = HashMap::new();
+        let mut logged = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            match operation {
+                BatchOperation::Put { key, value } => {
+                    let version = match pending.get(key.as_str()) {
+                        Some(version) => *version,
+                        None => state.next_version(key)?,
+                    };
+                    pending.insert(key, version + 1);
+
+                    logged.push(WalOperation::Put {
+                        key: key.clone(),
+                        value: value.to_vec(),
+                        expires_at: None,
+                        version: Some(version),
+                    });
+                    state.put_ops += 1;
+                }
+                BatchOperation::Delete { key } => {
+                    if !pending.contains_key(key.as_str()) {
+                        pending.insert(key, state.next_version(key)?);
+                    }
+                    logged.push(WalOperation::Delete { key: key.clone() });
+                    state.delete_ops += 1;
+                }
+            }
+        }
+
+        let operation = WalOperation::Batch { operations: logged };
+        let replaced = state.stored(pending.keys().copied())?;
+        self.commit(state, operation.clone(), replaced, |state| {
+            state.apply(&operation)
+        })
+    }
+
+    fn get(&self, key: &str) -> StorageResult<Value> {
+        validate_key(key)?;
+
+        let mut state = self.lock_state()?;
+        state.get_ops += 1;
+
+        state
+            .live_value(key)?
+            .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))
+    }
+
+    fn delete(&self, key: &str) -> StorageResult<bool> {
+        validate_key(key)?;
+
+        let mut state = self.lock_state()?;
+        state.delete_ops += 1;
+
+        let operation = WalOperation::Delete {
+            key: key.to_string(),
+        };
+        let replaced = state.stored([key])?;
+        self.commit(state, operation, replaced, |state| {
+            Ok(state
+                .remove(key)?
+                .is_some_and(|value| !value.metadata.is_expired()))
+        })
+    }
+
+    fn exists(&self, key: &str) -> StorageResult<bool> {
+        validate_key(key)?;
+
+        Ok(self.lock_state()?.live_value(key)?.is_some())
+    }
+
+    fn keys(&self) -> StorageResult<Vec<String>> {
+        Ok(self
+            .lock_state()?
+            .collect_live(None, |_| true, usize::MAX)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    fn values(&self) -> StorageResult<Vec<Value>> {
+        Ok(self
+            .lock_state()?
+            .collect_live(None, |_| true, usize::MAX)?
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect())
+    }
+
+    fn all(&self) -> StorageResult<HashMap<String, Value>> {
+        Ok(self
+            .lock_state()?
+            .collect_live(None, |_| true, usize::MAX)?
+            .into_iter()
+            .collect())
+    }
+
+    fn scan(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start >= end {
+                return Ok(Vec::new());
+            }
+        }
+
+        self.lock_state()?.collect_live(
+            start,
+            |key| end.is_none_or(|end| key < end),
+            limit.unwrap_or(usize::MAX),
+        )
+    }
+
+    fn scan_keys(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: usize,
+    ) -> StorageResult<Vec<String>> {
+        Ok(self
+            .scan(start, end, Some(limit))?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    fn iter_from(&self, start: Option<&str>, batch_size: usize) -> ScanBatches<'_> {
+        ScanBatches::new(self, start, batch_size)
+    }
+
+    fn scan_prefix(
+        &self,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        self.lock_state()?.collect_live(
+            Some(prefix),
+            |key| key.starts_with(prefix),
+            limit.unwrap_or(usize::MAX),
+        )
+    }
+
+    fn clear(&self) -> StorageResult<()> {
+        let mut state = self.lock_state()?;
+
+        // Undoing a clear would mean keeping a copy of everything, so it is
+        // only applied once its entry is durable
+        if let Err(e) = self.wal.log_operation(WalOperation::Clear) {
+            state.undo_lost_writes(&self.wal)?;
+            return Err(e);
+        }
+        state.clear()
+    }
+
+    fn purge_expired(&self) -> StorageResult<usize> {
+        let mut state = self.lock_state()?;
+
+        let expired = state.expired_keys()?;
+        let count = expired.len();
+        let expiries = expired
+            .iter()
+            .map(|key| WalOperation::Expire { key: key.clone() });
+        let pending = match self.wal.append_all(expiries) {
+            Ok(Some(pending)) => pending,
+            Ok(None) => return Ok(0),
+            Err(e) => {
+                state.undo_lost_writes(&self.wal)?;
+                return Err(e);
+            }
+        };
+
+        let mut replaced = Vec::with_capacity(count);
+        for key in expired {
+            let value = state.remove(&key)?;
+            replaced.push((key, value));
+        }
+        self.finish(state, pending, replaced, true)?;
+        Ok(count)
+    }
+
+    fn stats(&self) -> StorageResult<Stats> {
+        let state = self.lock_state()?;
+
+        Ok(Stats {
+            key_count: state
+                .key_count
+                .saturating_sub(state.expiries.expired_count(Utc::now())),
+            memory_usage: state.pool.cached_page_count() * usize::from(PAGE_SIZE),
+            get_operations_count: state.get_ops,
+            put_operations_count: state.put_ops,
+            delete_operations_count: state.delete_ops,
+        })
+    }
+
+    fn size_of_value(&self, key: &str) -> StorageResult<usize> {
+        validate_key(key)?;
+
+        self.lock_state()?
+            .live_value(key)?
+            .map(|value| value.metadata.size)
+            .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))
+    }
+}
+
+/// Write the dirty pages back with the current WAL sequence number, then
+/// delete the WAL segments they cover
+fn checkpoint(wal: &WalManager, state: &mut DiskState) -> StorageResult<u64> {
+    // The pages must not hold writes that could still be undone
+    state.undo.wait_durable(wal)?;
+
+    // Every entry up to here has been applied, since appends happen under the lock
+    let sequence_number = wal.current_sequence_number()?;
+    if let Some(disk) = state.pool.disk_manager_mut() {
+        let header = disk.header_mut();
+        header.set_checkpoint_sequence(sequence_number);
+        header.set_retired_version(state.retired_version);
+        header.set_key_count(state.key_count as u64);
+    }
+    let pages = state.pool.flush_dirty_pages()?;
+    state.checkpoint_sequence = sequence_number;
+
+    wal.seal_segment()?;
+    let segments_deleted = wal.delete_segments_through(sequence_number)?;
+
+    debug!(
+        "Checkpoint at sequence {}: {} pages written, {} WAL segments deleted, {:.1}% cache hits",
+        sequence_number,
+        pages.len(),
+        segments_deleted,
+        state.pool.stats().hit_ratio * 100.0
+    );
+    Ok(sequence_number)
+}
+
+/// The pages of a disk storage and what is kept in memory about them
+struct DiskState {
+    /// Cached pages of the database file
+    pool: BufferPool,
+    /// Records of keys and values
+    heap: HeapFile,
+    /// Where the record of each key is
+    index: Index,
+    /// Number of keys in the index, expired ones included
+    key_count: usize,
+    /// Expiry times of the stored keys with a TTL written or read since opening
+    expiries: KnownExpiries,
+    /// Key the next purge starts at, or `None` to start at the first one
+    purge_cursor: Option<String>,
+    /// Sequence number of the last WAL entry the database file reflects
+    checkpoint_sequence: u64,
+    /// Highest version any removed key had
+    retired_version: u64,
+    /// What the writes whose WAL entries may not be durable yet replaced
+    undo: UndoLog,
+    /// Number of gets since the storage was opened
+    get_ops: u64,
+    /// Number of puts since the storage was opened
+    put_ops: u64,
+    /// Number of deletes since the storage was opened
+    delete_ops: u64,
+}
+
+impl DiskState {
+    /// Open the database file at `path` with a buffer pool of `cache_pages` pages
+    ///
+    /// Files written before the header recorded the key count have their
+    /// index entries counted once.
+    fn open(path: &Path, cache_pages: usize) -> StorageResult<Self> {
+        let disk = DiskManager::open(path)?;
+        let header = disk.header().clone();
+        let mut pool = BufferPool::new(cache_pages).with_disk_manager(disk);
+        let index = Index::open(&mut pool)?;
+
+        let key_count = match header.key_count() {
+            Some(key_count) => usize::try_from(key_count).map_err(|_| {
+                StorageError::Internal(format!(
+                    "Database file {} records {key_count} keys",
+                    path.display()
+                ))
+            })?,
+            None => index
+                .entries(&mut pool)?
+                .try_fold(0, |count, entry| entry.map(|_| count + 1))?,
+        };
+
+        Ok(Self {
+            pool,
+            heap: HeapFile::new(),
+            index,
+            key_count,
+            expiries: KnownExpiries::default(),
+            purge_cursor: None,
+            checkpoint_sequence: header.checkpoint_sequence(),
+            retired_version: header.retired_version(),
+            undo: UndoLog::default(),
+            get_ops: 0,
+            put_ops: 0,
+            delete_ops: 0,
+        })
+    }
+
+    /// Apply a logged operation to the pages
+    fn apply(&mut self, operation: &WalOperation) -> StorageResult<()> {
+        match operation {
+            WalOperation::Put {
+                key,
+                value,
+                expires_at,
+                version,
+            } => self.store(key, value, *expires_at, *version),
+            WalOperation::Delete { key } | WalOperation::Expire { key } => {
+                self.remove(key).map(|_| ())
+            }
+            WalOperation::Batch { operations } => operations
+                .iter()
+                .try_for_each(|operation| self.apply(operation)),
+            WalOperation::Clear => self.clear(),
+        }
+    }
+
+    /// Find the record of `key` and read its value, expired or not
+    fn lookup(&mut self, key: &str) -> StorageResult<Option<(IndexEntry, Value)>> {
+        let Some(entry) = self.index.get(&mut self.pool, key)? else {
+            return Ok(None);
+        };
+        let value = self.read(&entry)?;
+        Ok(Some((entry, value)))
+    }
+
+    /// Read the value of the record `entry` points at, noting its expiry time
+    fn read(&mut self, entry: &IndexEntry) -> StorageResult<Value> {
+        let value = decode_value(&entry.key, &HeapFile::read(&mut self.pool, entry)?)?;
+        if let Some(expires_at) = value.metadata.expires_at {
+            if !self.expiries.contains(&entry.key) {
+                self.expiries.set(&entry.key, Some(expires_at));
+            }
+        }
+        Ok(value)
+    }
+
+    /// The value of `key`, unless it does not exist or has expired
+    fn live_value(&mut self, key: &str) -> StorageResult<Option<Value>> {
+        Ok(self
+            .lookup(key)?
+            .map(|(_, value)| value)
+            .filter(|value| !value.metadata.is_expired()))
+    }
+
+    /// Current version of a live key, or `None` if the key does not exist
+    fn version_of(&mut self, key: &str) -> StorageResult<Option<u64>> {
+        Ok(self.live_value(key)?.map(|value| value.metadata.version))
+    }
+
+    /// Version a put would give `key` now
+    fn next_version(&mut self, key: &str) -> StorageResult<u64> {
+        let stored = self.lookup(key)?.map(|(_, value)| value);
+        Ok(next_version(
+            stored.as_ref().map(|value| &value.metadata),
+            self.retired_version,
+        ))
+    }
+
+    /// Store a value, with the version [`next_version`] picks unless `version`
+    /// is given
+    ///
+    /// Updates keep the original creation timestamp.
+    fn store(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+        version: Option<u64>,
+    ) -> StorageResult<()> {
+        let previous = self.lookup(key)?;
+        let stored = previous.as_ref().map(|(_, value)| &value.metadata);
+        let next = next_version(stored, self.retired_version);
+        let live = stored.filter(|metadata| !metadata.is_expired());
+        if let Some(expired) = stored.filter(|metadata| metadata.is_expired()) {
+            self.retired_version = self.retired_version.max(expired.version);
+        }
+
+        let mut metadata = ValueMetadata::new(value.len());
+        metadata.expires_at = expires_at;
+        metadata.version = version.unwrap_or(next);
+        if let Some(previous) = live {
+            metadata.created_at.clone_from(&previous.created_at);
+        }
+
+        let previous = previous.map(|(entry, _)| entry);
+        self.write_record(key, previous.as_ref(), &metadata, value)
+    }
+
+    /// Write the record of `key`, replacing the one `previous` points at
+    fn write_record(
+        &mut self,
+        key: &str,
+        previous: Option<&IndexEntry>,
+        metadata: &ValueMetadata,
+        value: &[u8],
+    ) -> StorageResult<()> {
+        let record = encode_value(metadata, value)?;
+        let entry = match previous {
+            Some(entry) => self.heap.update(&mut self.pool, entry, &record)?,
+            None => self.heap.insert(&mut self.pool, key, &record)?,
+        };
+        if self.index.insert(&mut self.pool, entry)?.is_none() {
+            self.key_count += 1;
+        }
+        self.expiries.set(key, metadata.expires_at);
+        Ok(())
+    }
+
+    /// Stored values of `keys` as they are, expired or not, to put back with
+    /// [`Self::restore`]
+    fn stored<'a>(&mut self, keys: impl IntoIterator<Item = &'a str>) -> StorageResult<Replaced> {
+        keys.into_iter()
+            .map(|key| Ok((key.to_string(), self.lookup(key)?.map(|(_, value)| value))))
+            .collect()
+    }
+
+    /// Put back values taken with [`Self::stored`], removing the keys that had none
+    fn restore(&mut self, replaced: Replaced) -> StorageResult<()> {
+        for (key, value) in replaced {
+            match value {
+                Some(value) => {
+                    let previous = self.index.get(&mut self.pool, &key)?;
+                    self.write_record(&key, previous.as_ref(), &value.metadata, &value.value)?;
+                }
+                None => {
+                    self.remove(&key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Put back what the writes whose WAL entries were lost replaced
+    fn undo_lost_writes(&mut self, wal: &WalManager) -> StorageResult<()> {
+        let lost = self.undo.take_lost(wal)?;
+        if !lost.is_empty() {
+            warn!("Undoing {} writes whose WAL entries were lost", lost.len());
+        }
+
+        for replaced in lost {
+            self.restore(replaced)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a key and return its value, expired or not
+    fn remove(&mut self, key: &str) -> StorageResult<Option<Value>> {
+        let Some(entry) = self.index.remove(&mut self.pool, key)? else {
+            return Ok(None);
+        };
+        let value = self.read(&entry)?;
+        self.heap.delete(&mut self.pool, &entry)?;
+
+        self.retired_version = self.retired_version.max(value.metadata.version);
+        self.key_count -= 1;
+        self.expiries.set(key, None);
+        Ok(Some(value))
+    }
+
+    /// Remove every key, freeing the pages of their records and of the index
+    fn clear(&mut self) -> StorageResult<()> {
+        let entries = self
+            .index
+            .entries(&mut self.pool)?
+            .collect::<StorageResult<Vec<_>>>()?;
+        for entry in &entries {
+            let version = self.read(entry)?.metadata.version;
+            self.retired_version = self.retired_version.max(version);
+            self.heap.delete(&mut self.pool, entry)?;
+        }
+        self.index.clear(&mut self.pool)?;
+
+        self.key_count = 0;
+        self.expiries = KnownExpiries::default();
+        Ok(())
+    }
+
+    /// Expired keys among the next [`PURGE_CHUNK`] records from the purge
+    /// cursor on, wrapping around to the first key, in the order they were found
+    fn expired_keys(&mut self) -> StorageResult<Vec<String>> {
+        let now = Utc::now();
+        let start = self.purge_cursor.take();
+        let mut budget = PURGE_CHUNK;
+        let mut expired = Vec::new();
+
+        let mut resume = self.sweep(start.as_deref(), |_| true, now, &mut budget, &mut expired)?;
+        if let Some(start) = start.filter(|_| resume.is_none()) {
+            resume = self.sweep(
+                None,
+                |key| key < start.as_str(),
+                now,
+                &mut budget,
+                &mut expired,
+            )?;
+        }
+
+        self.purge_cursor = resume;
+        Ok(expired)
+    }
+
+    /// Collect the expired keys from `start` on while `in_range` holds, until
+    /// `budget` records have been examined, returning the key to continue at
+    /// if the budget ran out
+    fn sweep(
+        &mut self,
+        start: Option<&str>,
+        in_range: impl Fn(&str) -> bool,
+        now: DateTime<Utc>,
+        budget: &mut usize,
+        expired: &mut Vec<String>,
+    ) -> StorageResult<Option<String>> {
+        let mut resume = None;
+        self.for_each(start, in_range, |key, value| {
+            if *budget == 0 {
+                resume = Some(key);
+                return false;
+            }
+            *budget -= 1;
+            if value.metadata.is_expired_at(now) {
+                expired.push(key);
+            }
+            true
+        })?;
+        Ok(resume)
+    }
+
+    /// Visit the entries from `start` on in key order while `in_range` holds,
+    /// until `visit` returns false
+    ///
+    /// The index is read [`SCAN_CHUNK`] entries at a time, so values are read
+    /// without keeping the whole range in memory.
+    fn for_each(
+        &mut self,
+        start: Option<&str>,
+        in_range: impl Fn(&str) -> bool,
+        mut visit: impl FnMut(String, Value) -> bool,
+    ) -> StorageResult<()> {
+        let mut after: Option<String> = None;
+
+        loop {
+            let lower = match (&after, start) {
+                (Some(key), _) => Bound::Excluded(key.as_str()),
+                (None, Some(start)) => Bound::Included(start),
+                (None, None) => Bound::Unbounded,
+            };
+            let entries = self
+                .index
+                .range(&mut self.pool, lower, Bound::Unbounded)?
+                .take_while(|entry| entry.as_ref().map_or(true, |entry| in_range(&entry.key)))
+                .take(SCAN_CHUNK)
+                .collect::<StorageResult<Vec<_>>>()?;
+            let exhausted = entries.len() < SCAN_CHUNK;
+
+            for entry in entries {
+                let value = self.read(&entry)?;
+                after = Some(entry.key.clone());
+                if !visit(entry.key, value) {
+                    return Ok(());
+                }
+            }
+
+            if exhausted {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Collect live entries in key order from `start` while `in_range` holds, up to `limit`
+    fn collect_live(
+        &mut self,
+        start: Option<&str>,
+        in_range: impl Fn(&str) -> bool,
+        limit: usize,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        let mut live = Vec::new();
+        if limit == 0 {
+            return Ok(live);
+        }
+
+        let now = Utc::now();
+        self.for_each(start, in_range, |key, value| {
+            if !value.metadata.is_expired_at(now) {
+                live.push((key, value));
+            }
+            live.len() < limit
+        })?;
+        Ok(live)
+    }
+}
+
+/// Expiry times of stored keys, ordered by time so that counting the expired
+/// ones only visits those
+#[derive(Debug, Default)]
+struct KnownExpiries {
+    by_key: HashMap<String, DateTime<Utc>>,
+    by_time: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+impl KnownExpiries {
+    fn contains(&self, key: &str) -> bool {
+        self.by_key.contains_key(key)
+    }
+
+    /// Record when `key` expires, or forget it if it does not
+    fn set(&mut self, key: &str, expires_at: Option<DateTime<Utc>>) {
+        if let Some(previous) = self.by_key.remove(key) {
+            self.by_time.remove(&(previous, key.to_string()));
+        }
+        if let Some(expires_at) = expires_at {
+            self.by_key.insert(key.to_string(), expires_at);
+            self.by_time.insert((expires_at, key.to_string()));
+        }
+    }
+
+    /// Number of keys expired at `now`
+    fn expired_count(&self, now: DateTime<Utc>) -> usize {
+        self.by_time
+            .iter()
+            .take_while(|(expires_at, _)| *expires_at <= now)
+            .count()
+    }
+}
+
+/// Encode a value and its metadata as the value of its record
+///
+/// The version (`u64`) comes first, then a 1 followed by the expiry's seconds
+/// (`i64`) and nanoseconds (`u32`), or a 0 without expiry. The creation and
+/// update timestamps follow as a length (`u16`) and the text, then the value.
+fn encode_value(metadata: &ValueMetadata, value: &[u8]) -> StorageResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(64 + value.len());
+    bytes.extend_from_slice(&metadata.version.to_le_bytes());
+
+    match metadata.expires_at {
+        Some(expires_at) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&expires_at.timestamp().to_le_bytes());
+            bytes.extend_from_slice(&expires_at.timestamp_subsec_nanos().to_le_bytes());
+        }
+        None => bytes.push(0),
+    }
+
+    for timestamp in [&metadata.created_at, &metadata.updated_at] {
+        let len = u16::try_from(timestamp.len()).map_err(|_| {
+            StorageError::Internal(format!("Timestamp '{timestamp}' is too long to store"))
+        })?;
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(timestamp.as_bytes());
+    }
+
+    bytes.extend_from_slice(value);
+    Ok(bytes)
+}
+
+/// Decode the value of the record for `key`
+fn decode_value(key: &str, mut bytes: &[u8]) -> StorageResult<Value> {
+    let damaged = || StorageError::Internal(format!("Stored value of '{key}' is damaged"));
+    let mut take = |len: usize| -> StorageResult<&[u8]> {
+        let (taken, rest) = bytes.split_at_checked(len).ok_or_else(damaged)?;
+        bytes = rest;
+        Ok(taken)
+    };
+
+    let version = u64::from_le_bytes(take(8)?.try_into().unwrap_or_default());
+    let expires_at = match take(1)? {
+        [0] => None,
+        [1] => {
+            let seconds = i64::from_le_bytes(take(8)?.try_into().unwrap_or_default());
+            let nanos = u32::from_le_bytes(take(4)?.try_into().unwrap_or_default());
+            Some(DateTime::from_timestamp(seconds, nanos).ok_or_else(damaged)?)
+        }
+        _ => return Err(damaged()),
+    };
+
+    let mut timestamps = [String::new(), String::new()];
+    for timestamp in &mut timestamps {
+        let len = u16::from_le_bytes(take(2)?.try_into().unwrap_or_default());
+        *timestamp = String::from_utf8(take(usize::from(len))?.to_vec()).map_err(|_| damaged())?;
+    }
+    let [created_at, updated_at] = timestamps;
+
+    Ok(Value {
+        metadata: ValueMetadata {
+            size: bytes.len(),
+            created_at,
+            updated_at,
+            expires_at,
+            version,
+        },
+        value: Bytes::copy_from_slice(bytes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance::storage_engine_tests;
+    use crate::storage::engine::INITIAL_VERSION;
+    use crate::storage::wal::Durability;
+    use crate::storage::wal::fault::{self, FaultState};
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use tempfile::TempDir;
+
+    fn temp_storage() -> (TempDir, DiskStorage) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        (temp_dir, storage)
+    }
+
+    /// Drop the storage without its final checkpoint, as a crash would
+    fn crash(storage: DiskStorage) {
+        std::mem::forget(storage);
+    }
+
+    fn past() -> DateTime<Utc> {
+        Utc::now() - chrono::Duration::seconds(1)
+    }
+
+    /// Storage on a WAL whose syncs can be made to fail
+    fn faulty_storage(data_dir: &Path) -> (DiskStorage, Arc<FaultState>) {
+        let (wal, faults) = fault::open_faulty(&data_dir.join(WAL_FILE), Durability::Always);
+        let storage = DiskStorage::with_wal(data_dir.to_path_buf(), MIN_CACHE_PAGES, wal).unwrap();
+        (storage, faults)
+    }
+
+    storage_engine_tests!(temp_storage);
+
+    #[test]
+    fn test_memory_usage_counts_cached_pages() {
+        let (_dir, storage) = temp_storage();
+
+        storage.put("key", b"value").unwrap();
+        assert!(storage.stats().unwrap().memory_usage > 0);
+    }
+
+    #[test]
+    fn test_failed_sync_undoes_the_write() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (storage, faults) = faulty_storage(temp_dir.path());
+
+        storage.put("kept", b"value").unwrap();
+        storage.put("deleted", b"value").unwrap();
+
+        faults.fail_sync.store(true, Ordering::SeqCst);
+        let batch = [
+            BatchOperation::Put {
+                key: "kept".to_string(),
+                value: Bytes::from("changed"),
+            },
+            BatchOperation::Put {
+                key: "lost".to_string(),
+                value: Bytes::from("value"),
+            },
+            BatchOperation::Delete {
+                key: "deleted".to_string(),
+            },
+        ];
+        assert!(storage.write_batch(&batch).is_err());
+
+        let kept = storage.get("kept").unwrap();
+        assert_eq!(kept.value, "value");
+        assert_eq!(kept.metadata.version, INITIAL_VERSION);
+        assert!(!storage.exists("lost").unwrap());
+        assert!(storage.exists("deleted").unwrap());
+
+        // Neither a checkpoint nor a crash brings the lost write back
+        assert!(storage.checkpoint().is_err());
+        crash(storage);
+        fault::crash(&temp_dir.path().join(WAL_FILE), &faults);
+
+        let recovered = DiskStorage::new(temp_dir.path()).unwrap();
+        assert_eq!(recovered.get("kept").unwrap().value, "value");
+        assert!(!recovered.exists("lost").unwrap());
+        assert!(recovered.exists("deleted").unwrap());
+    }
+
+    #[test]
+    fn test_failed_sync_undoes_the_purge() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (storage, faults) = faulty_storage(temp_dir.path());
+
+        storage
+            .put_with_expiry("old", b"value", Some(past()))
+            .unwrap();
+
+        faults.fail_sync.store(true, Ordering::SeqCst);
+        assert!(storage.purge_expired().is_err());
+
+        let mut state = storage.lock_state().unwrap();
+        assert!(state.lookup("old").unwrap().is_some());
+        assert_eq!(state.key_count, 1);
+    }
+
+    #[test]
+    fn test_large_values() {
+        let (_dir, storage) = temp_storage();
+        let large: Vec<u8> = (0..1_048_576u32).map(|i| (i % 251) as u8).collect();
+
+        storage.put("large", &large).unwrap();
+        storage.put("small", b"value").unwrap();
+        assert_eq!(storage.get("large").unwrap().value.as_ref(), &large[..]);
+
+        storage.put("large", b"now small").unwrap();
+        assert_eq!(storage.get("large").unwrap().value, "now small");
+        assert_eq!(storage.get("small").unwrap().value, "value");
+    }
+
+    #[test]
+    fn test_purge_continues_where_the_last_one_stopped() {
+        let (_dir, storage) = temp_storage();
+
+        {
+            let mut state = storage.lock_state().unwrap();
+            for i in 0..PURGE_CHUNK + 10 {
+                let key = format!("key{i:05}");
+                state.store(&key, b"value", Some(past()), None).unwrap();
+            }
+        }
+
+        assert_eq!(storage.purge_expired().unwrap(), PURGE_CHUNK);
+        assert_eq!(storage.lock_state().unwrap().key_count, 10);
+
+        // The next purge finishes the keys after the cursor, then wraps around
+        storage.put_with_expiry("a", b"1", Some(past())).unwrap();
+        assert_eq!(storage.purge_expired().unwrap(), 11);
+        assert_eq!(storage.lock_state().unwrap().key_count, 0);
+        assert_eq!(storage.purge_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_scans_span_many_pages() {
+        let (_dir, storage) = temp_storage();
+        for i in 0..2000 {
+            storage
+                .put(&format!("key:{i:05}"), format!("value {i}").as_bytes())
+                .unwrap();
+        }
+
+        let keys = storage.keys().unwrap();
+        assert_eq!(keys.len(), 2000);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let entries = storage.scan(Some("key:00990"), None, Some(600)).unwrap();
+        assert_eq!(entries.len(), 600);
+        assert_eq!(entries[0].0, "key:00990");
+        assert_eq!(entries[599].1.value, "value 1589");
+    }
+
+    #[test]
+    fn test_data_survives_reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.put("a", b"1").unwrap();
+        storage.put("b", b"2").unwrap();
+        storage.put("a", b"3").unwrap();
+        storage
+            .put_with_ttl("ttl", b"value", Duration::from_secs(60))
+            .unwrap();
+        storage.delete("b").unwrap();
+        drop(storage);
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        assert_eq!(storage.recovery_report().entries_applied, 0);
+        assert_eq!(storage.keys().unwrap(), vec!["a", "ttl"]);
+        let a = storage.get("a").unwrap();
+        assert_eq!(a.value, "3");
+        assert_eq!(a.metadata.version, 2);
+        assert!(storage.get("ttl").unwrap().metadata.expires_at.is_some());
+        assert_eq!(storage.stats().unwrap().key_count, 2);
+
+        // The checkpoint remembers the version "b" had when it was deleted
+        assert_eq!(storage.put_if_absent("b", b"4").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_expired_keys_from_before_reopen_are_counted_until_read() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.put("live", b"1").unwrap();
+        storage.put_with_expiry("old", b"2", Some(past())).unwrap();
+        assert_eq!(storage.stats().unwrap().key_count, 1);
+        drop(storage);
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        assert_eq!(storage.stats().unwrap().key_count, 2);
+        assert!(!storage.exists("old").unwrap());
+        assert_eq!(storage.stats().unwrap().key_count, 1);
+        assert_eq!(storage.purge_expired().unwrap(), 1);
+        assert_eq!(storage.stats().unwrap().key_count, 1);
+    }
+
+    #[test]
+    fn test_version_1_file_has_its_keys_counted() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(DATABASE_FILE);
+        let set_version = |version: u16| {
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes[9..11].copy_from_slice(&version.to_le_bytes());
+            std::fs::write(&path, bytes).unwrap();
+        };
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.put("a", b"1").unwrap();
+        storage.put("b", b"2").unwrap();
+        drop(storage);
+        set_version(1);
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        assert_eq!(storage.stats().unwrap().key_count, 2);
+        drop(storage);
+
+        // The next checkpoint writes the header in the current format
+        let disk = DiskManager::open(&path).unwrap();
+        assert_eq!(disk.header().key_count(), Some(2));
+    }
+
+    #[test]
+    fn test_wal_is_replayed_after_a_crash() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.put("checkpointed", b"before").unwrap();
+        storage.checkpoint().unwrap();
+        storage.put("logged", b"after").unwrap();
+        storage.put("checkpointed", b"updated").unwrap();
+        crash(storage);
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        let report = storage.recovery_report();
+        assert_eq!(report.entries_applied, 2);
+        assert!(report.is_clean());
+        assert_eq!(storage.get("logged").unwrap().value, "after");
+        let checkpointed = storage.get("checkpointed").unwrap();
+        assert_eq!(checkpointed.value, "updated");
+        assert_eq!(checkpointed.metadata.version, 2);
+
+        // Sequence numbers continue after the replayed entries
+        storage.put("next", b"value").unwrap();
+        drop(storage);
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        assert_eq!(
+            storage.keys().unwrap(),
+            vec!["checkpointed", "logged", "next"]
+        );
+    }
+
+    #[test]
+    fn test_crash_with_evicted_pages_recovers() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = WalOptions::default();
+
+        // A small buffer pool writes dirty pages back between checkpoints
+        let storage = DiskStorage::with_options(temp_dir.path(), 0, &options).unwrap();
+        for i in 0..500 {
+            storage
+                .put(&format!("key:{i:04}"), &vec![b'x'; 300])
+                .unwrap();
+        }
+        storage.checkpoint().unwrap();
+        for i in 0..500 {
+            storage
+                .put(&format!("key:{i:04}"), format!("{i}").as_bytes())
+                .unwrap();
+        }
+        storage.delete("key:0000").unwrap();
+        crash(storage);
+
+        let storage = DiskStorage::with_options(temp_dir.path(), 0, &options).unwrap();
+        assert!(storage.recovery_report().is_clean());
+        assert_eq!(storage.stats().unwrap().key_count, 499);
+        assert!(!storage.exists("key:0000").unwrap());
+        assert_eq!(storage.get("key:0499").unwrap().value, "499");
+        assert_eq!(storage.get("key:0499").unwrap().metadata.version, 2);
+    }
+
+    #[test]
+    fn test_freed_pages_are_reused_after_reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database = temp_dir.path().join(DATABASE_FILE);
+        let large = vec![7; 200_000];
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.put("large", &large).unwrap();
+        drop(storage);
+        let len = std::fs::metadata(&database).unwrap().len();
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.delete("large").unwrap();
+        drop(storage);
+
+        let storage = DiskStorage::new(temp_dir.path()).unwrap();
+        storage.put("large", &large).unwrap();
+        drop(storage);
+        assert_eq!(std::fs::metadata(&database).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_checkpoint_deletes_covered_wal_segments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let options = WalOptions::default().with_max_segment_size(1024);
+
+        let storage = DiskStorage::with_options(temp_dir.path(), 0, &options).unwrap();
+        for i in 0..100 {
+            storage.put(&format!("key:{i}"), b"value").unwrap();
+        }
+        let segments = || {
+            std::fs::read_dir(temp_dir.path())
+                .unwrap()
+                .filter(|entry| {
+                    entry
+                        .as_ref()
+                        .unwrap()
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with(WAL_FILE)
+                })
+                .count()
+        };
+        assert!(segments() > 1);
+
+        let sequence_number = storage.checkpoint().unwrap();
+        assert_eq!(sequence_number, 100);
+        assert_eq!(segments(), 1);
+    }
+
+    #[test]
+    fn test_value_encoding_roundtrip() {
+        let mut metadata = ValueMetadata::new(5);
+        metadata.version = 42;
+        metadata.expires_at = DateTime::from_timestamp(1_700_000_000, 123_456_789);
+
+        let bytes = encode_value(&metadata, b"hello").unwrap();
+        let value = decode_value("key", &bytes).unwrap();
+        assert_eq!(value.value, "hello");
+        assert_eq!(value.metadata, metadata);
+
+        assert!(decode_value("key", &bytes[..10]).is_err());
+    }
+}
@@ -1,6 +1,27 @@
 use super::error::StorageResult;
+use super::utils::key_successor;
 use crate::utils::time;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// Version assigned to a key when it is first created
+pub const INITIAL_VERSION: u64 = 1;
+
+/// Version a put gives a key whose stored value, if any, has `stored` as metadata
+///
+/// A live key moves on to its next version. A key that does not exist or has
+/// expired continues after `retired`, the highest version any removed key had,
+/// so a key that is removed and created again never gets an old version back.
+#[must_use]
+pub fn next_version(stored: Option<&ValueMetadata>, retired: u64) -> u64 {
+    match stored {
+        Some(metadata) if !metadata.is_expired() => metadata.version + 1,
+        Some(metadata) => metadata.version.max(retired) + 1,
+        None => (retired + 1).max(INITIAL_VERSION),
+    }
+}
 
 /// Metadata of stored value.
 #[derive(Debug, Clone, PartialEq)]
@@ -13,6 +34,13 @@ pub struct ValueMetadata {
 
     /// Last modified timestamp (Unix timestamp)
     pub updated_at: String,
+
+    /// Point in time after which the value is considered gone, if stored with a TTL
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// Per-key version, incremented on every write to the key and never
+    /// handed out again after the key is removed
+    pub version: u64,
 }
 
 impl ValueMetadata {
@@ -25,6 +53,8 @@ impl ValueMetadata {
             size,
             created_at: timestamp.clone(),
             updated_at: timestamp,
+            expires_at: None,
+            version: INITIAL_VERSION,
         }
     }
     /// Updates the metadata with a new size and updates the timestamp
@@ -32,33 +62,95 @@ impl ValueMetadata {
         self.size = size;
         self.updated_at = time::current_timestamp();
     }
+
+    /// Returns true if the value has expired at the given instant
+    #[must_use]
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Returns true if the value has already expired
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(Utc::now())
+    }
+
+    /// Remaining time to live, or `None` if the value never expires
+    #[must_use]
+    pub fn ttl_remaining(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| (expires_at - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+    }
 }
 
 /// A stored value with its metadata
 #[derive(Debug, Clone, PartialEq)]
 pub struct Value {
-    /// The actual string value being stored
-    pub value: String,
+    /// The raw bytes being stored
+    pub value: Bytes,
     /// Metadata associated with the value (size, timestamps, etc.)
     pub metadata: ValueMetadata,
 }
 
 impl Value {
-    /// Creates a new Value with the given string and metadata
+    /// Creates a new Value with the given bytes and fresh metadata
     #[must_use]
-    pub fn new(value: String) -> Self {
+    pub fn new(value: impl Into<Bytes>) -> Self {
+        let value = value.into();
         let size = value.len();
         Self {
             value,
             metadata: ValueMetadata::new(size),
         }
     }
+
+    /// Creates a new Value that expires at the given instant
+    #[must_use]
+    pub fn with_expiry(value: impl Into<Bytes>, expires_at: Option<DateTime<Utc>>) -> Self {
+        let mut value = Self::new(value);
+        value.metadata.expires_at = expires_at;
+        value
+    }
+
+    /// Returns the value as a string slice if it is valid UTF-8
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.value).ok()
+    }
+}
+
+/// A single write within an atomic batch
+#[derive(Debug, Clone, PartialEq)]
+pub enum BatchOperation {
+    /// Store a value under a key
+    Put {
+        /// The key to store
+        key: String,
+        /// The value to store
+        value: Bytes,
+    },
+    /// Remove a key
+    Delete {
+        /// The key to delete
+        key: String,
+    },
+}
+
+impl BatchOperation {
+    /// The key this operation writes to
+    #[must_use]
+    pub fn key(&self) -> &str {
+        match self {
+            BatchOperation::Put { key, .. } | BatchOperation::Delete { key } => key,
+        }
+    }
 }
 
 /// Statistics of the storage engine
 #[derive(Debug, Clone, PartialEq)]
 pub struct Stats {
-    /// Total number of keys stored
+    /// Number of live keys, the ones [`StorageEngine::keys`] lists; expired
+    /// keys that have not been purged yet are left out
     pub key_count: usize,
     /// Total memory usage in bytes
     pub memory_usage: usize,
@@ -70,6 +162,63 @@ pub struct Stats {
     pub delete_operations_count: u64,
 }
 
+/// Iterator over live key-value pairs in key order, yielded in batches
+///
+/// Each batch is read with a separate [`StorageEngine::scan`] call, so the
+/// dataset is never cloned as a whole and writers are only held off for the
+/// duration of one batch. Writes that land between batches may or may not be
+/// observed, depending on where they fall relative to the current position.
+pub struct ScanBatches<'a> {
+    storage: &'a dyn StorageEngine,
+    next_start: Option<String>,
+    batch_size: usize,
+    finished: bool,
+}
+
+impl<'a> ScanBatches<'a> {
+    /// Create an iterator that starts at `start` (inclusive), or at the first key
+    ///
+    /// A `batch_size` of zero is treated as one.
+    #[must_use]
+    pub fn new(storage: &'a dyn StorageEngine, start: Option<&str>, batch_size: usize) -> Self {
+        Self {
+            storage,
+            next_start: start.map(str::to_string),
+            batch_size: batch_size.max(1),
+            finished: false,
+        }
+    }
+}
+
+impl Iterator for ScanBatches<'_> {
+    type Item = StorageResult<Vec<(String, Value)>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let batch = match self
+            .storage
+            .scan(self.next_start.as_deref(), None, Some(self.batch_size))
+        {
+            Ok(batch) => batch,
+            Err(e) => {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        };
+
+        // A short batch means the end of the keyspace was reached
+        self.finished = batch.len() < self.batch_size;
+
+        let (last_key, _) = batch.last()?;
+        self.next_start = Some(key_successor(last_key));
+
+        Some(Ok(batch))
+    }
+}
+
 /// Trait defining the interface for storage engines
 pub trait StorageEngine: Send + Sync {
     /// Store a key-value pair
@@ -77,7 +226,14 @@ pub trait StorageEngine: Send + Sync {
     ///
     /// # Errors
     /// Returns an error if the storage operation fails
-    fn put(&self, key: &str, value: &str) -> StorageResult<bool>;
+    fn put(&self, key: &str, value: &[u8]) -> StorageResult<bool>;
+
+    /// Store a key-value pair that expires after `ttl`
+    /// Returns Ok(true) if the key was created, Ok(false) if it was updated
+    ///
+    /// # Errors
+    /// Returns an error if the TTL is invalid or the storage operation fails
+    fn put_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> StorageResult<bool>;
 
     /// Retrieve a value by key
     ///
@@ -116,12 +272,94 @@ pub trait StorageEngine: Send + Sync {
     /// Returns an error if the storage operation fails
     fn all(&self) -> StorageResult<HashMap<String, Value>>;
 
+    /// Return live key-value pairs with `start <= key < end` in lexicographic order
+    ///
+    /// Missing bounds are open; at most `limit` pairs are returned when given.
+    ///
+    /// # Errors
+    /// Returns an error if the storage operation fails
+    fn scan(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>>;
+
+    /// Return up to `limit` live keys with `start <= key < end` in lexicographic order
+    ///
+    /// Walks the ordered keys only as far as needed, so a page of keys never
+    /// copies the rest of the key set or any values.
+    ///
+    /// # Errors
+    /// Returns an error if the storage operation fails
+    fn scan_keys(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: usize,
+    ) -> StorageResult<Vec<String>>;
+
+    /// Iterate over live key-value pairs in key order, starting at `start` (inclusive)
+    ///
+    /// Pairs are yielded in batches of at most `batch_size`, each read separately,
+    /// for export and scan jobs that must not clone the whole dataset at once.
+    fn iter_from(&self, start: Option<&str>, batch_size: usize) -> ScanBatches<'_>;
+
+    /// Return live key-value pairs whose key starts with `prefix`, in lexicographic order
+    ///
+    /// # Errors
+    /// Returns an error if the storage operation fails
+    fn scan_prefix(
+        &self,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>>;
+
     /// Clear all data from the storage
     ///
     /// # Errors
     /// Returns an error if the storage operation fails
     fn clear(&self) -> StorageResult<()>;
 
+    /// Replace the value of an existing key only if its version matches
+    /// Returns the new version of the key
+    ///
+    /// # Errors
+    /// Returns `StorageError::KeyNotFound` if the key does not exist,
+    /// `StorageError::VersionMismatch` if the current version differs from `expected_version`,
+    /// or another error if the storage operation fails
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+    ) -> StorageResult<u64>;
+
+    /// Store a key-value pair only if the key does not exist yet
+    /// Returns the version of the new key
+    ///
+    /// # Errors
+    /// Returns `StorageError::KeyAlreadyExists` if the key exists,
+    /// or another error if the storage operation fails
+    fn put_if_absent(&self, key: &str, value: &[u8]) -> StorageResult<u64>;
+
+    /// Apply a list of puts and deletes atomically
+    ///
+    /// Either every operation is applied or none is; operations are applied in order,
+    /// so later writes to the same key win.
+    ///
+    /// # Errors
+    /// Returns an error without applying anything if any key or value is invalid,
+    /// or if the storage operation fails
+    fn write_batch(&self, operations: &[BatchOperation]) -> StorageResult<()>;
+
+    /// Remove every expired key
+    /// Returns the number of keys removed
+    ///
+    /// # Errors
+    /// Returns an error if the storage operation fails
+    fn purge_expired(&self) -> StorageResult<usize>;
+
     /// Get storage statistics
     ///
     /// # Errors
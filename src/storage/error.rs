@@ -7,10 +7,21 @@ pub enum StorageError {
     #[error("Key not found: {0}")]
     KeyNotFound(String),
 
-    /// The requested value was not found
+    /// The key already exists
     #[error("Key already exists: {0}")]
     KeyAlreadyExists(String),
 
+    /// The key's current version does not match the expected one
+    #[error("Version mismatch for key {key}: expected {expected}, found {actual}")]
+    VersionMismatch {
+        /// The key being updated
+        key: String,
+        /// The version the caller expected
+        expected: u64,
+        /// The version currently stored
+        actual: u64,
+    },
+
     /// Invalid key format or content
     #[error("Invalid key: {0}")]
     InvalidKey(String),
@@ -23,6 +34,11 @@ pub enum StorageError {
     #[error("Internal storage error: {0}")]
     Internal(String),
 
+    /// Data could not be encrypted or decrypted, for example because the
+    /// configured key is missing or wrong
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
     /// Unsupported operation or feature
     #[error("Unsupported operation: {0}")]
     UnsupportedOperation(String),
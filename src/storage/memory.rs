@@ -1,17 +1,29 @@
 use crate::storage::Stats;
 use crate::storage::utils::validate_value;
 
-use super::engine::{StorageEngine, Value};
+use super::engine::{BatchOperation, ScanBatches, StorageEngine, Value, next_version};
 use super::error::{StorageError, StorageResult};
-use super::utils::validate_key;
-use std::collections::HashMap;
+use super::utils::{expiry_from_ttl, validate_batch, validate_key};
+use super::wal::Replaced;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
 /// In-memory storage engine implementation
+///
+/// Keys are kept in lexicographic order so range and prefix scans are cheap.
+///
+/// Keys stored with a TTL are expired lazily: reads treat them as missing and
+/// drop them, while [`StorageEngine::purge_expired`] sweeps the rest.
 #[derive(Debug, Default)]
 pub struct MemoryStorage {
-    data: Arc<RwLock<HashMap<String, Value>>>,
+    data: Arc<RwLock<BTreeMap<String, Value>>>,
+    /// Highest version any removed key had, raised under the data write lock
+    retired_version: Arc<AtomicU64>,
     get_ops: AtomicU64,
     put_ops: AtomicU64,
     delete_ops: AtomicU64,
@@ -22,7 +34,8 @@ impl MemoryStorage {
     #[must_use]
     pub fn new() -> Self {
         Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
+            data: Arc::new(RwLock::new(BTreeMap::new())),
+            retired_version: Arc::new(AtomicU64::new(0)),
             get_ops: AtomicU64::new(0),
             put_ops: AtomicU64::new(0),
             delete_ops: AtomicU64::new(0),
@@ -30,125 +43,524 @@ impl MemoryStorage {
     }
 
     /// Create a new in-memory storage with initial capacity
+    ///
+    /// The ordered map grows node by node, so the capacity is only a hint and
+    /// is currently not used to preallocate.
     #[must_use]
-    pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            data: Arc::new(RwLock::new(HashMap::with_capacity(capacity))),
-            get_ops: AtomicU64::new(0),
-            put_ops: AtomicU64::new(0),
-            delete_ops: AtomicU64::new(0),
-        }
+    pub fn with_capacity(_capacity: usize) -> Self {
+        Self::new()
     }
 
     /// Calculate memory usage of the current data
     #[must_use]
-    pub fn calculate_memory_usage(data: &HashMap<String, Value>) -> usize {
+    pub fn calculate_memory_usage(data: &BTreeMap<String, Value>) -> usize {
         data.iter()
             .map(|(key, value)| key.len() + value.value.len() + std::mem::size_of::<Value>())
             .sum()
     }
+
+    /// Store a key-value pair that expires at an absolute point in time
+    ///
+    /// Used when the expiry is already known, e.g. while replaying a WAL.
+    /// Returns Ok(true) if the key was created, Ok(false) if it was updated.
+    ///
+    /// # Errors
+    /// Returns an error if the key or value is invalid or the lock cannot be acquired
+    pub fn put_with_expiry(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<bool> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let mut data = self.write_data()?;
+
+        let (was_new, _) = self.store(&mut data, key, value, expires_at, None);
+        Ok(was_new)
+    }
+
+    /// Store a key-value pair with an explicit version
+    ///
+    /// Used by engines that decide the version themselves, e.g. while replaying a WAL.
+    /// Returns Ok(true) if the key was created, Ok(false) if it was updated.
+    ///
+    /// # Errors
+    /// Returns an error if the key or value is invalid or the lock cannot be acquired
+    pub fn put_versioned(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+        version: u64,
+    ) -> StorageResult<bool> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let mut data = self.write_data()?;
+
+        let (was_new, _) = self.store(&mut data, key, value, expires_at, Some(version));
+        Ok(was_new)
+    }
+
+    /// Apply a batch of writes under a single lock so readers never see it half-applied
+    ///
+    /// `versions` pins the version of the put at the same index; puts without an
+    /// entry get the next version after the key's current one.
+    ///
+    /// # Errors
+    /// Returns an error without applying anything if any key or value is invalid,
+    /// or if the lock cannot be acquired
+    pub fn apply_batch(
+        &self,
+        operations: &[BatchOperation],
+        versions: &[Option<u64>],
+    ) -> StorageResult<()> {
+        validate_batch(operations)?;
+
+        let mut data = self.write_data()?;
+
+        for (index, operation) in operations.iter().enumerate() {
+            match operation {
+                BatchOperation::Put { key, value } => {
+                    let version = versions.get(index).copied().flatten();
+                    self.store(&mut data, key, value, None, version);
+                }
+                BatchOperation::Delete { key } => {
+                    self.remove(&mut data, key);
+                    self.delete_ops.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Version a put would give `key` now
+    ///
+    /// # Errors
+    /// Returns an error if the read lock cannot be acquired
+    pub fn next_version(&self, key: &str) -> StorageResult<u64> {
+        let data = self.read_data()?;
+
+        Ok(next_version(
+            data.get(key).map(|value| &value.metadata),
+            self.retired_version(),
+        ))
+    }
+
+    /// Highest version any removed key had
+    #[must_use]
+    pub fn retired_version(&self) -> u64 {
+        self.retired_version.load(Ordering::Acquire)
+    }
+
+    /// Every live key in key order, and the highest version any key that is
+    /// gone had, expired keys that are still stored included
+    ///
+    /// # Errors
+    /// Returns an error if the read lock cannot be acquired
+    pub fn snapshot(&self) -> StorageResult<(Vec<(String, Value)>, u64)> {
+        let data = self.read_data()?;
+        let now = Utc::now();
+
+        let retired = data
+            .values()
+            .filter(|value| value.metadata.is_expired_at(now))
+            .map(|value| value.metadata.version)
+            .fold(self.retired_version(), u64::max);
+        let live = data
+            .iter()
+            .filter(|(_, value)| !value.metadata.is_expired_at(now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Ok((live, retired))
+    }
+
+    /// Raise the highest version removed keys had to at least `version`, e.g.
+    /// after restoring a snapshot that no longer holds the removed keys
+    pub fn retire_version(&self, version: u64) {
+        self.retired_version.fetch_max(version, Ordering::AcqRel);
+    }
+
+    /// Current version of a live key, or `None` if the key does not exist
+    ///
+    /// # Errors
+    /// Returns an error if the read lock cannot be acquired
+    pub fn version_of(&self, key: &str) -> StorageResult<Option<u64>> {
+        Ok(self
+            .read_data()?
+            .get(key)
+            .filter(|value| !value.metadata.is_expired())
+            .map(|value| value.metadata.version))
+    }
+
+    /// Insert a value into the locked map, returning whether the key is new and its version
+    ///
+    /// Without an explicit version the key gets the one [`next_version`] picks.
+    /// Updates keep the original creation timestamp.
+    fn store(
+        &self,
+        data: &mut BTreeMap<String, Value>,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+        version: Option<u64>,
+    ) -> (bool, u64) {
+        let stored = data.get(key);
+        let next = next_version(stored.map(|value| &value.metadata), self.retired_version());
+        let previous = stored.filter(|value| !value.metadata.is_expired());
+        if let Some(expired) = stored.filter(|value| value.metadata.is_expired()) {
+            self.retire_version(expired.metadata.version);
+        }
+
+        let mut stored_value = Value::with_expiry(Bytes::copy_from_slice(value), expires_at);
+        stored_value.metadata.version = version.unwrap_or(next);
+        if let Some(previous) = previous {
+            stored_value
+                .metadata
+                .created_at
+                .clone_from(&previous.metadata.created_at);
+        }
+
+        let was_new = previous.is_none();
+        let version = stored_value.metadata.version;
+        data.insert(key.to_string(), stored_value);
+
+        self.put_ops.fetch_add(1, Ordering::Relaxed);
+        (was_new, version)
+    }
+
+    /// Remove every expired key and return the keys and values that were removed
+    ///
+    /// # Errors
+    /// Returns an error if the write lock cannot be acquired
+    pub fn remove_expired(&self) -> StorageResult<Vec<(String, Value)>> {
+        let mut data = self.write_data()?;
+        let now = Utc::now();
+
+        let expired: Vec<String> = data
+            .iter()
+            .filter(|(_, value)| value.metadata.is_expired_at(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        Ok(expired
+            .into_iter()
+            .filter_map(|key| self.remove(&mut data, &key).map(|value| (key, value)))
+            .collect())
+    }
+
+    /// Remove a key from the locked map, retiring its version
+    fn remove(&self, data: &mut BTreeMap<String, Value>, key: &str) -> Option<Value> {
+        let removed = data.remove(key)?;
+        self.retire_version(removed.metadata.version);
+        Some(removed)
+    }
+
+    /// Stored values of `keys` as they are, expired or not, to put back with
+    /// [`Self::restore`]
+    ///
+    /// # Errors
+    /// Returns an error if the read lock cannot be acquired
+    pub fn stored<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> StorageResult<Replaced> {
+        let data = self.read_data()?;
+
+        Ok(keys
+            .into_iter()
+            .map(|key| (key.to_string(), data.get(key).cloned()))
+            .collect())
+    }
+
+    /// Put back values taken with [`Self::stored`], removing the keys that had none
+    ///
+    /// # Errors
+    /// Returns an error if the write lock cannot be acquired
+    pub fn restore(&self, replaced: Replaced) -> StorageResult<()> {
+        let mut data = self.write_data()?;
+
+        for (key, value) in replaced {
+            match value {
+                Some(value) => data.insert(key, value),
+                None => data.remove(&key),
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Remove a key only if it is still expired
+    ///
+    /// # Errors
+    /// Returns an error if the write lock cannot be acquired
+    pub fn remove_if_expired(&self, key: &str) -> StorageResult<bool> {
+        let mut data = self.write_data()?;
+
+        if data
+            .get(key)
+            .is_some_and(|value| value.metadata.is_expired())
+        {
+            self.remove(&mut data, key);
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn read_data(&self) -> StorageResult<RwLockReadGuard<'_, BTreeMap<String, Value>>> {
+        self.data
+            .read()
+            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))
+    }
+
+    fn write_data(&self) -> StorageResult<RwLockWriteGuard<'_, BTreeMap<String, Value>>> {
+        self.data
+            .write()
+            .map_err(|_| StorageError::Internal("Failed to acquire write lock".to_string()))
+    }
+
+    /// Collect live entries in key order from `range`, up to `limit`
+    fn collect_live<'a>(
+        range: impl Iterator<Item = (&'a String, &'a Value)>,
+        limit: Option<usize>,
+    ) -> Vec<(String, Value)> {
+        let now = Utc::now();
+
+        range
+            .filter(|(_, value)| !value.metadata.is_expired_at(now))
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Range over the ordered map with `start` inclusive and `end` exclusive
+    ///
+    /// Returns `None` for an inverted range, on which `BTreeMap::range` would panic.
+    fn key_range<'a>(
+        data: &'a BTreeMap<String, Value>,
+        start: Option<&str>,
+        end: Option<&str>,
+    ) -> Option<std::collections::btree_map::Range<'a, String, Value>> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if start >= end {
+                return None;
+            }
+        }
+
+        let lower = start.map_or(Bound::Unbounded, Bound::Included);
+        let upper = end.map_or(Bound::Unbounded, Bound::Excluded);
+
+        Some(data.range::<str, _>((lower, upper)))
+    }
+
+    /// Look up a live value, dropping it if it turns out to be expired
+    fn live_value(&self, key: &str) -> StorageResult<Option<Value>> {
+        let value = self.read_data()?.get(key).cloned();
+
+        match value {
+            Some(value) if value.metadata.is_expired() => {
+                self.remove_if_expired(key)?;
+                Ok(None)
+            }
+            value => Ok(value),
+        }
+    }
 }
 
 impl StorageEngine for MemoryStorage {
-    fn put(&self, key: &str, value: &str) -> StorageResult<bool> {
+    fn put(&self, key: &str, value: &[u8]) -> StorageResult<bool> {
+        self.put_with_expiry(key, value, None)
+    }
+
+    fn put_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> StorageResult<bool> {
+        self.put_with_expiry(key, value, Some(expiry_from_ttl(ttl)?))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+    ) -> StorageResult<u64> {
         validate_key(key)?;
         validate_value(value)?;
 
-        let mut data = self
-            .data
-            .write()
-            .map_err(|_| StorageError::Internal("Failed to acquire write lock".to_string()))?;
+        let mut data = self.write_data()?;
 
-        let stored_value = Value::new(value.to_string());
-        let was_new = data.insert(key.to_string(), stored_value).is_none();
+        let current = data
+            .get(key)
+            .filter(|value| !value.metadata.is_expired())
+            .map(|value| value.metadata.version)
+            .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))?;
 
-        self.put_ops.fetch_add(1, Ordering::Relaxed);
-        Ok(was_new)
+        if current != expected_version {
+            return Err(StorageError::VersionMismatch {
+                key: key.to_string(),
+                expected: expected_version,
+                actual: current,
+            });
+        }
+
+        let (_, version) = self.store(&mut data, key, value, None, None);
+        Ok(version)
     }
 
-    fn get(&self, key: &str) -> StorageResult<Value> {
+    fn put_if_absent(&self, key: &str, value: &[u8]) -> StorageResult<u64> {
         validate_key(key)?;
+        validate_value(value)?;
 
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
+        let mut data = self.write_data()?;
+
+        if data
+            .get(key)
+            .is_some_and(|value| !value.metadata.is_expired())
+        {
+            return Err(StorageError::KeyAlreadyExists(key.to_string()));
+        }
+
+        let (_, version) = self.store(&mut data, key, value, None, None);
+        Ok(version)
+    }
+
+    fn write_batch(&self, operations: &[BatchOperation]) -> StorageResult<()> {
+        self.apply_batch(operations, &[])
+    }
+
+    fn get(&self, key: &str) -> StorageResult<Value> {
+        validate_key(key)?;
 
         self.get_ops.fetch_add(1, Ordering::Relaxed);
 
-        data.get(key)
-            .cloned()
+        self.live_value(key)?
             .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))
     }
 
     fn delete(&self, key: &str) -> StorageResult<bool> {
         validate_key(key)?;
 
-        let mut data = self
-            .data
-            .write()
-            .map_err(|_| StorageError::Internal("Failed to acquire write lock".to_string()))?;
+        let mut data = self.write_data()?;
 
         self.delete_ops.fetch_add(1, Ordering::Relaxed);
-        Ok(data.remove(key).is_some())
+        Ok(self
+            .remove(&mut data, key)
+            .is_some_and(|value| !value.metadata.is_expired()))
     }
 
     fn exists(&self, key: &str) -> StorageResult<bool> {
         validate_key(key)?;
 
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
-
-        Ok(data.contains_key(key))
+        Ok(self.live_value(key)?.is_some())
     }
 
     fn keys(&self) -> StorageResult<Vec<String>> {
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
-
-        Ok(data.keys().cloned().collect())
+        let data = self.read_data()?;
+        let now = Utc::now();
+
+        Ok(data
+            .iter()
+            .filter(|(_, value)| !value.metadata.is_expired_at(now))
+            .map(|(key, _)| key.clone())
+            .collect())
     }
 
     fn values(&self) -> StorageResult<Vec<Value>> {
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
+        let data = self.read_data()?;
+        let now = Utc::now();
 
-        Ok(data.values().cloned().collect())
+        Ok(data
+            .values()
+            .filter(|value| !value.metadata.is_expired_at(now))
+            .cloned()
+            .collect())
     }
 
     fn all(&self) -> StorageResult<HashMap<String, Value>> {
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
+        let data = self.read_data()?;
+        let now = Utc::now();
+
+        Ok(data
+            .iter()
+            .filter(|(_, value)| !value.metadata.is_expired_at(now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn scan(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        let data = self.read_data()?;
+
+        Ok(Self::key_range(&data, start, end)
+            .map(|range| Self::collect_live(range, limit))
+            .unwrap_or_default())
+    }
+
+    fn scan_keys(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: usize,
+    ) -> StorageResult<Vec<String>> {
+        let data = self.read_data()?;
+        let now = Utc::now();
+
+        Ok(Self::key_range(&data, start, end)
+            .map(|range| {
+                range
+                    .filter(|(_, value)| !value.metadata.is_expired_at(now))
+                    .take(limit)
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
 
-        Ok(data.clone())
+    fn iter_from(&self, start: Option<&str>, batch_size: usize) -> ScanBatches<'_> {
+        ScanBatches::new(self, start, batch_size)
+    }
+
+    fn scan_prefix(
+        &self,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        let data = self.read_data()?;
+
+        Ok(Self::collect_live(
+            data.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(prefix)),
+            limit,
+        ))
     }
 
     fn clear(&self) -> StorageResult<()> {
-        let mut data = self
-            .data
-            .write()
-            .map_err(|_| StorageError::Internal("Failed to acquire write lock".to_string()))?;
+        let mut data = self.write_data()?;
 
+        if let Some(highest) = data.values().map(|value| value.metadata.version).max() {
+            self.retire_version(highest);
+        }
         data.clear();
         Ok(())
     }
 
+    fn purge_expired(&self) -> StorageResult<usize> {
+        Ok(self.remove_expired()?.len())
+    }
+
     fn stats(&self) -> StorageResult<Stats> {
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
+        let data = self.read_data()?;
 
+        let now = Utc::now();
         Ok(Stats {
-            key_count: data.len(),
+            key_count: data
+                .values()
+                .filter(|value| !value.metadata.is_expired_at(now))
+                .count(),
             memory_usage: Self::calculate_memory_usage(&data),
             get_operations_count: self.get_ops.load(Ordering::Relaxed),
             put_operations_count: self.put_ops.load(Ordering::Relaxed),
@@ -159,12 +571,7 @@ impl StorageEngine for MemoryStorage {
     fn size_of_value(&self, key: &str) -> StorageResult<usize> {
         validate_key(key)?;
 
-        let data = self
-            .data
-            .read()
-            .map_err(|_| StorageError::Internal("Failed to acquire read lock".to_string()))?;
-
-        data.get(key)
+        self.live_value(key)?
             .map(|stored_value| stored_value.metadata.size)
             .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))
     }
@@ -174,6 +581,7 @@ impl Clone for MemoryStorage {
     fn clone(&self) -> Self {
         Self {
             data: Arc::clone(&self.data),
+            retired_version: Arc::clone(&self.retired_version),
             get_ops: AtomicU64::new(self.get_ops.load(Ordering::Relaxed)),
             put_ops: AtomicU64::new(self.put_ops.load(Ordering::Relaxed)),
             delete_ops: AtomicU64::new(self.delete_ops.load(Ordering::Relaxed)),
@@ -188,115 +596,23 @@ impl Clone for MemoryStorage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::conformance::storage_engine_tests;
 
-    #[test]
-    fn test_new_storage() {
-        let storage = MemoryStorage::new();
-        let stats = storage.stats().unwrap();
-        assert_eq!(stats.key_count, 0);
-        assert_eq!(stats.memory_usage, 0);
-    }
-
-    #[test]
-    fn test_put_and_get() {
-        let storage = MemoryStorage::new();
-
-        // Test putting a new key
-        let was_new = storage.put("test_key", "test_value").unwrap();
-        assert!(was_new);
-
-        // Test getting the key
-        let stored_value = storage.get("test_key").unwrap();
-        assert_eq!(stored_value.value, "test_value");
-
-        // Test updating existing key
-        let was_new = storage.put("test_key", "updated_value").unwrap();
-        assert!(!was_new);
-
-        let stored_value = storage.get("test_key").unwrap();
-        assert_eq!(stored_value.value, "updated_value");
-    }
-
-    #[test]
-    fn test_delete() {
-        let storage = MemoryStorage::new();
-
-        storage.put("test_key", "test_value").unwrap();
-        assert!(storage.exists("test_key").unwrap());
-
-        let existed = storage.delete("test_key").unwrap();
-        assert!(existed);
-        assert!(!storage.exists("test_key").unwrap());
-
-        // Try deleting non-existent key
-        let existed = storage.delete("non_existent").unwrap();
-        assert!(!existed);
-    }
-
-    #[test]
-    fn test_list_operations() {
-        let storage = MemoryStorage::new();
-
-        storage.put("key1", "value1").unwrap();
-        storage.put("key2", "value2").unwrap();
-        storage.put("key3", "value3").unwrap();
-
-        let keys = storage.keys().unwrap();
-        assert_eq!(keys.len(), 3);
-        assert!(keys.contains(&"key1".to_string()));
-        assert!(keys.contains(&"key2".to_string()));
-        assert!(keys.contains(&"key3".to_string()));
-
-        let all_data = storage.all().unwrap();
-        assert_eq!(all_data.len(), 3);
-        assert_eq!(all_data.get("key1").unwrap().value, "value1");
-    }
-
-    #[test]
-    fn test_clear() {
-        let storage = MemoryStorage::new();
-
-        storage.put("key1", "value1").unwrap();
-        storage.put("key2", "value2").unwrap();
-
-        assert_eq!(storage.stats().unwrap().key_count, 2);
-
-        storage.clear().unwrap();
-        assert_eq!(storage.stats().unwrap().key_count, 0);
+    fn new_storage() -> ((), MemoryStorage) {
+        ((), MemoryStorage::new())
     }
 
-    #[test]
-    fn test_stats() {
-        let storage = MemoryStorage::new();
-
-        storage.put("key1", "value1").unwrap();
-        storage.get("key1").unwrap();
-        storage.delete("key1").unwrap();
-
-        let stats = storage.stats().unwrap();
-        assert_eq!(stats.get_operations_count, 1);
-        assert_eq!(stats.put_operations_count, 1);
-        assert_eq!(stats.delete_operations_count, 1);
-    }
+    storage_engine_tests!(new_storage);
 
     #[test]
-    fn test_invalid_key() {
+    fn test_memory_usage() {
         let storage = MemoryStorage::new();
+        assert_eq!(storage.stats().unwrap().memory_usage, 0);
 
-        // Empty key
-        let result = storage.put("", "value");
-        assert!(matches!(result, Err(StorageError::InvalidKey(_))));
-
-        // Key with null byte
-        let result = storage.put("key\0", "value");
-        assert!(matches!(result, Err(StorageError::InvalidKey(_))));
-    }
-
-    #[test]
-    fn test_key_not_found() {
-        let storage = MemoryStorage::new();
+        storage.put("key", b"value").unwrap();
+        assert!(storage.stats().unwrap().memory_usage > 0);
 
-        let result = storage.get("non_existent");
-        assert!(matches!(result, Err(StorageError::KeyNotFound(_))));
+        storage.delete("key").unwrap();
+        assert_eq!(storage.stats().unwrap().memory_usage, 0);
     }
 }
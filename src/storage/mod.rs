@@ -13,7 +13,7 @@
 //! let storage = MemoryStorage::new();
 //!
 //! // Store a value
-//! storage.put("hello", "world").unwrap();
+//! storage.put("hello", b"world").unwrap();
 //!
 //! // Retrieve a value
 //! let stored_value = storage.get("hello").unwrap();
@@ -24,6 +24,11 @@
 //! assert_eq!(keys.len(), 1);
 //! ```
 
+/// Automatic WAL compaction for persistent storage
+pub mod compaction;
+/// Tests shared by every storage engine
+#[cfg(test)]
+pub(crate) mod conformance;
 /// Disk-based storage implementation
 pub mod disk;
 /// Storage engine trait and core types
@@ -34,15 +39,24 @@ pub mod error;
 pub mod memory;
 /// Persistent storage implementation
 pub mod persistent;
+/// Point-in-time snapshots of persistent storage
+pub mod snapshot;
 /// Utility functions for storage operations
 pub mod utils;
 /// Write-ahead log (WAL) implementation
 pub mod wal;
+/// Background threads that run a task at a fixed interval
+pub(crate) mod worker;
 
-pub use engine::{Stats, StorageEngine, Value, ValueMetadata};
+pub use compaction::CompactionPolicy;
+pub use disk::DiskStorage;
+pub use engine::{
+    BatchOperation, INITIAL_VERSION, ScanBatches, Stats, StorageEngine, Value, ValueMetadata,
+};
 pub use error::{StorageError, StorageResult};
 pub use memory::MemoryStorage;
 pub use persistent::PersistentStorage;
+pub use wal::{Compression, Durability, RecoveryMode, RecoveryTarget, WalOptions};
 
 /// Create a new default storage engine
 ///
@@ -120,7 +134,7 @@ mod tests {
     fn test_basic_operations_through_trait() {
         let storage = storage();
 
-        storage.put("test", "value").unwrap();
+        storage.put("test", b"value").unwrap();
         assert!(storage.exists("test").unwrap());
 
         let retrieved = storage.get("test").unwrap();
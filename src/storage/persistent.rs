@@ -1,10 +1,19 @@
-use super::engine::{Stats, StorageEngine, Value};
-use super::error::StorageResult;
+use super::compaction::{CompactionPolicy, CompactionWorker, WalState};
+use super::engine::{BatchOperation, ScanBatches, Stats, StorageEngine, Value};
+use super::error::{StorageError, StorageResult};
 use super::memory::MemoryStorage;
-use super::wal::{WalManager, WalOperation};
+use super::snapshot::{self, Snapshot};
+use super::utils::{expiry_from_ttl, validate_batch, validate_key, validate_value};
+use super::wal::{
+    Compression, CompressionStats, DiscardedRecord, Durability, PendingWrite, RecoveryMode,
+    RecoveryTarget, Replaced, UndoLog, WalEntry, WalManager, WalOperation, WalOptions,
+};
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 
 /// Persistent storage engine that combines in-memory storage with Write-Ahead Logging
@@ -13,6 +22,27 @@ pub struct PersistentStorage {
     memory_storage: MemoryStorage,
     /// Write-Ahead Log manager for durability
     wal_manager: Arc<WalManager>,
+    /// Where checkpoints write their snapshot of the data
+    snapshot_path: PathBuf,
+    /// Serializes mutations so the WAL order always matches the in-memory order
+    ///
+    /// Writers wait for their WAL entry to become durable after releasing it, so
+    /// concurrent writers can share a group commit. Until then, other readers may
+    /// already see the change; the lock guards what each write replaced, which
+    /// is put back if its entry never becomes durable.
+    write_lock: Mutex<UndoLog>,
+    /// Serializes checkpoints, which write to the same snapshot file
+    compaction_lock: Mutex<()>,
+    /// Sequence number covered by the latest snapshot
+    checkpoint_sequence: AtomicU64,
+    /// When the WAL was last compacted, or the storage opened
+    last_compaction: Mutex<Instant>,
+    /// Number of WAL compactions that completed
+    compactions: AtomicU64,
+    /// Number of WAL compactions that failed
+    compaction_failures: AtomicU64,
+    /// What recovering from the WAL found when the storage was opened
+    recovery_report: RecoveryReport,
 }
 
 impl PersistentStorage {
@@ -21,17 +51,8 @@ impl PersistentStorage {
     /// # Errors
     /// Returns an error if the WAL file cannot be created or accessed.
     pub fn new(wal_file_path: impl AsRef<Path>) -> StorageResult<Self> {
-        let wal_manager = Arc::new(WalManager::new(wal_file_path)?);
-        let memory_storage = MemoryStorage::new();
-
-        let mut storage = Self {
-            memory_storage,
-            wal_manager,
-        };
-
-        storage.recover_from_wal()?;
-
-        Ok(storage)
+        let wal_manager = WalManager::new(&wal_file_path)?;
+        Self::recover_with(wal_file_path.as_ref(), wal_manager, MemoryStorage::new())
     }
 
     /// Create a new persistent storage with custom capacity and WAL settings
@@ -43,98 +64,320 @@ impl PersistentStorage {
         memory_capacity: usize,
         use_checksums: bool,
     ) -> StorageResult<Self> {
-        let wal_manager = Arc::new(WalManager::new_with_options(wal_file_path, use_checksums)?);
+        let wal_manager = WalManager::new_with_options(&wal_file_path, use_checksums)?;
         let memory_storage = MemoryStorage::with_capacity(memory_capacity);
+        Self::recover_with(wal_file_path.as_ref(), wal_manager, memory_storage)
+    }
+
+    /// Create a new persistent storage whose WAL is opened with `options`
+    ///
+    /// # Errors
+    /// Returns an error if the WAL file cannot be created, accessed or replayed.
+    pub fn with_wal_options(
+        wal_file_path: impl AsRef<Path>,
+        options: &WalOptions,
+    ) -> StorageResult<Self> {
+        let wal_manager = WalManager::open(&wal_file_path, options)?;
+        Self::recover_with(wal_file_path.as_ref(), wal_manager, MemoryStorage::new())
+    }
+
+    /// Recover the data as it was at `target` from the snapshot and WAL at
+    /// `source`, into a new persistent storage whose WAL is opened at `output`
+    ///
+    /// The source snapshot and WAL are only read, never modified. The recovered
+    /// data is written to `output` as a checkpoint, so the new storage can be
+    /// reopened with [`PersistentStorage::with_wal_options`] and written to like
+    /// any other. Sequence numbers continue from the last entry recovered.
+    ///
+    /// # Errors
+    /// Returns an error if `output` is the source or already holds data, if the
+    /// source snapshot is newer than `target`, or if the source cannot be read
+    /// or the output cannot be written.
+    pub fn recover_to(
+        source: impl AsRef<Path>,
+        target: RecoveryTarget,
+        output: impl AsRef<Path>,
+        options: &WalOptions,
+    ) -> StorageResult<Self> {
+        let (source, output) = (source.as_ref(), output.as_ref());
+        if source == output {
+            return Err(StorageError::Internal(
+                "Point-in-time recovery needs an output WAL separate from the source".to_string(),
+            ));
+        }
 
+        let mut storage = Self::with_wal_options(output, options)?;
+        let existing = &storage.recovery_report;
+        if existing.snapshot_used() || existing.entries_read > 0 {
+            return Err(StorageError::Internal(format!(
+                "Output WAL {} already holds data",
+                output.display()
+            )));
+        }
+
+        storage.recover_until(source, target)?;
+        storage.compact_wal()?;
+
+        Ok(storage)
+    }
+
+    /// Replay the snapshot and WAL at `source` up to `target` into memory
+    fn recover_until(&mut self, source: &Path, target: RecoveryTarget) -> StorageResult<()> {
+        info!(
+            "Starting point-in-time recovery from {:?} up to {}",
+            source, target
+        );
+        let started = Instant::now();
+        let mut report = RecoveryReport {
+            target: Some(target),
+            ..RecoveryReport::default()
+        };
+
+        let covered = match snapshot::read(&snapshot::path_for(source), self.wal_manager.keys())? {
+            Some(snapshot) => {
+                let newer = match target {
+                    RecoveryTarget::Sequence(sequence_number) => {
+                        snapshot.sequence_number > sequence_number
+                    }
+                    // A snapshot that does not know its age may be newer
+                    RecoveryTarget::Time(time) => snapshot
+                        .created_at
+                        .is_none_or(|created_at| created_at > time),
+                };
+                if newer {
+                    return Err(StorageError::Internal(format!(
+                        "Cannot recover to {target}: the snapshot of {} already covers sequence {}",
+                        source.display(),
+                        snapshot.sequence_number
+                    )));
+                }
+
+                report.snapshot_keys = snapshot.entries.len();
+                report.snapshot_sequence_number = Some(snapshot.sequence_number);
+                self.restore_snapshot(snapshot)?
+            }
+            None => 0,
+        };
+
+        let mut entries = WalManager::read_segments(source, self.wal_manager.keys())?;
+        report.entries_read = entries.len();
+        entries.retain(|entry| entry.sequence_number > covered);
+
+        let mut end = entries.len();
+        for (index, entry) in entries.iter().enumerate() {
+            if !target.includes(entry)? {
+                end = index;
+                break;
+            }
+        }
+        entries.truncate(end);
+        report.entries_skipped = report.entries_read - entries.len();
+
+        self.replay_entries(&entries, &mut report);
+        self.memory_storage.remove_expired()?;
+
+        let last = entries
+            .last()
+            .map_or(covered, |entry| entry.sequence_number);
+        self.wal_manager.advance_sequence_number(last)?;
+        report.last_sequence_number = self.wal_manager.current_sequence_number()?;
+        report.duration = started.elapsed();
+
+        info!(
+            "Point-in-time recovery replayed {} entries up to sequence {}",
+            report.entries_applied, last
+        );
+        if report.entries_failed > 0 {
+            warn!(
+                "Point-in-time recovery completed with {} failed operations",
+                report.entries_failed
+            );
+        }
+
+        self.recovery_report = report;
+        Ok(())
+    }
+
+    /// Build the storage around an opened WAL and recover its data
+    fn recover_with(
+        wal_file_path: &Path,
+        wal_manager: WalManager,
+        memory_storage: MemoryStorage,
+    ) -> StorageResult<Self> {
         let mut storage = Self {
             memory_storage,
-            wal_manager,
+            wal_manager: Arc::new(wal_manager),
+            snapshot_path: snapshot::path_for(wal_file_path),
+            write_lock: Mutex::new(UndoLog::default()),
+            compaction_lock: Mutex::new(()),
+            checkpoint_sequence: AtomicU64::new(0),
+            last_compaction: Mutex::new(Instant::now()),
+            compactions: AtomicU64::new(0),
+            compaction_failures: AtomicU64::new(0),
+            recovery_report: RecoveryReport::default(),
         };
 
-        storage.recover_from_wal()?;
+        let resealing = storage.recover_from_wal()?;
+
+        // Re-encrypt whatever is still under a rotated-out key right away
+        if resealing {
+            info!("Compacting WAL to re-encrypt it with the current key");
+            storage.compact_wal()?;
+        }
 
         Ok(storage)
     }
 
-    /// Recover data from the Write-Ahead Log
-    fn recover_from_wal(&mut self) -> StorageResult<()> {
+    /// Recover data from the latest snapshot and the Write-Ahead Log after it
+    ///
+    /// Returns whether anything read was sealed with a key that has been
+    /// rotated out.
+    fn recover_from_wal(&mut self) -> StorageResult<bool> {
         info!("Starting WAL recovery...");
+        let started = Instant::now();
+        let mut report = RecoveryReport {
+            mode: self.wal_manager.recovery_mode(),
+            ..RecoveryReport::default()
+        };
 
-        let entries = self.wal_manager.read_all_entries()?;
+        let mut resealing = false;
+        let covered = match snapshot::load(&self.snapshot_path, self.wal_manager.keys())? {
+            Some(snapshot) => {
+                resealing = snapshot.sealed_with_previous_key;
+                report.snapshot_keys = snapshot.entries.len();
+                report.snapshot_sequence_number = Some(snapshot.sequence_number);
+                self.restore_snapshot(snapshot)?
+            }
+            None => 0,
+        };
+
+        let recovery = self.wal_manager.recover_entries()?;
+        resealing |= recovery.sealed_with_previous_key;
+        report.entries_read = recovery.entries.len();
+        report.discarded = recovery.discarded;
+        if !report.discarded.is_empty() {
+            warn!(
+                "Discarded {} damaged WAL records ({} bytes) during recovery",
+                report.discarded.len(),
+                report.bytes_discarded()
+            );
+        }
+
+        let mut entries = recovery.entries;
+        // Entries up to the snapshot may still be around if a checkpoint was interrupted
+        entries.retain(|entry| entry.sequence_number > covered);
+        report.entries_skipped = report.entries_read - entries.len();
+        self.wal_manager.advance_sequence_number(covered)?;
+        self.checkpoint_sequence.store(covered, Ordering::Release);
 
         if entries.is_empty() {
             info!("No WAL entries found, starting with empty storage");
-            return Ok(());
+        } else {
+            info!("Recovering {} entries from WAL", entries.len());
+            self.replay_entries(&entries, &mut report);
         }
 
-        info!("Recovering {} entries from WAL", entries.len());
-
-        let mut recovered_ops = 0;
-        let mut failed_ops = 0;
+        // Values whose TTL ran out while the process was down must not come back
+        let expired = self.memory_storage.remove_expired()?;
+        if !expired.is_empty() {
+            info!(
+                "Dropped {} keys that expired before recovery",
+                expired.len()
+            );
+        }
 
-        self.recover(&entries, &mut recovered_ops, &mut failed_ops);
+        report.last_sequence_number = self.wal_manager.current_sequence_number()?;
+        report.duration = started.elapsed();
 
-        if failed_ops > 0 {
+        if report.entries_failed > 0 {
             warn!(
                 "WAL recovery completed with {} failed operations out of {} total",
-                failed_ops,
+                report.entries_failed,
                 entries.len()
             );
-        } else {
+        } else if !entries.is_empty() {
             info!(
                 "WAL recovery completed successfully: {} operations recovered",
-                recovered_ops
+                report.entries_applied
             );
         }
 
-        Ok(())
+        self.recovery_report = report;
+        Ok(resealing)
     }
 
-    fn recover(
-        &mut self,
-        entries: &Vec<super::wal::WalEntry>,
-        recovered_ops: &mut i32,
-        failed_ops: &mut i32,
-    ) {
+    /// Load a snapshot into memory and return the sequence number it covers
+    fn restore_snapshot(&self, snapshot: Snapshot) -> StorageResult<u64> {
+        info!(
+            "Restoring {} keys from snapshot {:?} covering WAL sequence {}",
+            snapshot.entries.len(),
+            self.snapshot_path,
+            snapshot.sequence_number
+        );
+
+        self.memory_storage.retire_version(snapshot.retired_version);
+        for entry in snapshot.entries {
+            self.memory_storage.put_versioned(
+                &entry.key,
+                &entry.value,
+                entry.expires_at,
+                entry.version,
+            )?;
+        }
+
+        Ok(snapshot.sequence_number)
+    }
+
+    /// Apply WAL entries to memory in order, counting them in `report`
+    fn replay_entries(&self, entries: &[WalEntry], report: &mut RecoveryReport) {
         for entry in entries {
-            match &entry.operation {
-                WalOperation::Put { key, value } => match self.memory_storage.put(key, value) {
-                    Ok(_) => {
-                        *recovered_ops += 1;
-                        debug!("Recovered PUT operation: key={}", key);
-                    }
-                    Err(e) => {
-                        *failed_ops += 1;
-                        warn!("Failed to recover PUT operation for key '{}': {}", key, e);
-                    }
-                },
-                WalOperation::Delete { key } => match self.memory_storage.delete(key) {
-                    Ok(_) => {
-                        *recovered_ops += 1;
-                        debug!("Recovered DELETE operation: key={}", key);
-                    }
-                    Err(e) => {
-                        *failed_ops += 1;
-                        warn!(
-                            "Failed to recover DELETE operation for key '{}': {}",
-                            key, e
-                        );
-                    }
-                },
-                WalOperation::Clear => match self.memory_storage.clear() {
-                    Ok(()) => {
-                        *recovered_ops += 1;
-                        debug!("Recovered CLEAR operation");
-                    }
-                    Err(e) => {
-                        *failed_ops += 1;
-                        warn!("Failed to recover CLEAR operation: {}", e);
-                    }
-                },
+            match self.replay(&entry.operation) {
+                Ok(()) => {
+                    report.entries_applied += 1;
+                    debug!(
+                        "Recovered {} operation: sequence={}",
+                        operation_name(&entry.operation),
+                        entry.sequence_number
+                    );
+                }
+                Err(e) => {
+                    report.entries_failed += 1;
+                    warn!(
+                        "Failed to recover {} operation at sequence {}: {}",
+                        operation_name(&entry.operation),
+                        entry.sequence_number,
+                        e
+                    );
+                }
             }
         }
     }
 
+    /// Apply a single logged operation to memory
+    fn replay(&self, operation: &WalOperation) -> StorageResult<()> {
+        match operation {
+            WalOperation::Put {
+                key,
+                value,
+                expires_at,
+                version,
+            } => self
+                .apply_put(key, value, *expires_at, *version)
+                .map(|_| ()),
+            WalOperation::Delete { key } | WalOperation::Expire { key } => {
+                self.memory_storage.delete(key).map(|_| ())
+            }
+            WalOperation::Batch { operations } => self.apply_batch(operations),
+            WalOperation::Clear => self.memory_storage.clear(),
+        }
+    }
+
+    /// What recovering from the WAL found when the storage was opened
+    #[must_use]
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
     /// Get statistics including WAL information
     ///
     /// # Errors
@@ -142,46 +385,148 @@ impl PersistentStorage {
     pub fn detailed_stats(&self) -> StorageResult<DetailedStats> {
         let memory_stats = self.memory_storage.stats()?;
         let wal_sequence = self.wal_manager.current_sequence_number()?;
+        let segments = self.wal_manager.segments()?;
 
         Ok(DetailedStats {
             memory_stats,
             wal_file_path: self.wal_manager.file_pat().to_string(),
             wal_sequence_number: wal_sequence,
+            durability: self.wal_manager.durability(),
+            wal_segment_count: segments.len(),
+            wal_bytes: segments.iter().map(|segment| segment.bytes).sum(),
+            compression: self.wal_manager.compression(),
+            compression_stats: self.wal_manager.compression_stats(),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            compaction_failures: self.compaction_failures.load(Ordering::Relaxed),
         })
     }
 
-    /// Compact the WAL by creating a snapshot and truncating the log
-    /// WARNING: This is a potentially dangerous operation that should be used carefully
+    /// The figures a [`CompactionPolicy`] decides on
+    ///
+    /// # Errors
+    /// Returns an error if the WAL segments or storage statistics cannot be read.
+    pub fn wal_state(&self) -> StorageResult<WalState> {
+        let wal_bytes = self
+            .wal_manager
+            .segments()?
+            .iter()
+            .map(|segment| segment.bytes)
+            .sum();
+        let wal_entries = self
+            .wal_manager
+            .current_sequence_number()?
+            .saturating_sub(self.checkpoint_sequence.load(Ordering::Acquire));
+        let since_last_compaction = self
+            .last_compaction
+            .lock()
+            .map_err(|_| StorageError::Internal("Failed to acquire compaction lock".to_string()))?
+            .elapsed();
+
+        Ok(WalState {
+            wal_bytes,
+            wal_entries,
+            live_keys: self.memory_storage.stats()?.key_count,
+            since_last_compaction,
+        })
+    }
+
+    /// Start compacting the WAL in the background whenever `policy` says so
+    ///
+    /// Compaction stops when the returned worker is dropped.
     ///
     /// # Errors
-    /// Returns an error if the WAL compaction fails or if storage operations fail.
+    /// Returns an error if the background thread cannot be started.
+    pub fn start_compaction(
+        self: &Arc<Self>,
+        policy: CompactionPolicy,
+    ) -> StorageResult<CompactionWorker> {
+        CompactionWorker::spawn(self, policy)
+    }
+
+    /// Compact the WAL with a checkpoint
+    ///
+    /// The current data is written to a snapshot, which is synced and atomically
+    /// renamed into place before the WAL segments it covers are deleted. A crash
+    /// at any point leaves either the previous snapshot with the full WAL, or the
+    /// new snapshot with the WAL after it. Writes can continue while the snapshot
+    /// is written; they go to a new WAL segment.
+    ///
+    /// The snapshot is encrypted with the current key, so after a key rotation
+    /// no data is left under the previous keys once compaction has run.
+    ///
+    /// # Errors
+    /// Returns an error if the snapshot cannot be written or old WAL segments
+    /// cannot be deleted.
     pub fn compact_wal(&self) -> StorageResult<CompactionResult> {
-        info!("Starting WAL compaction...");
+        let _compacting = self
+            .compaction_lock
+            .lock()
+            .map_err(|_| StorageError::Internal("Failed to acquire compaction lock".to_string()))?;
+
+        match self.checkpoint() {
+            Ok(result) => {
+                self.compactions.fetch_add(1, Ordering::Relaxed);
+                if let Ok(mut last_compaction) = self.last_compaction.lock() {
+                    *last_compaction = Instant::now();
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                self.compaction_failures.fetch_add(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
 
-        let all_data = self.memory_storage.all()?;
-        let entries_before = self.wal_manager.read_all_entries()?.len();
+    /// Write a snapshot and delete the WAL segments it covers
+    fn checkpoint(&self) -> StorageResult<CompactionResult> {
+        info!("Starting WAL checkpoint...");
 
-        // Truncate the WAL
-        self.wal_manager.truncate()?;
+        let (sequence_number, data, retired_version) = {
+            let mut undo = self.lock_writes()?;
 
-        // Re-write all current data to the WAL
-        let mut rewritten_entries = 0;
-        for (key, value) in all_data {
-            self.wal_manager.log_operation(WalOperation::Put {
-                key,
-                value: value.value,
-            })?;
-            rewritten_entries += 1;
-        }
+            // The snapshot must not hold writes that could still be undone
+            undo.wait_durable(&self.wal_manager)?;
+
+            // Every entry up to here is reflected in memory and stays in the sealed segments
+            let (data, retired_version) = self.memory_storage.snapshot()?;
+            self.wal_manager.seal_segment()?;
+            (
+                self.wal_manager.current_sequence_number()?,
+                data,
+                retired_version,
+            )
+        };
+
+        let snapshot_bytes = snapshot::write(
+            &self.snapshot_path,
+            sequence_number,
+            retired_version,
+            &data,
+            self.wal_manager.compression(),
+            self.wal_manager.keys(),
+        )?;
+        let previous = self
+            .checkpoint_sequence
+            .swap(sequence_number, Ordering::AcqRel);
+        let entries_before =
+            usize::try_from(sequence_number.saturating_sub(previous)).unwrap_or(usize::MAX);
+        let segments_deleted = self.wal_manager.delete_segments_through(sequence_number)?;
 
         info!(
-            "WAL compaction completed: {} entries before, {} entries after",
-            entries_before, rewritten_entries
+            "WAL checkpoint completed at sequence {}: {} entries before, {} keys in a {} byte snapshot, {} segments deleted",
+            sequence_number,
+            entries_before,
+            data.len(),
+            snapshot_bytes,
+            segments_deleted
         );
 
         Ok(CompactionResult {
             entries_before,
-            entries_after: rewritten_entries,
+            entries_after: data.len(),
+            sequence_number,
+            segments_deleted,
         })
     }
 
@@ -189,16 +534,257 @@ impl PersistentStorage {
     pub fn wal_file_path(&self) -> &str {
         self.wal_manager.file_pat()
     }
+
+    fn lock_writes(&self) -> StorageResult<MutexGuard<'_, UndoLog>> {
+        self.write_lock
+            .lock()
+            .map_err(|_| StorageError::Internal("Failed to acquire write lock".to_string()))
+    }
+
+    /// Replay a logged put, deriving the version for entries written without one
+    fn apply_put(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+        version: Option<u64>,
+    ) -> StorageResult<bool> {
+        match version {
+            Some(version) => self
+                .memory_storage
+                .put_versioned(key, value, expires_at, version),
+            None => self.memory_storage.put_with_expiry(key, value, expires_at),
+        }
+    }
+
+    /// Replay a logged batch in one step so a partial batch is never visible
+    fn apply_batch(&self, operations: &[WalOperation]) -> StorageResult<()> {
+        let mut batch = Vec::with_capacity(operations.len());
+        let mut versions = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            match operation {
+                WalOperation::Put {
+                    key,
+                    value,
+                    version,
+                    ..
+                } => {
+                    batch.push(BatchOperation::Put {
+                        key: key.clone(),
+                        value: value.clone().into(),
+                    });
+                    versions.push(*version);
+                }
+                WalOperation::Delete { key } => {
+                    batch.push(BatchOperation::Delete { key: key.clone() });
+                    versions.push(None);
+                }
+                other => {
+                    return Err(StorageError::Internal(format!(
+                        "Unexpected operation in WAL batch: {other:?}"
+                    )));
+                }
+            }
+        }
+
+        self.memory_storage.apply_batch(&batch, &versions)
+    }
+
+    /// Append `operation` to the WAL and `apply` it in memory under the write lock,
+    /// then release the lock and wait until the entry is durable
+    ///
+    /// If the entry never becomes durable, `replaced` is put back along with
+    /// what every later write replaced, since their entries are lost too.
+    fn commit<T>(
+        &self,
+        undo: MutexGuard<'_, UndoLog>,
+        operation: WalOperation,
+        replaced: Replaced,
+        apply: impl FnOnce() -> StorageResult<T>,
+    ) -> StorageResult<T> {
+        let pending = self.wal_manager.append(operation)?;
+        let result = apply();
+
+        self.finish(undo, pending, replaced)?;
+        result
+    }
+
+    /// Record what the write logged as `pending` replaced, release the write
+    /// lock and wait until the entry is durable, undoing it if it never is
+    fn finish(
+        &self,
+        mut undo: MutexGuard<'_, UndoLog>,
+        pending: PendingWrite,
+        replaced: Replaced,
+    ) -> StorageResult<()> {
+        undo.record(&self.wal_manager, pending, replaced)?;
+        drop(undo);
+
+        if let Err(e) = self.wal_manager.wait_durable(&pending) {
+            self.undo_lost_writes(&mut *self.lock_writes()?)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Put back what the writes whose WAL entries were lost replaced
+    fn undo_lost_writes(&self, undo: &mut UndoLog) -> StorageResult<()> {
+        let lost = undo.take_lost(&self.wal_manager)?;
+        if !lost.is_empty() {
+            warn!("Undoing {} writes whose WAL entries were lost", lost.len());
+        }
+
+        for replaced in lost {
+            self.memory_storage.restore(replaced)?;
+        }
+        Ok(())
+    }
+
+    /// Log and apply a put with an explicit version
+    ///
+    /// Callers pass in the write lock so the version cannot change between
+    /// being computed and being logged.
+    fn log_put(
+        &self,
+        undo: MutexGuard<'_, UndoLog>,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+        version: u64,
+    ) -> StorageResult<bool> {
+        let operation = WalOperation::Put {
+            key: key.to_string(),
+            value: value.to_vec(),
+            expires_at,
+            version: Some(version),
+        };
+
+        let replaced = self.memory_storage.stored([key])?;
+        self.commit(undo, operation, replaced, || {
+            self.memory_storage
+                .put_versioned(key, value, expires_at, version)
+        })
+    }
+
+    /// Log and apply a put with an optional absolute expiry
+    fn put_with_expiry(
+        &self,
+        key: &str,
+        value: &[u8],
+        expires_at: Option<DateTime<Utc>>,
+    ) -> StorageResult<bool> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let guard = self.lock_writes()?;
+
+        let version = self.memory_storage.next_version(key)?;
+        self.log_put(guard, key, value, expires_at, version)
+    }
 }
 
 impl StorageEngine for PersistentStorage {
-    fn put(&self, key: &str, value: &str) -> StorageResult<bool> {
-        self.wal_manager.log_operation(WalOperation::Put {
-            key: key.to_string(),
-            value: value.to_string(),
-        })?;
+    fn put(&self, key: &str, value: &[u8]) -> StorageResult<bool> {
+        self.put_with_expiry(key, value, None)
+    }
+
+    fn put_with_ttl(&self, key: &str, value: &[u8], ttl: Duration) -> StorageResult<bool> {
+        self.put_with_expiry(key, value, Some(expiry_from_ttl(ttl)?))
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+    ) -> StorageResult<u64> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let guard = self.lock_writes()?;
+
+        let current = self
+            .memory_storage
+            .version_of(key)?
+            .ok_or_else(|| StorageError::KeyNotFound(key.to_string()))?;
+
+        if current != expected_version {
+            return Err(StorageError::VersionMismatch {
+                key: key.to_string(),
+                expected: expected_version,
+                actual: current,
+            });
+        }
+
+        self.log_put(guard, key, value, None, current + 1)?;
+        Ok(current + 1)
+    }
+
+    fn put_if_absent(&self, key: &str, value: &[u8]) -> StorageResult<u64> {
+        validate_key(key)?;
+        validate_value(value)?;
+
+        let guard = self.lock_writes()?;
+
+        if self.memory_storage.version_of(key)?.is_some() {
+            return Err(StorageError::KeyAlreadyExists(key.to_string()));
+        }
+
+        let version = self.memory_storage.next_version(key)?;
+        self.log_put(guard, key, value, None, version)?;
+        Ok(version)
+    }
+
+    fn write_batch(&self, operations: &[BatchOperation]) -> StorageResult<()> {
+        validate_batch(operations)?;
+
+        if operations.is_empty() {
+            return Ok(());
+        }
 
-        self.memory_storage.put(key, value)
+        let guard = self.lock_writes()?;
+
+        // Pin every put's version up front so replay assigns exactly the same ones.
+        // A key deleted in the batch keeps its next version, so it is not reused.
+        let mut pending: HashMap<&str, u64> = HashMap::new();
+        let mut logged = Vec::with_capacity(operations.len());
+        let mut versions = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            match operation {
+                BatchOperation::Put { key, value } => {
+                    let version = match pending.get(key.as_str()) {
+                        Some(version) => *version,
+                        None => self.memory_storage.next_version(key)?,
+                    };
+                    pending.insert(key, version + 1);
+
+                    logged.push(WalOperation::Put {
+                        key: key.clone(),
+                        value: value.to_vec(),
+                        expires_at: None,
+                        version: Some(version),
+                    });
+                    versions.push(Some(version));
+                }
+                BatchOperation::Delete { key } => {
+                    if !pending.contains_key(key.as_str()) {
+                        pending.insert(key, self.memory_storage.next_version(key)?);
+                    }
+                    logged.push(WalOperation::Delete { key: key.clone() });
+                    versions.push(None);
+                }
+            }
+        }
+
+        let replaced = self.memory_storage.stored(pending.keys().copied())?;
+        self.commit(
+            guard,
+            WalOperation::Batch { operations: logged },
+            replaced,
+            || self.memory_storage.apply_batch(operations, &versions),
+        )
     }
 
     fn get(&self, key: &str) -> StorageResult<Value> {
@@ -206,11 +792,17 @@ impl StorageEngine for PersistentStorage {
     }
 
     fn delete(&self, key: &str) -> StorageResult<bool> {
-        self.wal_manager.log_operation(WalOperation::Delete {
+        validate_key(key)?;
+
+        let guard = self.lock_writes()?;
+        let operation = WalOperation::Delete {
             key: key.to_string(),
-        })?;
+        };
+        let replaced = self.memory_storage.stored([key])?;
 
-        self.memory_storage.delete(key)
+        self.commit(guard, operation, replaced, || {
+            self.memory_storage.delete(key)
+        })
     }
 
     fn exists(&self, key: &str) -> StorageResult<bool> {
@@ -229,12 +821,73 @@ impl StorageEngine for PersistentStorage {
         self.memory_storage.all()
     }
 
+    fn scan(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        self.memory_storage.scan(start, end, limit)
+    }
+
+    fn scan_keys(
+        &self,
+        start: Option<&str>,
+        end: Option<&str>,
+        limit: usize,
+    ) -> StorageResult<Vec<String>> {
+        self.memory_storage.scan_keys(start, end, limit)
+    }
+
+    fn iter_from(&self, start: Option<&str>, batch_size: usize) -> ScanBatches<'_> {
+        self.memory_storage.iter_from(start, batch_size)
+    }
+
+    fn scan_prefix(
+        &self,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> StorageResult<Vec<(String, Value)>> {
+        self.memory_storage.scan_prefix(prefix, limit)
+    }
+
     fn clear(&self) -> StorageResult<()> {
-        self.wal_manager.log_operation(WalOperation::Clear)?;
+        let mut undo = self.lock_writes()?;
 
+        // Undoing a clear would mean keeping a copy of everything, so it is
+        // only applied once its entry is durable
+        if let Err(e) = self.wal_manager.log_operation(WalOperation::Clear) {
+            self.undo_lost_writes(&mut undo)?;
+            return Err(e);
+        }
         self.memory_storage.clear()
     }
 
+    fn purge_expired(&self) -> StorageResult<usize> {
+        let mut undo = self.lock_writes()?;
+
+        let expired = self.memory_storage.remove_expired()?;
+        let count = expired.len();
+        let replaced: Replaced = expired
+            .into_iter()
+            .map(|(key, value)| (key, Some(value)))
+            .collect();
+
+        let expiries = replaced
+            .iter()
+            .map(|(key, _)| WalOperation::Expire { key: key.clone() });
+        match self.wal_manager.append_all(expiries) {
+            Ok(Some(pending)) => self.finish(undo, pending, replaced)?,
+            Ok(None) => {}
+            Err(e) => {
+                self.memory_storage.restore(replaced)?;
+                self.undo_lost_writes(&mut undo)?;
+                return Err(e);
+            }
+        }
+        Ok(count)
+    }
+
     fn stats(&self) -> StorageResult<Stats> {
         self.memory_storage.stats()
     }
@@ -244,6 +897,17 @@ impl StorageEngine for PersistentStorage {
     }
 }
 
+/// Name of a logged operation, for recovery logs
+fn operation_name(operation: &WalOperation) -> &'static str {
+    match operation {
+        WalOperation::Put { .. } => "PUT",
+        WalOperation::Delete { .. } => "DELETE",
+        WalOperation::Expire { .. } => "EXPIRE",
+        WalOperation::Clear => "CLEAR",
+        WalOperation::Batch { .. } => "BATCH",
+    }
+}
+
 /// Detailed statistics including WAL information
 #[derive(Debug, Clone, PartialEq)]
 pub struct DetailedStats {
@@ -253,29 +917,116 @@ pub struct DetailedStats {
     pub wal_file_path: String,
     /// Current WAL sequence number
     pub wal_sequence_number: u64,
+    /// When WAL writes are forced to stable storage
+    pub durability: Durability,
+    /// Number of segment files the WAL consists of
+    pub wal_segment_count: usize,
+    /// Total size of the WAL segment files in bytes
+    pub wal_bytes: u64,
+    /// How new WAL records and snapshots are compressed
+    pub compression: Compression,
+    /// What compression saved on the WAL records written since the storage was opened
+    pub compression_stats: CompressionStats,
+    /// Number of WAL compactions that completed, manual or automatic
+    pub compactions: u64,
+    /// Number of WAL compactions that failed
+    pub compaction_failures: u64,
+}
+
+/// What recovering from the snapshot and WAL found when the storage was opened
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecoveryReport {
+    /// How damaged WAL records were handled
+    pub mode: RecoveryMode,
+    /// Where replay stopped, for point-in-time recovery
+    pub target: Option<RecoveryTarget>,
+    /// Sequence number covered by the snapshot recovery started from, if there was one
+    pub snapshot_sequence_number: Option<u64>,
+    /// Number of keys restored from the snapshot
+    pub snapshot_keys: usize,
+    /// Number of entries read from the WAL
+    pub entries_read: usize,
+    /// Number of WAL entries applied to memory
+    pub entries_applied: usize,
+    /// Number of WAL entries that could not be applied
+    pub entries_failed: usize,
+    /// Number of WAL entries not replayed because the snapshot already covers
+    /// them or they are past the recovery target
+    pub entries_skipped: usize,
+    /// Damaged WAL records that were left out, in log order
+    pub discarded: Vec<DiscardedRecord>,
+    /// Sequence number the WAL continues from
+    pub last_sequence_number: u64,
+    /// How long recovery took
+    pub duration: Duration,
+}
+
+impl RecoveryReport {
+    /// Whether every WAL record could be read and applied
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.discarded.is_empty() && self.entries_failed == 0
+    }
+
+    /// Whether recovery started from a snapshot
+    #[must_use]
+    pub fn snapshot_used(&self) -> bool {
+        self.snapshot_sequence_number.is_some()
+    }
+
+    /// Total number of bytes left out of the WAL
+    #[must_use]
+    pub fn bytes_discarded(&self) -> u64 {
+        self.discarded.iter().map(|record| record.bytes).sum()
+    }
 }
 
 /// Result of a WAL compaction operation
 #[derive(Debug, Clone, PartialEq)]
 pub struct CompactionResult {
-    /// Number of entries in WAL before compaction
+    /// Number of entries in WAL since the previous checkpoint
     pub entries_before: usize,
-    /// Number of entries in WAL after compaction
+    /// Number of keys in the snapshot written by the compaction
     pub entries_after: usize,
+    /// Sequence number of the last WAL entry covered by the snapshot
+    pub sequence_number: u64,
+    /// Number of WAL segments deleted because the snapshot covers them
+    pub segments_deleted: usize,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::NamedTempFile;
+    use crate::storage::conformance::storage_engine_tests;
+    use crate::storage::engine::INITIAL_VERSION;
+    use crate::storage::wal::fault::{self, FaultState};
+    use crate::storage::wal::{EncryptionKey, Keyring};
+    use bytes::Bytes;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// A WAL path in a fresh directory, so its segments are removed with it
+    fn temp_wal() -> (TempDir, PathBuf) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        (temp_dir, wal_path)
+    }
+
+    fn temp_storage() -> (TempDir, PersistentStorage) {
+        let (temp_dir, wal_path) = temp_wal();
+        let storage = PersistentStorage::new(&wal_path).unwrap();
+        (temp_dir, storage)
+    }
+
+    storage_engine_tests!(temp_storage);
 
     #[test]
     fn test_persistent_storage_basic_operations() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let storage = PersistentStorage::new(temp_file.path()).unwrap();
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = PersistentStorage::new(&temp_path).unwrap();
 
         // Test put operation
-        let was_new = storage.put("test_key", "test_value").unwrap();
+        let was_new = storage.put("test_key", b"test_value").unwrap();
         assert!(was_new);
 
         // Test get operation
@@ -294,14 +1045,13 @@ mod tests {
 
     #[test]
     fn test_persistent_storage_recovery() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let temp_path = temp_file.path().to_path_buf();
+        let (_temp_dir, temp_path) = temp_wal();
 
         // Create storage, add some data, then drop it
         {
             let storage = PersistentStorage::new(&temp_path).unwrap();
-            storage.put("key1", "value1").unwrap();
-            storage.put("key2", "value2").unwrap();
+            storage.put("key1", b"value1").unwrap();
+            storage.put("key2", b"value2").unwrap();
             storage.delete("key1").unwrap();
         }
 
@@ -316,14 +1066,255 @@ mod tests {
         assert_eq!(retrieved.value, "value2");
     }
 
+    #[test]
+    fn test_persistent_storage_binary_recovery() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let payload = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff];
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("image", &payload).unwrap();
+        }
+
+        let recovered_storage = PersistentStorage::new(&temp_path).unwrap();
+        let retrieved = recovered_storage.get("image").unwrap();
+        assert_eq!(retrieved.value.as_ref(), &payload);
+    }
+
+    #[test]
+    fn test_persistent_storage_ttl_survives_restart() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage
+                .put_with_ttl("session", b"active", Duration::from_secs(3600))
+                .unwrap();
+            storage
+                .put_with_expiry(
+                    "stale",
+                    b"gone",
+                    Some(Utc::now() + chrono::Duration::milliseconds(20)),
+                )
+                .unwrap();
+        }
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        let recovered_storage = PersistentStorage::new(&temp_path).unwrap();
+        assert!(!recovered_storage.exists("stale").unwrap());
+
+        let session = recovered_storage.get("session").unwrap();
+        assert!(session.metadata.expires_at.is_some());
+        assert!(session.metadata.ttl_remaining().unwrap() > Duration::from_secs(3500));
+    }
+
+    #[test]
+    fn test_persistent_storage_purge_logs_expiry() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = PersistentStorage::new(&temp_path).unwrap();
+
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        storage
+            .put_with_expiry("old", b"value", Some(past))
+            .unwrap();
+        storage.put("kept", b"value").unwrap();
+
+        assert_eq!(storage.purge_expired().unwrap(), 1);
+
+        let entries = storage.wal_manager.read_all_entries().unwrap();
+        assert_eq!(
+            entries.last().unwrap().operation,
+            WalOperation::Expire {
+                key: "old".to_string()
+            }
+        );
+    }
+
+    /// Storage on a WAL whose syncs can be made to fail
+    fn faulty_storage(wal_path: &Path) -> (PersistentStorage, Arc<FaultState>) {
+        let (wal_manager, faults) = fault::open_faulty(wal_path, Durability::Always);
+        let storage =
+            PersistentStorage::recover_with(wal_path, wal_manager, MemoryStorage::new()).unwrap();
+        (storage, faults)
+    }
+
+    #[test]
+    fn test_failed_sync_undoes_the_write() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let (storage, faults) = faulty_storage(&temp_path);
+
+        storage.put("kept", b"value").unwrap();
+        storage.put("deleted", b"value").unwrap();
+
+        faults.fail_sync.store(true, Ordering::SeqCst);
+        let batch = [
+            BatchOperation::Put {
+                key: "kept".to_string(),
+                value: Bytes::from("changed"),
+            },
+            BatchOperation::Put {
+                key: "lost".to_string(),
+                value: Bytes::from("value"),
+            },
+            BatchOperation::Delete {
+                key: "deleted".to_string(),
+            },
+        ];
+        assert!(storage.write_batch(&batch).is_err());
+
+        let kept = storage.get("kept").unwrap();
+        assert_eq!(kept.value, "value");
+        assert_eq!(kept.metadata.version, INITIAL_VERSION);
+        assert!(!storage.exists("lost").unwrap());
+        assert!(storage.exists("deleted").unwrap());
+
+        drop(storage);
+        fault::crash(&temp_path, &faults);
+        let recovered = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(recovered.get("kept").unwrap().value, "value");
+        assert!(!recovered.exists("lost").unwrap());
+        assert!(recovered.exists("deleted").unwrap());
+    }
+
+    #[test]
+    fn test_failed_sync_undoes_the_purge() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let (storage, faults) = faulty_storage(&temp_path);
+
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        storage
+            .put_with_expiry("old", b"value", Some(past))
+            .unwrap();
+
+        faults.fail_sync.store(true, Ordering::SeqCst);
+        assert!(storage.purge_expired().is_err());
+
+        let stored = storage.memory_storage.stored(["old"]).unwrap();
+        assert!(stored[0].1.is_some());
+        assert!(!storage.exists("old").unwrap());
+    }
+
+    #[test]
+    fn test_persistent_storage_versions_survive_restart() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            assert_eq!(storage.put_if_absent("counter", b"1").unwrap(), 1);
+            assert_eq!(storage.compare_and_swap("counter", 1, b"2").unwrap(), 2);
+            storage.put("counter", b"3").unwrap();
+
+            let result = storage.compare_and_swap("counter", 2, b"stale");
+            assert!(matches!(result, Err(StorageError::VersionMismatch { .. })));
+        }
+
+        let recovered_storage = PersistentStorage::new(&temp_path).unwrap();
+        let counter = recovered_storage.get("counter").unwrap();
+        assert_eq!(counter.value, "3");
+        assert_eq!(counter.metadata.version, 3);
+
+        // The failed swap must not have been logged
+        assert_eq!(
+            recovered_storage
+                .wal_manager
+                .read_all_entries()
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_removed_versions_survive_compaction() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("counter", b"1").unwrap();
+            storage.put("counter", b"2").unwrap();
+            storage.delete("counter").unwrap();
+            storage.compact_wal().unwrap();
+        }
+
+        // Neither the snapshot nor the WAL holds the key any more
+        let recovered_storage = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(recovered_storage.put_if_absent("counter", b"3").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_persistent_storage_batch_recovery() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("old", b"value").unwrap();
+            storage
+                .write_batch(&[
+                    BatchOperation::Put {
+                        key: "a".to_string(),
+                        value: Bytes::from_static(b"1"),
+                    },
+                    BatchOperation::Put {
+                        key: "b".to_string(),
+                        value: Bytes::from_static(b"2"),
+                    },
+                    BatchOperation::Delete {
+                        key: "old".to_string(),
+                    },
+                ])
+                .unwrap();
+
+            // The whole batch is a single WAL entry
+            assert_eq!(storage.wal_manager.read_all_entries().unwrap().len(), 2);
+        }
+
+        let recovered_storage = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(recovered_storage.get("a").unwrap().value, "1");
+        assert_eq!(recovered_storage.get("b").unwrap().value, "2");
+        assert!(!recovered_storage.exists("old").unwrap());
+    }
+
+    #[test]
+    fn test_persistent_storage_invalid_batch_is_not_replayed() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let wal_manager = WalManager::new(&temp_path).unwrap();
+            wal_manager
+                .log_operation(WalOperation::Batch {
+                    operations: vec![
+                        WalOperation::Put {
+                            key: "a".to_string(),
+                            value: b"1".to_vec(),
+                            expires_at: None,
+                            version: Some(1),
+                        },
+                        WalOperation::Delete { key: String::new() },
+                    ],
+                })
+                .unwrap();
+        }
+
+        // A batch that cannot be applied in full is skipped as a whole
+        let recovered_storage = PersistentStorage::new(&temp_path).unwrap();
+        assert!(!recovered_storage.exists("a").unwrap());
+
+        let report = recovered_storage.recovery_report();
+        assert_eq!(report.entries_read, 1);
+        assert_eq!(report.entries_applied, 0);
+        assert_eq!(report.entries_failed, 1);
+        assert!(!report.is_clean());
+    }
+
     #[test]
     fn test_persistent_storage_clear_operation() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let storage = PersistentStorage::new(temp_file.path()).unwrap();
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = PersistentStorage::new(&temp_path).unwrap();
 
         // Add some data
-        storage.put("key1", "value1").unwrap();
-        storage.put("key2", "value2").unwrap();
+        storage.put("key1", b"value1").unwrap();
+        storage.put("key2", b"value2").unwrap();
 
         // Verify data exists
         assert_eq!(storage.keys().unwrap().len(), 2);
@@ -339,29 +1330,154 @@ mod tests {
 
     #[test]
     fn test_persistent_storage_detailed_stats() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let storage = PersistentStorage::new(temp_file.path()).unwrap();
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = PersistentStorage::new(&temp_path).unwrap();
 
         // Add some data to get meaningful stats
-        storage.put("test", "value").unwrap();
+        storage.put("test", b"value").unwrap();
 
         let detailed_stats = storage.detailed_stats().unwrap();
         assert_eq!(detailed_stats.memory_stats.key_count, 1);
         assert!(detailed_stats.wal_sequence_number > 0);
         assert!(!detailed_stats.wal_file_path.is_empty());
+        assert_eq!(detailed_stats.durability, Durability::Os);
+        assert_eq!(detailed_stats.wal_segment_count, 1);
+        assert!(detailed_stats.wal_bytes > 0);
+    }
+
+    #[test]
+    fn test_persistent_storage_compression_stats() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let options = WalOptions::default().with_compression(Compression::Zstd(3));
+        let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+
+        let document = br#"{"name":"John Doe","email":"john@example.com","active":true}"#.repeat(8);
+        for i in 0..10 {
+            storage.put(&format!("user:{i}"), &document).unwrap();
+        }
+        storage.delete("user:0").unwrap();
+
+        let detailed_stats = storage.detailed_stats().unwrap();
+        let stats = detailed_stats.compression_stats;
+        assert_eq!(detailed_stats.compression, Compression::Zstd(3));
+        assert_eq!(stats.records, 11);
+        assert_eq!(stats.compressed_records, 10);
+        assert!(stats.stored_bytes < stats.raw_bytes);
+        assert!(stats.ratio() > 2.0);
+        assert!(detailed_stats.wal_bytes < stats.raw_bytes);
+    }
+
+    #[test]
+    fn test_persistent_storage_mixed_compression_recovery() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let document = br#"{"status":"active","tags":["a","b","c"]}"#.repeat(8);
+
+        for (i, compression) in [Compression::None, Compression::Lz4, Compression::Zstd(3)]
+            .into_iter()
+            .enumerate()
+        {
+            let options = WalOptions::default().with_compression(compression);
+            let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+            assert_eq!(storage.keys().unwrap().len(), i);
+            storage.put(&format!("doc:{i}"), &document).unwrap();
+        }
+
+        let storage = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(storage.recovery_report().entries_applied, 3);
+        for i in 0..3 {
+            assert_eq!(storage.get(&format!("doc:{i}")).unwrap().value, document);
+        }
+
+        // Compaction writes a compressed snapshot that reads back the same way
+        let options = WalOptions::default().with_compression(Compression::Lz4);
+        drop(storage);
+        let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+        storage.compact_wal().unwrap();
+        drop(storage);
+        let storage = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(storage.recovery_report().snapshot_keys, 3);
+        assert_eq!(storage.get("doc:2").unwrap().value, document);
+    }
+
+    #[test]
+    fn test_persistent_storage_recovers_from_segments() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let options = WalOptions::default().with_max_segment_size(256);
+
+        {
+            let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+            for i in 0..50 {
+                storage.put(&format!("key{i:02}"), b"value").unwrap();
+            }
+            storage.delete("key07").unwrap();
+
+            let stats = storage.detailed_stats().unwrap();
+            assert!(stats.wal_segment_count > 1);
+            assert!(stats.wal_bytes > 256);
+        }
+
+        let recovered = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+        assert_eq!(recovered.keys().unwrap().len(), 49);
+        assert!(!recovered.exists("key07").unwrap());
+        assert_eq!(recovered.get("key49").unwrap().value, "value");
+    }
+
+    #[test]
+    fn test_persistent_storage_durability_option() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let options = WalOptions::default().with_durability(Durability::Always);
+
+        {
+            let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+            storage.put("key", b"value").unwrap();
+            assert_eq!(
+                storage.detailed_stats().unwrap().durability,
+                Durability::Always
+            );
+        }
+
+        let recovered_storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+        assert_eq!(recovered_storage.get("key").unwrap().value, "value");
+    }
+
+    #[test]
+    fn test_persistent_storage_concurrent_writers_recover() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let options = WalOptions::default().with_durability(Durability::Always);
+
+        {
+            let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+            std::thread::scope(|scope| {
+                for thread in 0..4 {
+                    let storage = &storage;
+                    scope.spawn(move || {
+                        for i in 0..25 {
+                            storage.put(&format!("{thread}:{i}"), b"v1").unwrap();
+                            storage.put(&format!("{thread}:{i}"), b"v2").unwrap();
+                        }
+                    });
+                }
+            });
+        }
+
+        let recovered = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+        assert_eq!(recovered.keys().unwrap().len(), 100);
+        let value = recovered.get("3:24").unwrap();
+        assert_eq!(value.value, "v2");
+        assert_eq!(value.metadata.version, 2);
     }
 
     #[test]
     fn test_persistent_storage_compaction() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let storage = PersistentStorage::new(temp_file.path()).unwrap();
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = PersistentStorage::new(&temp_path).unwrap();
 
         // Perform many operations to create WAL entries
-        storage.put("key1", "value1").unwrap();
-        storage.put("key2", "value2").unwrap();
+        storage.put("key1", b"value1").unwrap();
+        storage.put("key2", b"value2").unwrap();
         storage.delete("key1").unwrap();
-        storage.put("key3", "value3").unwrap();
-        storage.put("key2", "updated_value2").unwrap(); // Update existing key
+        storage.put("key3", b"value3").unwrap();
+        storage.put("key2", b"updated_value2").unwrap(); // Update existing key
 
         // Before compaction, we should have 5 WAL entries
         let entries_before = storage.wal_manager.read_all_entries().unwrap().len();
@@ -386,14 +1502,13 @@ mod tests {
 
     #[test]
     fn test_persistent_storage_recovery_after_compaction() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let temp_path = temp_file.path().to_path_buf();
+        let (_temp_dir, temp_path) = temp_wal();
 
         // Create storage, add data, compact, then drop it
         {
             let storage = PersistentStorage::new(&temp_path).unwrap();
-            storage.put("key1", "value1").unwrap();
-            storage.put("key2", "value2").unwrap();
+            storage.put("key1", b"value1").unwrap();
+            storage.put("key2", b"value2").unwrap();
             storage.delete("key1").unwrap();
             storage.compact_wal().unwrap();
         }
@@ -407,4 +1522,418 @@ mod tests {
         let retrieved = recovered_storage.get("key2").unwrap();
         assert_eq!(retrieved.value, "value2");
     }
+
+    #[test]
+    fn test_compaction_replays_wal_tail_after_snapshot() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("key1", b"value1").unwrap();
+            storage.put("key2", b"value2").unwrap();
+
+            let result = storage.compact_wal().unwrap();
+            assert_eq!(result.sequence_number, 2);
+            assert_eq!(result.segments_deleted, 1);
+            assert!(storage.wal_manager.read_all_entries().unwrap().is_empty());
+
+            // Writes after the checkpoint continue the sequence in the WAL tail
+            storage.put("key1", b"updated").unwrap();
+            storage.delete("key2").unwrap();
+            let tail = storage.wal_manager.read_all_entries().unwrap();
+            assert_eq!(tail[0].sequence_number, 3);
+        }
+
+        let recovered = PersistentStorage::new(&temp_path).unwrap();
+        let value = recovered.get("key1").unwrap();
+        assert_eq!(value.value, "updated");
+        assert_eq!(value.metadata.version, 2);
+        assert!(!recovered.exists("key2").unwrap());
+
+        // Sequence numbers keep growing after recovering from a snapshot alone
+        recovered.compact_wal().unwrap();
+        drop(recovered);
+        let recovered = PersistentStorage::new(&temp_path).unwrap();
+        recovered.put("key3", b"value3").unwrap();
+        assert_eq!(recovered.detailed_stats().unwrap().wal_sequence_number, 5);
+    }
+
+    #[test]
+    fn test_interrupted_checkpoint_keeps_data() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("key1", b"value1").unwrap();
+            storage.compact_wal().unwrap();
+            storage.put("key1", b"value2").unwrap();
+            storage.put("key2", b"value").unwrap();
+        }
+
+        // A crash while writing the next snapshot leaves a partial temp file behind
+        let mut temp = snapshot::path_for(&temp_path).into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, b"ZSNP").unwrap();
+
+        let recovered = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(recovered.get("key1").unwrap().value, "value2");
+        assert!(recovered.exists("key2").unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_overlapping_wal_is_not_replayed_twice() {
+        let (_temp_dir, temp_path) = temp_wal();
+
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("key", b"v1").unwrap();
+            storage.put("key", b"v2").unwrap();
+
+            // Snapshot written, but the crash came before old segments were deleted
+            let data = storage.memory_storage.scan(None, None, None).unwrap();
+            snapshot::write(
+                &snapshot::path_for(&temp_path),
+                2,
+                0,
+                &data,
+                Compression::None,
+                &Keyring::default(),
+            )
+            .unwrap();
+            storage.put("key", b"v3").unwrap();
+        }
+
+        let recovered = PersistentStorage::new(&temp_path).unwrap();
+        let value = recovered.get("key").unwrap();
+        assert_eq!(value.value, "v3");
+        assert_eq!(value.metadata.version, 3);
+    }
+
+    #[test]
+    fn test_wal_state_counts_entries_since_checkpoint() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = PersistentStorage::new(&temp_path).unwrap();
+
+        for value in [b"1", b"2", b"3"] {
+            storage.put("key", value).unwrap();
+        }
+        let state = storage.wal_state().unwrap();
+        assert_eq!(state.wal_entries, 3);
+        assert_eq!(state.live_keys, 1);
+        assert!((state.entry_ratio() - 3.0).abs() < f64::EPSILON);
+
+        let result = storage.compact_wal().unwrap();
+        assert_eq!(result.entries_before, 3);
+        assert_eq!(storage.wal_state().unwrap().wal_entries, 0);
+
+        let detailed = storage.detailed_stats().unwrap();
+        assert_eq!(detailed.compactions, 1);
+        assert_eq!(detailed.compaction_failures, 0);
+    }
+
+    #[test]
+    fn test_background_compaction_runs_on_threshold() {
+        let (_temp_dir, temp_path) = temp_wal();
+        let storage = Arc::new(PersistentStorage::new(&temp_path).unwrap());
+        let policy = CompactionPolicy::disabled()
+            .with_check_interval(Duration::from_millis(5))
+            .with_max_entry_ratio(Some(2.0))
+            .with_min_wal_entries(4);
+        let worker = storage.start_compaction(policy).unwrap();
+
+        for value in [b"1", b"2", b"3", b"4"] {
+            storage.put("key", value).unwrap();
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while storage.detailed_stats().unwrap().compactions == 0 {
+            assert!(Instant::now() < deadline, "WAL was never compacted");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(worker);
+
+        assert_eq!(storage.wal_state().unwrap().wal_entries, 0);
+        drop(storage);
+        let recovered = PersistentStorage::new(&temp_path).unwrap();
+        assert_eq!(recovered.get("key").unwrap().value, "4");
+    }
+
+    #[test]
+    fn test_recovery_report_lists_discarded_tail() {
+        let (_temp_dir, temp_path) = temp_wal();
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("a", b"1").unwrap();
+            storage.put("b", b"2").unwrap();
+            assert!(storage.recovery_report().is_clean());
+        }
+
+        let segment = storage_segment(&temp_path);
+        let len = std::fs::metadata(&segment).unwrap().len();
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap();
+        file.set_len(len - 1).unwrap();
+        drop(file);
+
+        assert!(PersistentStorage::new(&temp_path).is_err());
+
+        let options = WalOptions::default().with_recovery_mode(RecoveryMode::TruncateTail);
+        let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+        let report = storage.recovery_report();
+        assert_eq!(report.mode, RecoveryMode::TruncateTail);
+        assert_eq!(report.entries_read, 1);
+        assert_eq!(report.discarded.len(), 1);
+        assert_eq!(report.bytes_discarded(), report.discarded[0].bytes);
+
+        assert_eq!(storage.get("a").unwrap().value, "1");
+        assert!(storage.get("b").is_err());
+    }
+
+    fn storage_segment(wal_path: &Path) -> PathBuf {
+        let mut path = wal_path.as_os_str().to_owned();
+        path.push(".000001");
+        PathBuf::from(path)
+    }
+
+    #[test]
+    fn test_recovery_report_counts_snapshot_and_wal() {
+        let (_temp_dir, temp_path) = temp_wal();
+        {
+            let storage = PersistentStorage::new(&temp_path).unwrap();
+            storage.put("a", b"1").unwrap();
+            storage.put("b", b"2").unwrap();
+            storage.compact_wal().unwrap();
+            storage.put("c", b"3").unwrap();
+            storage.delete("a").unwrap();
+        }
+
+        let storage = PersistentStorage::new(&temp_path).unwrap();
+        let report = storage.recovery_report();
+        assert!(report.snapshot_used());
+        assert_eq!(report.snapshot_sequence_number, Some(2));
+        assert_eq!(report.snapshot_keys, 2);
+        assert_eq!(report.entries_read, 2);
+        assert_eq!(report.entries_applied, 2);
+        assert_eq!(report.entries_failed, 0);
+        assert_eq!(report.entries_skipped, 0);
+        assert_eq!(report.last_sequence_number, 4);
+        assert!(report.is_clean());
+    }
+
+    /// Every file next to `wal_path`, with its contents
+    fn wal_files(wal_path: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files: Vec<_> = std::fs::read_dir(wal_path.parent().unwrap())
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let contents = std::fs::read(&path).unwrap();
+                (path, contents)
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_recover_to_sequence_number() {
+        let (_temp_dir, source) = temp_wal();
+        {
+            let storage = PersistentStorage::new(&source).unwrap();
+            storage.put("a", b"1").unwrap();
+            storage.put("b", b"2").unwrap();
+            storage.compact_wal().unwrap();
+            storage.put("c", b"3").unwrap();
+            storage.clear().unwrap();
+            storage.put("d", b"4").unwrap();
+        }
+        let before = wal_files(&source);
+
+        let (_output_dir, output) = temp_wal();
+        let options = WalOptions::default();
+        {
+            let storage = PersistentStorage::recover_to(
+                &source,
+                RecoveryTarget::Sequence(3),
+                &output,
+                &options,
+            )
+            .unwrap();
+            assert!(storage.exists("a").unwrap());
+            assert!(storage.exists("c").unwrap());
+            assert!(!storage.exists("d").unwrap());
+
+            let report = storage.recovery_report();
+            assert_eq!(report.target, Some(RecoveryTarget::Sequence(3)));
+            assert_eq!(report.snapshot_sequence_number, Some(2));
+            assert_eq!(report.entries_applied, 1);
+            assert_eq!(report.entries_skipped, 2);
+            assert_eq!(report.last_sequence_number, 3);
+
+            storage.put("e", b"5").unwrap();
+        }
+        assert_eq!(wal_files(&source), before);
+
+        // The recovered storage reopens like any other and continues the log
+        let storage = PersistentStorage::with_wal_options(&output, &options).unwrap();
+        assert_eq!(storage.keys().unwrap().len(), 4);
+        assert_eq!(storage.recovery_report().snapshot_sequence_number, Some(3));
+        assert_eq!(storage.recovery_report().last_sequence_number, 4);
+    }
+
+    #[test]
+    fn test_recover_to_time() {
+        let (temp_dir, source) = temp_wal();
+        let target = {
+            let storage = PersistentStorage::new(&source).unwrap();
+            storage.put("a", b"1").unwrap();
+            storage.compact_wal().unwrap();
+            storage.put("b", b"2").unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            let target = Utc::now();
+            std::thread::sleep(Duration::from_millis(20));
+            storage.clear().unwrap();
+            target
+        };
+
+        let output = temp_dir.path().join("recovered.wal");
+        let storage = PersistentStorage::recover_to(
+            &source,
+            RecoveryTarget::Time(target),
+            &output,
+            &WalOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(storage.keys().unwrap().len(), 2);
+        assert_eq!(storage.recovery_report().last_sequence_number, 2);
+    }
+
+    #[test]
+    fn test_recover_to_rejects_bad_targets() {
+        let (temp_dir, source) = temp_wal();
+        {
+            let storage = PersistentStorage::new(&source).unwrap();
+            storage.put("a", b"1").unwrap();
+            storage.put("b", b"2").unwrap();
+            storage.compact_wal().unwrap();
+        }
+        let options = WalOptions::default();
+
+        // The snapshot already covers sequence 2
+        let output = temp_dir.path().join("early.wal");
+        assert!(
+            PersistentStorage::recover_to(&source, RecoveryTarget::Sequence(1), &output, &options)
+                .is_err()
+        );
+        let early = RecoveryTarget::Time(Utc::now() - chrono::Duration::hours(1));
+        assert!(PersistentStorage::recover_to(&source, early, &output, &options).is_err());
+
+        // Recovering in place would overwrite the source
+        assert!(
+            PersistentStorage::recover_to(&source, RecoveryTarget::Sequence(2), &source, &options)
+                .is_err()
+        );
+
+        // The output already holds data
+        let output = temp_dir.path().join("used.wal");
+        PersistentStorage::new(&output)
+            .unwrap()
+            .put("x", b"1")
+            .unwrap();
+        assert!(
+            PersistentStorage::recover_to(&source, RecoveryTarget::Sequence(2), &output, &options)
+                .is_err()
+        );
+    }
+
+    fn encrypted(key: u8) -> WalOptions {
+        WalOptions::default().with_encryption(Keyring::new(EncryptionKey::from_bytes(&[key; 32])))
+    }
+
+    #[test]
+    fn test_encrypted_storage_keeps_no_plaintext() {
+        let (_temp_dir, temp_path) = temp_wal();
+        {
+            let storage = PersistentStorage::with_wal_options(&temp_path, &encrypted(1)).unwrap();
+            storage.put("customer:1", b"jane@example.com").unwrap();
+            storage.compact_wal().unwrap();
+            storage.put("customer:2", b"john@example.com").unwrap();
+        }
+
+        for (path, contents) in wal_files(&temp_path) {
+            assert!(
+                !contents.windows(8).any(|window| window == b"customer"),
+                "{} holds a plaintext key",
+                path.display()
+            );
+        }
+
+        let storage = PersistentStorage::with_wal_options(&temp_path, &encrypted(1)).unwrap();
+        assert_eq!(storage.keys().unwrap().len(), 2);
+        assert_eq!(
+            storage.get("customer:2").unwrap().value,
+            b"john@example.com".as_slice()
+        );
+    }
+
+    #[test]
+    fn test_encrypted_storage_needs_the_right_key() {
+        let (_temp_dir, temp_path) = temp_wal();
+        PersistentStorage::with_wal_options(&temp_path, &encrypted(1))
+            .unwrap()
+            .put("key", b"value")
+            .unwrap();
+
+        let missing = PersistentStorage::new(&temp_path).err();
+        assert!(
+            matches!(&missing, Some(StorageError::Encryption(msg)) if msg.contains("no encryption key")),
+            "{missing:?}"
+        );
+
+        // A wrong key is not mistaken for damage, whatever the recovery mode
+        let options = encrypted(2).with_recovery_mode(RecoveryMode::SkipCorrupt);
+        let wrong = PersistentStorage::with_wal_options(&temp_path, &options).err();
+        assert!(
+            matches!(&wrong, Some(StorageError::Encryption(msg)) if msg.contains("not among")),
+            "{wrong:?}"
+        );
+
+        let storage = PersistentStorage::with_wal_options(&temp_path, &encrypted(1)).unwrap();
+        assert!(storage.exists("key").unwrap());
+    }
+
+    #[test]
+    fn test_key_rotation_reencrypts_on_compaction() {
+        let (_temp_dir, temp_path) = temp_wal();
+        {
+            let storage = PersistentStorage::with_wal_options(&temp_path, &encrypted(1)).unwrap();
+            storage.put("a", b"1").unwrap();
+            storage.compact_wal().unwrap();
+            storage.put("b", b"2").unwrap();
+        }
+
+        let new_key = EncryptionKey::from_bytes(&[2; 32]);
+        let old_key = EncryptionKey::from_bytes(&[1; 32]);
+        let rotating = WalOptions::default()
+            .with_encryption(Keyring::new(new_key.clone()).with_previous(old_key));
+        {
+            let storage = PersistentStorage::with_wal_options(&temp_path, &rotating).unwrap();
+            assert_eq!(storage.keys().unwrap().len(), 2);
+            assert_eq!(storage.detailed_stats().unwrap().compactions, 1);
+            storage.put("c", b"3").unwrap();
+        }
+        {
+            // Everything was re-encrypted, so there is nothing left to compact
+            let storage = PersistentStorage::with_wal_options(&temp_path, &rotating).unwrap();
+            assert_eq!(storage.keys().unwrap().len(), 3);
+            assert_eq!(storage.detailed_stats().unwrap().compactions, 0);
+        }
+
+        // Nothing needs the old key any more
+        let options = WalOptions::default().with_encryption(Keyring::new(new_key));
+        let storage = PersistentStorage::with_wal_options(&temp_path, &options).unwrap();
+        assert_eq!(storage.keys().unwrap().len(), 3);
+        assert!(storage.recovery_report().snapshot_used());
+    }
 }
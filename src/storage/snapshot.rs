@@ -0,0 +1,439 @@
+//! Point-in-time snapshots of persistent storage
+//!
+//! A snapshot holds every live key with its value, version and expiry, and the
+//! sequence number of the last WAL entry it reflects. Replaying the WAL entries
+//! after that number on top of the snapshot restores the data, so the WAL
+//! segments before it can be deleted.
+//!
+//! The file starts with an 8 byte header: the magic `ZSNP`, the format version
+//! as a little-endian `u16` and two reserved zero bytes. The covered sequence
+//! number, the number of keys, the time the snapshot was taken in milliseconds
+//! since the Unix epoch and the highest version any removed key had follow as
+//! little-endian 64-bit integers, then one checksummed WAL record per key, see
+//! [`format`], compressed and encrypted like the WAL it belongs to. Version 1
+//! files do not record the time, and versions 1 and 2 not the removed version.
+
+use super::engine::Value;
+use super::error::{StorageError, StorageResult};
+use super::wal::{Compression, Keyring, WalEntry, WalOperation, format, segment};
+use crate::utils::time;
+use chrono::{DateTime, Utc};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Magic bytes at the start of every snapshot file
+pub const MAGIC: &[u8; 4] = b"ZSNP";
+
+/// Current version of the snapshot format
+pub const FORMAT_VERSION: u16 = 3;
+
+/// Length of the file header in bytes
+const HEADER_LEN: usize = 40;
+
+/// Length of the header of version 2 files, which do not record the removed version
+const V2_HEADER_LEN: usize = 32;
+
+/// Length of the header of version 1 files, which do not record the time
+const V1_HEADER_LEN: usize = 24;
+
+/// A key as recorded in a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    /// The key
+    pub key: String,
+    /// The stored value
+    pub value: Vec<u8>,
+    /// Absolute expiry time, if the value was stored with a TTL
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Version of the key
+    pub version: u64,
+}
+
+/// The contents of a snapshot file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Sequence number of the last WAL entry reflected in the snapshot
+    pub sequence_number: u64,
+    /// When the snapshot was taken; unknown for version 1 files
+    pub created_at: Option<DateTime<Utc>>,
+    /// Highest version any key removed before the snapshot had; 0 for files
+    /// before version 3
+    pub retired_version: u64,
+    /// Every live key at that point, in key order
+    pub entries: Vec<SnapshotEntry>,
+    /// Whether any record was sealed with a key that has been rotated out
+    pub sealed_with_previous_key: bool,
+}
+
+/// Path of the snapshot belonging to the WAL at `wal_path`
+#[must_use]
+pub fn path_for(wal_path: &Path) -> PathBuf {
+    let mut path = wal_path.as_os_str().to_owned();
+    path.push(".snapshot");
+    PathBuf::from(path)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+/// Write a snapshot of `entries` covering the WAL up to `sequence_number`,
+/// after keys up to version `retired_version` were removed, compressing each
+/// record with `compression` and encrypting it with the current key of `keys`
+///
+/// The snapshot is written to a temporary file, synced and renamed over `path`,
+/// so a crash at any point leaves either the old or the new snapshot in place.
+/// Returns the size of the snapshot in bytes.
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if the snapshot cannot be written, synced
+/// or renamed into place, and a `StorageError::Encryption` if a record cannot
+/// be encrypted.
+pub fn write(
+    path: &Path,
+    sequence_number: u64,
+    retired_version: u64,
+    entries: &[(String, Value)],
+    compression: Compression,
+    keys: &Keyring,
+) -> StorageResult<u64> {
+    let temp = temp_path(path);
+    let io_error =
+        |e: std::io::Error| StorageError::Internal(format!("Failed to write snapshot: {e}"));
+
+    let file = File::create(&temp).map_err(io_error)?;
+    let mut writer = BufWriter::new(file);
+
+    let created_at = Utc::now();
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    header[4..6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header[8..16].copy_from_slice(&sequence_number.to_le_bytes());
+    header[16..24].copy_from_slice(&(entries.len() as u64).to_le_bytes());
+    header[24..32].copy_from_slice(&created_at.timestamp_millis().to_le_bytes());
+    header[32..].copy_from_slice(&retired_version.to_le_bytes());
+    writer.write_all(&header).map_err(io_error)?;
+
+    let timestamp = time::format_timestamp(created_at);
+    let mut bytes = header.len() as u64;
+    for (key, value) in entries {
+        let entry = WalEntry {
+            sequence_number,
+            operation: WalOperation::Put {
+                key: key.clone(),
+                value: value.value.to_vec(),
+                expires_at: value.metadata.expires_at,
+                version: Some(value.metadata.version),
+            },
+            timestamp: timestamp.clone(),
+        };
+        let (record, _) = format::encode_record_with(&entry, true, compression, keys)?;
+        writer.write_all(&record).map_err(io_error)?;
+        bytes += record.len() as u64;
+    }
+
+    let file = writer.into_inner().map_err(|e| io_error(e.into_error()))?;
+    file.sync_all().map_err(io_error)?;
+    drop(file);
+
+    fs::rename(&temp, path)
+        .map_err(|e| StorageError::Internal(format!("Failed to move snapshot into place: {e}")))?;
+    segment::sync_dir(path)?;
+
+    Ok(bytes)
+}
+
+/// Load the snapshot at `path`, if there is one
+///
+/// A temporary file left behind by a snapshot that never finished is deleted.
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if the unfinished snapshot cannot be
+/// removed, or if the snapshot cannot be read, has an unsupported version, or
+/// is truncated or corrupted, and a `StorageError::Encryption` if it is
+/// encrypted with a key that is not in `keys`.
+pub fn load(path: &Path, keys: &Keyring) -> StorageResult<Option<Snapshot>> {
+    let temp = temp_path(path);
+    if temp.exists() {
+        warn!("Removing unfinished snapshot {:?}", temp);
+        fs::remove_file(&temp).map_err(|e| {
+            StorageError::Internal(format!("Failed to remove unfinished snapshot: {e}"))
+        })?;
+    }
+
+    read(path, keys)
+}
+
+/// Read the snapshot at `path`, if there is one, without touching any file
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if the snapshot cannot be read, has an
+/// unsupported version, or is truncated or corrupted, and a
+/// `StorageError::Encryption` if it is encrypted with a key that is not in `keys`.
+pub fn read(path: &Path, keys: &Keyring) -> StorageResult<Option<Snapshot>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(StorageError::Internal(format!(
+                "Failed to open snapshot: {e}"
+            )));
+        }
+    };
+    let mut reader = BufReader::new(file);
+
+    let header_error =
+        |e: std::io::Error| StorageError::Internal(format!("Failed to read snapshot header: {e}"));
+    let mut header = [0; HEADER_LEN];
+    reader
+        .read_exact(&mut header[..V1_HEADER_LEN])
+        .map_err(header_error)?;
+    if &header[..4] != MAGIC {
+        return Err(StorageError::Internal(
+            "Snapshot does not start with a valid header".to_string(),
+        ));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(StorageError::Internal(format!(
+            "Unsupported snapshot format version {version}"
+        )));
+    }
+
+    let sequence_number = read_u64(&header[8..16]);
+    let count = read_u64(&header[16..24]);
+
+    let mut created_at = None;
+    if version >= 2 {
+        reader
+            .read_exact(&mut header[V1_HEADER_LEN..V2_HEADER_LEN])
+            .map_err(header_error)?;
+        let millis = i64::from_le_bytes(header[24..32].try_into().unwrap_or_default());
+        created_at = Some(
+            DateTime::<Utc>::from_timestamp_millis(millis).ok_or_else(|| {
+                StorageError::Internal("Invalid creation time in snapshot header".to_string())
+            })?,
+        );
+    }
+
+    let mut retired_version = 0;
+    if version >= 3 {
+        reader
+            .read_exact(&mut header[V2_HEADER_LEN..])
+            .map_err(header_error)?;
+        retired_version = read_u64(&header[32..]);
+    }
+
+    let mut entries = Vec::new();
+    let mut sealed_with_previous_key = false;
+    for index in 0..count {
+        let Some((entry, _, stale)) = format::read_sealed_record(&mut reader, keys)? else {
+            return Err(StorageError::Internal(format!(
+                "Snapshot ends after {index} of {count} keys"
+            )));
+        };
+        sealed_with_previous_key |= stale;
+
+        match entry.operation {
+            WalOperation::Put {
+                key,
+                value,
+                expires_at,
+                version: Some(version),
+            } => entries.push(SnapshotEntry {
+                key,
+                value,
+                expires_at,
+                version,
+            }),
+            other => {
+                return Err(StorageError::Internal(format!(
+                    "Unexpected record in snapshot: {other:?}"
+                )));
+            }
+        }
+    }
+
+    Ok(Some(Snapshot {
+        sequence_number,
+        created_at,
+        retired_version,
+        entries,
+        sealed_with_previous_key,
+    }))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(bytes);
+    u64::from_le_bytes(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::engine::ValueMetadata;
+
+    fn value(bytes: &[u8], version: u64) -> Value {
+        let mut metadata = ValueMetadata::new(bytes.len());
+        metadata.version = version;
+        Value {
+            value: bytes.to_vec().into(),
+            metadata,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = path_for(&temp_dir.path().join("test.wal"));
+        assert!(load(&path, &Keyring::default()).unwrap().is_none());
+
+        let entries = vec![
+            ("a".to_string(), value(b"1", 3)),
+            ("b".to_string(), value(&[0xff, 0x00], 1)),
+        ];
+        write(
+            &path,
+            42,
+            5,
+            &entries,
+            Compression::None,
+            &Keyring::default(),
+        )
+        .unwrap();
+
+        let snapshot = load(&path, &Keyring::default()).unwrap().unwrap();
+        assert_eq!(snapshot.sequence_number, 42);
+        assert_eq!(snapshot.retired_version, 5);
+        assert!(snapshot.created_at.is_some());
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(snapshot.entries[0].key, "a");
+        assert_eq!(snapshot.entries[0].version, 3);
+        assert_eq!(snapshot.entries[1].value, vec![0xff, 0x00]);
+    }
+
+    #[test]
+    fn test_compressed_snapshot_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = path_for(&temp_dir.path().join("test.wal"));
+        let document = br#"{"user":"john","settings":{"theme":"dark"}}"#.repeat(10);
+        let entries: Vec<_> = (0..10)
+            .map(|i| (format!("doc:{i}"), value(&document, 1)))
+            .collect();
+
+        let plain = write(
+            &path,
+            1,
+            0,
+            &entries,
+            Compression::None,
+            &Keyring::default(),
+        )
+        .unwrap();
+        let compressed =
+            write(&path, 1, 0, &entries, Compression::Lz4, &Keyring::default()).unwrap();
+        assert!(compressed < plain);
+
+        let snapshot = load(&path, &Keyring::default()).unwrap().unwrap();
+        assert_eq!(snapshot.entries.len(), 10);
+        assert_eq!(snapshot.entries[0].value, document);
+    }
+
+    #[test]
+    fn test_unfinished_snapshot_is_ignored() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = path_for(&temp_dir.path().join("test.wal"));
+        write(
+            &path,
+            1,
+            0,
+            &[("a".to_string(), value(b"1", 1))],
+            Compression::None,
+            &Keyring::default(),
+        )
+        .unwrap();
+
+        // A crash while writing the next snapshot leaves the previous one in place
+        fs::write(temp_path(&path), b"ZSNP partial").unwrap();
+        let snapshot = load(&path, &Keyring::default()).unwrap().unwrap();
+        assert_eq!(snapshot.sequence_number, 1);
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn test_truncated_snapshot_is_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = path_for(&temp_dir.path().join("test.wal"));
+        write(
+            &path,
+            7,
+            0,
+            &[("a".to_string(), value(b"1", 1))],
+            Compression::None,
+            &Keyring::default(),
+        )
+        .unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(load(&path, &Keyring::default()).is_err());
+    }
+
+    #[test]
+    fn test_version_1_snapshot_has_no_creation_time() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = path_for(&temp_dir.path().join("test.wal"));
+        write(
+            &path,
+            3,
+            0,
+            &[("a".to_string(), value(b"1", 1))],
+            Compression::None,
+            &Keyring::default(),
+        )
+        .unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.drain(V1_HEADER_LEN..HEADER_LEN);
+        bytes[4] = 1;
+        fs::write(&path, bytes).unwrap();
+
+        let snapshot = read(&path, &Keyring::default()).unwrap().unwrap();
+        assert_eq!(snapshot.sequence_number, 3);
+        assert_eq!(snapshot.created_at, None);
+        assert_eq!(snapshot.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_version_2_snapshot_has_no_retired_version() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = path_for(&temp_dir.path().join("test.wal"));
+        write(
+            &path,
+            3,
+            9,
+            &[("a".to_string(), value(b"1", 1))],
+            Compression::None,
+            &Keyring::default(),
+        )
+        .unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.drain(V2_HEADER_LEN..HEADER_LEN);
+        bytes[4] = 2;
+        fs::write(&path, bytes).unwrap();
+
+        let snapshot = read(&path, &Keyring::default()).unwrap().unwrap();
+        assert!(snapshot.created_at.is_some());
+        assert_eq!(snapshot.retired_version, 0);
+        assert_eq!(snapshot.entries.len(), 1);
+    }
+}
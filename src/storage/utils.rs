@@ -1,4 +1,7 @@
+use super::engine::BatchOperation;
 use super::error::{StorageError, StorageResult};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
 
 /// Helper functions for key validation
 ///
@@ -95,9 +98,8 @@ pub fn validate_key_strict(key: &str, allow_slashes: bool, allow_dots: bool) ->
 ///
 /// # Errors
 /// Returns `StorageError::InvalidValue` if the value is too large (exceeds 1MB limit)
-pub fn validate_value(value: &str) -> StorageResult<()> {
-    // For now, we'll allow any UTF-8 string as a value
-    // In the future, we might add size limits or other constraints
+pub fn validate_value(value: &[u8]) -> StorageResult<()> {
+    // Values are opaque bytes; only the size is constrained for now
 
     if value.len() > 1_048_576 {
         return Err(StorageError::InvalidValue(
@@ -108,6 +110,71 @@ pub fn validate_value(value: &str) -> StorageResult<()> {
     Ok(())
 }
 
+/// Most operations a batch may hold
+pub const MAX_BATCH_OPS: usize = 10_000;
+
+/// Most bytes of keys and values a batch may hold (16 MiB)
+pub const MAX_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+/// Validate every key and value of a batch, and the size of the batch as a
+/// whole, before any of it is applied
+///
+/// # Errors
+/// Returns `StorageError::InvalidValue` if the batch holds more than
+/// [`MAX_BATCH_OPS`] operations or [`MAX_BATCH_BYTES`] bytes of keys and values,
+/// otherwise the first `StorageError::InvalidKey` or `StorageError::InvalidValue` found
+pub fn validate_batch(operations: &[BatchOperation]) -> StorageResult<()> {
+    if operations.len() > MAX_BATCH_OPS {
+        return Err(StorageError::InvalidValue(format!(
+            "Batch too large (max {MAX_BATCH_OPS} operations)"
+        )));
+    }
+
+    let mut bytes = 0;
+    for operation in operations {
+        validate_key(operation.key())?;
+        bytes += operation.key().len();
+        if let BatchOperation::Put { value, .. } = operation {
+            validate_value(value)?;
+            bytes += value.len();
+        }
+    }
+
+    if bytes > MAX_BATCH_BYTES {
+        return Err(StorageError::InvalidValue(
+            "Batch too large (max 16MB of keys and values)".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Convert a TTL into an absolute expiry time
+///
+/// # Errors
+/// Returns `StorageError::InvalidValue` if the TTL is zero or too large to represent
+pub fn expiry_from_ttl(ttl: Duration) -> StorageResult<DateTime<Utc>> {
+    if ttl.is_zero() {
+        return Err(StorageError::InvalidValue(
+            "TTL must be greater than zero".to_string(),
+        ));
+    }
+
+    chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+        .ok_or_else(|| StorageError::InvalidValue("TTL too large".to_string()))
+}
+
+/// The smallest string that sorts strictly after `key`
+///
+/// Valid keys never contain a NUL byte, so appending one yields a start bound
+/// that resumes a scan right after `key` without skipping any other key.
+#[must_use]
+pub fn key_successor(key: &str) -> String {
+    format!("{key}\0")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -371,14 +438,14 @@ mod tests {
     #[test]
     fn test_value_validation_size_limit() {
         // Valid value
-        assert!(validate_value("normal value").is_ok());
+        assert!(validate_value(b"normal value").is_ok());
 
         // Large but within limit
-        let large_value = "a".repeat(1_048_576); // Exactly 1MB
+        let large_value = vec![b'a'; 1_048_576]; // Exactly 1MB
         assert!(validate_value(&large_value).is_ok());
 
         // Too large
-        let too_large_value = "a".repeat(1_048_577); // 1MB + 1 byte
+        let too_large_value = vec![b'a'; 1_048_577]; // 1MB + 1 byte
         let result = validate_value(&too_large_value);
         assert!(matches!(result, Err(StorageError::InvalidValue(_))));
         assert_eq!(
@@ -390,12 +457,58 @@ mod tests {
     #[test]
     fn test_value_validation_allows_any_utf8() {
         // Special characters should be OK in values
-        assert!(validate_value("value\nwith\nnewlines").is_ok());
-        assert!(validate_value("value\0with\0nulls").is_ok());
-        assert!(validate_value("value\twith\ttabs").is_ok());
-        assert!(validate_value("value with spaces").is_ok());
-        assert!(validate_value("value..with..dots").is_ok());
-        assert!(validate_value("__zephyrite_internal_value").is_ok());
-        assert!(validate_value("🚀emoji values 中文").is_ok());
+        assert!(validate_value(b"value\nwith\nnewlines").is_ok());
+        assert!(validate_value(b"value\0with\0nulls").is_ok());
+        assert!(validate_value(b"value\twith\ttabs").is_ok());
+        assert!(validate_value(b"value with spaces").is_ok());
+        assert!(validate_value(b"value..with..dots").is_ok());
+        assert!(validate_value(b"__zephyrite_internal_value").is_ok());
+        assert!(validate_value("🚀emoji values 中文".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_value_validation_allows_binary() {
+        // Values are not required to be UTF-8
+        assert!(validate_value(&[0xff, 0xfe, 0x00, 0x80]).is_ok());
+        assert!(validate_value(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_batch_size_limits() {
+        let delete = |i: usize| BatchOperation::Delete {
+            key: format!("key{i}"),
+        };
+        let too_many: Vec<_> = (0..=MAX_BATCH_OPS).map(delete).collect();
+        assert!(validate_batch(&too_many[..MAX_BATCH_OPS]).is_ok());
+        assert!(matches!(
+            validate_batch(&too_many),
+            Err(StorageError::InvalidValue(_))
+        ));
+
+        let value = bytes::Bytes::from(vec![0u8; 1_048_576]);
+        let put = |i: usize| BatchOperation::Put {
+            key: format!("key{i}"),
+            value: value.clone(),
+        };
+        let too_big: Vec<_> = (0..16).map(put).collect();
+        assert!(validate_batch(&too_big[..15]).is_ok());
+        assert!(matches!(
+            validate_batch(&too_big),
+            Err(StorageError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_expiry_from_ttl() {
+        let before = Utc::now();
+        let expires_at = expiry_from_ttl(Duration::from_secs(60)).unwrap();
+        assert!(expires_at > before);
+        assert!(expires_at <= Utc::now() + chrono::Duration::seconds(60));
+
+        let result = expiry_from_ttl(Duration::ZERO);
+        assert!(matches!(result, Err(StorageError::InvalidValue(_))));
+
+        let result = expiry_from_ttl(Duration::MAX);
+        assert!(matches!(result, Err(StorageError::InvalidValue(_))));
     }
 }
@@ -0,0 +1,261 @@
+//! Compression of WAL record payloads
+//!
+//! Each record is compressed on its own, and only when that makes it smaller,
+//! so compressed and uncompressed records sit side by side in the same log and
+//! the setting can be changed between restarts. A compressed payload starts with
+//! the uncompressed length as a little-endian `u32`; which algorithm produced
+//! it is recorded in the record flags, see [`format`](super::format).
+
+use crate::storage::error::{StorageError, StorageResult};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Default zstd compression level
+pub const DEFAULT_ZSTD_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+
+/// Payloads shorter than this are not worth compressing
+const MIN_COMPRESS_LEN: usize = 64;
+
+/// Highest ratio an LZ4 block can expand to, used to reject corrupt lengths
+const MAX_LZ4_RATIO: usize = 255;
+
+/// How WAL record payloads are compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Records are written as they are
+    #[default]
+    None,
+    /// zstd at the given level; better ratios for verbose values
+    Zstd(i32),
+    /// LZ4; faster, with lower ratios
+    Lz4,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Zstd(level) => write!(f, "zstd:{level}"),
+            Compression::Lz4 => write!(f, "lz4"),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    /// Parse `none`, `lz4`, `zstd` or `zstd:<level>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd(DEFAULT_ZSTD_LEVEL)),
+            other => other
+                .strip_prefix("zstd:")
+                .and_then(|level| level.parse().ok())
+                .filter(|level| zstd::compression_level_range().contains(level))
+                .map(Compression::Zstd)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown compression '{other}', expected 'none', 'lz4', 'zstd' or 'zstd:<level>'"
+                    )
+                }),
+        }
+    }
+}
+
+/// Algorithm a compressed payload was written with, as recorded in its flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// zstd
+    Zstd,
+    /// LZ4 block format
+    Lz4,
+}
+
+/// Compress `payload`, unless compression is off or would not make it smaller
+pub(super) fn compress(compression: Compression, payload: &[u8]) -> Option<(Algorithm, Vec<u8>)> {
+    if payload.len() < MIN_COMPRESS_LEN {
+        return None;
+    }
+    let raw_len = u32::try_from(payload.len()).ok()?;
+
+    let mut compressed = raw_len.to_le_bytes().to_vec();
+    let algorithm = match compression {
+        Compression::None => return None,
+        Compression::Zstd(level) => {
+            compressed.extend(zstd::bulk::compress(payload, level).ok()?);
+            Algorithm::Zstd
+        }
+        Compression::Lz4 => {
+            compressed.extend(lz4_flex::block::compress(payload));
+            Algorithm::Lz4
+        }
+    };
+
+    (compressed.len() < payload.len()).then_some((algorithm, compressed))
+}
+
+/// Restore a payload compressed with `algorithm`
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if the payload cannot be decompressed or
+/// does not decompress to the recorded length.
+pub(super) fn decompress(algorithm: Algorithm, payload: &[u8]) -> StorageResult<Vec<u8>> {
+    let corrupt = |reason: String| {
+        StorageError::Internal(format!("Failed to decompress WAL record: {reason}"))
+    };
+
+    let (len, data) = payload
+        .split_first_chunk::<4>()
+        .ok_or_else(|| corrupt("payload is truncated".to_string()))?;
+    let raw_len = u32::from_le_bytes(*len) as usize;
+
+    let raw = match algorithm {
+        Algorithm::Zstd => {
+            // Decode through `take` so a corrupt length cannot trigger a huge allocation
+            let mut raw = Vec::new();
+            zstd::stream::read::Decoder::new(data)
+                .map_err(|e| corrupt(e.to_string()))?
+                .take(raw_len as u64 + 1)
+                .read_to_end(&mut raw)
+                .map_err(|e| corrupt(e.to_string()))?;
+            raw
+        }
+        Algorithm::Lz4 => {
+            if raw_len > data.len().saturating_mul(MAX_LZ4_RATIO) {
+                return Err(corrupt(format!("implausible length {raw_len}")));
+            }
+            lz4_flex::block::decompress(data, raw_len).map_err(|e| corrupt(e.to_string()))?
+        }
+    };
+
+    if raw.len() != raw_len {
+        return Err(corrupt(format!(
+            "expected {raw_len} bytes, got {}",
+            raw.len()
+        )));
+    }
+    Ok(raw)
+}
+
+/// How much compression saved on the records written since the WAL was opened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Number of records written
+    pub records: u64,
+    /// Number of those records that were stored compressed
+    pub compressed_records: u64,
+    /// Bytes the records would have taken up uncompressed
+    pub raw_bytes: u64,
+    /// Bytes the records took up on disk
+    pub stored_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Uncompressed size over stored size; 1.0 when nothing was saved or written
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+        self.raw_bytes as f64 / self.stored_bytes as f64
+    }
+}
+
+/// Running totals behind [`CompressionStats`], updated by concurrent writers
+#[derive(Debug, Default)]
+pub(super) struct CompressionCounters {
+    records: AtomicU64,
+    compressed_records: AtomicU64,
+    raw_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+}
+
+impl CompressionCounters {
+    /// Count a record of `stored` bytes that would have been `raw` bytes uncompressed
+    pub(super) fn add(&self, raw: usize, stored: usize, compressed: bool) {
+        self.records.fetch_add(1, Ordering::Relaxed);
+        if compressed {
+            self.compressed_records.fetch_add(1, Ordering::Relaxed);
+        }
+        self.raw_bytes.fetch_add(raw as u64, Ordering::Relaxed);
+        self.stored_bytes
+            .fetch_add(stored as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            records: self.records.load(Ordering::Relaxed),
+            compressed_records: self.compressed_records.load(Ordering::Relaxed),
+            raw_bytes: self.raw_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verbose_payload() -> Vec<u8> {
+        br#"{"name":"John Doe","email":"john@example.com","roles":["admin","user"]}"#.repeat(20)
+    }
+
+    #[test]
+    fn test_compression_parsing() {
+        for compression in [
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd(DEFAULT_ZSTD_LEVEL),
+            Compression::Zstd(19),
+        ] {
+            assert_eq!(compression.to_string().parse(), Ok(compression));
+        }
+        assert_eq!("zstd".parse(), Ok(Compression::Zstd(DEFAULT_ZSTD_LEVEL)));
+        assert!("zstd:1000".parse::<Compression>().is_err());
+        assert!("gzip".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let payload = verbose_payload();
+
+        for (compression, expected) in [
+            (Compression::Zstd(DEFAULT_ZSTD_LEVEL), Algorithm::Zstd),
+            (Compression::Lz4, Algorithm::Lz4),
+        ] {
+            let (algorithm, compressed) = compress(compression, &payload).unwrap();
+            assert_eq!(algorithm, expected);
+            assert!(compressed.len() < payload.len());
+            assert_eq!(decompress(algorithm, &compressed).unwrap(), payload);
+        }
+
+        assert!(compress(Compression::None, &payload).is_none());
+    }
+
+    #[test]
+    fn test_incompressible_payload_is_left_alone() {
+        assert!(compress(Compression::Lz4, b"short").is_none());
+
+        // Bytes without repetition do not get smaller
+        let random: Vec<u8> = (0..256u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        assert!(compress(Compression::Lz4, &random).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_length_is_rejected() {
+        let payload = verbose_payload();
+        for compression in [Compression::Zstd(DEFAULT_ZSTD_LEVEL), Compression::Lz4] {
+            let (algorithm, mut compressed) = compress(compression, &payload).unwrap();
+            compressed[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(decompress(algorithm, &compressed).is_err());
+        }
+    }
+}
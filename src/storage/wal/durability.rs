@@ -0,0 +1,170 @@
+//! Durability policy of the write-ahead log and the file layer it syncs through
+
+use super::segment::ActiveSegment;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::storage::worker::PeriodicWorker;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// When WAL writes are forced to stable storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// `fsync` after every write, before the write is acknowledged
+    Always,
+    /// `fsync` from a background thread at most this often; a crash can lose
+    /// writes acknowledged within the last interval
+    Interval(Duration),
+    /// Leave flushing to the operating system; a power failure can lose any
+    /// write that the OS has not written back yet
+    #[default]
+    Os,
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Durability::Always => write!(f, "always"),
+            Durability::Interval(interval) => write!(f, "interval:{}", interval.as_millis()),
+            Durability::Os => write!(f, "os"),
+        }
+    }
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    /// Parse `always`, `os` or `interval:<milliseconds>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "always" => Ok(Durability::Always),
+            "os" => Ok(Durability::Os),
+            other => other
+                .strip_prefix("interval:")
+                .and_then(|ms| ms.parse().ok())
+                .filter(|&ms| ms > 0)
+                .map(|ms| Durability::Interval(Duration::from_millis(ms)))
+                .ok_or_else(|| {
+                    format!(
+                        "unknown durability '{other}', expected 'always', 'os' or 'interval:<ms>'"
+                    )
+                }),
+        }
+    }
+}
+
+/// File operations the WAL writes through
+///
+/// Implemented for [`File`]; tests substitute layers that inject faults.
+pub trait WalFile: Write + Send {
+    /// Force written data to stable storage
+    ///
+    /// # Errors
+    /// Returns an error if the data could not be synced
+    fn sync_data(&mut self) -> io::Result<()>;
+
+    /// Truncate or extend the file to `len` bytes
+    ///
+    /// # Errors
+    /// Returns an error if the file length could not be changed
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl WalFile for File {
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+}
+
+/// Shared handle to the segment the WAL appends to
+pub(super) type SharedSegment = Arc<Mutex<ActiveSegment>>;
+
+/// Background thread that syncs the WAL file for [`Durability::Interval`]
+///
+/// The thread stops as soon as the syncer is dropped.
+pub(super) struct IntervalSyncer {
+    _worker: PeriodicWorker,
+}
+
+impl IntervalSyncer {
+    /// Start syncing `file` every `interval` while `dirty` is set
+    pub(super) fn spawn(
+        file: &SharedSegment,
+        dirty: &Arc<AtomicBool>,
+        interval: Duration,
+    ) -> StorageResult<Self> {
+        let file: Weak<_> = Arc::downgrade(file);
+        let dirty = Arc::clone(dirty);
+
+        let worker = PeriodicWorker::spawn("zephyrite-wal-sync", interval, move || {
+            let Some(file) = file.upgrade() else {
+                return ControlFlow::Break(());
+            };
+
+            if !dirty.swap(false, Ordering::AcqRel) {
+                return ControlFlow::Continue(());
+            }
+
+            let result = match file.lock() {
+                Ok(mut active) => active.file.sync_data(),
+                Err(_) => return ControlFlow::Break(()),
+            };
+
+            match result {
+                Ok(()) => debug!("Synced WAL file"),
+                Err(e) => {
+                    // Try again on the next tick
+                    dirty.store(true, Ordering::Release);
+                    warn!("Failed to sync WAL file: {}", e);
+                }
+            }
+            ControlFlow::Continue(())
+        })
+        .map_err(|e| StorageError::Internal(format!("Failed to start WAL syncer: {e}")))?;
+
+        Ok(Self { _worker: worker })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durability_parsing() {
+        assert_eq!("always".parse::<Durability>().unwrap(), Durability::Always);
+        assert_eq!("os".parse::<Durability>().unwrap(), Durability::Os);
+        assert_eq!(
+            "interval:250".parse::<Durability>().unwrap(),
+            Durability::Interval(Duration::from_millis(250))
+        );
+
+        assert!("interval:0".parse::<Durability>().is_err());
+        assert!("interval:soon".parse::<Durability>().is_err());
+        assert!("sometimes".parse::<Durability>().is_err());
+    }
+
+    #[test]
+    fn test_durability_display_roundtrip() {
+        for durability in [
+            Durability::Always,
+            Durability::Os,
+            Durability::Interval(Duration::from_millis(100)),
+        ] {
+            assert_eq!(
+                durability.to_string().parse::<Durability>().unwrap(),
+                durability
+            );
+        }
+    }
+}
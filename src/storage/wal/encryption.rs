@@ -0,0 +1,370 @@
+//! Encryption at rest of WAL records and snapshots
+//!
+//! Each record payload is sealed on its own with ChaCha20-Poly1305 under a
+//! random nonce, after compression. A sealed payload is laid out as the id of
+//! the key as a little-endian `u32`, the 12 byte nonce, then the ciphertext and
+//! its tag. The record flags are authenticated along with it, so they cannot be
+//! changed without the record failing to open.
+//!
+//! Records name the key they were sealed with, so a [`Keyring`] can still read
+//! records sealed with a key that has since been rotated out, and a wrong or
+//! missing key is reported as such rather than as a damaged record.
+
+use crate::storage::error::{StorageError, StorageResult};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fmt;
+use std::path::Path;
+
+/// Length of an encryption key in bytes
+pub const KEY_LEN: usize = 32;
+
+/// Length of the nonce in front of every sealed payload
+const NONCE_LEN: usize = 12;
+
+/// Length of the key id and nonce in front of every sealed payload
+const SEALED_HEADER_LEN: usize = 4 + NONCE_LEN;
+
+/// Associated data of the message a key's id is derived from
+const KEY_ID_CONTEXT: &[u8] = b"zephyrite key id";
+
+/// A 256-bit key that WAL records and snapshots are encrypted with
+///
+/// Keys are identified by an id derived from the key itself, which reveals
+/// nothing about the key but lets records name the key they need.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: u32,
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptionKey {
+    /// Create a key from its raw bytes
+    #[must_use]
+    pub fn from_bytes(bytes: &[u8; KEY_LEN]) -> Self {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(bytes));
+
+        // The tag of an empty message under a nonce no record uses
+        let id = cipher
+            .encrypt(
+                Nonce::from_slice(&[0xff; NONCE_LEN]),
+                Payload {
+                    msg: &[],
+                    aad: KEY_ID_CONTEXT,
+                },
+            )
+            .ok()
+            .and_then(|tag| tag.first_chunk::<4>().copied())
+            .map_or(0, u32::from_le_bytes);
+
+        Self { id, cipher }
+    }
+
+    /// Parse a key written as 64 hexadecimal digits or in base64
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Encryption` if the text is not a key of the
+    /// right length in either encoding.
+    pub fn parse(text: &str) -> StorageResult<Self> {
+        let text = text.trim();
+        let bytes = decode_hex(text)
+            .or_else(|| STANDARD.decode(text).ok())
+            .ok_or_else(|| {
+                StorageError::Encryption(
+                    "Encryption key is neither hexadecimal nor base64".to_string(),
+                )
+            })?;
+
+        let bytes: [u8; KEY_LEN] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            StorageError::Encryption(format!(
+                "Encryption key is {} bytes long, expected {KEY_LEN}",
+                bytes.len()
+            ))
+        })?;
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    /// Load a key from a file holding it as text, see [`EncryptionKey::parse`],
+    /// or as its 32 raw bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Encryption` if the file cannot be read or does
+    /// not hold a key.
+    pub fn from_file(path: impl AsRef<Path>) -> StorageResult<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read(path).map_err(|e| {
+            StorageError::Encryption(format!(
+                "Failed to read encryption key file {}: {e}",
+                path.display()
+            ))
+        })?;
+
+        let parsed = std::str::from_utf8(&contents)
+            .map_err(|_| StorageError::Encryption("Encryption key is not text".to_string()))
+            .and_then(Self::parse);
+        match (parsed, <[u8; KEY_LEN]>::try_from(contents.as_slice())) {
+            (Ok(key), _) => Ok(key),
+            (Err(_), Ok(raw)) => Ok(Self::from_bytes(&raw)),
+            (Err(e), Err(_)) => Err(StorageError::Encryption(format!(
+                "{e} in key file {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Load a key from the environment variable `name`, see [`EncryptionKey::parse`]
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Encryption` if the variable is not set or does
+    /// not hold a key.
+    pub fn from_env(name: &str) -> StorageResult<Self> {
+        let text = std::env::var(name).map_err(|e| {
+            StorageError::Encryption(format!("Failed to read encryption key from ${name}: {e}"))
+        })?;
+        Self::parse(&text).map_err(|e| StorageError::Encryption(format!("{e} in ${name}")))
+    }
+
+    /// Id that records sealed with this key carry
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &format_args!("{:08x}", self.id))
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for EncryptionKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+/// The keys records are sealed and opened with
+///
+/// New records are sealed with the current key. Previous keys are only used to
+/// open records written before the current key was rotated in; compacting the
+/// WAL rewrites everything with the current key. An empty keyring leaves
+/// records unencrypted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Keyring {
+    current: Option<EncryptionKey>,
+    previous: Vec<EncryptionKey>,
+}
+
+impl Keyring {
+    /// A keyring that seals new records with `current`
+    #[must_use]
+    pub fn new(current: EncryptionKey) -> Self {
+        Self {
+            current: Some(current),
+            previous: Vec::new(),
+        }
+    }
+
+    /// Adds a key that records written before a rotation may be sealed with
+    #[must_use]
+    pub fn with_previous(mut self, key: EncryptionKey) -> Self {
+        self.previous.push(key);
+        self
+    }
+
+    /// Whether new records are encrypted
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Whether keys that have been rotated out are still configured
+    #[must_use]
+    pub fn is_rotating(&self) -> bool {
+        !self.previous.is_empty()
+    }
+
+    /// The key new records are sealed with
+    #[must_use]
+    pub fn current(&self) -> Option<&EncryptionKey> {
+        self.current.as_ref()
+    }
+
+    /// Seal `payload` with the current key, authenticating `flags` with it
+    ///
+    /// Returns `Ok(None)` if the keyring is empty.
+    pub(super) fn seal(&self, flags: u8, payload: &[u8]) -> StorageResult<Option<Vec<u8>>> {
+        let Some(key) = &self.current else {
+            return Ok(None);
+        };
+
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: payload,
+                    aad: &[flags],
+                },
+            )
+            .map_err(|_| StorageError::Encryption("Failed to encrypt WAL record".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(SEALED_HEADER_LEN + ciphertext.len());
+        sealed.extend_from_slice(&key.id.to_le_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(Some(sealed))
+    }
+
+    /// Whether `sealed` was sealed with the current key
+    pub(super) fn is_current(&self, sealed: &[u8]) -> bool {
+        let id = sealed
+            .get(..4)
+            .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]));
+        id.is_some() && id == self.current.as_ref().map(EncryptionKey::id)
+    }
+
+    /// Open a payload sealed with any key in the keyring
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Encryption` if the keyring is empty, the key the
+    /// payload was sealed with is not in it, or the payload or `flags` were
+    /// altered.
+    pub(super) fn open(&self, flags: u8, sealed: &[u8]) -> StorageResult<Vec<u8>> {
+        if sealed.len() < SEALED_HEADER_LEN {
+            return Err(StorageError::Encryption(
+                "Encrypted WAL record is truncated".to_string(),
+            ));
+        }
+        let (id, rest) = sealed.split_at(4);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let id = u32::from_le_bytes([id[0], id[1], id[2], id[3]]);
+
+        if !self.is_enabled() {
+            return Err(StorageError::Encryption(format!(
+                "WAL record is encrypted with key {id:08x}, but no encryption key is configured"
+            )));
+        }
+        let key = self
+            .current
+            .iter()
+            .chain(&self.previous)
+            .find(|key| key.id == id)
+            .ok_or_else(|| {
+                StorageError::Encryption(format!(
+                    "WAL record is encrypted with key {id:08x}, which is not among the configured keys"
+                ))
+            })?;
+
+        key.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &[flags],
+                },
+            )
+            .map_err(|_| {
+                StorageError::Encryption(format!(
+                    "WAL record encrypted with key {id:08x} failed authentication"
+                ))
+            })
+    }
+}
+
+/// Decode 64 hexadecimal digits
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn key(byte: u8) -> EncryptionKey {
+        EncryptionKey::from_bytes(&[byte; KEY_LEN])
+    }
+
+    #[test]
+    fn test_key_parsing() {
+        let bytes: [u8; KEY_LEN] = std::array::from_fn(|i| u8::try_from(i).unwrap());
+        let expected = EncryptionKey::from_bytes(&bytes);
+
+        assert_eq!(EncryptionKey::parse(HEX_KEY).unwrap(), expected);
+        assert_eq!(
+            EncryptionKey::parse(&format!(" {}\n", STANDARD.encode(bytes))).unwrap(),
+            expected
+        );
+        assert!(EncryptionKey::parse("not a key").is_err());
+        assert!(EncryptionKey::parse(&STANDARD.encode([0; 16])).is_err());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("key");
+        std::fs::write(&path, format!("{HEX_KEY}\n")).unwrap();
+        assert_eq!(EncryptionKey::from_file(&path).unwrap(), expected);
+        std::fs::write(&path, bytes).unwrap();
+        assert_eq!(EncryptionKey::from_file(&path).unwrap(), expected);
+        assert!(EncryptionKey::from_file(temp_dir.path().join("missing")).is_err());
+
+        // Debug output never shows the key
+        assert!(!format!("{expected:?}").contains("0102"));
+        assert_ne!(key(1).id(), key(2).id());
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let keyring = Keyring::new(key(1));
+        let sealed = keyring.seal(0x08, b"secret value").unwrap().unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+        assert_eq!(keyring.open(0x08, &sealed).unwrap(), b"secret value");
+
+        // Sealing twice uses different nonces
+        assert_ne!(
+            keyring.seal(0x08, b"secret value").unwrap().unwrap(),
+            sealed
+        );
+
+        // Flags are authenticated
+        assert!(keyring.open(0x09, &sealed).is_err());
+
+        assert!(Keyring::default().seal(0x08, b"plain").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_wrong_or_missing_key() {
+        let sealed = Keyring::new(key(1)).seal(0, b"value").unwrap().unwrap();
+
+        let missing = Keyring::default().open(0, &sealed).unwrap_err();
+        assert!(
+            matches!(missing, StorageError::Encryption(msg) if msg.contains("no encryption key"))
+        );
+
+        let wrong = Keyring::new(key(2)).open(0, &sealed).unwrap_err();
+        assert!(matches!(wrong, StorageError::Encryption(msg) if msg.contains("not among")));
+
+        // A rotated keyring still opens records sealed with the previous key
+        let rotated = Keyring::new(key(2)).with_previous(key(1));
+        assert!(rotated.is_rotating());
+        assert_eq!(rotated.open(0, &sealed).unwrap(), b"value");
+        assert!(!rotated.is_current(&sealed));
+        assert!(rotated.is_current(&rotated.seal(0, b"value").unwrap().unwrap()));
+    }
+}
@@ -0,0 +1,108 @@
+//! File layer for tests that injects sync failures
+//!
+//! [`open_faulty`] opens a WAL whose active segment keeps track of which bytes
+//! reached stable storage, and whose syncs fail or stall on request.
+
+use super::durability::WalFile;
+use super::segment::{self, ActiveSegment};
+use super::{Durability, WalManager, WalOptions};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+/// Bytes written to and synced through a [`FaultyFile`]
+#[derive(Default)]
+pub(crate) struct FaultState {
+    pub(crate) written_len: AtomicU64,
+    pub(crate) synced_len: AtomicU64,
+    pub(crate) syncs: AtomicUsize,
+    pub(crate) fail_sync: AtomicBool,
+    pub(crate) slow_sync: AtomicBool,
+}
+
+/// File layer that tracks which bytes reached stable storage and can fail syncs
+pub(crate) struct FaultyFile {
+    file: File,
+    state: Arc<FaultState>,
+}
+
+impl Write for FaultyFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.state
+            .written_len
+            .fetch_add(written as u64, Ordering::SeqCst);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WalFile for FaultyFile {
+    fn sync_data(&mut self) -> std::io::Result<()> {
+        if self.state.fail_sync.load(Ordering::SeqCst) {
+            return Err(std::io::Error::other("injected sync failure"));
+        }
+        if self.state.slow_sync.load(Ordering::SeqCst) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        self.file.sync_data()?;
+        self.state.syncs.fetch_add(1, Ordering::SeqCst);
+        self.state.synced_len.store(
+            self.state.written_len.load(Ordering::SeqCst),
+            Ordering::SeqCst,
+        );
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)?;
+        self.state.written_len.store(len, Ordering::SeqCst);
+        self.state.synced_len.fetch_min(len, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Open the WAL at `path` with `durability` on top of a [`FaultyFile`]
+pub(crate) fn open_faulty(path: &Path, durability: Durability) -> (WalManager, Arc<FaultState>) {
+    open_faulty_with(path, &WalOptions::default().with_durability(durability))
+}
+
+/// Open the WAL at `path` with `options` on top of a [`FaultyFile`]
+pub(crate) fn open_faulty_with(path: &Path, options: &WalOptions) -> (WalManager, Arc<FaultState>) {
+    let (number, segment_path, header) = WalManager::prepare_segments(path, true).unwrap();
+
+    let file = OpenOptions::new().append(true).open(segment_path).unwrap();
+    let bytes = file.metadata().unwrap().len();
+    let state = Arc::new(FaultState::default());
+    state.written_len.store(bytes, Ordering::SeqCst);
+    state.synced_len.store(bytes, Ordering::SeqCst);
+
+    let faulty = FaultyFile {
+        file,
+        state: Arc::clone(&state),
+    };
+    let active = ActiveSegment {
+        file: Box::new(faulty),
+        number,
+        bytes,
+        header_len: header.encoded_len(),
+    };
+    let wal_manager =
+        WalManager::with_segment(path, active, header.base_sequence_number, options).unwrap();
+
+    (wal_manager, state)
+}
+
+/// Simulate a power failure: everything that was not synced is lost
+pub(crate) fn crash(path: &Path, state: &FaultState) {
+    let (_, active) = segment::list(path).unwrap().pop().unwrap();
+    let file = OpenOptions::new().write(true).open(active).unwrap();
+    file.set_len(state.synced_len.load(Ordering::SeqCst))
+        .unwrap();
+}
@@ -0,0 +1,704 @@
+//! Binary on-disk format of the write-ahead log
+//!
+//! A WAL file starts with a 16 byte header: the magic `ZWAL`, the format
+//! version as a little-endian `u16`, two reserved zero bytes and the base
+//! sequence number as a little-endian `u64`. The base sequence number is the
+//! last one handed out when the file was started, so the log keeps counting
+//! from there even once every entry before it has been deleted. Version 1
+//! files have an 8 byte header without it. Records follow back to back, each
+//! framed as:
+//!
+//! | Field    | Size | Contents                                                           |
+//! | -------- | ---- | ------------------------------------------------------------------ |
+//! | length   | 4    | Payload length                                                     |
+//! | checksum | 4    | CRC32C over the flags byte and payload, or 0                       |
+//! | flags    | 1    | [`FLAG_CHECKSUM`], [`FLAG_ZSTD`], [`FLAG_LZ4`], [`FLAG_ENCRYPTED`] |
+//! | payload  | n    | The encoded [`WalEntry`], possibly compressed and encrypted        |
+//!
+//! A payload flagged as compressed is laid out as described in [`compression`],
+//! and one flagged as encrypted as described in [`encryption`]; compression is
+//! applied first. The checksum covers the payload as stored, so damage is
+//! caught before anything is decrypted or decompressed.
+//!
+//! All integers are little-endian. Unlike the hasher used by the old JSON
+//! format, CRC32C is fixed by specification, so checksums stay valid across
+//! toolchain upgrades.
+
+use super::compression::{self, Algorithm, Compression};
+use super::encryption::Keyring;
+use super::{WalEntry, WalOperation};
+use crate::storage::error::{StorageError, StorageResult};
+use chrono::{DateTime, Utc};
+use std::io::{ErrorKind, Read};
+
+/// Magic bytes at the start of every binary WAL file
+pub const MAGIC: &[u8; 4] = b"ZWAL";
+
+/// Current version of the binary WAL format
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Length of the file header in bytes
+pub const HEADER_LEN: u64 = 16;
+
+/// Length of the header of version 1 files, which have no base sequence number
+pub const V1_HEADER_LEN: u64 = 8;
+
+/// Length of the framing in front of each record payload
+pub const RECORD_HEADER_LEN: usize = 9;
+
+/// Record flag: the checksum field holds a CRC32C of the record
+pub const FLAG_CHECKSUM: u8 = 0x01;
+
+/// Record flag: the payload is compressed with zstd
+pub const FLAG_ZSTD: u8 = 0x02;
+
+/// Record flag: the payload is compressed with LZ4
+pub const FLAG_LZ4: u8 = 0x04;
+
+/// Record flag: the payload is encrypted
+pub const FLAG_ENCRYPTED: u8 = 0x08;
+
+/// Every record flag this version understands
+const KNOWN_FLAGS: u8 = FLAG_CHECKSUM | FLAG_ZSTD | FLAG_LZ4 | FLAG_ENCRYPTED;
+
+const TAG_PUT: u8 = 1;
+const TAG_DELETE: u8 = 2;
+const TAG_EXPIRE: u8 = 3;
+const TAG_CLEAR: u8 = 4;
+const TAG_BATCH: u8 = 5;
+
+/// A decoded file header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    /// Format version of the file
+    pub version: u16,
+    /// Last sequence number handed out when the file was started; 0 for
+    /// version 1 files
+    pub base_sequence_number: u64,
+}
+
+impl FileHeader {
+    /// Length of the header in bytes
+    #[must_use]
+    pub fn encoded_len(&self) -> u64 {
+        if self.version == 1 {
+            V1_HEADER_LEN
+        } else {
+            HEADER_LEN
+        }
+    }
+}
+
+/// Encode the file header for the current format version
+#[must_use]
+pub fn encode_header(base_sequence_number: u64) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[..4].copy_from_slice(MAGIC);
+    header[4..6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header[8..].copy_from_slice(&base_sequence_number.to_le_bytes());
+    header
+}
+
+/// Check that `header` starts a binary WAL file this version can read, and
+/// return its format version
+///
+/// # Errors
+/// Returns `StorageError::Internal` if the magic is missing or the version is unsupported
+pub fn check_header(header: &[u8]) -> StorageResult<u16> {
+    if header.len() < 8 || &header[..4] != MAGIC {
+        return Err(StorageError::Internal(
+            "WAL file does not start with a valid header".to_string(),
+        ));
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(StorageError::Internal(format!(
+            "Unsupported WAL format version {version} (expected at most {FORMAT_VERSION})"
+        )));
+    }
+
+    Ok(version)
+}
+
+/// Read and check the file header at the start of `reader`
+///
+/// # Errors
+/// Returns `StorageError::Internal` if the header cannot be read, the magic is
+/// missing or the version is unsupported
+pub fn read_header(reader: &mut impl Read) -> StorageResult<FileHeader> {
+    let io_error =
+        |e: std::io::Error| StorageError::Internal(format!("Failed to read WAL header: {e}"));
+
+    let mut header = [0; 16];
+    reader.read_exact(&mut header[..8]).map_err(io_error)?;
+    let version = check_header(&header)?;
+
+    let mut base_sequence_number = 0;
+    if version >= 2 {
+        reader.read_exact(&mut header[8..]).map_err(io_error)?;
+        base_sequence_number = u64::from_le_bytes([
+            header[8], header[9], header[10], header[11], header[12], header[13], header[14],
+            header[15],
+        ]);
+    }
+
+    Ok(FileHeader {
+        version,
+        base_sequence_number,
+    })
+}
+
+/// Encode an entry as a complete, uncompressed and unencrypted framed record
+///
+/// # Errors
+/// Returns `StorageError::Internal` if a length does not fit the record format
+pub fn encode_record(entry: &WalEntry, checksum: bool) -> StorageResult<Vec<u8>> {
+    let mut payload = Vec::new();
+    encode_entry(&mut payload, entry)?;
+    frame(if checksum { FLAG_CHECKSUM } else { 0 }, &payload)
+}
+
+/// Encode an entry as a complete framed record, compressing the payload when
+/// that makes it smaller and encrypting it with the current key of `keys`
+///
+/// Returns the record and the length it would have had uncompressed and
+/// unencrypted.
+///
+/// # Errors
+/// Returns `StorageError::Encryption` if the payload cannot be encrypted, and
+/// `StorageError::Internal` if a length does not fit the record format
+pub fn encode_record_with(
+    entry: &WalEntry,
+    checksum: bool,
+    compression: Compression,
+    keys: &Keyring,
+) -> StorageResult<(Vec<u8>, usize)> {
+    let mut payload = Vec::new();
+    encode_entry(&mut payload, entry)?;
+    let raw_len = RECORD_HEADER_LEN + payload.len();
+
+    let mut flags = if checksum { FLAG_CHECKSUM } else { 0 };
+    if let Some((algorithm, compressed)) = compression::compress(compression, &payload) {
+        flags |= match algorithm {
+            Algorithm::Zstd => FLAG_ZSTD,
+            Algorithm::Lz4 => FLAG_LZ4,
+        };
+        payload = compressed;
+    }
+    if keys.is_enabled() {
+        flags |= FLAG_ENCRYPTED;
+        if let Some(sealed) = keys.seal(flags, &payload)? {
+            payload = sealed;
+        }
+    }
+
+    Ok((frame(flags, &payload)?, raw_len))
+}
+
+/// Frame a payload stored with `flags` as a record
+fn frame(flags: u8, payload: &[u8]) -> StorageResult<Vec<u8>> {
+    let crc = if flags & FLAG_CHECKSUM != 0 {
+        crc32c::crc32c_append(crc32c::crc32c(&[flags]), payload)
+    } else {
+        0
+    };
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&len_u32(payload.len())?.to_le_bytes());
+    record.extend_from_slice(&crc.to_le_bytes());
+    record.push(flags);
+    record.extend_from_slice(payload);
+    Ok(record)
+}
+
+/// Read the next framed record of a log without encrypted records, see
+/// [`read_record_with`]
+///
+/// # Errors
+/// Returns `StorageError::Internal` if the record is truncated, fails its
+/// checksum, or cannot be decoded, and `StorageError::Encryption` if it is
+/// encrypted
+pub fn read_record(reader: &mut impl Read) -> StorageResult<Option<(WalEntry, usize)>> {
+    read_record_with(reader, &Keyring::default())
+}
+
+/// Read the next framed record, decrypting it with `keys` if it is encrypted,
+/// and return the entry and the bytes it took up
+///
+/// Returns `Ok(None)` at a clean end of file.
+///
+/// # Errors
+/// Returns `StorageError::Internal` if the record is truncated, fails its
+/// checksum, or cannot be decoded, and `StorageError::Encryption` if it is
+/// encrypted with a key that is not in `keys` or fails authentication
+pub fn read_record_with(
+    reader: &mut impl Read,
+    keys: &Keyring,
+) -> StorageResult<Option<(WalEntry, usize)>> {
+    Ok(read_sealed_record(reader, keys)?.map(|(entry, len, _)| (entry, len)))
+}
+
+/// Read the next framed record like [`read_record_with`], also returning
+/// whether it was sealed with a key other than the current one of `keys`, and
+/// needs sealing again before that key can be dropped
+///
+/// # Errors
+/// See [`read_record_with`].
+pub fn read_sealed_record(
+    reader: &mut impl Read,
+    keys: &Keyring,
+) -> StorageResult<Option<(WalEntry, usize, bool)>> {
+    let mut header = [0; RECORD_HEADER_LEN];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(None),
+        RECORD_HEADER_LEN => {}
+        _ => return Err(truncated()),
+    }
+
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let flags = header[8];
+    if flags & !KNOWN_FLAGS != 0 || flags & (FLAG_ZSTD | FLAG_LZ4) == FLAG_ZSTD | FLAG_LZ4 {
+        return Err(StorageError::Internal(format!(
+            "Unknown flags {flags:#04x} on WAL record"
+        )));
+    }
+
+    // Read through `take` so a corrupt length cannot trigger a huge allocation
+    let mut payload = Vec::new();
+    reader
+        .take(len as u64)
+        .read_to_end(&mut payload)
+        .map_err(|e| StorageError::Internal(format!("Failed to read WAL record: {e}")))?;
+    if payload.len() < len {
+        return Err(truncated());
+    }
+
+    if flags & FLAG_CHECKSUM != 0
+        && crc32c::crc32c_append(crc32c::crc32c(&[flags]), &payload) != crc
+    {
+        return Err(StorageError::Internal(
+            "Checksum verification failed for WAL record".to_string(),
+        ));
+    }
+
+    let stale = flags & FLAG_ENCRYPTED != 0 && !keys.is_current(&payload);
+    if flags & FLAG_ENCRYPTED != 0 {
+        payload = keys.open(flags, &payload)?;
+    }
+    if flags & FLAG_ZSTD != 0 {
+        payload = compression::decompress(Algorithm::Zstd, &payload)?;
+    } else if flags & FLAG_LZ4 != 0 {
+        payload = compression::decompress(Algorithm::Lz4, &payload)?;
+    }
+
+    let entry = decode_entry(&mut Decoder::new(&payload))?;
+    Ok(Some((entry, RECORD_HEADER_LEN + len, stale)))
+}
+
+/// Fill `buf` from `reader`, returning how many bytes were read before end of file
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> StorageResult<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                return Err(StorageError::Internal(format!(
+                    "Failed to read WAL record: {e}"
+                )));
+            }
+        }
+    }
+
+    Ok(filled)
+}
+
+fn truncated() -> StorageError {
+    StorageError::Internal("WAL record is truncated".to_string())
+}
+
+/// A length as stored in a record, which cannot exceed `u32::MAX`
+///
+/// Validation keeps keys, values and batches far below that, so a longer one
+/// is refused rather than written as a record recovery would reject.
+fn len_u32(len: usize) -> StorageResult<u32> {
+    u32::try_from(len).map_err(|_| {
+        StorageError::Internal(format!(
+            "WAL record field of {len} bytes exceeds the record format's limit"
+        ))
+    })
+}
+
+fn encode_entry(out: &mut Vec<u8>, entry: &WalEntry) -> StorageResult<()> {
+    out.extend_from_slice(&entry.sequence_number.to_le_bytes());
+    encode_bytes(out, entry.timestamp.as_bytes())?;
+    encode_operation(out, &entry.operation)
+}
+
+fn encode_operation(out: &mut Vec<u8>, operation: &WalOperation) -> StorageResult<()> {
+    match operation {
+        WalOperation::Put {
+            key,
+            value,
+            expires_at,
+            version,
+        } => {
+            out.push(TAG_PUT);
+            encode_bytes(out, key.as_bytes())?;
+            encode_bytes(out, value)?;
+            match expires_at {
+                Some(expires_at) => {
+                    out.push(1);
+                    out.extend_from_slice(&expires_at.timestamp().to_le_bytes());
+                    out.extend_from_slice(&expires_at.timestamp_subsec_nanos().to_le_bytes());
+                }
+                None => out.push(0),
+            }
+            match version {
+                Some(version) => {
+                    out.push(1);
+                    out.extend_from_slice(&version.to_le_bytes());
+                }
+                None => out.push(0),
+            }
+        }
+        WalOperation::Delete { key } => {
+            out.push(TAG_DELETE);
+            encode_bytes(out, key.as_bytes())?;
+        }
+        WalOperation::Expire { key } => {
+            out.push(TAG_EXPIRE);
+            encode_bytes(out, key.as_bytes())?;
+        }
+        WalOperation::Clear => out.push(TAG_CLEAR),
+        WalOperation::Batch { operations } => {
+            out.push(TAG_BATCH);
+            out.extend_from_slice(&len_u32(operations.len())?.to_le_bytes());
+            for operation in operations {
+                encode_operation(out, operation)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> StorageResult<()> {
+    out.extend_from_slice(&len_u32(bytes.len())?.to_le_bytes());
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn decode_entry(decoder: &mut Decoder<'_>) -> StorageResult<WalEntry> {
+    let sequence_number = decoder.u64()?;
+    let timestamp = decoder.string()?;
+    let operation = decode_operation(decoder)?;
+
+    if !decoder.is_empty() {
+        return Err(StorageError::Internal(
+            "WAL record has trailing bytes".to_string(),
+        ));
+    }
+
+    Ok(WalEntry {
+        sequence_number,
+        operation,
+        timestamp,
+    })
+}
+
+fn decode_operation(decoder: &mut Decoder<'_>) -> StorageResult<WalOperation> {
+    match decoder.u8()? {
+        TAG_PUT => {
+            let key = decoder.string()?;
+            let value = decoder.bytes()?.to_vec();
+            let expires_at = if decoder.flag()? {
+                let secs = decoder.i64()?;
+                let nanos = decoder.u32()?;
+                Some(DateTime::<Utc>::from_timestamp(secs, nanos).ok_or_else(|| {
+                    StorageError::Internal("Invalid expiry in WAL record".to_string())
+                })?)
+            } else {
+                None
+            };
+            let version = if decoder.flag()? {
+                Some(decoder.u64()?)
+            } else {
+                None
+            };
+
+            Ok(WalOperation::Put {
+                key,
+                value,
+                expires_at,
+                version,
+            })
+        }
+        TAG_DELETE => Ok(WalOperation::Delete {
+            key: decoder.string()?,
+        }),
+        TAG_EXPIRE => Ok(WalOperation::Expire {
+            key: decoder.string()?,
+        }),
+        TAG_CLEAR => Ok(WalOperation::Clear),
+        TAG_BATCH => {
+            let count = decoder.u32()?;
+            let operations = (0..count)
+                .map(|_| decode_operation(decoder))
+                .collect::<StorageResult<_>>()?;
+
+            Ok(WalOperation::Batch { operations })
+        }
+        tag => Err(StorageError::Internal(format!(
+            "Unknown operation tag {tag} in WAL record"
+        ))),
+    }
+}
+
+/// Cursor over a record payload
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> StorageResult<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(StorageError::Internal(
+                "WAL record payload is truncated".to_string(),
+            ));
+        }
+
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> StorageResult<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> StorageResult<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    /// Presence marker in front of an optional field
+    fn flag(&mut self) -> StorageResult<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u32(&mut self) -> StorageResult<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> StorageResult<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> StorageResult<i64> {
+        self.array().map(i64::from_le_bytes)
+    }
+
+    fn bytes(&mut self) -> StorageResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> StorageResult<String> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| StorageError::Internal("Invalid UTF-8 in WAL record".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(entry: &WalEntry, checksum: bool) -> WalEntry {
+        let record = encode_record(entry, checksum).unwrap();
+        let (decoded, len) = read_record(&mut record.as_slice()).unwrap().unwrap();
+        assert_eq!(len, record.len());
+        decoded
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let operations = [
+            WalOperation::Put {
+                key: "key".to_string(),
+                value: vec![0x00, 0xff, b'a'],
+                expires_at: DateTime::from_timestamp(1_750_000_000, 123_456_789),
+                version: Some(7),
+            },
+            WalOperation::Put {
+                key: "plain".to_string(),
+                value: b"value".to_vec(),
+                expires_at: None,
+                version: None,
+            },
+            WalOperation::Delete {
+                key: "gone".to_string(),
+            },
+            WalOperation::Expire {
+                key: "old".to_string(),
+            },
+            WalOperation::Clear,
+            WalOperation::Batch {
+                operations: vec![
+                    WalOperation::Put {
+                        key: "a".to_string(),
+                        value: b"1".to_vec(),
+                        expires_at: None,
+                        version: Some(1),
+                    },
+                    WalOperation::Delete {
+                        key: "b".to_string(),
+                    },
+                ],
+            },
+        ];
+
+        for (sequence_number, operation) in (1..).zip(operations) {
+            let entry = WalEntry::new(sequence_number, operation);
+            assert_eq!(roundtrip(&entry, true), entry);
+            assert_eq!(roundtrip(&entry, false), entry);
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(check_header(&encode_header(0)).unwrap(), FORMAT_VERSION);
+        assert!(check_header(b"{\"seque").is_err());
+
+        let mut future = encode_header(0);
+        future[4] = 99;
+        assert!(check_header(&future).is_err());
+
+        let header = read_header(&mut &encode_header(42)[..]).unwrap();
+        assert_eq!(header.base_sequence_number, 42);
+        assert_eq!(header.encoded_len(), HEADER_LEN);
+    }
+
+    #[test]
+    fn test_version_1_header_has_no_base_sequence_number() {
+        let mut bytes = encode_header(42)[..8].to_vec();
+        bytes[4] = 1;
+        bytes.extend(encode_record(&WalEntry::new(1, WalOperation::Clear), true).unwrap());
+
+        let mut reader = bytes.as_slice();
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.base_sequence_number, 0);
+        assert_eq!(header.encoded_len(), V1_HEADER_LEN);
+        assert!(read_record(&mut reader).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_compressed_record_roundtrip() {
+        let entry = WalEntry::new(
+            1,
+            WalOperation::Put {
+                key: "doc".to_string(),
+                value: br#"{"status":"active","tags":["a","b"]}"#.repeat(10),
+                expires_at: None,
+                version: Some(1),
+            },
+        );
+        let plain = encode_record(&entry, true).unwrap();
+
+        for (compression, flag) in [
+            (Compression::Zstd(3), FLAG_ZSTD),
+            (Compression::Lz4, FLAG_LZ4),
+        ] {
+            for checksum in [true, false] {
+                let (record, raw_len) =
+                    encode_record_with(&entry, checksum, compression, &Keyring::default()).unwrap();
+                assert_eq!(raw_len, plain.len());
+                assert!(record.len() < plain.len());
+                assert_ne!(record[8] & flag, 0);
+
+                let (decoded, len) = read_record(&mut record.as_slice()).unwrap().unwrap();
+                assert_eq!(decoded, entry);
+                assert_eq!(len, record.len());
+            }
+        }
+
+        // Too small to be worth compressing
+        let small = WalEntry::new(2, WalOperation::Clear);
+        let (record, raw_len) =
+            encode_record_with(&small, true, Compression::Lz4, &Keyring::default()).unwrap();
+        assert_eq!(record.len(), raw_len);
+        assert_eq!(record[8], FLAG_CHECKSUM);
+    }
+
+    #[test]
+    fn test_encrypted_record_roundtrip() {
+        use super::super::encryption::EncryptionKey;
+
+        let entry = WalEntry::new(
+            1,
+            WalOperation::Put {
+                key: "customer:42".to_string(),
+                value: br#"{"email":"jane@example.com"}"#.repeat(4),
+                expires_at: None,
+                version: Some(1),
+            },
+        );
+        let keys = Keyring::new(EncryptionKey::from_bytes(&[7; 32]));
+
+        for compression in [Compression::None, Compression::Lz4] {
+            let (record, _) = encode_record_with(&entry, true, compression, &keys).unwrap();
+            assert_ne!(record[8] & FLAG_ENCRYPTED, 0);
+            assert!(!record.windows(8).any(|window| window == b"customer"));
+
+            let (decoded, len) = read_record_with(&mut record.as_slice(), &keys)
+                .unwrap()
+                .unwrap();
+            assert_eq!(decoded, entry);
+            assert_eq!(len, record.len());
+
+            let result = read_record(&mut record.as_slice());
+            assert!(matches!(result, Err(StorageError::Encryption(_))));
+        }
+    }
+
+    #[test]
+    fn test_unknown_flags_are_rejected() {
+        let mut record = encode_record(&WalEntry::new(1, WalOperation::Clear), false).unwrap();
+        record[8] = 0x80;
+        assert!(read_record(&mut record.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_record_fails_checksum() {
+        let entry = WalEntry::new(1, WalOperation::Clear);
+        let mut record = encode_record(&entry, true).unwrap();
+        let last = record.len() - 1;
+        record[last] ^= 0xff;
+
+        let result = read_record(&mut record.as_slice());
+        assert!(matches!(result, Err(StorageError::Internal(msg)) if msg.contains("Checksum")));
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let entry = WalEntry::new(1, WalOperation::Delete { key: "k".into() });
+        let record = encode_record(&entry, true).unwrap();
+
+        assert!(read_record(&mut &record[..4]).is_err());
+        assert!(read_record(&mut &record[..record.len() - 1]).is_err());
+        assert!(read_record(&mut &[][..]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,1980 @@
+//! Write-ahead log
+//!
+//! Entries are stored in the binary format described in [`format`], split over
+//! the numbered files described in [`segment`]. WAL files written by earlier
+//! versions, as a single file or as one JSON object per line, are migrated the
+//! first time they are opened. Damaged records are handled during replay as
+//! described in [`recovery`].
+
+pub mod compression;
+pub mod durability;
+pub mod encryption;
+#[cfg(test)]
+pub(crate) mod fault;
+pub mod format;
+pub mod recovery;
+pub mod segment;
+pub mod undo;
+
+pub use compression::{Compression, CompressionStats};
+pub use durability::{Durability, WalFile};
+pub use encryption::{EncryptionKey, Keyring};
+pub use recovery::{DiscardedRecord, RecoveryMode, RecoveryTarget, WalRecovery};
+pub use segment::{DEFAULT_MAX_SEGMENT_SIZE, SegmentInfo};
+pub use undo::{Replaced, UndoLog};
+
+use super::error::{StorageError, StorageResult};
+use crate::utils::time;
+use chrono::{DateTime, Utc};
+use compression::CompressionCounters;
+use durability::{IntervalSyncer, SharedSegment};
+use recovery::Damage;
+use segment::ActiveSegment;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use tracing::{debug, info, warn};
+
+/// Types of operations that can be logged in the WAL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WalOperation {
+    /// Put operation: key, value
+    Put {
+        /// The key to store
+        key: String,
+        /// The value to store
+        #[serde(with = "value_encoding")]
+        value: Vec<u8>,
+        /// Absolute expiry time, if the value was stored with a TTL
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<DateTime<Utc>>,
+        /// Version assigned to the key by this write; absent in entries written
+        /// before keys were versioned
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<u64>,
+    },
+    /// Delete operation: key
+    Delete {
+        /// The key to delete
+        key: String,
+    },
+    /// Expire operation: key removed by the TTL reaper
+    Expire {
+        /// The key that expired
+        key: String,
+    },
+    /// Clear operation: clear all data
+    Clear,
+    /// Batch operation: puts and deletes that are applied all-or-nothing
+    Batch {
+        /// The operations in the batch, in application order
+        operations: Vec<WalOperation>,
+    },
+}
+
+/// Serde helpers for WAL values
+///
+/// UTF-8 values are written as plain JSON strings, so text payloads stay
+/// readable and existing WAL files keep parsing. Anything else is written as
+/// `{"base64": "..."}` instead of a JSON array of numbers.
+mod value_encoding {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum EncodedValue {
+        Text(String),
+        Binary { base64: String },
+    }
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(value) {
+            Ok(text) => serializer.serialize_str(text),
+            Err(_) => EncodedValue::Binary {
+                base64: STANDARD.encode(value),
+            }
+            .serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        match EncodedValue::deserialize(deserializer)? {
+            EncodedValue::Text(text) => Ok(text.into_bytes()),
+            EncodedValue::Binary { base64 } => {
+                STANDARD.decode(base64).map_err(serde::de::Error::custom)
+            }
+        }
+    }
+}
+
+/// A single entry in the Write-Ahead Log
+///
+/// Integrity is checked by the CRC32C framing of each record on disk, see [`format`].
+/// The `checksum` field of legacy JSON entries is ignored when they are migrated:
+/// it was produced by `DefaultHasher`, whose output is not stable across Rust versions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// Unique sequence number for this entry
+    pub sequence_number: u64,
+    /// The operation being logged
+    pub operation: WalOperation,
+    /// Timestamp when the operation was logged
+    pub timestamp: String,
+}
+
+impl WalEntry {
+    /// Create a new WAL entry
+    #[must_use]
+    pub fn new(sequence_number: u64, operation: WalOperation) -> Self {
+        Self {
+            sequence_number,
+            operation,
+            timestamp: time::current_timestamp(),
+        }
+    }
+
+    /// Serialize the entry to JSON string
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if JSON serialization fails.
+    pub fn to_json(&self) -> StorageResult<String> {
+        serde_json::to_string(self)
+            .map_err(|e| StorageError::Internal(format!("Failed to serialize WAL entry: {e}")))
+    }
+
+    /// Deserialize the entry from JSON string
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if JSON deserialization fails.
+    pub fn from_json(json: &str) -> StorageResult<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| StorageError::Internal(format!("Failed to deserialize WAL entry: {e}")))
+    }
+}
+
+/// Settings of a [`WalManager`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalOptions {
+    /// Whether records carry a CRC32C checksum
+    pub use_checksums: bool,
+    /// When writes are forced to stable storage
+    pub durability: Durability,
+    /// Whether concurrent writers share one write and sync, see [`WalManager::append`]
+    pub group_commit: bool,
+    /// Size in bytes after which the WAL rolls over to a new segment
+    pub max_segment_size: u64,
+    /// What [`WalManager::recover_entries`] does with damaged records
+    pub recovery_mode: RecoveryMode,
+    /// How new records are compressed
+    pub compression: Compression,
+    /// The keys records are encrypted and decrypted with
+    pub encryption: Keyring,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self {
+            use_checksums: true,
+            durability: Durability::default(),
+            group_commit: true,
+            max_segment_size: DEFAULT_MAX_SEGMENT_SIZE,
+            recovery_mode: RecoveryMode::default(),
+            compression: Compression::default(),
+            encryption: Keyring::default(),
+        }
+    }
+}
+
+impl WalOptions {
+    /// Sets whether records carry a checksum
+    #[must_use]
+    pub fn with_checksums(mut self, use_checksums: bool) -> Self {
+        self.use_checksums = use_checksums;
+        self
+    }
+
+    /// Sets when writes are forced to stable storage
+    #[must_use]
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets whether concurrent writers share one write and sync
+    #[must_use]
+    pub fn with_group_commit(mut self, group_commit: bool) -> Self {
+        self.group_commit = group_commit;
+        self
+    }
+
+    /// Sets the size in bytes after which the WAL rolls over to a new segment
+    #[must_use]
+    pub fn with_max_segment_size(mut self, max_segment_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self
+    }
+
+    /// Sets what recovery does with damaged records
+    #[must_use]
+    pub fn with_recovery_mode(mut self, recovery_mode: RecoveryMode) -> Self {
+        self.recovery_mode = recovery_mode;
+        self
+    }
+
+    /// Sets how new records are compressed
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the keys records are encrypted and decrypted with
+    #[must_use]
+    pub fn with_encryption(mut self, encryption: Keyring) -> Self {
+        self.encryption = encryption;
+        self
+    }
+}
+
+/// An operation appended to the WAL that may not have been written yet
+///
+/// Pass it to [`WalManager::wait_durable`] to wait until it has been.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingWrite {
+    /// Sequence number assigned to the entry
+    pub sequence_number: u64,
+    /// Position of the entry among all appends since the WAL was opened
+    ticket: u64,
+}
+
+/// Entries waiting to be written, shared by all writers
+#[derive(Debug, Default)]
+struct CommitQueue {
+    /// Last sequence number handed out
+    sequence_number: u64,
+    /// Encoded records appended but not written yet, in sequence order
+    pending: Vec<u8>,
+    /// Number of entries appended since the WAL was opened
+    appended: u64,
+    /// Number of appended entries that have been written under the durability policy
+    written: u64,
+    /// Whether a writer is currently writing queued entries for everyone
+    leader_active: bool,
+    /// The write or sync failure that made the WAL unusable
+    failure: Option<String>,
+    /// Tickets of the entries the last truncate discarded before they were written
+    discarded: Range<u64>,
+}
+
+impl CommitQueue {
+    /// Assign the next sequence number, unless an earlier write failed
+    fn reserve(&mut self) -> StorageResult<PendingWrite> {
+        if let Some(failure) = &self.failure {
+            return Err(StorageError::Internal(format!(
+                "WAL is unavailable after an earlier write failed: {failure}"
+            )));
+        }
+
+        self.sequence_number += 1;
+        self.appended += 1;
+
+        Ok(PendingWrite {
+            sequence_number: self.sequence_number,
+            ticket: self.appended,
+        })
+    }
+
+    /// Whether the entry of `pending` has been written under the durability policy
+    fn is_written(&self, pending: &PendingWrite) -> bool {
+        self.written >= pending.ticket && !self.discarded.contains(&pending.ticket)
+    }
+
+    /// Record the outcome of writing every entry up to `ticket`
+    fn complete(&mut self, ticket: u64, result: StorageResult<()>) -> StorageResult<()> {
+        match result {
+            Ok(()) => {
+                self.written = self.written.max(ticket);
+                Ok(())
+            }
+            Err(StorageError::Internal(msg)) => {
+                self.failure = Some(msg.clone());
+                Err(StorageError::Internal(msg))
+            }
+            Err(e) => {
+                self.failure = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Write-Ahead Log manager
+///
+/// With group commit enabled, writers that arrive while a write is in progress
+/// queue their entries; the next writer to wait writes and syncs everything queued
+/// in one go and wakes the others. A failed write or sync leaves the file in an
+/// unknown state, so every later append fails until the WAL is reopened.
+///
+/// Entries go to the newest [`segment`]; once it would grow past the configured
+/// size, it is sealed and a new segment is started.
+pub struct WalManager {
+    /// Path the WAL segment file names are derived from
+    file_path: String,
+    /// The segment entries are appended to
+    file: SharedSegment,
+    /// Sequence numbers and entries waiting to be written
+    queue: Mutex<CommitQueue>,
+    /// Signalled whenever queued entries have been written or have failed
+    committed: Condvar,
+    /// Whether writers queue entries to share writes and syncs
+    group_commit: bool,
+    /// Whether to use checksums for entries
+    use_checksums: bool,
+    /// Size in bytes after which the WAL rolls over to a new segment
+    max_segment_size: u64,
+    /// When writes are forced to stable storage
+    durability: Durability,
+    /// What recovery does with damaged records
+    recovery_mode: RecoveryMode,
+    /// How new records are compressed
+    compression: Compression,
+    /// What compression saved on the records written so far
+    compression_counters: CompressionCounters,
+    /// The keys records are encrypted and decrypted with
+    keys: Keyring,
+    /// Set when the file has writes that have not been synced yet
+    dirty: Arc<AtomicBool>,
+    /// Background syncer for [`Durability::Interval`]
+    _syncer: Option<IntervalSyncer>,
+}
+
+impl WalManager {
+    /// Create a new WAL manager
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the WAL file cannot be opened or created.
+    pub fn new(file_path: impl AsRef<Path>) -> StorageResult<Self> {
+        Self::open(file_path, &WalOptions::default())
+    }
+
+    /// Create a new WAL manager with custom settings
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the WAL file cannot be opened or created.
+    pub fn new_with_options(
+        file_path: impl AsRef<Path>,
+        use_checksums: bool,
+    ) -> StorageResult<Self> {
+        Self::open(
+            file_path,
+            &WalOptions::default().with_checksums(use_checksums),
+        )
+    }
+
+    /// Open the WAL at `file_path` with the given options
+    ///
+    /// A single-file WAL at `file_path` is moved into the first segment, after
+    /// rewriting it in the binary format if it is a legacy JSON WAL.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the WAL files cannot be opened, created
+    /// or migrated, if they were written in an unsupported format version, or if the
+    /// background syncer cannot be started.
+    pub fn open(file_path: impl AsRef<Path>, options: &WalOptions) -> StorageResult<Self> {
+        let path = file_path.as_ref();
+        let (number, segment_path, header) = Self::prepare_segments(path, options.use_checksums)?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&segment_path)
+            .map_err(|e| StorageError::Internal(format!("Failed to open WAL file: {e}")))?;
+        let bytes = file
+            .metadata()
+            .map_err(|e| StorageError::Internal(format!("Failed to read WAL file size: {e}")))?
+            .len();
+
+        let active = ActiveSegment {
+            file: Box::new(file),
+            number,
+            bytes,
+            header_len: header.encoded_len(),
+        };
+        Self::with_segment(path, active, header.base_sequence_number, options)
+    }
+
+    /// Build a manager that appends to `active`, the prepared last segment of
+    /// the WAL at `path`, handing out sequence numbers after `base_sequence_number`
+    fn with_segment(
+        path: &Path,
+        active: ActiveSegment,
+        base_sequence_number: u64,
+        options: &WalOptions,
+    ) -> StorageResult<Self> {
+        let file: SharedSegment = Arc::new(Mutex::new(active));
+        let dirty = Arc::new(AtomicBool::new(false));
+
+        let syncer = match options.durability {
+            Durability::Interval(interval) => Some(IntervalSyncer::spawn(&file, &dirty, interval)?),
+            Durability::Always | Durability::Os => None,
+        };
+
+        Ok(Self {
+            file_path: path.to_string_lossy().to_string(),
+            file,
+            queue: Mutex::new(CommitQueue {
+                sequence_number: base_sequence_number,
+                ..CommitQueue::default()
+            }),
+            committed: Condvar::new(),
+            group_commit: options.group_commit,
+            use_checksums: options.use_checksums,
+            max_segment_size: options.max_segment_size,
+            durability: options.durability,
+            recovery_mode: options.recovery_mode,
+            compression: options.compression,
+            compression_counters: CompressionCounters::default(),
+            keys: options.encryption.clone(),
+            dirty,
+            _syncer: syncer,
+        })
+    }
+
+    /// Make sure the WAL at `base` has a last segment to append to, and return it
+    /// with its header
+    ///
+    /// A non-empty file at `base` itself is a WAL written before segments existed
+    /// and becomes the first segment.
+    fn prepare_segments(
+        base: &Path,
+        use_checksums: bool,
+    ) -> StorageResult<(u64, PathBuf, format::FileHeader)> {
+        let mut segments = segment::list(base)?;
+
+        let unsegmented = fs::metadata(base).is_ok_and(|meta| meta.is_file() && meta.len() > 0);
+        if unsegmented {
+            if !segments.is_empty() {
+                return Err(StorageError::Internal(format!(
+                    "Found both a single-file WAL and WAL segments at {}",
+                    base.display()
+                )));
+            }
+
+            Self::prepare_file(base, use_checksums)?;
+
+            let first = segment::segment_path(base, 1);
+            fs::rename(base, &first)
+                .map_err(|e| StorageError::Internal(format!("Failed to move WAL file: {e}")))?;
+            segment::sync_dir(base)?;
+
+            info!("Moved WAL {:?} into segment {:?}", base, first);
+            segments.push((1, first));
+        }
+
+        let (number, path) = segments
+            .pop()
+            .unwrap_or_else(|| (1, segment::segment_path(base, 1)));
+        let header = Self::prepare_file(&path, use_checksums)?;
+
+        Ok((number, path, header))
+    }
+
+    /// Make sure the file at `path` exists and starts with a binary WAL header,
+    /// and return the header
+    fn prepare_file(path: &Path, use_checksums: bool) -> StorageResult<format::FileHeader> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| StorageError::Internal(format!("Failed to open WAL file: {e}")))?;
+
+        let mut header = Vec::with_capacity(8);
+        (&mut file)
+            .take(format::V1_HEADER_LEN)
+            .read_to_end(&mut header)
+            .map_err(|e| StorageError::Internal(format!("Failed to read WAL header: {e}")))?;
+
+        if header.is_empty() {
+            file.write_all(&format::encode_header(0))
+                .and_then(|()| file.sync_all())
+                .map_err(|e| StorageError::Internal(format!("Failed to write WAL header: {e}")))?;
+            return Ok(format::FileHeader {
+                version: format::FORMAT_VERSION,
+                base_sequence_number: 0,
+            });
+        }
+
+        if !header.starts_with(format::MAGIC) {
+            drop(file);
+            Self::migrate_legacy(path, use_checksums)?;
+            return Ok(format::FileHeader {
+                version: format::FORMAT_VERSION,
+                base_sequence_number: 0,
+            });
+        }
+
+        let mut reader = header.as_slice().chain(file);
+        format::read_header(&mut reader)
+    }
+
+    /// Rewrite a legacy JSON-lines WAL in the binary format
+    ///
+    /// The new file is written next to the old one and renamed over it, so a
+    /// crash during migration leaves the original file untouched.
+    fn migrate_legacy(path: &Path, use_checksums: bool) -> StorageResult<()> {
+        let file = File::open(path).map_err(|e| {
+            StorageError::Internal(format!("Failed to open WAL file for reading: {e}"))
+        })?;
+
+        let mut output = format::encode_header(0).to_vec();
+        let mut migrated = 0;
+
+        for (line_num, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| {
+                StorageError::Internal(format!(
+                    "Failed to read line {} from WAL: {}",
+                    line_num + 1,
+                    e
+                ))
+            })?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let entry = WalEntry::from_json(&line)?;
+            output.extend_from_slice(&format::encode_record(&entry, use_checksums)?);
+            migrated += 1;
+        }
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".migrating");
+
+        let mut temp = File::create(&temp_path).map_err(|e| {
+            StorageError::Internal(format!("Failed to create migrated WAL file: {e}"))
+        })?;
+        temp.write_all(&output)
+            .and_then(|()| temp.sync_all())
+            .map_err(|e| StorageError::Internal(format!("Failed to write migrated WAL: {e}")))?;
+
+        fs::rename(&temp_path, path)
+            .map_err(|e| StorageError::Internal(format!("Failed to replace legacy WAL: {e}")))?;
+
+        info!(
+            "Migrated {} entries from legacy JSON WAL {:?} to the binary format",
+            migrated, path
+        );
+
+        Ok(())
+    }
+
+    /// Write an operation to the WAL and wait until it is written
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if:
+    /// - The queue or file lock cannot be acquired
+    /// - Writing, flushing or syncing the WAL file fails, now or for an earlier entry
+    pub fn log_operation(&self, operation: WalOperation) -> StorageResult<u64> {
+        let pending = self.append(operation)?;
+        self.wait_durable(&pending)?;
+        Ok(pending.sequence_number)
+    }
+
+    /// Assign the operation a sequence number and queue it for writing
+    ///
+    /// Entries are written in the order they are appended. With group commit the
+    /// entry is only queued, and [`Self::wait_durable`] must be called before the
+    /// operation is acknowledged; without it the entry is written right away.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if:
+    /// - The queue or file lock cannot be acquired
+    /// - An earlier write failed
+    /// - Writing the entry right away fails
+    pub fn append(&self, operation: WalOperation) -> StorageResult<PendingWrite> {
+        if self.group_commit {
+            let mut queue = self.lock_queue()?;
+            let pending = queue.reserve()?;
+
+            let entry = WalEntry::new(pending.sequence_number, operation);
+            queue.pending.extend_from_slice(&self.encode(&entry)?);
+
+            return Ok(pending);
+        }
+
+        // Holding the file lock keeps sequence numbers in file order
+        let mut active = self.lock_file()?;
+        let pending = self.lock_queue()?.reserve()?;
+
+        let entry = WalEntry::new(pending.sequence_number, operation);
+        let record = self.encode(&entry)?;
+        let result = self.write_records(&mut active, &record);
+
+        self.lock_queue()?.complete(pending.ticket, result)?;
+        Ok(pending)
+    }
+
+    /// Append each operation as an entry of its own, returning the pending
+    /// write of the last one, or `None` if there were no operations
+    ///
+    /// Entries are written in order, so once the last one is durable the rest
+    /// are too.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first append that fails, see [`Self::append`].
+    pub fn append_all(
+        &self,
+        operations: impl IntoIterator<Item = WalOperation>,
+    ) -> StorageResult<Option<PendingWrite>> {
+        let mut last = None;
+        for operation in operations {
+            last = Some(self.append(operation)?);
+        }
+        Ok(last)
+    }
+
+    /// Encode `entry` as a record with the configured checksums, compression
+    /// and encryption
+    fn encode(&self, entry: &WalEntry) -> StorageResult<Vec<u8>> {
+        let (record, raw_len) =
+            format::encode_record_with(entry, self.use_checksums, self.compression, &self.keys)?;
+        let compressed =
+            record[format::RECORD_HEADER_LEN - 1] & (format::FLAG_ZSTD | format::FLAG_LZ4) != 0;
+        self.compression_counters
+            .add(raw_len, record.len(), compressed);
+        Ok(record)
+    }
+
+    /// Wait until an appended entry has been written under the durability policy
+    ///
+    /// If no other writer is writing queued entries, the caller writes everything
+    /// queued so far, including entries of other writers, with a single write and
+    /// sync.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the queue or file lock cannot be
+    /// acquired, or if writing the entry failed.
+    pub fn wait_durable(&self, pending: &PendingWrite) -> StorageResult<()> {
+        let mut queue = self.lock_queue()?;
+
+        loop {
+            if queue.discarded.contains(&pending.ticket) {
+                return Err(StorageError::Internal(
+                    "WAL entry was discarded by a truncate before it was written".to_string(),
+                ));
+            }
+
+            if queue.is_written(pending) {
+                return Ok(());
+            }
+
+            if let Some(failure) = &queue.failure {
+                return Err(StorageError::Internal(failure.clone()));
+            }
+
+            if queue.leader_active {
+                queue = self.committed.wait(queue).map_err(|_| {
+                    StorageError::Internal("Failed to acquire WAL queue lock".to_string())
+                })?;
+                continue;
+            }
+
+            queue.leader_active = true;
+            drop(queue);
+
+            let result = self.write_queued();
+
+            queue = self.lock_queue()?;
+            queue.leader_active = false;
+            self.committed.notify_all();
+
+            if let Err(e) = result {
+                // Writers whose entries were already written are not affected
+                if queue.written < pending.ticket {
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Whether an appended entry has been written under the durability policy
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the queue lock cannot be acquired.
+    pub fn is_durable(&self, pending: &PendingWrite) -> StorageResult<bool> {
+        Ok(self.lock_queue()?.is_written(pending))
+    }
+
+    /// Write everything queued so far; the caller must be the queue's leader
+    fn write_queued(&self) -> StorageResult<()> {
+        let mut active = self.lock_file()?;
+
+        // Taken only once the file is ours, so a truncate cannot slip in between
+        let (batch, ticket) = {
+            let mut queue = self.lock_queue()?;
+            (std::mem::take(&mut queue.pending), queue.appended)
+        };
+
+        let result = if batch.is_empty() {
+            Ok(())
+        } else {
+            self.write_records(&mut active, &batch)
+        };
+
+        self.lock_queue()?.complete(ticket, result)
+    }
+
+    /// Write encoded records and apply the durability policy, rolling over to a
+    /// new segment first if they would not fit
+    fn write_records(&self, active: &mut ActiveSegment, records: &[u8]) -> StorageResult<()> {
+        let len = records.len() as u64;
+        if active.has_entries() && active.bytes + len > self.max_segment_size {
+            self.roll_over(active)?;
+        }
+
+        active
+            .file
+            .write_all(records)
+            .map_err(|e| StorageError::Internal(format!("Failed to write to WAL: {e}")))?;
+        active.bytes += len;
+
+        active
+            .file
+            .flush()
+            .map_err(|e| StorageError::Internal(format!("Failed to flush WAL: {e}")))?;
+
+        self.apply_durability(&mut active.file)
+    }
+
+    /// Seal the active segment and continue in a new one
+    ///
+    /// The new segment's header records the last sequence number handed out, so
+    /// numbering survives the deletion of every segment before it.
+    fn roll_over(&self, active: &mut ActiveSegment) -> StorageResult<()> {
+        // Later entries must never become durable while earlier ones can still be lost
+        active
+            .file
+            .sync_data()
+            .map_err(|e| StorageError::Internal(format!("Failed to sync WAL: {e}")))?;
+
+        let number = active.number + 1;
+        let path = segment::segment_path(Path::new(&self.file_path), number);
+
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| StorageError::Internal(format!("Failed to create WAL segment: {e}")))?;
+        let base_sequence_number = self.lock_queue()?.sequence_number;
+        file.write_all(&format::encode_header(base_sequence_number))
+            .and_then(|()| file.sync_data())
+            .map_err(|e| StorageError::Internal(format!("Failed to write WAL header: {e}")))?;
+        segment::sync_dir(&path)?;
+
+        debug!("Rolled WAL over to segment {:?}", path);
+        *active = ActiveSegment {
+            file: Box::new(file),
+            number,
+            bytes: format::HEADER_LEN,
+            header_len: format::HEADER_LEN,
+        };
+
+        Ok(())
+    }
+
+    /// Force everything written so far to stable storage, whatever the durability
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the file lock cannot be acquired or syncing fails.
+    pub fn sync(&self) -> StorageResult<()> {
+        let mut active = self.lock_file()?;
+        self.dirty.store(false, Ordering::Release);
+
+        active
+            .file
+            .sync_data()
+            .map_err(|e| StorageError::Internal(format!("Failed to sync WAL: {e}")))
+    }
+
+    /// The durability policy writes are made with
+    #[must_use]
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// What recovery does with damaged records
+    #[must_use]
+    pub fn recovery_mode(&self) -> RecoveryMode {
+        self.recovery_mode
+    }
+
+    /// How new records are compressed
+    #[must_use]
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// What compression saved on the records written since the WAL was opened
+    #[must_use]
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.compression_counters.stats()
+    }
+
+    /// The keys records are encrypted and decrypted with
+    #[must_use]
+    pub fn keys(&self) -> &Keyring {
+        &self.keys
+    }
+
+    /// Sync or mark the file dirty after a write, as the durability policy asks
+    fn apply_durability(&self, file: &mut Box<dyn WalFile>) -> StorageResult<()> {
+        match self.durability {
+            Durability::Always => file
+                .sync_data()
+                .map_err(|e| StorageError::Internal(format!("Failed to sync WAL: {e}"))),
+            Durability::Interval(_) => {
+                self.dirty.store(true, Ordering::Release);
+                Ok(())
+            }
+            Durability::Os => Ok(()),
+        }
+    }
+
+    fn lock_file(&self) -> StorageResult<MutexGuard<'_, ActiveSegment>> {
+        self.file
+            .lock()
+            .map_err(|_| StorageError::Internal("Failed to acquire file lock".to_string()))
+    }
+
+    /// Lock the commit queue; never wait for the file lock while holding it
+    fn lock_queue(&self) -> StorageResult<MutexGuard<'_, CommitQueue>> {
+        self.queue
+            .lock()
+            .map_err(|_| StorageError::Internal("Failed to acquire WAL queue lock".to_string()))
+    }
+
+    /// Read all entries from the WAL, segment by segment
+    ///
+    /// Fails on the first damaged record, whatever the recovery mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if:
+    /// - The WAL segments cannot be listed or opened for reading
+    /// - A file header is missing or has an unsupported version
+    /// - A record is truncated, fails checksum verification or cannot be decoded
+    /// - The file or queue lock cannot be acquired
+    pub fn read_all_entries(&self) -> StorageResult<Vec<WalEntry>> {
+        Ok(self.read_entries(RecoveryMode::Strict)?.entries)
+    }
+
+    /// Read all entries from the WAL, handling damaged records as the recovery
+    /// mode asks
+    ///
+    /// Meant to be called right after the WAL is opened. A torn tail is cut off
+    /// the active segment, so later writes do not end up behind it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if:
+    /// - The WAL segments cannot be listed, opened for reading or truncated
+    /// - A file header is missing or has an unsupported version
+    /// - A record is damaged in a way the recovery mode does not tolerate
+    /// - The file or queue lock cannot be acquired
+    ///
+    /// Returns a `StorageError::Encryption` if a record is encrypted with a key
+    /// that is not configured, whatever the recovery mode.
+    pub fn recover_entries(&self) -> StorageResult<WalRecovery> {
+        self.read_entries(self.recovery_mode)
+    }
+
+    /// Read all entries of the WAL at `base` without opening it for writing
+    ///
+    /// Unlike [`Self::open`], this never creates, migrates or truncates a file, so
+    /// the WAL is left exactly as it was. Fails on the first damaged record.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if:
+    /// - The WAL segments cannot be listed or opened for reading
+    /// - The WAL is a single file that has not been split into segments yet
+    /// - A file header is missing or has an unsupported version
+    /// - A record is truncated, fails checksum verification or cannot be decoded
+    ///
+    /// Returns a `StorageError::Encryption` if a record is encrypted with a key
+    /// that is not in `keys`.
+    pub fn read_segments(base: impl AsRef<Path>, keys: &Keyring) -> StorageResult<Vec<WalEntry>> {
+        let base = base.as_ref();
+        let segments = segment::list(base)?;
+        if segments.is_empty() && fs::metadata(base).is_ok_and(|meta| meta.len() > 0) {
+            return Err(StorageError::Internal(format!(
+                "WAL {} has not been split into segments yet, open it once first",
+                base.display()
+            )));
+        }
+
+        let mut entries = Vec::new();
+        for (_, path) in segments {
+            let (mut reader, header) = Self::open_segment(&path)?;
+            let mut offset = header.encoded_len();
+            let mut record = 0;
+
+            loop {
+                record += 1;
+                match format::read_record_with(&mut reader, keys) {
+                    Ok(Some((entry, len))) => {
+                        entries.push(entry);
+                        offset += len as u64;
+                    }
+                    Ok(None) => break,
+                    Err(StorageError::Internal(msg)) => {
+                        return Err(StorageError::Internal(format!(
+                            "{msg} (record {record} at offset {offset} of {})",
+                            path.display()
+                        )));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn read_entries(&self, mode: RecoveryMode) -> StorageResult<WalRecovery> {
+        // Keeps writers from appending to a tail that is about to be cut off
+        let mut active = self.lock_file()?;
+        let mut recovery = WalRecovery::default();
+
+        let segments = segment::list(Path::new(&self.file_path))?;
+        let newest = segments.last().map(|(number, _)| *number);
+
+        for (number, path) in segments {
+            let (mut reader, header) = Self::open_segment(&path)?;
+            let mut offset = header.encoded_len();
+            let mut record = 0;
+
+            loop {
+                record += 1;
+                let reason = match format::read_sealed_record(&mut reader, &self.keys) {
+                    Ok(Some((entry, len, stale))) => {
+                        recovery.entries.push(entry);
+                        recovery.sealed_with_previous_key |= stale;
+                        offset += len as u64;
+                        continue;
+                    }
+                    Ok(None) => break,
+                    Err(StorageError::Internal(msg)) => msg,
+                    Err(e) => return Err(e),
+                };
+
+                let damage = match mode {
+                    RecoveryMode::Strict => None,
+                    RecoveryMode::TruncateTail | RecoveryMode::SkipCorrupt => {
+                        Some(recovery::inspect(&path, offset, Some(number) == newest)?)
+                    }
+                };
+
+                let (bytes, truncated) = match (mode, damage) {
+                    (_, Some(Damage::TornTail { bytes })) => (bytes, true),
+                    (
+                        RecoveryMode::SkipCorrupt,
+                        Some(Damage::Record { bytes } | Damage::Rest { bytes }),
+                    ) => (bytes, false),
+                    _ => {
+                        return Err(StorageError::Internal(format!(
+                            "{reason} (record {record} at offset {offset} of {})",
+                            path.display()
+                        )));
+                    }
+                };
+
+                warn!(
+                    "Discarding {} bytes of damaged WAL record {} at offset {} of {:?}: {}",
+                    bytes, record, offset, path, reason
+                );
+                recovery.discarded.push(DiscardedRecord {
+                    segment: path.clone(),
+                    offset,
+                    bytes,
+                    reason,
+                    truncated,
+                });
+
+                if truncated {
+                    Self::truncate_tail(&mut active, number, offset)?;
+                    break;
+                }
+                if !matches!(damage, Some(Damage::Record { .. })) {
+                    break;
+                }
+
+                offset += bytes;
+                reader.seek(SeekFrom::Start(offset)).map_err(|e| {
+                    StorageError::Internal(format!("Failed to skip damaged WAL record: {e}"))
+                })?;
+            }
+        }
+
+        // Update the sequence number to the highest seen
+        if let Some(last_entry) = recovery.entries.last() {
+            let mut queue = self.lock_queue()?;
+            queue.sequence_number = queue.sequence_number.max(last_entry.sequence_number);
+        }
+
+        Ok(recovery)
+    }
+
+    /// Cut the active segment, `number`, off at `len` bytes
+    fn truncate_tail(active: &mut ActiveSegment, number: u64, len: u64) -> StorageResult<()> {
+        if active.number != number {
+            return Err(StorageError::Internal(format!(
+                "Cannot truncate WAL segment {number}, it is no longer the active segment"
+            )));
+        }
+
+        active
+            .file
+            .set_len(len)
+            .and_then(|()| active.file.sync_data())
+            .map_err(|e| StorageError::Internal(format!("Failed to truncate WAL file: {e}")))?;
+        active.bytes = len;
+
+        Ok(())
+    }
+
+    /// Open a segment for reading, positioned after its checked header
+    fn open_segment(path: &Path) -> StorageResult<(BufReader<File>, format::FileHeader)> {
+        let file = File::open(path).map_err(|e| {
+            StorageError::Internal(format!("Failed to open WAL file for reading: {e}"))
+        })?;
+
+        let mut reader = BufReader::new(file);
+        let header = format::read_header(&mut reader)?;
+
+        Ok((reader, header))
+    }
+
+    /// Sequence number of the first entry in a segment, if it has any
+    fn first_sequence_number(path: &Path, keys: &Keyring) -> StorageResult<Option<u64>> {
+        let (mut reader, _) = Self::open_segment(path)?;
+        Ok(format::read_record_with(&mut reader, keys)?.map(|(entry, _)| entry.sequence_number))
+    }
+
+    /// The segments the WAL currently consists of, oldest first
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the segments cannot be listed or their
+    /// sizes cannot be read.
+    pub fn segments(&self) -> StorageResult<Vec<SegmentInfo>> {
+        segment::list(Path::new(&self.file_path))?
+            .into_iter()
+            .map(|(number, path)| {
+                let bytes = fs::metadata(&path)
+                    .map_err(|e| {
+                        StorageError::Internal(format!("Failed to read WAL segment size: {e}"))
+                    })?
+                    .len();
+                Ok(SegmentInfo {
+                    number,
+                    path,
+                    bytes,
+                })
+            })
+            .collect()
+    }
+
+    /// Seal the active segment so entries written from now on go to a new one
+    ///
+    /// Does nothing if the active segment has no entries yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the file lock cannot be acquired or
+    /// the new segment cannot be created.
+    pub fn seal_segment(&self) -> StorageResult<()> {
+        let mut active = self.lock_file()?;
+        if active.has_entries() {
+            self.roll_over(&mut active)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure sequence numbers handed out from now on are above `sequence_number`
+    ///
+    /// Used after recovering from a snapshot that covers entries no longer in the WAL.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the queue lock cannot be acquired.
+    pub fn advance_sequence_number(&self, sequence_number: u64) -> StorageResult<()> {
+        let mut queue = self.lock_queue()?;
+        queue.sequence_number = queue.sequence_number.max(sequence_number);
+        Ok(())
+    }
+
+    /// Delete the sealed segments that only hold entries up to `sequence_number`
+    ///
+    /// Called once a checkpoint covers everything up to `sequence_number`.
+    /// Segments are deleted oldest first, and the active segment is always kept.
+    /// Returns how many segments were deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the file or queue lock cannot be
+    /// acquired, or if a segment cannot be read or deleted.
+    pub fn delete_segments_through(&self, sequence_number: u64) -> StorageResult<usize> {
+        // Holding the file lock keeps the active segment from rolling over meanwhile
+        let active = self.lock_file()?;
+        let next_sequence_number = self.lock_queue()?.sequence_number + 1;
+
+        let segments = segment::list(Path::new(&self.file_path))?;
+        let mut deleted = 0;
+
+        for pair in segments.windows(2) {
+            let ((number, path), (_, next_path)) = (&pair[0], &pair[1]);
+            if *number >= active.number {
+                break;
+            }
+
+            // A segment ends right before the first entry of the next one
+            let next_first =
+                Self::first_sequence_number(next_path, &self.keys)?.unwrap_or(next_sequence_number);
+            if next_first.saturating_sub(1) > sequence_number {
+                break;
+            }
+
+            fs::remove_file(path).map_err(|e| {
+                StorageError::Internal(format!("Failed to delete WAL segment: {e}"))
+            })?;
+            debug!("Deleted WAL segment {:?}", path);
+            deleted += 1;
+        }
+
+        if deleted > 0 {
+            segment::sync_dir(Path::new(&self.file_path))?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Get the current sequence number
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the queue lock cannot be acquired.
+    pub fn current_sequence_number(&self) -> StorageResult<u64> {
+        Ok(self.lock_queue()?.sequence_number)
+    }
+
+    /// Delete every entry in the WAL (use with caution!)
+    ///
+    /// Entries still queued for writing are discarded along with the written ones,
+    /// and their writers get an error from [`Self::wait_durable`]. The WAL
+    /// continues in a new, empty segment and sequence numbers keep counting from
+    /// where they were.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if:
+    /// - The file or queue lock cannot be acquired
+    /// - The new segment cannot be created
+    /// - Deleting an old segment fails
+    pub fn truncate(&self) -> StorageResult<()> {
+        let mut active = self.lock_file()?;
+
+        // The new segment is durable before the old ones go, so a crash in
+        // between never loses the sequence number
+        self.roll_over(&mut active)?;
+
+        {
+            // Queued entries can only be written once the file lock is released
+            let mut queue = self.lock_queue()?;
+            queue.pending.clear();
+            queue.discarded = queue.written + 1..queue.appended + 1;
+            self.committed.notify_all();
+        }
+
+        for (number, path) in segment::list(Path::new(&self.file_path))? {
+            if number < active.number {
+                fs::remove_file(&path).map_err(|e| {
+                    StorageError::Internal(format!("Failed to delete WAL segment: {e}"))
+                })?;
+            }
+        }
+        segment::sync_dir(Path::new(&self.file_path))?;
+
+        Ok(())
+    }
+
+    /// Get the path the WAL segment file names are derived from
+    #[must_use]
+    pub fn file_pat(&self) -> &str {
+        &self.file_path
+    }
+}
+
+impl Drop for WalManager {
+    fn drop(&mut self) {
+        // Writes made under an interval policy may still be waiting for the syncer
+        if self.dirty.load(Ordering::Acquire) {
+            if let Err(e) = self.sync() {
+                warn!("Failed to sync WAL on close: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fault::{FaultState, crash, open_faulty, open_faulty_with};
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A WAL path in a fresh directory, so its segments are removed with it
+    fn temp_wal() -> (TempDir, PathBuf) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let wal_path = temp_dir.path().join("test.wal");
+        (temp_dir, wal_path)
+    }
+
+    #[test]
+    fn test_wal_entry_creation() {
+        let operation = WalOperation::Put {
+            key: "test".to_string(),
+            value: b"value".to_vec(),
+            expires_at: None,
+            version: None,
+        };
+        let entry = WalEntry::new(1, operation.clone());
+
+        assert_eq!(entry.sequence_number, 1);
+        assert_eq!(entry.operation, operation);
+    }
+
+    #[test]
+    fn test_legacy_entry_checksum_is_ignored() {
+        // Checksums from the old hasher may not match on a newer toolchain
+        let legacy = r#"{"sequence_number":1,"operation":{"Delete":{"key":"k"}},"timestamp":"2025-01-01T00:00:00.000Z","checksum":"deadbeef"}"#;
+        let entry = WalEntry::from_json(legacy).unwrap();
+
+        assert_eq!(
+            entry.operation,
+            WalOperation::Delete {
+                key: "k".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_wal_entry_serialization() {
+        let operation = WalOperation::Delete {
+            key: "test".to_string(),
+        };
+        let entry = WalEntry::new(42, operation);
+
+        let json = entry.to_json().unwrap();
+        let deserialized = WalEntry::from_json(&json).unwrap();
+
+        assert_eq!(entry, deserialized);
+    }
+
+    #[test]
+    fn test_wal_entry_binary_value_serialization() {
+        let operation = WalOperation::Put {
+            key: "blob".to_string(),
+            value: vec![0x00, 0xff, 0x80, 0x7f],
+            expires_at: None,
+            version: None,
+        };
+        let entry = WalEntry::new(7, operation);
+
+        let json = entry.to_json().unwrap();
+        assert!(json.contains("\"base64\""));
+
+        let deserialized = WalEntry::from_json(&json).unwrap();
+        assert_eq!(entry, deserialized);
+    }
+
+    #[test]
+    fn test_wal_entry_text_value_stays_plain() {
+        let operation = WalOperation::Put {
+            key: "greeting".to_string(),
+            value: b"hello".to_vec(),
+            expires_at: None,
+            version: None,
+        };
+        let json = WalEntry::new(1, operation.clone()).to_json().unwrap();
+        assert!(json.contains("\"value\":\"hello\""));
+
+        // Entries written before values became bytes must still parse
+        let legacy = r#"{"sequence_number":1,"operation":{"Put":{"key":"greeting","value":"hello"}},"timestamp":"2025-01-01T00:00:00.000Z","checksum":null}"#;
+        assert_eq!(WalEntry::from_json(legacy).unwrap().operation, operation);
+    }
+
+    #[test]
+    fn test_wal_entry_batch_serialization() {
+        let operation = WalOperation::Batch {
+            operations: vec![
+                WalOperation::Put {
+                    key: "a".to_string(),
+                    value: b"1".to_vec(),
+                    expires_at: None,
+                    version: Some(1),
+                },
+                WalOperation::Delete {
+                    key: "b".to_string(),
+                },
+            ],
+        };
+        let entry = WalEntry::new(3, operation);
+
+        let json = entry.to_json().unwrap();
+        assert!(!json.contains('\n'));
+
+        let deserialized = WalEntry::from_json(&json).unwrap();
+        assert_eq!(entry, deserialized);
+    }
+
+    #[test]
+    fn test_wal_manager_basic_operations() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+
+        // Test logging operations
+        let seq1 = wal_manager
+            .log_operation(WalOperation::Put {
+                key: "key1".to_string(),
+                value: b"value1".to_vec(),
+                expires_at: None,
+                version: None,
+            })
+            .unwrap();
+
+        let seq2 = wal_manager
+            .log_operation(WalOperation::Delete {
+                key: "key2".to_string(),
+            })
+            .unwrap();
+
+        assert_eq!(seq1, 1);
+        assert_eq!(seq2, 2);
+
+        // Test reading entries
+        let entries = wal_manager.read_all_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].sequence_number, 1);
+        assert_eq!(entries[1].sequence_number, 2);
+
+        match &entries[0].operation {
+            WalOperation::Put { key, value, .. } => {
+                assert_eq!(key, "key1");
+                assert_eq!(value, b"value1");
+            }
+            _ => panic!("Expected Put operation"),
+        }
+
+        match &entries[1].operation {
+            WalOperation::Delete { key } => {
+                assert_eq!(key, "key2");
+            }
+            _ => panic!("Expected Delete operation"),
+        }
+    }
+
+    #[test]
+    fn test_wal_manager_truncate() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+
+        wal_manager
+            .log_operation(WalOperation::Put {
+                key: "key1".to_string(),
+                value: b"value1".to_vec(),
+                expires_at: None,
+                version: None,
+            })
+            .unwrap();
+
+        wal_manager.log_operation(WalOperation::Clear).unwrap();
+
+        // Verify entries exist
+        assert_eq!(wal_manager.read_all_entries().unwrap().len(), 2);
+
+        wal_manager.truncate().unwrap();
+
+        // Verify entries are gone, but numbering goes on
+        assert_eq!(wal_manager.read_all_entries().unwrap().len(), 0);
+        assert_eq!(wal_manager.current_sequence_number().unwrap(), 2);
+    }
+
+    fn put(key: &str, value: &str) -> WalOperation {
+        WalOperation::Put {
+            key: key.to_string(),
+            value: value.as_bytes().to_vec(),
+            expires_at: None,
+            version: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_wal_manager_writes_binary_header() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        wal_manager.log_operation(put("key", "value")).unwrap();
+
+        let segment = segment::segment_path(&wal_path, 1);
+        let bytes = std::fs::read(&segment).unwrap();
+        assert!(bytes.starts_with(format::MAGIC));
+
+        // Truncation continues in a segment that only holds a header
+        wal_manager.truncate().unwrap();
+        assert!(!segment.exists());
+        assert_eq!(
+            std::fs::read(segment::segment_path(&wal_path, 2)).unwrap(),
+            format::encode_header(1)
+        );
+        assert!(WalManager::new(&wal_path).is_ok());
+    }
+
+    #[test]
+    fn test_wal_manager_migrates_legacy_json() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let legacy = [
+            WalEntry::new(1, put("a", "1")),
+            WalEntry::new(2, WalOperation::Delete { key: "b".into() }),
+        ];
+        let json: String = legacy
+            .iter()
+            .map(|entry| entry.to_json().unwrap() + "\n")
+            .collect();
+        std::fs::write(&wal_path, json).unwrap();
+
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        assert_eq!(wal_manager.read_all_entries().unwrap(), legacy);
+
+        // New entries are appended in the binary format after the migrated ones
+        assert_eq!(wal_manager.log_operation(WalOperation::Clear).unwrap(), 3);
+        let reopened = WalManager::new(&wal_path).unwrap();
+        assert_eq!(reopened.read_all_entries().unwrap().len(), 3);
+        assert!(
+            std::fs::read(segment::segment_path(&wal_path, 1))
+                .unwrap()
+                .starts_with(format::MAGIC)
+        );
+        assert!(!wal_path.exists());
+    }
+
+    #[test]
+    fn test_wal_manager_detects_corruption() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        wal_manager.log_operation(put("key", "value")).unwrap();
+
+        let segment = segment::segment_path(&wal_path, 1);
+        let mut bytes = std::fs::read(&segment).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&segment, bytes).unwrap();
+
+        let result = wal_manager.read_all_entries();
+        assert!(matches!(result, Err(StorageError::Internal(msg)) if msg.contains("Checksum")));
+    }
+
+    #[test]
+    fn test_binary_records_are_smaller_than_json() {
+        let entry = WalEntry::new(1, put("user:123:profile", "{\"name\":\"alice\"}"));
+
+        assert!(
+            format::encode_record(&entry, true).unwrap().len() < entry.to_json().unwrap().len()
+        );
+    }
+
+    fn entries_after_crash(path: &Path, wal_manager: WalManager, state: &FaultState) -> usize {
+        crash(path, state);
+        drop(wal_manager);
+        WalManager::new(path)
+            .unwrap()
+            .read_all_entries()
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn test_always_durability_survives_crash() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let (wal_manager, state) = open_faulty(&wal_path, Durability::Always);
+
+        for key in ["a", "b", "c"] {
+            wal_manager.log_operation(put(key, "value")).unwrap();
+        }
+
+        assert_eq!(state.syncs.load(Ordering::SeqCst), 3);
+        assert_eq!(entries_after_crash(&wal_path, wal_manager, &state), 3);
+    }
+
+    #[test]
+    fn test_os_durability_loses_unsynced_writes() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let (wal_manager, state) = open_faulty(&wal_path, Durability::Os);
+
+        for key in ["a", "b", "c"] {
+            wal_manager.log_operation(put(key, "value")).unwrap();
+        }
+
+        assert_eq!(state.syncs.load(Ordering::SeqCst), 0);
+        assert_eq!(entries_after_crash(&wal_path, wal_manager, &state), 0);
+    }
+
+    #[test]
+    fn test_interval_durability_syncs_in_background() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let (wal_manager, state) = open_faulty(
+            &wal_path,
+            Durability::Interval(std::time::Duration::from_millis(5)),
+        );
+
+        wal_manager.log_operation(put("a", "1")).unwrap();
+        wal_manager.log_operation(put("b", "2")).unwrap();
+        assert_eq!(
+            wal_manager.durability(),
+            Durability::Interval(std::time::Duration::from_millis(5))
+        );
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while state.synced_len.load(Ordering::SeqCst) < state.written_len.load(Ordering::SeqCst) {
+            assert!(std::time::Instant::now() < deadline, "WAL was never synced");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        assert_eq!(entries_after_crash(&wal_path, wal_manager, &state), 2);
+    }
+
+    #[test]
+    fn test_always_durability_reports_sync_failure() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let (wal_manager, state) = open_faulty(&wal_path, Durability::Always);
+
+        state.fail_sync.store(true, Ordering::SeqCst);
+        let result = wal_manager.log_operation(put("a", "1"));
+        assert!(matches!(result, Err(StorageError::Internal(msg)) if msg.contains("sync")));
+
+        // Under the OS policy the same fault goes unnoticed until a crash
+        let (wal_manager, state) = open_faulty(&wal_path, Durability::Os);
+        state.fail_sync.store(true, Ordering::SeqCst);
+        assert!(wal_manager.log_operation(put("b", "2")).is_ok());
+    }
+
+    fn write_concurrently(wal_manager: &WalManager, threads: usize, writes: usize) -> Vec<u64> {
+        let mut sequence_numbers: Vec<u64> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        (0..writes)
+                            .map(|i| {
+                                wal_manager
+                                    .log_operation(put(&format!("{thread}:{i}"), "value"))
+                                    .unwrap()
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        sequence_numbers.sort_unstable();
+        sequence_numbers
+    }
+
+    #[test]
+    fn test_group_commit_shares_syncs_between_writers() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let (wal_manager, state) = open_faulty(&wal_path, Durability::Always);
+        state.slow_sync.store(true, Ordering::SeqCst);
+
+        let sequence_numbers = write_concurrently(&wal_manager, 8, 25);
+        assert_eq!(sequence_numbers, (1..=200).collect::<Vec<_>>());
+
+        // Every acknowledged write was synced, with fewer syncs than writes
+        let syncs = state.syncs.load(Ordering::SeqCst);
+        assert!(syncs < 200, "expected shared syncs, got {syncs}");
+
+        let entries = wal_manager.read_all_entries().unwrap();
+        let logged: Vec<u64> = entries.iter().map(|entry| entry.sequence_number).collect();
+        assert_eq!(logged, sequence_numbers);
+        assert_eq!(entries_after_crash(&wal_path, wal_manager, &state), 200);
+    }
+
+    #[test]
+    fn test_without_group_commit_every_write_is_synced() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let options = WalOptions::default()
+            .with_durability(Durability::Always)
+            .with_group_commit(false);
+        let (wal_manager, state) = open_faulty_with(&wal_path, &options);
+
+        let sequence_numbers = write_concurrently(&wal_manager, 4, 10);
+        assert_eq!(sequence_numbers, (1..=40).collect::<Vec<_>>());
+        assert_eq!(state.syncs.load(Ordering::SeqCst), 40);
+
+        let entries = wal_manager.read_all_entries().unwrap();
+        assert!(
+            entries
+                .windows(2)
+                .all(|pair| { pair[0].sequence_number < pair[1].sequence_number })
+        );
+    }
+
+    #[test]
+    fn test_failed_sync_makes_wal_unavailable() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let (wal_manager, state) = open_faulty(&wal_path, Durability::Always);
+
+        wal_manager.log_operation(put("a", "1")).unwrap();
+        state.fail_sync.store(true, Ordering::SeqCst);
+        assert!(wal_manager.log_operation(put("b", "2")).is_err());
+
+        // Whatever reached the file is unknown, so nothing more may be acknowledged
+        state.fail_sync.store(false, Ordering::SeqCst);
+        let result = wal_manager.append(put("c", "3"));
+        assert!(matches!(result, Err(StorageError::Internal(msg)) if msg.contains("unavailable")));
+    }
+
+    #[test]
+    fn test_truncate_fails_queued_writes() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+
+        let written = wal_manager.append(put("a", "1")).unwrap();
+        wal_manager.wait_durable(&written).unwrap();
+        let discarded = wal_manager.append(put("b", "2")).unwrap();
+        wal_manager.truncate().unwrap();
+
+        assert!(wal_manager.wait_durable(&discarded).is_err());
+        assert!(!wal_manager.is_durable(&discarded).unwrap());
+        assert!(wal_manager.is_durable(&written).unwrap());
+        assert!(wal_manager.read_all_entries().unwrap().is_empty());
+
+        // Later entries are written as usual, without covering the discarded one
+        assert_eq!(wal_manager.log_operation(put("c", "3")).unwrap(), 3);
+        assert!(wal_manager.wait_durable(&discarded).is_err());
+    }
+
+    #[test]
+    fn test_wal_rolls_over_to_new_segments() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let options = WalOptions::default().with_max_segment_size(256);
+
+        {
+            let wal_manager = WalManager::open(&wal_path, &options).unwrap();
+            for i in 0..20 {
+                wal_manager
+                    .log_operation(put(&format!("key{i}"), "some value"))
+                    .unwrap();
+            }
+
+            let segments = wal_manager.segments().unwrap();
+            assert!(segments.len() > 1);
+            assert!(segments.iter().all(|segment| segment.bytes <= 256));
+            assert!(
+                segments
+                    .windows(2)
+                    .all(|pair| pair[0].number + 1 == pair[1].number)
+            );
+        }
+
+        // Segments are replayed in order after a restart, and writes continue in the last one
+        let wal_manager = WalManager::open(&wal_path, &options).unwrap();
+        let count = wal_manager.segments().unwrap().len();
+        let entries = wal_manager.read_all_entries().unwrap();
+        let sequence_numbers: Vec<u64> =
+            entries.iter().map(|entry| entry.sequence_number).collect();
+        assert_eq!(sequence_numbers, (1..=20).collect::<Vec<_>>());
+
+        assert_eq!(wal_manager.log_operation(WalOperation::Clear).unwrap(), 21);
+        assert!(wal_manager.segments().unwrap().len() <= count + 1);
+    }
+
+    #[test]
+    fn test_record_larger_than_segment_gets_its_own_segment() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let options = WalOptions::default().with_max_segment_size(64);
+        let wal_manager = WalManager::open(&wal_path, &options).unwrap();
+
+        wal_manager
+            .log_operation(put("big", &"x".repeat(200)))
+            .unwrap();
+        wal_manager.log_operation(put("next", "1")).unwrap();
+
+        assert_eq!(wal_manager.segments().unwrap().len(), 2);
+        assert_eq!(wal_manager.read_all_entries().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_delete_segments_covered_by_checkpoint() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let options = WalOptions::default().with_max_segment_size(128);
+        let wal_manager = WalManager::open(&wal_path, &options).unwrap();
+
+        for i in 0..12 {
+            wal_manager
+                .log_operation(put(&format!("key{i}"), "value"))
+                .unwrap();
+        }
+        let before = wal_manager.segments().unwrap();
+        assert!(before.len() > 2);
+
+        // Nothing is covered before the first entry
+        assert_eq!(wal_manager.delete_segments_through(0).unwrap(), 0);
+
+        // Only segments whose entries are all covered go away
+        let deleted = wal_manager.delete_segments_through(6).unwrap();
+        assert!(deleted > 0);
+        let entries = wal_manager.read_all_entries().unwrap();
+        assert!(entries[0].sequence_number <= 7);
+        assert_eq!(entries.last().unwrap().sequence_number, 12);
+
+        // The active segment survives even a checkpoint covering everything
+        wal_manager.delete_segments_through(12).unwrap();
+        let after = wal_manager.segments().unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].number, before.last().unwrap().number);
+    }
+
+    #[test]
+    fn test_truncate_deletes_sealed_segments() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let options = WalOptions::default().with_max_segment_size(128);
+        let wal_manager = WalManager::open(&wal_path, &options).unwrap();
+
+        for i in 0..12 {
+            wal_manager
+                .log_operation(put(&format!("key{i}"), "value"))
+                .unwrap();
+        }
+        wal_manager.truncate().unwrap();
+
+        let segments = wal_manager.segments().unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].bytes, format::HEADER_LEN);
+        assert!(wal_manager.read_all_entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_single_file_wal_becomes_first_segment() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let mut bytes = format::encode_header(0).to_vec();
+        bytes.extend(format::encode_record(&WalEntry::new(1, put("a", "1")), true).unwrap());
+        std::fs::write(&wal_path, bytes).unwrap();
+
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        assert_eq!(wal_manager.read_all_entries().unwrap().len(), 1);
+        assert!(!wal_path.exists());
+        assert_eq!(wal_manager.segments().unwrap()[0].number, 1);
+
+        // A stray single file next to existing segments is ambiguous
+        std::fs::write(&wal_path, format::encode_header(0)).unwrap();
+        drop(wal_manager);
+        assert!(WalManager::new(&wal_path).is_err());
+    }
+
+    /// Write `count` entries to a fresh WAL and return the active segment's path
+    fn write_entries(wal_path: &Path, count: usize) -> PathBuf {
+        let wal_manager = WalManager::new(wal_path).unwrap();
+        for i in 0..count {
+            wal_manager
+                .log_operation(put(&format!("key{i}"), "value"))
+                .unwrap();
+        }
+        segment::segment_path(wal_path, 1)
+    }
+
+    fn open_with_recovery(wal_path: &Path, mode: RecoveryMode) -> StorageResult<WalRecovery> {
+        WalManager::open(wal_path, &WalOptions::default().with_recovery_mode(mode))?
+            .recover_entries()
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let segment = write_entries(&wal_path, 3);
+
+        // A crash in the middle of the last write
+        let len = std::fs::metadata(&segment).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&segment).unwrap();
+        file.set_len(len - 3).unwrap();
+        drop(file);
+
+        assert!(open_with_recovery(&wal_path, RecoveryMode::Strict).is_err());
+
+        let wal_manager = WalManager::open(
+            &wal_path,
+            &WalOptions::default().with_recovery_mode(RecoveryMode::TruncateTail),
+        )
+        .unwrap();
+        let recovery = wal_manager.recover_entries().unwrap();
+        assert_eq!(recovery.entries.len(), 2);
+        assert_eq!(recovery.discarded.len(), 1);
+
+        let discarded = &recovery.discarded[0];
+        assert!(discarded.truncated);
+        assert_eq!(discarded.segment, segment);
+        assert_eq!(discarded.offset + discarded.bytes, len - 3);
+        assert_eq!(std::fs::metadata(&segment).unwrap().len(), discarded.offset);
+
+        // New entries follow the last good record
+        wal_manager.log_operation(put("key3", "value")).unwrap();
+        drop(wal_manager);
+        let entries = WalManager::new(&wal_path)
+            .unwrap()
+            .read_all_entries()
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].sequence_number, 3);
+    }
+
+    #[test]
+    fn test_zero_filled_tail_is_truncated() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let segment = write_entries(&wal_path, 2);
+
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[0; 100]).unwrap();
+        drop(file);
+
+        let recovery = open_with_recovery(&wal_path, RecoveryMode::TruncateTail).unwrap();
+        assert_eq!(recovery.entries.len(), 2);
+        assert_eq!(recovery.discarded[0].bytes, 100);
+    }
+
+    #[test]
+    fn test_corruption_before_the_tail_is_not_truncated() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let segment = write_entries(&wal_path, 3);
+
+        // Damage the payload of the first record
+        let mut bytes = std::fs::read(&segment).unwrap();
+        let first_payload =
+            usize::try_from(format::HEADER_LEN).unwrap() + format::RECORD_HEADER_LEN;
+        bytes[first_payload] ^= 0xff;
+        std::fs::write(&segment, &bytes).unwrap();
+
+        let result = open_with_recovery(&wal_path, RecoveryMode::TruncateTail);
+        assert!(matches!(result, Err(StorageError::Internal(msg)) if msg.contains("record 1")));
+
+        let recovery = open_with_recovery(&wal_path, RecoveryMode::SkipCorrupt).unwrap();
+        let keys: Vec<_> = recovery
+            .entries
+            .iter()
+            .map(|entry| entry.sequence_number)
+            .collect();
+        assert_eq!(keys, [2, 3]);
+        assert_eq!(recovery.discarded.len(), 1);
+        assert!(!recovery.discarded[0].truncated);
+        assert_eq!(recovery.discarded[0].offset, format::HEADER_LEN);
+
+        // Skipping never modifies the file
+        assert_eq!(std::fs::read(&segment).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_torn_sealed_segment_is_not_truncated() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        wal_manager.log_operation(put("a", "1")).unwrap();
+        wal_manager.log_operation(put("b", "2")).unwrap();
+        wal_manager.seal_segment().unwrap();
+        wal_manager.log_operation(put("c", "3")).unwrap();
+        drop(wal_manager);
+
+        let sealed = segment::segment_path(&wal_path, 1);
+        let len = std::fs::metadata(&sealed).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&sealed).unwrap();
+        file.set_len(len - 1).unwrap();
+        drop(file);
+
+        assert!(open_with_recovery(&wal_path, RecoveryMode::TruncateTail).is_err());
+
+        let recovery = open_with_recovery(&wal_path, RecoveryMode::SkipCorrupt).unwrap();
+        assert_eq!(recovery.entries.len(), 2);
+        assert!(!recovery.discarded[0].truncated);
+        assert_eq!(std::fs::metadata(&sealed).unwrap().len(), len - 1);
+    }
+
+    #[test]
+    fn test_sequence_numbers_survive_truncate_and_restart() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        for key in ["a", "b", "c"] {
+            wal_manager.log_operation(put(key, "1")).unwrap();
+        }
+        wal_manager.truncate().unwrap();
+        drop(wal_manager);
+
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        assert!(wal_manager.read_all_entries().unwrap().is_empty());
+        assert_eq!(wal_manager.current_sequence_number().unwrap(), 3);
+        assert_eq!(wal_manager.log_operation(put("d", "1")).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_sequence_numbers_survive_deleted_segments() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        for key in ["a", "b", "c"] {
+            wal_manager.log_operation(put(key, "1")).unwrap();
+        }
+        wal_manager.seal_segment().unwrap();
+        assert_eq!(wal_manager.delete_segments_through(3).unwrap(), 1);
+        drop(wal_manager);
+
+        // Only an empty segment is left, its header still knows where the log was
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        assert_eq!(wal_manager.current_sequence_number().unwrap(), 3);
+        assert_eq!(wal_manager.log_operation(put("d", "1")).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_version_1_segment_is_still_appended_to() {
+        let (_temp_dir, wal_path) = temp_wal();
+        let mut bytes = format::encode_header(0)[..8].to_vec();
+        bytes[4] = 1;
+        bytes.extend(format::encode_record(&WalEntry::new(1, put("a", "1")), true).unwrap());
+        std::fs::write(segment::segment_path(&wal_path, 1), bytes).unwrap();
+
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        assert_eq!(wal_manager.read_all_entries().unwrap().len(), 1);
+        assert_eq!(wal_manager.log_operation(put("b", "2")).unwrap(), 2);
+
+        // Sealing it starts a segment in the current format
+        wal_manager.seal_segment().unwrap();
+        let segments = wal_manager.segments().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].bytes, format::HEADER_LEN);
+        assert_eq!(wal_manager.read_all_entries().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,297 @@
+//! How damaged records are handled when the write-ahead log is replayed
+//!
+//! A crash in the middle of a write can leave a partial record at the end of
+//! the newest segment: a torn tail. Whether that, or damage anywhere else in the
+//! log, fails recovery is decided by the [`RecoveryMode`]. A [`RecoveryTarget`]
+//! stops replay at an earlier point in the log.
+
+use super::WalEntry;
+use super::format;
+use crate::storage::error::{StorageError, StorageResult};
+use crate::utils::time;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// What recovery does with a WAL record that cannot be read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// Fail on the first damaged record
+    #[default]
+    Strict,
+    /// Drop a damaged record at the end of the newest segment and truncate the
+    /// segment to the last good record; fail on damage anywhere else
+    TruncateTail,
+    /// Truncate a torn tail like [`RecoveryMode::TruncateTail`], and skip
+    /// damaged records anywhere else in the log
+    SkipCorrupt,
+}
+
+impl fmt::Display for RecoveryMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryMode::Strict => write!(f, "strict"),
+            RecoveryMode::TruncateTail => write!(f, "truncate-tail"),
+            RecoveryMode::SkipCorrupt => write!(f, "skip-corrupt"),
+        }
+    }
+}
+
+impl FromStr for RecoveryMode {
+    type Err = String;
+
+    /// Parse `strict`, `truncate-tail` or `skip-corrupt`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "strict" => Ok(RecoveryMode::Strict),
+            "truncate-tail" => Ok(RecoveryMode::TruncateTail),
+            "skip-corrupt" => Ok(RecoveryMode::SkipCorrupt),
+            other => Err(format!(
+                "unknown recovery mode '{other}', expected 'strict', 'truncate-tail' or 'skip-corrupt'"
+            )),
+        }
+    }
+}
+
+/// How far point-in-time recovery replays the WAL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Up to and including the entry with this sequence number
+    Sequence(u64),
+    /// Up to and including the last entry logged at or before this time
+    Time(DateTime<Utc>),
+}
+
+impl RecoveryTarget {
+    /// Whether `entry` is at or before the target
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError::Internal` if the target is a time and the
+    /// entry's timestamp cannot be parsed.
+    pub fn includes(&self, entry: &WalEntry) -> StorageResult<bool> {
+        match self {
+            RecoveryTarget::Sequence(sequence_number) => {
+                Ok(entry.sequence_number <= *sequence_number)
+            }
+            RecoveryTarget::Time(target) => {
+                let logged_at = DateTime::parse_from_rfc3339(&entry.timestamp).map_err(|e| {
+                    StorageError::Internal(format!(
+                        "Invalid timestamp '{}' on WAL entry {}: {e}",
+                        entry.timestamp, entry.sequence_number
+                    ))
+                })?;
+                Ok(logged_at <= *target)
+            }
+        }
+    }
+}
+
+impl fmt::Display for RecoveryTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryTarget::Sequence(sequence_number) => write!(f, "{sequence_number}"),
+            RecoveryTarget::Time(target) => write!(f, "{}", time::format_timestamp(*target)),
+        }
+    }
+}
+
+impl FromStr for RecoveryTarget {
+    type Err = String;
+
+    /// Parse a sequence number, or an RFC 3339 timestamp such as `2025-06-22T10:30:00Z`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(sequence_number) = s.parse() {
+            return Ok(RecoveryTarget::Sequence(sequence_number));
+        }
+
+        DateTime::parse_from_rfc3339(s)
+            .map(|target| RecoveryTarget::Time(target.with_timezone(&Utc)))
+            .map_err(|_| {
+                format!(
+                    "unknown recovery target '{s}', expected a sequence number or an RFC 3339 timestamp"
+                )
+            })
+    }
+}
+
+/// A damaged part of the WAL that recovery left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscardedRecord {
+    /// Segment file the record was in
+    pub segment: PathBuf,
+    /// Offset of the record in the segment
+    pub offset: u64,
+    /// Number of bytes left out, starting at `offset`
+    pub bytes: u64,
+    /// Why the record could not be read
+    pub reason: String,
+    /// Whether the segment was truncated at `offset`
+    pub truncated: bool,
+}
+
+/// The entries read back from the WAL, and the damaged records left out
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WalRecovery {
+    /// Entries that were read, in log order
+    pub entries: Vec<WalEntry>,
+    /// Damaged records that were left out, in log order
+    pub discarded: Vec<DiscardedRecord>,
+    /// Whether any entry was sealed with a key that has been rotated out
+    pub sealed_with_previous_key: bool,
+}
+
+/// How far a damaged record extends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Damage {
+    /// Everything from the record to the end of the newest segment is the
+    /// remainder of an interrupted write
+    TornTail {
+        /// Bytes from the record to the end of the segment
+        bytes: u64,
+    },
+    /// The record is complete and more data follows it
+    Record {
+        /// Bytes the framed record takes up
+        bytes: u64,
+    },
+    /// The record runs past the end of a sealed segment
+    Rest {
+        /// Bytes from the record to the end of the segment
+        bytes: u64,
+    },
+}
+
+/// Work out how far the damaged record at `offset` of `path` extends
+///
+/// A record in the `newest` segment that reaches its end, or is followed by
+/// nothing but zeros, is a torn tail.
+pub(super) fn inspect(path: &Path, offset: u64, newest: bool) -> StorageResult<Damage> {
+    let io_error =
+        |e: std::io::Error| StorageError::Internal(format!("Failed to inspect WAL record: {e}"));
+
+    let mut file = File::open(path).map_err(io_error)?;
+    let remaining = file
+        .metadata()
+        .map_err(io_error)?
+        .len()
+        .saturating_sub(offset);
+    file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+    let mut reader = BufReader::new(file);
+
+    let mut header = Vec::with_capacity(format::RECORD_HEADER_LEN);
+    (&mut reader)
+        .take(format::RECORD_HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .map_err(io_error)?;
+
+    let framed = (header.len() == format::RECORD_HEADER_LEN).then(|| {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        format::RECORD_HEADER_LEN as u64 + u64::from(len)
+    });
+
+    match framed {
+        Some(bytes) if bytes < remaining => {
+            if newest && header.iter().all(|&b| b == 0) && only_zeros(&mut reader)? {
+                Ok(Damage::TornTail { bytes: remaining })
+            } else {
+                Ok(Damage::Record { bytes })
+            }
+        }
+        _ if newest => Ok(Damage::TornTail { bytes: remaining }),
+        _ => Ok(Damage::Rest { bytes: remaining }),
+    }
+}
+
+/// Whether everything left in `reader` is zero, as in space a file system
+/// allocated for a write that never made it to disk
+fn only_zeros(reader: &mut impl Read) -> StorageResult<bool> {
+    let mut buf = [0; 8192];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| StorageError::Internal(format!("Failed to inspect WAL record: {e}")))?;
+        if n == 0 {
+            return Ok(true);
+        }
+        if buf[..n].iter().any(|&b| b != 0) {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::wal::WalOperation;
+
+    #[test]
+    fn test_recovery_mode_parsing() {
+        for mode in [
+            RecoveryMode::Strict,
+            RecoveryMode::TruncateTail,
+            RecoveryMode::SkipCorrupt,
+        ] {
+            assert_eq!(mode.to_string().parse::<RecoveryMode>(), Ok(mode));
+        }
+        assert!("lenient".parse::<RecoveryMode>().is_err());
+    }
+
+    #[test]
+    fn test_recovery_target() {
+        let mut entry = WalEntry::new(5, WalOperation::Clear);
+        entry.timestamp = "2025-06-22T10:30:00.000Z".to_string();
+
+        let target: RecoveryTarget = "5".parse().unwrap();
+        assert_eq!(target, RecoveryTarget::Sequence(5));
+        assert!(target.includes(&entry).unwrap());
+        assert!(!RecoveryTarget::Sequence(4).includes(&entry).unwrap());
+
+        let target: RecoveryTarget = "2025-06-22T12:30:00+02:00".parse().unwrap();
+        assert_eq!(target.to_string(), "2025-06-22T10:30:00.000Z");
+        assert!(target.includes(&entry).unwrap());
+        let before: RecoveryTarget = "2025-06-22T10:29:59.999Z".parse().unwrap();
+        assert!(!before.includes(&entry).unwrap());
+
+        assert!("yesterday".parse::<RecoveryTarget>().is_err());
+    }
+
+    #[test]
+    fn test_inspect_damage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("segment");
+
+        // A 4 byte record followed by another record
+        let mut bytes = 4u32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[0; 9]);
+        bytes.extend_from_slice(&[1; 20]);
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            inspect(&path, 0, true).unwrap(),
+            Damage::Record { bytes: 13 }
+        );
+        assert_eq!(
+            inspect(&path, 13, true).unwrap(),
+            Damage::TornTail { bytes: 20 }
+        );
+        assert_eq!(
+            inspect(&path, 13, false).unwrap(),
+            Damage::Rest { bytes: 20 }
+        );
+
+        // Zeros the file system left behind an interrupted write
+        std::fs::write(&path, [0; 64]).unwrap();
+        assert_eq!(
+            inspect(&path, 0, true).unwrap(),
+            Damage::TornTail { bytes: 64 }
+        );
+        assert_eq!(
+            inspect(&path, 0, false).unwrap(),
+            Damage::Record { bytes: 9 }
+        );
+    }
+}
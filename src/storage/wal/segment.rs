@@ -0,0 +1,146 @@
+//! Numbered segment files the write-ahead log is split into
+//!
+//! A WAL configured at `path` is stored as `path.000001`, `path.000002`, ...
+//! Every segment starts with the file header from [`super::format`], which
+//! records the sequence number the log had reached when it was started. Only the
+//! highest-numbered segment is written to; the others are sealed and are only
+//! read during recovery, until a checkpoint makes them obsolete.
+
+use super::durability::WalFile;
+use crate::storage::error::{StorageError, StorageResult};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Default size after which the WAL rolls over to a new segment (64 MiB)
+pub const DEFAULT_MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A segment file of the WAL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    /// Position of the segment in the log, starting at 1
+    pub number: u64,
+    /// Path of the segment file
+    pub path: PathBuf,
+    /// Size of the segment file in bytes
+    pub bytes: u64,
+}
+
+/// The segment the WAL is currently appending to
+pub(super) struct ActiveSegment {
+    /// File handle positioned at the end of the segment
+    pub(super) file: Box<dyn WalFile>,
+    /// Number of the segment
+    pub(super) number: u64,
+    /// Bytes written to the segment so far, header included
+    pub(super) bytes: u64,
+    /// Length of the segment's file header
+    pub(super) header_len: u64,
+}
+
+impl ActiveSegment {
+    /// Whether any entries have been written to the segment
+    pub(super) fn has_entries(&self) -> bool {
+        self.bytes > self.header_len
+    }
+}
+
+/// Path of segment `number` of the WAL at `base`
+pub(super) fn segment_path(base: &Path, number: u64) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(format!(".{number:06}"));
+    PathBuf::from(path)
+}
+
+/// Segments of the WAL at `base`, in log order
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if the WAL directory cannot be read.
+pub(super) fn list(base: &Path) -> StorageResult<Vec<(u64, PathBuf)>> {
+    let Some(name) = base.file_name().and_then(|name| name.to_str()) else {
+        return Err(StorageError::Internal(format!(
+            "Invalid WAL file path: {}",
+            base.display()
+        )));
+    };
+    let prefix = format!("{name}.");
+
+    let dir = match base.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let entries = fs::read_dir(dir)
+        .map_err(|e| StorageError::Internal(format!("Failed to list WAL segments: {e}")))?;
+
+    let mut segments = Vec::new();
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| StorageError::Internal(format!("Failed to list WAL segments: {e}")))?;
+
+        let number = entry
+            .file_name()
+            .to_str()
+            .and_then(|file_name| file_name.strip_prefix(&prefix))
+            .filter(|suffix| !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|suffix| suffix.parse::<u64>().ok());
+
+        if let Some(number) = number {
+            segments.push((number, segment_path(base, number)));
+        }
+    }
+
+    segments.sort_unstable_by_key(|(number, _)| *number);
+    Ok(segments)
+}
+
+/// Make a created, renamed or deleted segment file durable
+///
+/// # Errors
+///
+/// Returns a `StorageError::Internal` if the directory cannot be synced.
+pub(crate) fn sync_dir(path: &Path) -> StorageResult<()> {
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| StorageError::Internal(format!("Failed to sync WAL directory: {e}")))?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_are_listed_in_order() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let base = temp_dir.path().join("data.wal");
+
+        for number in [10, 2, 1] {
+            File::create(segment_path(&base, number)).unwrap();
+        }
+        // Neither segments of this WAL nor segments at all
+        File::create(temp_dir.path().join("data.wal.migrating")).unwrap();
+        File::create(temp_dir.path().join("other.wal.000003")).unwrap();
+        File::create(&base).unwrap();
+
+        let numbers: Vec<u64> = list(&base).unwrap().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(numbers, [1, 2, 10]);
+        assert!(
+            segment_path(&base, 2)
+                .to_string_lossy()
+                .ends_with("data.wal.000002")
+        );
+    }
+}
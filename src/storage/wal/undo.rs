@@ -0,0 +1,87 @@
+//! Undoing writes whose WAL entries never became durable
+//!
+//! Storage engines apply a write as soon as its entry is appended, and only
+//! then wait for the entry to become durable, so that concurrent writers can
+//! share a group commit. An [`UndoLog`] keeps what each of those writes
+//! replaced until its entry is durable. A failed WAL write loses every entry
+//! that was not written yet, and the writes they belong to are undone.
+
+use super::{PendingWrite, WalManager};
+use crate::storage::engine::Value;
+use crate::storage::error::StorageResult;
+use std::collections::VecDeque;
+
+/// What a write replaced: the stored value of each key it changed, expired or
+/// not, or `None` for keys that did not exist
+pub type Replaced = Vec<(String, Option<Value>)>;
+
+/// Writes applied ahead of their WAL entries becoming durable, oldest first
+///
+/// Kept under the lock that serializes writes, so the order of the writes
+/// matches the order of their entries.
+#[derive(Debug, Default)]
+pub struct UndoLog {
+    writes: VecDeque<(PendingWrite, Replaced)>,
+}
+
+impl UndoLog {
+    /// Remember what the write logged as `pending` replaced, forgetting the
+    /// writes that have become durable since the last one
+    ///
+    /// # Errors
+    /// Returns an error if the WAL queue lock cannot be acquired.
+    pub fn record(
+        &mut self,
+        wal: &WalManager,
+        pending: PendingWrite,
+        replaced: Replaced,
+    ) -> StorageResult<()> {
+        self.forget_durable(wal)?;
+        self.writes.push_back((pending, replaced));
+        Ok(())
+    }
+
+    /// Wait until every recorded write is durable
+    ///
+    /// # Errors
+    /// Returns an error if writing the WAL fails; the writes are then still
+    /// recorded, for their writers to undo.
+    pub fn wait_durable(&mut self, wal: &WalManager) -> StorageResult<()> {
+        if let Some((pending, _)) = self.writes.back() {
+            wal.wait_durable(pending)?;
+        }
+
+        self.writes.clear();
+        Ok(())
+    }
+
+    /// Take what the writes whose entries were lost replaced, newest first,
+    /// to be put back in that order
+    ///
+    /// Only call this once writing the WAL has failed: after that, no entry
+    /// that has not been written yet ever will be.
+    ///
+    /// # Errors
+    /// Returns an error if the WAL queue lock cannot be acquired.
+    pub fn take_lost(&mut self, wal: &WalManager) -> StorageResult<Vec<Replaced>> {
+        self.forget_durable(wal)?;
+
+        Ok(self
+            .writes
+            .drain(..)
+            .rev()
+            .map(|(_, replaced)| replaced)
+            .collect())
+    }
+
+    /// Drop the writes at the front whose entries are durable
+    fn forget_durable(&mut self, wal: &WalManager) -> StorageResult<()> {
+        while let Some((pending, _)) = self.writes.front() {
+            if !wal.is_durable(pending)? {
+                break;
+            }
+            self.writes.pop_front();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+//! Background threads that run a task at a fixed interval
+
+use std::io;
+use std::ops::ControlFlow;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Background thread that runs a task every interval
+///
+/// The thread stops as soon as the worker is dropped, or once the task breaks.
+pub(crate) struct PeriodicWorker {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PeriodicWorker {
+    /// Start a thread called `name` that runs `task` every `interval`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the thread cannot be started.
+    pub(crate) fn spawn(
+        name: &str,
+        interval: Duration,
+        mut task: impl FnMut() -> ControlFlow<()> + Send + 'static,
+    ) -> io::Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if task().is_break() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for PeriodicWorker {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up immediately
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Instant;
+
+    #[test]
+    fn test_task_runs_until_it_breaks() {
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&runs);
+        let _worker = PeriodicWorker::spawn("test-worker", Duration::from_millis(1), move || {
+            if counted.fetch_add(1, Ordering::SeqCst) + 1 == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+
+        let started = Instant::now();
+        while runs.load(Ordering::SeqCst) < 3 && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_drop_stops_the_thread_right_away() {
+        let worker = PeriodicWorker::spawn("test-worker", Duration::from_secs(3600), || {
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        let started = Instant::now();
+        drop(worker);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,7 +1,13 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 
 /// Returns the current timestamp in ISO 8601 format (YYYY-MM-DDTHH:mm:ss.sssZ)
 #[must_use]
 pub fn current_timestamp() -> String {
-    Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+    format_timestamp(Utc::now())
+}
+
+/// Formats a timestamp in ISO 8601 format (YYYY-MM-DDTHH:mm:ss.sssZ)
+#[must_use]
+pub fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
 }
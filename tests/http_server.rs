@@ -4,19 +4,32 @@ use std::time::Duration;
 
 use reqwest::Client;
 use serde_json::json;
-use zephyrite::Config;
 use zephyrite::server::Server;
+use zephyrite::storage::WalOptions;
+use zephyrite::storage::disk::storage::WAL_FILE;
+use zephyrite::storage::wal::{Keyring, WalManager, WalOperation};
+use zephyrite::{Config, KeySource, StorageConfig};
 
 /// Helper function to create a test server and return the client and server address
 async fn setup_test_server() -> (
     Client,
     std::net::SocketAddr,
     tokio::sync::oneshot::Sender<()>,
+) {
+    let config = Config::new(0); // Let OS pick a free port
+    start_server(Server::new(config).expect("Failed to create server")).await
+}
+
+/// Start `server` and return the client and server address
+async fn start_server(
+    server: Server,
+) -> (
+    Client,
+    std::net::SocketAddr,
+    tokio::sync::oneshot::Sender<()>,
 ) {
     let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
     let (addr_tx, addr_rx) = tokio::sync::oneshot::channel::<std::net::SocketAddr>();
-    let config = Config::new(0); // Let OS pick a free port
-    let server = Server::new(config).expect("Failed to create server");
 
     tokio::spawn(async move {
         server
@@ -298,3 +311,834 @@ async fn invalid_json_returns_400() {
 
     let _ = shutdown_tx.send(());
 }
+
+#[tokio::test]
+async fn binary_value_roundtrip_works() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let payload: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x00, 0xff, 0xfe, 0x80];
+    let url = format!("http://{addr}/keys/image");
+
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("Content-Type", "application/octet-stream")
+            .body(payload.clone())
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 201);
+
+    // Raw bytes come back when requested
+    let get_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .get(&url)
+            .header("Accept", "application/octet-stream")
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert!(get_resp.status().is_success());
+    assert_eq!(
+        get_resp.headers()["content-type"],
+        "application/octet-stream"
+    );
+    let body = get_resp.bytes().await.expect("Failed to read body");
+    assert_eq!(body.as_ref(), payload.as_slice());
+
+    // The JSON form falls back to base64 for non UTF-8 values
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    let json: serde_json::Value = get_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["encoding"], "base64");
+    assert_eq!(json["value"], "iVBORwD//oA=");
+    assert_eq!(json["size"], payload.len());
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn base64_json_value_is_decoded() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/keys/encoded");
+    let put_body = json!({"value": "aGVsbG8=", "encoding": "base64"});
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.put(&url).json(&put_body).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 201);
+
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    let json: serde_json::Value = get_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["value"], "hello");
+    assert_eq!(json["encoding"], "utf8");
+
+    let bad_body = json!({"value": "not base64!", "encoding": "base64"});
+    let bad_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.put(&url).json(&bad_body).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(bad_resp.status(), 400);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn unsupported_content_type_returns_415() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/keys/plain");
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("Content-Type", "text/plain")
+            .body("hello")
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 415);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn ttl_keys_expire() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/keys/session");
+    let put_body = json!({"value": "token", "ttl": 1});
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.put(&url).json(&put_body).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 201);
+
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    assert!(get_resp.status().is_success());
+    let json: serde_json::Value = get_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["ttl"], 1);
+    assert!(json["expires_at"].is_string());
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    assert_eq!(get_resp.status(), 404);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn ttl_header_is_applied_to_raw_values() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/keys/cache");
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("Content-Type", "application/octet-stream")
+            .header("X-Zephyrite-TTL", "120")
+            .body(vec![1u8, 2, 3])
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 201);
+
+    let get_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .get(&url)
+            .header("Accept", "application/octet-stream")
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(get_resp.headers()["x-zephyrite-ttl"], "120");
+
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("Content-Type", "application/octet-stream")
+            .header("X-Zephyrite-TTL", "soon")
+            .body(vec![1u8])
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 400);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn if_match_performs_compare_and_swap() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/keys/config");
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.put(&url).json(&json!({"value": "v1"})).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 201);
+
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    let etag = get_resp.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(etag, "\"1\"");
+    let json: serde_json::Value = get_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["version"], 1);
+
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("If-Match", &etag)
+            .json(&json!({"value": "v2"}))
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 200);
+    assert_eq!(put_resp.headers()["etag"], "\"2\"");
+
+    // A second writer holding the old ETag loses
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("If-Match", &etag)
+            .json(&json!({"value": "v3"}))
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 412);
+    let json: serde_json::Value = put_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["error"], "precondition_failed");
+
+    let get_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.get(&url).header("If-None-Match", "\"2\"").send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(get_resp.status(), 304);
+
+    // Deleting and creating the key again never brings an old ETag back
+    let delete_resp = tokio::time::timeout(Duration::from_secs(2), client.delete(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+    assert_eq!(delete_resp.status(), 204);
+
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.put(&url).json(&json!({"value": "v4"})).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+    assert_eq!(put_resp.status(), 201);
+
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&url)
+            .header("If-Match", "\"2\"")
+            .json(&json!({"value": "v5"}))
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+    assert_eq!(put_resp.status(), 412);
+
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+    assert_eq!(get_resp.headers()["etag"], "\"3\"");
+
+    let missing_url = format!("http://{addr}/keys/missing");
+    let put_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .put(&missing_url)
+            .header("If-Match", "*")
+            .json(&json!({"value": "v1"}))
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(put_resp.status(), 412);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn if_none_match_creates_only_once() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/keys/lock");
+    for (owner, expected_status) in [("a", 201), ("b", 412)] {
+        let put_resp = tokio::time::timeout(
+            Duration::from_secs(2),
+            client
+                .put(&url)
+                .header("If-None-Match", "*")
+                .json(&json!({"value": owner}))
+                .send(),
+        )
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+        assert_eq!(put_resp.status(), expected_status);
+    }
+
+    let get_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    let json: serde_json::Value = get_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["value"], "a");
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn batch_writes_are_all_or_nothing() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/batch");
+    let batch_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .post(&url)
+            .json(&json!([
+                {"op": "put", "key": "user:1", "value": "alice"},
+                {"op": "put", "key": "user:2", "value": "AP8=", "encoding": "base64"},
+                {"op": "delete", "key": "user:3"}
+            ]))
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(batch_resp.status(), 200);
+    let json: serde_json::Value = batch_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["applied"], 3);
+
+    // An invalid key rejects the whole batch
+    let batch_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client
+            .post(&url)
+            .json(&json!([
+                {"op": "put", "key": "user:4", "value": "dave"},
+                {"op": "delete", "key": "__zephyrite_internal"}
+            ]))
+            .send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    assert_eq!(batch_resp.status(), 400);
+
+    let list_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.get(format!("http://{addr}/keys")).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+
+    let json: serde_json::Value = list_resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["count"], 2);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn batch_body_limit_matches_the_batch_limits() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+    let url = format!("http://{addr}/batch");
+
+    // Larger than axum's default body limit, but within the batch limits
+    let value = "v".repeat(1_000_000);
+    let batch: Vec<serde_json::Value> = (0..4)
+        .map(|i| json!({"op": "put", "key": format!("big:{i}"), "value": value}))
+        .collect();
+    let batch_resp = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.post(&url).json(&batch).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+    assert_eq!(batch_resp.status(), 200);
+
+    // Too many operations
+    let batch: Vec<serde_json::Value> = (0..=10_000)
+        .map(|i| json!({"op": "delete", "key": format!("key:{i}")}))
+        .collect();
+    let batch_resp = tokio::time::timeout(
+        Duration::from_secs(10),
+        client.post(&url).json(&batch).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+    assert_eq!(batch_resp.status(), 400);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn list_keys_supports_prefix_and_range() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let keys = [
+        "user:123:name",
+        "user:123:email",
+        "user:124:name",
+        "order:1",
+        "order:2",
+    ];
+    for key in keys {
+        let put_url = format!("http://{addr}/keys/{key}");
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            client.put(&put_url).json(&json!({"value": "v"})).send(),
+        )
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+    }
+
+    let cases = [
+        (
+            "",
+            vec![
+                "order:1",
+                "order:2",
+                "user:123:email",
+                "user:123:name",
+                "user:124:name",
+            ],
+        ),
+        ("?prefix=user:123:", vec!["user:123:email", "user:123:name"]),
+        (
+            "?start=order:2&end=user:124",
+            vec!["order:2", "user:123:email", "user:123:name"],
+        ),
+        (
+            "?prefix=user:&start=user:123:name&limit=5",
+            vec!["user:123:name", "user:124:name"],
+        ),
+        ("?limit=1", vec!["order:1"]),
+    ];
+
+    for (query, expected) in cases {
+        let list_url = format!("http://{addr}/keys{query}");
+        let list_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&list_url).send())
+            .await
+            .expect("Request timed out")
+            .expect("Failed to send request");
+
+        assert!(list_resp.status().is_success());
+        let json: serde_json::Value = list_resp.json().await.expect("Invalid JSON");
+        assert_eq!(json["keys"], json!(expected), "query: {query}");
+        assert_eq!(json["count"], expected.len());
+    }
+
+    let list_url = format!("http://{addr}/keys?limit=many");
+    let list_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&list_url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    assert_eq!(list_resp.status(), 400);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn list_keys_paginates_with_cursor() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    for index in 0..5 {
+        let put_url = format!("http://{addr}/keys/page:{index}");
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            client.put(&put_url).json(&json!({"value": "v"})).send(),
+        )
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+    }
+
+    let mut pages = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let list_url = match &cursor {
+            Some(cursor) => format!("http://{addr}/keys?prefix=page:&limit=2&cursor={cursor}"),
+            None => format!("http://{addr}/keys?prefix=page:&limit=2"),
+        };
+        let list_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&list_url).send())
+            .await
+            .expect("Request timed out")
+            .expect("Failed to send request");
+
+        assert!(list_resp.status().is_success());
+        let json: serde_json::Value = list_resp.json().await.expect("Invalid JSON");
+        pages.push(json["keys"].clone());
+
+        match json["next_cursor"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+
+    assert_eq!(
+        pages,
+        vec![
+            json!(["page:0", "page:1"]),
+            json!(["page:2", "page:3"]),
+            json!(["page:4"]),
+        ]
+    );
+
+    let list_url = format!("http://{addr}/keys?cursor=not%20a%20cursor");
+    let list_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&list_url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    assert_eq!(list_resp.status(), 400);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn dump_streams_all_entries_as_ndjson() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    // More keys than one storage batch so the dump spans several reads
+    let mut batch: Vec<serde_json::Value> = (0..300)
+        .map(|index| json!({"op": "put", "key": format!("item:{index:03}"), "value": "v"}))
+        .collect();
+    batch.push(json!({"op": "put", "key": "blob", "value": "AP8=", "encoding": "base64"}));
+
+    let batch_url = format!("http://{addr}/batch");
+    let batch_resp = tokio::time::timeout(
+        Duration::from_secs(2),
+        client.post(&batch_url).json(&batch).send(),
+    )
+    .await
+    .expect("Request timed out")
+    .expect("Failed to send request");
+    assert!(batch_resp.status().is_success());
+
+    let dump_url = format!("http://{addr}/dump");
+    let dump_resp = tokio::time::timeout(Duration::from_secs(2), client.get(&dump_url).send())
+        .await
+        .expect("Request timed out")
+        .expect("Failed to send request");
+
+    assert!(dump_resp.status().is_success());
+    assert_eq!(dump_resp.headers()["content-type"], "application/x-ndjson");
+
+    let body = dump_resp.text().await.expect("Failed to read body");
+    let records: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Invalid JSON line"))
+        .collect();
+
+    assert_eq!(records.len(), 301);
+    assert_eq!(records[0]["key"], "blob");
+    assert_eq!(records[0]["value"], "AP8=");
+    assert_eq!(records[0]["encoding"], "base64");
+    assert_eq!(records[1]["key"], "item:000");
+    assert_eq!(records[1]["version"], 1);
+    assert_eq!(records[300]["key"], "item:299");
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn recovery_report_is_served_for_persistent_storage() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let wal_path = temp_dir.path().join("server.wal");
+    {
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        for key in ["a", "b"] {
+            wal_manager
+                .log_operation(WalOperation::Put {
+                    key: key.to_string(),
+                    value: b"value".to_vec(),
+                    expires_at: None,
+                    version: Some(1),
+                })
+                .unwrap();
+        }
+    }
+
+    let config = Config::with_storage(0, StorageConfig::persistent(wal_path.to_string_lossy()));
+    let server = Server::new(config).expect("Failed to create server");
+    let (client, addr, shutdown_tx) = start_server(server).await;
+
+    let url = format!("http://{addr}/admin/recovery");
+    let resp = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["mode"], "strict");
+    assert_eq!(json["snapshot_used"], false);
+    assert_eq!(json["entries_read"], 2);
+    assert_eq!(json["entries_applied"], 2);
+    assert_eq!(json["entries_failed"], 0);
+    assert_eq!(json["last_sequence_number"], 2);
+    assert_eq!(json["clean"], true);
+    assert!(json["duration_ms"].is_number());
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn recovery_report_is_served_for_disk_storage() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    {
+        let wal_manager = WalManager::new(temp_dir.path().join(WAL_FILE)).unwrap();
+        for key in ["a", "b"] {
+            wal_manager
+                .log_operation(WalOperation::Put {
+                    key: key.to_string(),
+                    value: b"value".to_vec(),
+                    expires_at: None,
+                    version: Some(1),
+                })
+                .unwrap();
+        }
+    }
+
+    let config = Config::with_storage(0, StorageConfig::disk(temp_dir.path().to_string_lossy()));
+    let server = Server::new(config).expect("Failed to create server");
+    let (client, addr, shutdown_tx) = start_server(server).await;
+
+    let url = format!("http://{addr}/admin/recovery");
+    let resp = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(resp.status(), 200);
+    let json: serde_json::Value = resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["snapshot_used"], false);
+    assert_eq!(json["entries_read"], 2);
+    assert_eq!(json["entries_applied"], 2);
+    assert_eq!(json["last_sequence_number"], 2);
+    assert_eq!(json["clean"], true);
+
+    let _ = shutdown_tx.send(());
+}
+
+#[tokio::test]
+async fn recovery_report_is_not_found_for_memory_storage() {
+    let (client, addr, shutdown_tx) = setup_test_server().await;
+
+    let url = format!("http://{addr}/admin/recovery");
+    let resp = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(resp.status(), 404);
+    let json: serde_json::Value = resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["error"], "not_persistent");
+
+    let _ = shutdown_tx.send(());
+}
+
+#[test]
+fn server_refuses_to_start_after_failed_replay() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let wal_path = temp_dir.path().join("server.wal");
+    {
+        // A batch with an invalid key cannot be replayed
+        let wal_manager = WalManager::new(&wal_path).unwrap();
+        wal_manager
+            .log_operation(WalOperation::Batch {
+                operations: vec![WalOperation::Delete { key: String::new() }],
+            })
+            .unwrap();
+    }
+
+    let storage = StorageConfig::persistent(wal_path.to_string_lossy());
+    assert!(Server::new(Config::with_storage(0, storage.clone())).is_ok());
+
+    let strict = storage.with_fail_on_replay_errors(true);
+    let result = Server::new(Config::with_storage(0, strict));
+    assert!(matches!(
+        result,
+        Err(zephyrite::server::ServerError::StartupError(msg)) if msg.contains("failed to replay")
+    ));
+}
+
+#[test]
+fn server_needs_the_encryption_key_to_start() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let wal_path = temp_dir.path().join("server.wal");
+    let key_path = temp_dir.path().join("current.key");
+    let other_key_path = temp_dir.path().join("other.key");
+    std::fs::write(&key_path, "11".repeat(32)).unwrap();
+    std::fs::write(&other_key_path, "22".repeat(32)).unwrap();
+    let key = KeySource::File(key_path.to_string_lossy().to_string());
+    let other_key = KeySource::File(other_key_path.to_string_lossy().to_string());
+
+    let storage = StorageConfig::persistent(wal_path.to_string_lossy());
+    let encrypted = storage.clone().with_encryption_key(key.clone());
+    {
+        let options = WalOptions::default().with_encryption(Keyring::new(key.load().unwrap()));
+        let wal_manager = WalManager::open(&wal_path, &options).unwrap();
+        wal_manager.log_operation(WalOperation::Clear).unwrap();
+    }
+
+    for config in [storage.clone(), storage.with_encryption_key(other_key)] {
+        let result = Server::new(Config::with_storage(0, config));
+        assert!(matches!(
+            result,
+            Err(zephyrite::server::ServerError::StorageError(
+                zephyrite::StorageError::Encryption(_)
+            ))
+        ));
+    }
+
+    assert!(Server::new(Config::with_storage(0, encrypted)).is_ok());
+}
+
+#[tokio::test]
+async fn disk_storage_serves_keys() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let config = Config::with_storage(0, StorageConfig::disk(temp_dir.path().to_string_lossy()));
+    let server = Server::new(config).expect("Failed to create server");
+    let (client, addr, shutdown_tx) = start_server(server).await;
+
+    let url = format!("http://{addr}/keys/disk_key");
+    let resp = client
+        .put(&url)
+        .json(&json!({"value": "on disk"}))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(resp.status(), 201);
+
+    let resp = client
+        .get(&url)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(resp.status().is_success());
+    let json: serde_json::Value = resp.json().await.expect("Invalid JSON");
+    assert_eq!(json["value"], "on disk");
+
+    let _ = shutdown_tx.send(());
+}
+
+#[test]
+fn disk_storage_keeps_data_across_restarts() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = StorageConfig::disk(temp_dir.path().to_string_lossy());
+    {
+        let disk = zephyrite::DiskStorage::new(temp_dir.path()).unwrap();
+        zephyrite::StorageEngine::put(&disk, "kept", b"value").unwrap();
+    }
+
+    assert!(Server::new(Config::with_storage(0, storage.clone())).is_ok());
+    let disk = zephyrite::DiskStorage::new(temp_dir.path()).unwrap();
+    assert!(zephyrite::StorageEngine::exists(&disk, "kept").unwrap());
+}
+
+#[test]
+fn disk_storage_refuses_an_encryption_key() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = StorageConfig::disk(temp_dir.path().to_string_lossy());
+
+    let key_path = temp_dir.path().join("current.key");
+    std::fs::write(&key_path, "11".repeat(32)).unwrap();
+    let encrypted =
+        storage.with_encryption_key(KeySource::File(key_path.to_string_lossy().to_string()));
+    let result = Server::new(Config::with_storage(0, encrypted));
+    assert!(matches!(
+        result,
+        Err(zephyrite::server::ServerError::StartupError(msg)) if msg.contains("encryption")
+    ));
+}